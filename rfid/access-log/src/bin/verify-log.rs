@@ -0,0 +1,104 @@
+//! Checks an access log copied off the SD card and reports every broken link.
+//!
+//! ```text
+//! cargo run --bin verify-log -- ACCESS.LOG [--print]
+//! ```
+//!
+//! `--print` lists the records as well. Exits with 1 if anything is wrong.
+
+use std::process::ExitCode;
+
+use access_log::{Break, Kind, Verifier, RECORD_SIZE};
+
+fn main() -> ExitCode {
+    let mut path = None;
+    let mut print = false;
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "--print" => print = true,
+            _ if path.is_none() => path = Some(arg),
+            _ => return usage(),
+        }
+    }
+    let Some(path) = path else {
+        return usage();
+    };
+
+    let data = match std::fs::read(&path) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("{}: {}", path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut verifier = Verifier::new();
+    let mut problems = 0;
+    let chunks = data.chunks_exact(RECORD_SIZE);
+    let tail = chunks.remainder().len();
+    for (i, chunk) in chunks.enumerate() {
+        let (record, problem) = verifier.check(chunk.try_into().unwrap());
+
+        if print {
+            match &record {
+                Some(r) if r.kind == Kind::Boot => {
+                    println!("{:6} {:>14} boot", r.seq, r.timestamp_us)
+                }
+                Some(r) => println!(
+                    "{:6} {:>14} {:<20} {}",
+                    r.seq,
+                    r.timestamp_us,
+                    hex(r.uid()),
+                    r.reason.as_str()
+                ),
+                None => println!("{:>6} {:>14} ?", "?", "?"),
+            }
+        }
+
+        if let Some(problem) = problem {
+            problems += 1;
+            match problem {
+                Break::Sequence { expected, found } => println!(
+                    "record {} (offset {}): {}: expected {}, found {}",
+                    i,
+                    i * RECORD_SIZE,
+                    problem.as_str(),
+                    expected,
+                    found
+                ),
+                _ => println!(
+                    "record {} (offset {}): {}",
+                    i,
+                    i * RECORD_SIZE,
+                    problem.as_str()
+                ),
+            }
+        }
+    }
+    if tail != 0 {
+        problems += 1;
+        println!("{} stray bytes at the end, last record cut short", tail);
+    }
+
+    let records = data.len() / RECORD_SIZE;
+    if problems == 0 {
+        println!("{}: {} records, chain intact", path, records);
+        ExitCode::SUCCESS
+    } else {
+        println!("{}: {} records, {} problems", path, records, problems);
+        ExitCode::FAILURE
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+fn usage() -> ExitCode {
+    eprintln!("usage: verify-log <file> [--print]");
+    ExitCode::FAILURE
+}
@@ -0,0 +1,520 @@
+//! A tamper-evident log of every card presented to the reader.
+//!
+//! The log is a file of fixed-size records. Each one carries a sequence
+//! number and a CRC-32 over its own contents and the CRC of the record
+//! before it, so editing or deleting a record breaks the chain at that point.
+//! Cutting records off the end can't be told apart from a shorter log.
+//!
+//! The chain has no secret in it. It shows accidents and casual edits, not a
+//! determined forger who rewrites everything after the change.
+//!
+//! Record layout, little endian:
+//!
+//! | Bytes  | Field                                   |
+//! |--------|-----------------------------------------|
+//! | 0..4   | sequence number, from 0                 |
+//! | 4..12  | microseconds since the reader booted    |
+//! | 12     | kind: 0 boot, 1 tap                     |
+//! | 13     | decision: 1 granted, 0 denied           |
+//! | 14     | reason                                  |
+//! | 15     | UID length                              |
+//! | 16..26 | UID, zero padded                        |
+//! | 26..28 | reserved, zero                          |
+//! | 28..32 | chain CRC                               |
+#![no_std]
+
+use allowlist::Denial;
+
+pub const RECORD_SIZE: usize = 32;
+/// The log file in the root of the SD card.
+pub const LOG_FILE: &str = "ACCESS.LOG";
+/// Fills the rest of a record cut short by a power loss, so the records
+/// after it stay aligned. It never decodes.
+pub const PADDING: u8 = 0xFF;
+
+const MAX_UID_LEN: usize = 10;
+const CHAIN: usize = 28;
+
+/// CRC-32 (IEEE 802.3) of `data`, continuing from `crc`. Start with 0.
+pub fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// The chain CRC of a record: its contents after the CRC of the one before.
+fn link(prev: u32, bytes: &[u8; RECORD_SIZE]) -> u32 {
+    crc32(crc32(0, &prev.to_le_bytes()), &bytes[..CHAIN])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// The reader started; timestamps start again from 0.
+    Boot,
+    /// A card was presented.
+    Tap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reason {
+    Granted,
+    Unknown,
+    NotYetValid,
+    Expired,
+    NoClock,
+}
+
+impl Reason {
+    pub const fn code(self) -> u8 {
+        match self {
+            Reason::Granted => 0,
+            Reason::Unknown => 1,
+            Reason::NotYetValid => 2,
+            Reason::Expired => 3,
+            Reason::NoClock => 4,
+        }
+    }
+
+    pub const fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(Reason::Granted),
+            1 => Some(Reason::Unknown),
+            2 => Some(Reason::NotYetValid),
+            3 => Some(Reason::Expired),
+            4 => Some(Reason::NoClock),
+            _ => None,
+        }
+    }
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Reason::Granted => "granted",
+            Reason::Unknown => Denial::Unknown.as_str(),
+            Reason::NotYetValid => Denial::NotYetValid.as_str(),
+            Reason::Expired => Denial::Expired.as_str(),
+            Reason::NoClock => Denial::NoClock.as_str(),
+        }
+    }
+}
+
+impl From<Denial> for Reason {
+    fn from(denial: Denial) -> Self {
+        match denial {
+            Denial::Unknown => Reason::Unknown,
+            Denial::NotYetValid => Reason::NotYetValid,
+            Denial::Expired => Reason::Expired,
+            Denial::NoClock => Reason::NoClock,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record {
+    pub seq: u32,
+    pub timestamp_us: u64,
+    pub kind: Kind,
+    pub reason: Reason,
+    uid: [u8; MAX_UID_LEN],
+    uid_len: u8,
+}
+
+impl Record {
+    /// A boot record. The sequence number is filled in by [`Chain::seal`].
+    pub fn boot(timestamp_us: u64) -> Self {
+        Self {
+            seq: 0,
+            timestamp_us,
+            kind: Kind::Boot,
+            reason: Reason::Granted,
+            uid: [0; MAX_UID_LEN],
+            uid_len: 0,
+        }
+    }
+
+    /// A tap of the card with `uid`, granted if `reason` is `Granted`. UIDs
+    /// longer than 10 bytes are cut short.
+    pub fn tap(timestamp_us: u64, uid: &[u8], reason: Reason) -> Self {
+        let len = uid.len().min(MAX_UID_LEN);
+        let mut record = Self::boot(timestamp_us);
+        record.kind = Kind::Tap;
+        record.reason = reason;
+        record.uid[..len].copy_from_slice(&uid[..len]);
+        record.uid_len = len as u8;
+        record
+    }
+
+    pub fn uid(&self) -> &[u8] {
+        &self.uid[..self.uid_len as usize]
+    }
+
+    pub fn granted(&self) -> bool {
+        self.reason == Reason::Granted
+    }
+
+    /// The record's bytes, without the chain CRC.
+    fn encode(&self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0u8; RECORD_SIZE];
+        bytes[0..4].copy_from_slice(&self.seq.to_le_bytes());
+        bytes[4..12].copy_from_slice(&self.timestamp_us.to_le_bytes());
+        bytes[12] = match self.kind {
+            Kind::Boot => 0,
+            Kind::Tap => 1,
+        };
+        bytes[13] = self.granted() as u8;
+        bytes[14] = self.reason.code();
+        bytes[15] = self.uid_len;
+        bytes[16..26].copy_from_slice(&self.uid);
+        bytes
+    }
+
+    /// Decodes a record, returning it and its chain CRC.
+    pub fn decode(bytes: &[u8; RECORD_SIZE]) -> Option<(Self, u32)> {
+        let kind = match bytes[12] {
+            0 => Kind::Boot,
+            1 => Kind::Tap,
+            _ => return None,
+        };
+        let reason = Reason::from_code(bytes[14])?;
+        let uid_len = bytes[15];
+        if bytes[13] != (reason == Reason::Granted) as u8
+            || uid_len as usize > MAX_UID_LEN
+            || bytes[26..CHAIN] != [0, 0]
+        {
+            return None;
+        }
+        let record = Self {
+            seq: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            timestamp_us: u64::from_le_bytes(bytes[4..12].try_into().unwrap()),
+            kind,
+            reason,
+            uid: bytes[16..26].try_into().unwrap(),
+            uid_len,
+        };
+        let chain = u32::from_le_bytes(bytes[CHAIN..].try_into().unwrap());
+        Some((record, chain))
+    }
+}
+
+/// The writing end of the log: numbers records and links each to the last.
+pub struct Chain {
+    next_seq: u32,
+    prev: u32,
+}
+
+impl Default for Chain {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Chain {
+    /// The chain of an empty log.
+    pub const fn new() -> Self {
+        Self {
+            next_seq: 0,
+            prev: 0,
+        }
+    }
+
+    /// Continues a log whose last record is `last`. A damaged record is
+    /// continued from as it is; the verifier reports it either way.
+    pub fn resume(last: &[u8; RECORD_SIZE]) -> Self {
+        Self {
+            next_seq: u32::from_le_bytes(last[0..4].try_into().unwrap()).wrapping_add(1),
+            prev: u32::from_le_bytes(last[CHAIN..].try_into().unwrap()),
+        }
+    }
+
+    /// Numbers `record`, links it and returns the bytes to append.
+    pub fn seal(&mut self, record: &Record) -> [u8; RECORD_SIZE] {
+        let mut record = *record;
+        record.seq = self.next_seq;
+        let mut bytes = record.encode();
+        let chain = link(self.prev, &bytes);
+        bytes[CHAIN..].copy_from_slice(&chain.to_le_bytes());
+
+        self.next_seq = self.next_seq.wrapping_add(1);
+        self.prev = chain;
+        bytes
+    }
+}
+
+/// What's wrong at one point of the log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Break {
+    /// Not a valid record, like a torn write.
+    Malformed,
+    /// The record was changed, or the one before it was.
+    BadLink,
+    /// Records are missing, or came back from elsewhere.
+    Sequence { expected: u32, found: u32 },
+    /// The clock went back without a boot record in between.
+    TimeWentBack,
+}
+
+impl Break {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Break::Malformed => "malformed record",
+            Break::BadLink => "chain broken, record or its predecessor changed",
+            Break::Sequence { .. } => "sequence gap, records removed or inserted",
+            Break::TimeWentBack => "time went back without a reboot",
+        }
+    }
+}
+
+/// Walks a log record by record.
+///
+/// After a break it starts over from the record that broke, so every bad
+/// link is reported once and the rest of the log is still checked.
+pub struct Verifier {
+    expected_seq: u32,
+    prev: u32,
+    last_time: u64,
+    /// The last record was malformed, so its sequence number and CRC are
+    /// unknown.
+    lost: bool,
+}
+
+impl Default for Verifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Verifier {
+    pub const fn new() -> Self {
+        Self {
+            expected_seq: 0,
+            prev: 0,
+            last_time: 0,
+            lost: false,
+        }
+    }
+
+    /// Checks the next record of the log. On a break the record is still
+    /// returned if it decodes.
+    pub fn check(&mut self, bytes: &[u8; RECORD_SIZE]) -> (Option<Record>, Option<Break>) {
+        let Some((record, chain)) = Record::decode(bytes) else {
+            self.lost = true;
+            return (None, Some(Break::Malformed));
+        };
+
+        // After a malformed record the next one either carries on from the
+        // record before it, as the writer does after a torn write, or follows
+        // a whole record that was damaged in place, whose CRC is lost.
+        let after_damaged = self.lost && record.seq == self.expected_seq.wrapping_add(1);
+        let problem = if record.seq != self.expected_seq && !after_damaged {
+            Some(Break::Sequence {
+                expected: self.expected_seq,
+                found: record.seq,
+            })
+        } else if !after_damaged && link(self.prev, bytes) != chain {
+            Some(Break::BadLink)
+        } else if record.kind != Kind::Boot && record.timestamp_us < self.last_time {
+            Some(Break::TimeWentBack)
+        } else {
+            None
+        };
+
+        self.expected_seq = record.seq.wrapping_add(1);
+        self.prev = chain;
+        self.last_time = record.timestamp_us;
+        self.lost = false;
+        (Some(record), problem)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UID: [u8; 4] = [0x13, 0x37, 0x73, 0x31];
+
+    /// A boot record followed by taps a second apart.
+    fn log(records: usize) -> [[u8; RECORD_SIZE]; 8] {
+        let mut chain = Chain::new();
+        let mut log = [[0u8; RECORD_SIZE]; 8];
+        log[0] = chain.seal(&Record::boot(0));
+        for (i, bytes) in log.iter_mut().enumerate().take(records).skip(1) {
+            *bytes = chain.seal(&Record::tap(i as u64 * 1_000_000, &UID, Reason::Granted));
+        }
+        log
+    }
+
+    /// The break reported for each record, in order.
+    fn verify(records: &[[u8; RECORD_SIZE]]) -> [Option<Break>; 8] {
+        let mut verifier = Verifier::new();
+        let mut breaks = [None; 8];
+        for (bytes, slot) in records.iter().zip(breaks.iter_mut()) {
+            *slot = verifier.check(bytes).1;
+        }
+        breaks
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(0, b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(crc32(0, b"1234"), b"56789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn sealed_log_verifies() {
+        let log = log(5);
+        assert_eq!(verify(&log[..5]), [None; 8]);
+
+        let mut verifier = Verifier::new();
+        let (boot, _) = verifier.check(&log[0]);
+        assert_eq!(boot.unwrap().kind, Kind::Boot);
+        let (tap, _) = verifier.check(&log[1]);
+        let tap = tap.unwrap();
+        assert_eq!((tap.seq, tap.kind, tap.uid()), (1, Kind::Tap, &UID[..]));
+        assert!(tap.granted());
+    }
+
+    #[test]
+    fn denied_taps_round_trip() {
+        let mut chain = Chain::new();
+        for reason in [
+            Reason::Unknown,
+            Reason::NotYetValid,
+            Reason::Expired,
+            Reason::NoClock,
+        ] {
+            let bytes = chain.seal(&Record::tap(7, &[1; 7], reason));
+            let (record, _) = Record::decode(&bytes).unwrap();
+            assert_eq!(record.reason, reason);
+            assert!(!record.granted());
+            assert_eq!(record.uid(), [1; 7]);
+        }
+    }
+
+    #[test]
+    fn edited_record_breaks_its_own_link() {
+        let mut log = log(5);
+        // Take 64 µs off the tap in record 2
+        log[2][4] = 0x40;
+        let mut expected = [None; 8];
+        expected[2] = Some(Break::BadLink);
+        assert_eq!(verify(&log[..5]), expected);
+    }
+
+    #[test]
+    fn edited_uid_breaks_its_own_link() {
+        let mut log = log(5);
+        log[3][16] ^= 0x01;
+        let mut expected = [None; 8];
+        expected[3] = Some(Break::BadLink);
+        assert_eq!(verify(&log[..5]), expected);
+    }
+
+    #[test]
+    fn edited_chain_crc_breaks_two_links() {
+        let mut log = log(5);
+        log[2][CHAIN] ^= 0x01;
+        let mut expected = [None; 8];
+        expected[2] = Some(Break::BadLink);
+        expected[3] = Some(Break::BadLink);
+        assert_eq!(verify(&log[..5]), expected);
+    }
+
+    #[test]
+    fn deleted_record_is_a_sequence_gap() {
+        let log = log(5);
+        let without_2 = [log[0], log[1], log[3], log[4]];
+        let mut expected = [None; 8];
+        expected[2] = Some(Break::Sequence {
+            expected: 2,
+            found: 3,
+        });
+        assert_eq!(verify(&without_2), expected);
+    }
+
+    #[test]
+    fn deleted_last_records_go_unnoticed() {
+        let log = log(5);
+        assert_eq!(verify(&log[..3]), [None; 8]);
+    }
+
+    #[test]
+    fn padded_short_record() {
+        // The reader lost power 10 bytes into record 3. At the next boot it
+        // pads the record out and carries on from record 2, the last whole one.
+        let mut log = log(3);
+        log[3] = [PADDING; RECORD_SIZE];
+        log[3][..10].copy_from_slice(&Chain::new().seal(&Record::boot(0))[..10]);
+        let mut chain = Chain::resume(&log[2]);
+        log[4] = chain.seal(&Record::boot(0));
+        log[5] = chain.seal(&Record::tap(5, &UID, Reason::Unknown));
+
+        let mut expected = [None; 8];
+        expected[3] = Some(Break::Malformed);
+        assert_eq!(verify(&log[..6]), expected);
+        assert_eq!(Record::decode(&log[4]).unwrap().0.seq, 3);
+    }
+
+    #[test]
+    fn damaged_record_still_checks_the_next() {
+        // Record 2 is overwritten in place, so record 3 can't be linked but
+        // its sequence number and timestamp still are checked.
+        let mut log = log(5);
+        let late = Chain::resume(&log[2]).seal(&Record::tap(500, &UID, Reason::Granted));
+        log[2] = [0; RECORD_SIZE];
+        let mut expected = [None; 8];
+        expected[2] = Some(Break::Malformed);
+        assert_eq!(verify(&log[..5]), expected);
+
+        expected[3] = Some(Break::TimeWentBack);
+        assert_eq!(verify(&[log[0], log[1], log[2], late]), expected);
+
+        expected[3] = Some(Break::Sequence {
+            expected: 2,
+            found: 4,
+        });
+        assert_eq!(verify(&[log[0], log[1], log[2], log[4]]), expected);
+    }
+
+    #[test]
+    fn edited_record_after_a_torn_write_breaks_its_link() {
+        let mut log = log(3);
+        log[3] = [PADDING; RECORD_SIZE];
+        let mut chain = Chain::resume(&log[2]);
+        log[4] = chain.seal(&Record::boot(0));
+        log[4][16] = 0x01;
+        let mut expected = [None; 8];
+        expected[3] = Some(Break::Malformed);
+        expected[4] = Some(Break::BadLink);
+        assert_eq!(verify(&log[..5]), expected);
+    }
+
+    #[test]
+    fn padding_and_blank_records_are_malformed() {
+        assert_eq!(Record::decode(&[PADDING; RECORD_SIZE]), None);
+        // A boot record says granted, so all zeros doesn't decode either
+        assert_eq!(Record::decode(&[0; RECORD_SIZE]), None);
+    }
+
+    #[test]
+    fn time_only_goes_back_after_a_boot() {
+        let mut chain = Chain::new();
+        let log = [
+            chain.seal(&Record::boot(0)),
+            chain.seal(&Record::tap(2_000, &UID, Reason::Granted)),
+            chain.seal(&Record::tap(1_000, &UID, Reason::Granted)),
+            chain.seal(&Record::boot(0)),
+            chain.seal(&Record::tap(500, &UID, Reason::Granted)),
+        ];
+        let mut expected = [None; 8];
+        expected[2] = Some(Break::TimeWentBack);
+        assert_eq!(verify(&log), expected);
+    }
+}
@@ -0,0 +1,121 @@
+//! Runs `verify-log` on logs written to a temporary directory.
+
+use std::path::PathBuf;
+use std::process::Command;
+
+use access_log::{Chain, Reason, Record, PADDING, RECORD_SIZE};
+
+/// A boot record and four taps.
+fn records() -> Vec<[u8; RECORD_SIZE]> {
+    let mut chain = Chain::new();
+    let mut log = vec![chain.seal(&Record::boot(0))];
+    for i in 1..5 {
+        log.push(chain.seal(&Record::tap(
+            i * 1_000,
+            &[0x13, 0x37, 0x73, 0x31],
+            Reason::Granted,
+        )));
+    }
+    log
+}
+
+/// Writes `data` to a file named after the test and runs the verifier on
+/// it, returning whether it passed and what it printed.
+fn verify(name: &str, data: &[u8], args: &[&str]) -> (bool, String) {
+    let path: PathBuf =
+        std::env::temp_dir().join(format!("verify-log-{}-{}.log", name, std::process::id()));
+    std::fs::write(&path, data).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_verify-log"))
+        .arg(&path)
+        .args(args)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    (output.status.success(), stdout)
+}
+
+#[test]
+fn intact_log() {
+    let (ok, out) = verify("intact", &records().concat(), &[]);
+    assert!(ok, "{out}");
+    assert!(out.ends_with(": 5 records, chain intact\n"), "{out}");
+}
+
+#[test]
+fn print_lists_records() {
+    let (ok, out) = verify("print", &records().concat(), &["--print"]);
+    assert!(ok, "{out}");
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines.len(), 6, "{out}");
+    assert!(lines[0].ends_with(" boot"), "{out}");
+    assert!(lines[1].contains("13:37:73:31"), "{out}");
+    assert!(lines[1].ends_with("granted"), "{out}");
+}
+
+#[test]
+fn edited_record() {
+    let mut log = records();
+    log[2][4] ^= 0x01;
+    let (ok, out) = verify("edited", &log.concat(), &[]);
+    assert!(!ok);
+    assert_eq!(
+        out.lines().next().unwrap(),
+        "record 2 (offset 64): chain broken, record or its predecessor changed"
+    );
+    assert!(out.ends_with(": 5 records, 1 problems\n"), "{out}");
+}
+
+#[test]
+fn deleted_record() {
+    let mut log = records();
+    log.remove(3);
+    let (ok, out) = verify("deleted", &log.concat(), &[]);
+    assert!(!ok);
+    assert_eq!(
+        out.lines().next().unwrap(),
+        "record 3 (offset 96): sequence gap, records removed or inserted: expected 3, found 4"
+    );
+    assert!(out.ends_with(": 4 records, 1 problems\n"), "{out}");
+}
+
+#[test]
+fn padded_short_record() {
+    let mut log = records();
+    log[4] = [PADDING; RECORD_SIZE];
+    let (ok, out) = verify("padded", &log.concat(), &[]);
+    assert!(!ok);
+    assert_eq!(
+        out.lines().next().unwrap(),
+        "record 4 (offset 128): malformed record"
+    );
+}
+
+#[test]
+fn torn_tail() {
+    let mut data = records().concat();
+    data.truncate(4 * RECORD_SIZE + 10);
+    let (ok, out) = verify("torn", &data, &[]);
+    assert!(!ok);
+    assert_eq!(
+        out.lines().next().unwrap(),
+        "10 stray bytes at the end, last record cut short"
+    );
+    assert!(out.ends_with(": 4 records, 1 problems\n"), "{out}");
+}
+
+#[test]
+fn missing_file_and_usage() {
+    let missing = Command::new(env!("CARGO_BIN_EXE_verify-log"))
+        .arg(std::env::temp_dir().join("verify-log-does-not-exist.log"))
+        .output()
+        .unwrap();
+    assert!(!missing.status.success());
+    assert!(String::from_utf8_lossy(&missing.stderr).contains("verify-log-does-not-exist.log: "));
+
+    let usage = Command::new(env!("CARGO_BIN_EXE_verify-log"))
+        .output()
+        .unwrap();
+    assert!(!usage.status.success());
+    assert!(String::from_utf8_lossy(&usage.stderr).starts_with("usage: verify-log"));
+}
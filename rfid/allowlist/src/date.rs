@@ -0,0 +1,53 @@
+//! Dates and times as seconds since 1970-01-01 00:00, for validity windows.
+//!
+//! There's no time zone handling: the reader's clock and the allowlist are
+//! expected to use the same local time. The calendar arithmetic is
+//! `sdcard_clock`'s; this only adds the allowlist's formats, where the time
+//! of day is optional.
+
+use sdcard_clock::date as calendar;
+
+/// A parsed `YYYY-MM-DD` or `YYYY-MM-DD HH:MM` (or with a `T` in between).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    /// Seconds since 1970-01-01 00:00.
+    pub seconds: u64,
+    /// Whether a time of day was given, rather than just a date.
+    pub has_time: bool,
+}
+
+/// Parses a date with an optional time. Returns `None` for anything
+/// malformed or before 1970.
+pub fn parse(s: &str) -> Option<DateTime> {
+    let (date, time) = match s.find(['T', ' ']) {
+        Some(i) => (&s[..i], Some(s[i + 1..].trim_start())),
+        None => (s, None),
+    };
+
+    let mut parts = date.split('-');
+    let year: u16 = number(parts.next()?, 4)?;
+    let month: u8 = number(parts.next()?, 2)?;
+    let day: u8 = number(parts.next()?, 2)?;
+    if parts.next().is_some() || year < 1970 {
+        return None;
+    }
+    let mut parsed = calendar::DateTime::midnight(year, month, day);
+
+    if let Some(time) = time {
+        let (hour, minute) = time.split_once(':')?;
+        parsed.hour = number(hour, 2)?;
+        parsed.minute = number(minute, 2)?;
+    }
+    parsed.is_valid().then(|| DateTime {
+        seconds: parsed.to_unix(),
+        has_time: time.is_some(),
+    })
+}
+
+/// A decimal number of exactly `digits` digits.
+fn number<T: core::str::FromStr>(s: &str, digits: usize) -> Option<T> {
+    if s.len() != digits || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
@@ -0,0 +1,521 @@
+//! An allowlist of card UIDs for the RFID access projects.
+//!
+//! The list is a text file with one card per line and comma separated
+//! fields: UID, name, and optionally the start and end of its validity
+//! window. Blank lines and lines starting with `#` are ignored.
+//!
+//! ```text
+//! # uid, name, valid from, valid until
+//! 13:37:73:31, Front desk
+//! 04A1B2C3D4E5F6, Alice, 2026-01-01, 2026-12-31
+//! 04 11 22 33 44 55 66, Contractor, 2026-03-02 08:00, 2026-03-02 18:00
+//! ```
+//!
+//! UIDs are 4, 7 or 10 bytes of hex, with optional `:`, `-` or spaces
+//! between bytes. A window end left empty is open. A date without a time
+//! starts at the beginning of the day when used as the start and includes
+//! the whole day when used as the end.
+//!
+//! Like the `mifare` crate this is plain `no_std` code, so it can be built and
+//! checked on the host.
+#![no_std]
+
+use core::fmt;
+
+use sdcard_clock::date::SECONDS_PER_DAY;
+
+pub mod date;
+
+/// Longest name kept for an entry, in bytes.
+pub const NAME_LEN: usize = 24;
+
+const MAX_UID_LEN: usize = 10;
+const COMMENT: char = '#';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The UID isn't hex, or has an odd number of digits.
+    BadUid,
+    /// The UID isn't 4, 7 or 10 bytes long.
+    UidLength,
+    MissingName,
+    NameTooLong,
+    BadDate,
+    /// The window ends before it starts.
+    EmptyWindow,
+    TooManyFields,
+    /// The UID is already on the list.
+    Duplicate,
+    /// The list has no room for another entry.
+    Full,
+}
+
+impl ErrorKind {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::BadUid => "UID is not hex",
+            ErrorKind::UidLength => "UID must be 4, 7 or 10 bytes",
+            ErrorKind::MissingName => "missing name",
+            ErrorKind::NameTooLong => "name too long",
+            ErrorKind::BadDate => "date must be YYYY-MM-DD or YYYY-MM-DD HH:MM",
+            ErrorKind::EmptyWindow => "window ends before it starts",
+            ErrorKind::TooManyFields => "too many fields",
+            ErrorKind::Duplicate => "duplicate UID",
+            ErrorKind::Full => "allowlist full",
+        }
+    }
+}
+
+/// A line that couldn't be added, numbered from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub kind: ErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind.as_str())
+    }
+}
+
+/// Why a card was turned away.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Denial {
+    /// The UID isn't on the list.
+    Unknown,
+    NotYetValid,
+    Expired,
+    /// The entry has a window but the reader doesn't know the time.
+    NoClock,
+}
+
+impl Denial {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Denial::Unknown => "unknown card",
+            Denial::NotYetValid => "not yet valid",
+            Denial::Expired => "expired",
+            Denial::NoClock => "no clock to check the window",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry {
+    uid: [u8; MAX_UID_LEN],
+    uid_len: u8,
+    name: [u8; NAME_LEN],
+    name_len: u8,
+    /// First second of the window, if it has a start.
+    pub valid_from: Option<u64>,
+    /// First second after the window, if it has an end.
+    pub valid_until: Option<u64>,
+}
+
+impl Entry {
+    const EMPTY: Self = Self {
+        uid: [0; MAX_UID_LEN],
+        uid_len: 0,
+        name: [0; NAME_LEN],
+        name_len: 0,
+        valid_from: None,
+        valid_until: None,
+    };
+
+    pub fn uid(&self) -> &[u8] {
+        &self.uid[..self.uid_len as usize]
+    }
+
+    pub fn name(&self) -> &str {
+        // Only ever copied from a &str, and never cut
+        core::str::from_utf8(&self.name[..self.name_len as usize]).unwrap_or_default()
+    }
+
+    /// Checks the window against `now`, in seconds since 1970. Entries
+    /// without a window are always valid; entries with one need the time.
+    pub fn check(&self, now: Option<u64>) -> Result<(), Denial> {
+        if self.valid_from.is_none() && self.valid_until.is_none() {
+            return Ok(());
+        }
+        let now = now.ok_or(Denial::NoClock)?;
+        if self.valid_from.is_some_and(|from| now < from) {
+            return Err(Denial::NotYetValid);
+        }
+        if self.valid_until.is_some_and(|until| now >= until) {
+            return Err(Denial::Expired);
+        }
+        Ok(())
+    }
+
+    /// Parses one line. Returns `None` for blank lines and comments.
+    pub fn parse(line: &str) -> Option<Result<Self, ErrorKind>> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(COMMENT) {
+            return None;
+        }
+        Some(Self::parse_fields(line))
+    }
+
+    fn parse_fields(line: &str) -> Result<Self, ErrorKind> {
+        let mut entry = Self::EMPTY;
+        let mut fields = line.split(',').map(str::trim);
+
+        let uid = fields.next().unwrap_or_default();
+        entry.uid_len = parse_uid(uid, &mut entry.uid)? as u8;
+
+        let name = fields.next().unwrap_or_default();
+        if name.is_empty() {
+            return Err(ErrorKind::MissingName);
+        }
+        if name.len() > NAME_LEN {
+            return Err(ErrorKind::NameTooLong);
+        }
+        entry.name[..name.len()].copy_from_slice(name.as_bytes());
+        entry.name_len = name.len() as u8;
+
+        if let Some(from) = fields.next().filter(|f| !f.is_empty()) {
+            let from = date::parse(from).ok_or(ErrorKind::BadDate)?;
+            entry.valid_from = Some(from.seconds);
+        }
+        if let Some(until) = fields.next().filter(|f| !f.is_empty()) {
+            let until = date::parse(until).ok_or(ErrorKind::BadDate)?;
+            // A bare date includes the whole day
+            let end = if until.has_time {
+                until.seconds
+            } else {
+                until.seconds + SECONDS_PER_DAY
+            };
+            entry.valid_until = Some(end);
+        }
+        if fields.next().is_some() {
+            return Err(ErrorKind::TooManyFields);
+        }
+        if let (Some(from), Some(until)) = (entry.valid_from, entry.valid_until) {
+            if until <= from {
+                return Err(ErrorKind::EmptyWindow);
+            }
+        }
+        Ok(entry)
+    }
+}
+
+/// Parses a hex UID into `out` and returns its length.
+fn parse_uid(s: &str, out: &mut [u8; MAX_UID_LEN]) -> Result<usize, ErrorKind> {
+    let mut len = 0;
+    let mut high: Option<u8> = None;
+    for c in s.chars() {
+        if matches!(c, ':' | '-' | ' ') {
+            // Separators only go between bytes
+            if high.is_some() {
+                return Err(ErrorKind::BadUid);
+            }
+            continue;
+        }
+        let digit = c.to_digit(16).ok_or(ErrorKind::BadUid)? as u8;
+        match high.take() {
+            None => high = Some(digit),
+            Some(h) => {
+                if len == MAX_UID_LEN {
+                    return Err(ErrorKind::UidLength);
+                }
+                out[len] = (h << 4) | digit;
+                len += 1;
+            }
+        }
+    }
+    if high.is_some() {
+        return Err(ErrorKind::BadUid);
+    }
+    match len {
+        4 | 7 | 10 => Ok(len),
+        0 => Err(ErrorKind::BadUid),
+        _ => Err(ErrorKind::UidLength),
+    }
+}
+
+/// Up to `N` entries, kept sorted by UID so a lookup is a binary search.
+pub struct Allowlist<const N: usize> {
+    entries: [Entry; N],
+    len: usize,
+}
+
+impl<const N: usize> Default for Allowlist<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Allowlist<N> {
+    pub const fn new() -> Self {
+        Self {
+            entries: [Entry::EMPTY; N],
+            len: 0,
+        }
+    }
+
+    /// Parses a whole file, stopping at the first bad line.
+    pub fn parse(text: &str) -> Result<Self, ParseError> {
+        let mut list = Self::new();
+        for (i, line) in text.lines().enumerate() {
+            list.add_line(i + 1, line)?;
+        }
+        Ok(list)
+    }
+
+    /// Parses line number `line_no` and adds its entry. Blank lines and
+    /// comments are accepted and ignored. A bad line leaves the list as it
+    /// was, so a caller can skip it and go on.
+    pub fn add_line(&mut self, line_no: usize, line: &str) -> Result<(), ParseError> {
+        let error = |kind| ParseError {
+            line: line_no,
+            kind,
+        };
+        match Entry::parse(line) {
+            None => Ok(()),
+            Some(Ok(entry)) => self.insert(entry).map_err(error),
+            Some(Err(kind)) => Err(error(kind)),
+        }
+    }
+
+    /// Adds an entry, keeping the list sorted.
+    pub fn insert(&mut self, entry: Entry) -> Result<(), ErrorKind> {
+        let pos = match self.position(entry.uid()) {
+            Ok(_) => return Err(ErrorKind::Duplicate),
+            Err(pos) => pos,
+        };
+        if self.len == N {
+            return Err(ErrorKind::Full);
+        }
+        self.entries.copy_within(pos..self.len, pos + 1);
+        self.entries[pos] = entry;
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn get(&self, uid: &[u8]) -> Option<&Entry> {
+        self.position(uid).ok().map(|i| &self.entries[i])
+    }
+
+    /// Decides whether `uid` may pass at `now`, in seconds since 1970 if the
+    /// reader knows the time.
+    pub fn check(&self, uid: &[u8], now: Option<u64>) -> Result<&Entry, Denial> {
+        let entry = self.get(uid).ok_or(Denial::Unknown)?;
+        entry.check(now)?;
+        Ok(entry)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The entries, sorted by UID.
+    pub fn iter(&self) -> impl Iterator<Item = &Entry> {
+        self.entries[..self.len].iter()
+    }
+
+    fn position(&self, uid: &[u8]) -> Result<usize, usize> {
+        self.entries[..self.len].binary_search_by(|e| e.uid().cmp(uid))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE: [u8; 7] = [0x04, 0xA1, 0xB2, 0xC3, 0xD4, 0xE5, 0xF6];
+    /// 2026-01-01 00:00 and 2027-01-01 00:00.
+    const NEW_YEAR: u64 = 1_767_225_600;
+    const NEXT_NEW_YEAR: u64 = 1_798_761_600;
+
+    fn entry(line: &str) -> Result<Entry, ErrorKind> {
+        Entry::parse(line).unwrap()
+    }
+
+    fn error(line: &str) -> ErrorKind {
+        entry(line).unwrap_err()
+    }
+
+    #[test]
+    fn parses_entries() {
+        let desk = entry("13:37:73:31, Front desk").unwrap();
+        assert_eq!(desk.uid(), [0x13, 0x37, 0x73, 0x31]);
+        assert_eq!(desk.name(), "Front desk");
+        assert_eq!((desk.valid_from, desk.valid_until), (None, None));
+
+        let alice = entry("04A1B2C3D4E5F6, Alice, 2026-01-01, 2026-12-31").unwrap();
+        assert_eq!(alice.uid(), ALICE);
+        assert_eq!(alice.valid_from, Some(NEW_YEAR));
+        // A bare end date includes the whole day
+        assert_eq!(alice.valid_until, Some(NEXT_NEW_YEAR));
+
+        let contractor =
+            entry("04 11 22 33 44 55 66, Contractor, 2026-03-02 08:00, 2026-03-02 18:00").unwrap();
+        assert_eq!(contractor.valid_from, Some(1_772_438_400));
+        assert_eq!(contractor.valid_until, Some(1_772_474_400));
+
+        let open_end = entry("01-02-03-04-05-06-07-08-09-0a, Bob, 2026-01-01,").unwrap();
+        assert_eq!(open_end.uid().len(), 10);
+        assert_eq!(open_end.valid_until, None);
+    }
+
+    #[test]
+    fn blank_lines_and_comments() {
+        assert_eq!(Entry::parse(""), None);
+        assert_eq!(Entry::parse("   \r"), None);
+        assert_eq!(Entry::parse("# 13:37:73:31, Front desk"), None);
+        assert_eq!(Entry::parse("  # indented"), None);
+    }
+
+    #[test]
+    fn malformed_lines() {
+        assert_eq!(error("13:37:73:3G, Desk"), ErrorKind::BadUid);
+        assert_eq!(error("1337733, Desk"), ErrorKind::BadUid);
+        assert_eq!(error("1:337:73:31, Desk"), ErrorKind::BadUid);
+        assert_eq!(error(", Desk"), ErrorKind::BadUid);
+        assert_eq!(error("13377331AA, Desk"), ErrorKind::UidLength);
+        assert_eq!(error("0102030405060708090A0B, Desk"), ErrorKind::UidLength);
+        assert_eq!(error("13377331"), ErrorKind::MissingName);
+        assert_eq!(error("13377331, "), ErrorKind::MissingName);
+        assert_eq!(
+            error("13377331, A name that is far too long"),
+            ErrorKind::NameTooLong
+        );
+        assert_eq!(error("13377331, Desk, 2026-13-01"), ErrorKind::BadDate);
+        assert_eq!(error("13377331, Desk, 2026-02-29"), ErrorKind::BadDate);
+        assert_eq!(error("13377331, Desk, , 26-01-01"), ErrorKind::BadDate);
+        assert_eq!(
+            error("13377331, Desk, 2026-01-01 24:00"),
+            ErrorKind::BadDate
+        );
+        assert_eq!(error("13377331, Desk, 1969-12-31"), ErrorKind::BadDate);
+        assert_eq!(
+            error("13377331, Desk, 2026-01-02, 2026-01-01"),
+            ErrorKind::EmptyWindow
+        );
+        assert_eq!(
+            error("13377331, Desk, 2026-01-01 10:00, 2026-01-01 10:00"),
+            ErrorKind::EmptyWindow
+        );
+        assert_eq!(
+            error("13377331, Desk, 2026-01-01, 2026-01-02, x"),
+            ErrorKind::TooManyFields
+        );
+    }
+
+    #[test]
+    fn check_window() {
+        let alice = entry("04A1B2C3D4E5F6, Alice, 2026-01-01, 2026-12-31").unwrap();
+        assert_eq!(alice.check(None), Err(Denial::NoClock));
+        assert_eq!(alice.check(Some(NEW_YEAR - 1)), Err(Denial::NotYetValid));
+        assert_eq!(alice.check(Some(NEW_YEAR)), Ok(()));
+        assert_eq!(alice.check(Some(NEXT_NEW_YEAR - 1)), Ok(()));
+        assert_eq!(alice.check(Some(NEXT_NEW_YEAR)), Err(Denial::Expired));
+
+        let from = entry("13377331, Desk, 2026-01-01").unwrap();
+        assert_eq!(from.check(Some(NEW_YEAR - 1)), Err(Denial::NotYetValid));
+        assert_eq!(from.check(Some(u64::MAX)), Ok(()));
+
+        let until = entry("13377331, Desk, , 2026-12-31").unwrap();
+        assert_eq!(until.check(Some(0)), Ok(()));
+        assert_eq!(until.check(Some(NEXT_NEW_YEAR)), Err(Denial::Expired));
+
+        // No window, no clock needed
+        let always = entry("13377331, Desk").unwrap();
+        assert_eq!(always.check(None), Ok(()));
+    }
+
+    #[test]
+    fn add_line_numbers_errors() {
+        let mut list = Allowlist::<4>::new();
+        assert_eq!(list.add_line(1, "# uid, name"), Ok(()));
+        assert_eq!(list.add_line(2, ""), Ok(()));
+        assert_eq!(list.add_line(3, "13:37:73:31, Front desk"), Ok(()));
+        assert_eq!(
+            list.add_line(4, "13:37:73, Short"),
+            Err(ParseError {
+                line: 4,
+                kind: ErrorKind::UidLength
+            })
+        );
+        assert_eq!(list.len(), 1);
+        assert_eq!(
+            list.get(&[0x13, 0x37, 0x73, 0x31]).unwrap().name(),
+            "Front desk"
+        );
+    }
+
+    #[test]
+    fn duplicate_leaves_the_first_entry() {
+        let mut list = Allowlist::<4>::new();
+        list.add_line(1, "13:37:73:31, Front desk").unwrap();
+        assert_eq!(
+            list.add_line(2, "13377331, Impostor"),
+            Err(ParseError {
+                line: 2,
+                kind: ErrorKind::Duplicate
+            })
+        );
+        assert_eq!(list.len(), 1);
+        assert_eq!(list.iter().next().unwrap().name(), "Front desk");
+
+        let text = "13377331, Desk\n04A1B2C3D4E5F6, Alice\n13:37:73:31, Again\n";
+        assert_eq!(
+            Allowlist::<4>::parse(text).err(),
+            Some(ParseError {
+                line: 3,
+                kind: ErrorKind::Duplicate
+            })
+        );
+    }
+
+    #[test]
+    fn full_list() {
+        let mut list = Allowlist::<2>::new();
+        list.add_line(1, "00000002, Two").unwrap();
+        list.add_line(2, "00000001, One").unwrap();
+        assert_eq!(
+            list.add_line(3, "00000003, Three"),
+            Err(ParseError {
+                line: 3,
+                kind: ErrorKind::Full
+            })
+        );
+        // A duplicate is reported as such even when the list is full
+        assert_eq!(
+            list.add_line(4, "00000001, One again").unwrap_err().kind,
+            ErrorKind::Duplicate
+        );
+        assert_eq!(list.len(), 2);
+        assert!(list.iter().map(Entry::name).eq(["One", "Two"]));
+    }
+
+    #[test]
+    fn check_by_uid() {
+        let list = Allowlist::<4>::parse(
+            "# uid, name, valid from, valid until\n\
+             13:37:73:31, Front desk\n\
+             04A1B2C3D4E5F6, Alice, 2026-01-01, 2026-12-31\n",
+        )
+        .unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(
+            list.check(&[0x13, 0x37, 0x73, 0x31], None).unwrap().name(),
+            "Front desk"
+        );
+        assert_eq!(list.check(&ALICE, Some(NEW_YEAR)).unwrap().name(), "Alice");
+        assert_eq!(list.check(&ALICE, None).err(), Some(Denial::NoClock));
+        assert_eq!(
+            list.check(&ALICE, Some(NEXT_NEW_YEAR)).err(),
+            Some(Denial::Expired)
+        );
+        assert_eq!(
+            list.check(&[1, 2, 3, 4], Some(NEW_YEAR)).err(),
+            Some(Denial::Unknown)
+        );
+    }
+}
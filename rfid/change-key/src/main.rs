@@ -9,12 +9,23 @@ use rp235x_hal::{self as hal, Clock};
 use usb_device::{class_prelude::*, prelude::*};
 use usbd_serial::SerialPort;
 
+use core::cell::RefCell;
 use core::fmt::Write;
 
 use embedded_hal_bus::spi::ExclusiveDevice;
 
 use mfrc522::{comm::blocking::spi::SpiInterface, Mfrc522};
 
+use mifare::access::{AccessConditions, KeyType};
+use mifare::card;
+use mifare::diag;
+use mifare::error::RfidError;
+use mifare::geometry::CardType;
+use mifare::keys::{self, FoundKey};
+use mifare::pcd::{Pcd, SharedSpi};
+use mifare::rotation::{self, RotationError};
+use mifare::serial::{halt, report};
+
 use hal::fugit::RateExtU32;
 
 #[link_section = ".start_block"]
@@ -85,44 +96,78 @@ fn main() -> ! {
     );
 
     let spi = ExclusiveDevice::new(spi, spi_cs, timer).unwrap();
-    let itf = SpiInterface::new(spi);
-    let mut rfid = Mfrc522::new(itf).init().unwrap();
+    // Pcd shares the SPI device with the driver to read the card's SAK
+    let spi = RefCell::new(spi);
+    let mut pcd = Pcd::new(SharedSpi::new(&spi));
+    let mut rfid = diag::probe(
+        &mut pcd,
+        Mfrc522::new(SpiInterface::new(SharedSpi::new(&spi))),
+    )
+    .unwrap_or_else(|message| halt(&mut usb_dev, &mut serial, message));
 
     let target_sector = 1;
-    let rel_block = 3;
-    const DATA: [u8; 16] = [
-        0x52, 0x75, 0x73, 0x74, 0x65, 0x64, // Key A: "Rusted"
-        0xFF, 0x07, 0x80, 0x69, // Access bits and trailer byte
-        0x46, 0x65, 0x72, 0x72, 0x69, 0x73, // Key B: "Ferris"
-    ];
-    let current_key = &[0xFF; 6];
-    let new_key: &[u8; 6] = &DATA[..6].try_into().unwrap();
+    const KEY_A: [u8; 6] = *b"Rusted";
+    const KEY_B: [u8; 6] = *b"Ferris";
+    // Encoded at compile time, so a bad combination of access bits fails the
+    // build instead of locking the sector.
+    const DATA: [u8; 16] = match AccessConditions::TRANSPORT.to_trailer(&KEY_A, &KEY_B, 0x69) {
+        Ok(trailer) => trailer,
+        Err(_) => panic!("invalid access conditions"),
+    };
+    let current_key = FoundKey {
+        key_type: KeyType::A,
+        key: keys::TRANSPORT_KEY,
+    };
+    let new_key = &KEY_A;
+    // Sector 0 and trailers that freeze their access bits need this
+    let force = false;
 
     loop {
         let _ = usb_dev.poll(&mut [&mut serial]);
 
         if let Ok(atqa) = rfid.reqa() {
             if let Ok(uid) = rfid.select(&atqa) {
+                let card = match card::detect_classic(&uid, &mut pcd) {
+                    Ok(card) => card,
+                    Err(e) => {
+                        report(&e, &mut serial);
+                        rfid.hlta().unwrap();
+                        continue;
+                    }
+                };
                 serial
                     .write("\r\n----Before Write----\r\n".as_bytes())
                     .unwrap();
-                if let Err(e) =
-                    read_sector(&uid, target_sector, current_key, &mut rfid, &mut serial)
-                {
-                    serial.write(e.as_bytes()).unwrap();
+                if let Err(e) = read_sector(
+                    &uid,
+                    card,
+                    target_sector,
+                    &current_key.key,
+                    &mut rfid,
+                    &mut serial,
+                ) {
+                    report(&e, &mut serial);
                 }
 
-                if let Err(e) =
-                    write_block(&uid, target_sector, rel_block, DATA, current_key, &mut rfid)
-                {
-                    serial.write(e.as_bytes()).unwrap();
+                if let Err(e) = rotation::change_trailer(
+                    &uid,
+                    card,
+                    target_sector,
+                    &current_key,
+                    &DATA,
+                    force,
+                    &mut pcd,
+                ) {
+                    print_rotation_error(&e, &mut serial);
                 }
 
                 serial
                     .write("\r\n----After Write----\r\n".as_bytes())
                     .unwrap();
-                if let Err(e) = read_sector(&uid, target_sector, new_key, &mut rfid, &mut serial) {
-                    serial.write(e.as_bytes()).unwrap();
+                if let Err(e) =
+                    read_sector(&uid, card, target_sector, new_key, &mut rfid, &mut serial)
+                {
+                    report(&e, &mut serial);
                 }
                 rfid.hlta().unwrap();
                 rfid.stop_crypto1().unwrap();
@@ -131,42 +176,46 @@ fn main() -> ! {
     }
 }
 
-fn write_block<E, COMM: mfrc522::comm::Interface<Error = E>>(
-    uid: &mfrc522::Uid,
-    sector: u8,
-    rel_block: u8,
-    data: [u8; 16],
-    key: &[u8; 6],
-    rfid: &mut Mfrc522<COMM, mfrc522::Initialized>,
-) -> Result<(), &'static str> {
-    let block_offset = sector * 4;
-    let abs_block = block_offset + rel_block;
-
-    rfid.mf_authenticate(uid, block_offset, key)
-        .map_err(|_| "Auth failed")?;
-
-    rfid.mf_write(abs_block, data).map_err(|_| "Write failed")?;
-
-    Ok(())
+fn print_rotation_error<E, B: UsbBus>(e: &RotationError<E>, serial: &mut SerialPort<B>) {
+    serial.write(e.as_str().as_bytes()).unwrap();
+    if let RotationError::Failed { cause: Some(_), .. } = e {
+        serial
+            .write(" (the trailer write itself failed)".as_bytes())
+            .unwrap();
+    }
+    serial.write("\r\n".as_bytes()).unwrap();
 }
 
 fn read_sector<E, COMM: mfrc522::comm::Interface<Error = E>, B: UsbBus>(
     uid: &mfrc522::Uid,
+    card: CardType,
     sector: u8,
     key: &[u8; 6],
     rfid: &mut Mfrc522<COMM, mfrc522::Initialized>,
     serial: &mut SerialPort<B>,
-) -> Result<(), &'static str> {
-    let block_offset = sector * 4;
+) -> Result<(), RfidError<E>> {
+    let out_of_range = || RfidError::OutOfRange {
+        sector,
+        block: None,
+    };
+    let block_offset = card.first_block(sector).ok_or_else(out_of_range)?;
+    let blocks = card.blocks_in_sector(sector).ok_or_else(out_of_range)?;
     rfid.mf_authenticate(uid, block_offset, key)
-        .map_err(|_| "Auth failed")?;
-
-    for abs_block in block_offset..block_offset + 4 {
-        let data = rfid.mf_read(abs_block).map_err(|_| "Read failed")?;
+        .map_err(|cause| RfidError::Auth {
+            sector,
+            key_type: KeyType::A,
+            cause,
+        })?;
+
+    for rel_block in 0..blocks {
+        let block = block_offset + rel_block;
+        let data = rfid.mf_read(block).map_err(|cause| RfidError::Read {
+            sector,
+            block,
+            cause,
+        })?;
         print_hex_to_serial(&data, serial);
-        serial
-            .write("\r\n".as_bytes())
-            .map_err(|_| "Write failed")?;
+        serial.write("\r\n".as_bytes()).unwrap();
     }
     Ok(())
 }
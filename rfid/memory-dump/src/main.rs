@@ -4,17 +4,38 @@
 use hal::block::ImageDef;
 use heapless::String;
 use panic_halt as _;
-use rp235x_hal::{self as hal, Clock};
+use rp235x_hal::{self as hal, Clock as _};
 
 use usb_device::{class_prelude::*, prelude::*};
 use usbd_serial::SerialPort;
 
-use core::fmt::Write;
+use core::cell::RefCell;
+use core::fmt::{self, Write};
 
+use embedded_hal::spi::SpiDevice;
 use embedded_hal_bus::spi::ExclusiveDevice;
 
 use mfrc522::{comm::blocking::spi::SpiInterface, Mfrc522};
 
+use embedded_sdmmc::{BlockDevice, Mode, SdCard, TimeSource, Timestamp, VolumeIdx, VolumeManager};
+use hal::gpio::{FunctionI2C, Pin};
+
+use mifare::access::KeyType;
+use mifare::card::{self, Card};
+use mifare::diag;
+use mifare::dump::{self, Dump, RestoreOptions};
+use mifare::error::RfidError;
+use mifare::geometry::{self, CardType};
+use mifare::keys::{self, FoundKey};
+use mifare::pcd::{Pcd, SharedSpi};
+use mifare::serial::{halt, report};
+use mifare::trailer::SectorTrailer;
+use mifare::ultralight::{self, CapabilityContainer, TagType};
+
+use sdcard_clock::aon::AonClock;
+use sdcard_clock::date::DateTime;
+use sdcard_clock::ds3231::Ds3231;
+
 use hal::fugit::RateExtU32;
 
 #[link_section = ".start_block"]
@@ -23,6 +44,91 @@ pub static IMAGE_DEF: ImageDef = hal::block::ImageDef::secure_exe();
 
 const XTAL_FREQ_HZ: u32 = 12_000_000u32;
 
+/// A sector that fails with a garbled frame is read again this many times.
+const SECTOR_RETRIES: u8 = 1;
+
+/// Keys tried on every sector, each as Key A and as Key B
+const KEYS: [[u8; 6]; 7] = [
+    keys::TRANSPORT_KEY,
+    keys::MAD_KEY,
+    keys::NDEF_KEY,
+    keys::VENDOR_KEY_B,
+    keys::ZERO_KEY,
+    // Our own keys, as set by the change-key project
+    *b"Rusted",
+    *b"Ferris",
+];
+
+/// If one of these is on the SD card, cards presented get it written to them
+/// instead of being dumped.
+const RESTORE_MFD: &str = "RESTORE.MFD";
+const RESTORE_JSON: &str = "RESTORE.JSN";
+/// Block 0 only takes on magic cards, and a trailer from another card may
+/// hold keys we don't know; leave both out unless asked for.
+const RESTORE_OPTIONS: RestoreOptions = RestoreOptions {
+    block0: false,
+    trailers: false,
+};
+/// Largest JSON dump we read, enough for a 4K card.
+const MAX_JSON: usize = 16 * 1024;
+
+/// File dates for saved dumps, from the AON timer. After a power cycle it's
+/// set from a DS3231 on I2C1 (GPIO18 SDA, GPIO19 SCL) if there is one; until
+/// then this gives 1980-01-01, the earliest date FAT can store.
+#[derive(Clone, Copy)]
+struct Clock<'a>(&'a AonClock);
+
+impl TimeSource for Clock<'_> {
+    fn get_timestamp(&self) -> Timestamp {
+        let now = self.0.now().unwrap_or(DateTime::FAT_MIN).fat_clamped();
+        Timestamp {
+            year_since_1970: (now.year - 1970) as u8,
+            zero_indexed_month: now.month - 1,
+            zero_indexed_day: now.day - 1,
+            hours: now.hour,
+            minutes: now.minute,
+            seconds: now.second,
+        }
+    }
+}
+
+/// Collects formatted text into sector-sized writes, so writing JSON doesn't
+/// rewrite an SD block for every few bytes.
+struct BlockWriter<F: FnMut(&[u8]) -> bool> {
+    buf: [u8; 512],
+    len: usize,
+    sink: F,
+}
+
+impl<F: FnMut(&[u8]) -> bool> BlockWriter<F> {
+    fn new(sink: F) -> Self {
+        Self {
+            buf: [0; 512],
+            len: 0,
+            sink,
+        }
+    }
+
+    fn flush(&mut self) -> bool {
+        let ok = self.len == 0 || (self.sink)(&self.buf[..self.len]);
+        self.len = 0;
+        ok
+    }
+}
+
+impl<F: FnMut(&[u8]) -> bool> fmt::Write for BlockWriter<F> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for &b in s.as_bytes() {
+            if self.len == self.buf.len() && !self.flush() {
+                return Err(fmt::Error);
+            }
+            self.buf[self.len] = b;
+            self.len += 1;
+        }
+        Ok(())
+    }
+}
+
 #[hal::entry]
 fn main() -> ! {
     // Boiler Plate
@@ -85,15 +191,85 @@ fn main() -> ! {
     );
 
     let spi = ExclusiveDevice::new(spi, spi_cs, timer).unwrap();
-    let itf = SpiInterface::new(spi);
-    let mut rfid = Mfrc522::new(itf).init().unwrap();
+    // The driver and Pcd share the SPI device; Pcd adds Key B authentication
+    let spi = RefCell::new(spi);
+    let mut pcd = Pcd::new(SharedSpi::new(&spi));
+    let mut rfid = diag::probe(
+        &mut pcd,
+        Mfrc522::new(SpiInterface::new(SharedSpi::new(&spi))),
+    )
+    .unwrap_or_else(|message| halt(&mut usb_dev, &mut serial, message));
+
+    // An optional DS3231 RTC module on I2C1
+    let sda_pin: Pin<_, FunctionI2C, _> = pins.gpio18.reconfigure();
+    let scl_pin: Pin<_, FunctionI2C, _> = pins.gpio19.reconfigure();
+    let i2c = hal::I2C::i2c1(
+        pac.I2C1,
+        sda_pin,
+        scl_pin,
+        400.kHz(),
+        &mut pac.RESETS,
+        &clocks.system_clock,
+    );
+    let mut ds3231 = Ds3231::new(i2c);
+
+    // The AON timer keeps the time across resets. After a power cycle, take
+    // it from the DS3231 if there is one and it's been set.
+    let aon = AonClock::new(pac.POWMAN, XTAL_FREQ_HZ);
+    if !aon.is_set() {
+        if let Ok(Some(now)) = ds3231.now() {
+            aon.set(&now);
+        }
+    }
+
+    // SD card Setup, on SPI1 since the reader has SPI0
+    let sd_cs = pins.gpio13.into_push_pull_output();
+    let sd_sck = pins.gpio10.into_function::<hal::gpio::FunctionSpi>();
+    let sd_mosi = pins.gpio11.into_function::<hal::gpio::FunctionSpi>();
+    let sd_miso = pins.gpio12.into_function::<hal::gpio::FunctionSpi>();
+    let sd_bus = hal::spi::Spi::<_, _, _, 8>::new(pac.SPI1, (sd_mosi, sd_miso, sd_sck));
+
+    let sd_spi = sd_bus.init(
+        &mut pac.RESETS,
+        clocks.peripheral_clock.freq(),
+        400.kHz(), // card initialization happens at low baud rate
+        embedded_hal::spi::MODE_0,
+    );
+
+    let sd_spi = ExclusiveDevice::new(sd_spi, sd_cs, timer).unwrap();
+    let sdcard = SdCard::new(sd_spi, timer);
+    let mut volume_mgr = VolumeManager::new(sdcard, Clock(&aon));
+
+    // Serial isn't up yet, so say which mode we're in on every card
+    let restore = load_restore(&mut volume_mgr);
 
     loop {
         let _ = usb_dev.poll(&mut [&mut serial]);
         if let Ok(atqa) = rfid.reqa() {
             if let Ok(uid) = rfid.select(&atqa) {
-                if let Err(e) = dump_memory(&uid, &mut rfid, &mut serial) {
-                    serial.write(e.as_bytes()).unwrap();
+                match &restore {
+                    Ok(Some(dump)) => {
+                        if let Err(e) = restore_card(&uid, dump, &mut pcd, &mut serial) {
+                            report(&e, &mut serial);
+                        }
+                    }
+                    Ok(None) => match dump_memory(&uid, &mut rfid, &mut pcd, &mut serial) {
+                        Ok(Some(dump)) => {
+                            let saved = save_dump(&mut volume_mgr, &dump);
+                            let message = match saved {
+                                Ok(()) => "\r\nSaved to SD card\r\n",
+                                Err(_) => "\r\nCould not save to SD card\r\n",
+                            };
+                            serial.write(message.as_bytes()).unwrap();
+                        }
+                        Ok(None) => {}
+                        Err(e) => report(&e, &mut serial),
+                    },
+                    Err(e) => {
+                        serial.write("\r\nRestore file: ".as_bytes()).unwrap();
+                        serial.write(e.as_bytes()).unwrap();
+                        serial.write("\r\n".as_bytes()).unwrap();
+                    }
                 }
                 rfid.hlta().unwrap();
                 rfid.stop_crypto1().unwrap();
@@ -102,39 +278,358 @@ fn main() -> ! {
     }
 }
 
-fn dump_memory<E, COMM: mfrc522::comm::Interface<Error = E>, B: UsbBus>(
+/// Loads the dump to restore, if there is one. `Ok(None)` means dump mode.
+fn load_restore<D: BlockDevice, T: TimeSource>(
+    volume_mgr: &mut VolumeManager<D, T>,
+) -> Result<Option<Dump>, &'static str> {
+    let Ok(mut volume) = volume_mgr.open_volume(VolumeIdx(0)) else {
+        return Ok(None);
+    };
+    let Ok(mut root_dir) = volume.open_root_dir() else {
+        return Ok(None);
+    };
+
+    let mut buffer = [0u8; MAX_JSON];
+    for name in [RESTORE_MFD, RESTORE_JSON] {
+        let Ok(mut file) = root_dir.open_file_in_dir(name, Mode::ReadOnly) else {
+            continue;
+        };
+        if file.length() as usize > buffer.len() {
+            return Err("file too large");
+        }
+        let mut len = 0;
+        while !file.is_eof() {
+            len += file.read(&mut buffer[len..]).map_err(|_| "read error")?;
+        }
+
+        let dump = if name == RESTORE_MFD {
+            Dump::from_mfd(&buffer[..len])
+        } else {
+            let text = core::str::from_utf8(&buffer[..len]).map_err(|_| "not UTF-8")?;
+            Dump::from_json(text)
+        };
+        return dump.map(Some).map_err(|e| e.as_str());
+    }
+    Ok(None)
+}
+
+/// Writes the dump as `<UID>.MFD` and `<UID>.JSN`, named after the last four
+/// bytes of the UID to fit 8.3 names.
+fn save_dump<D: BlockDevice, T: TimeSource>(
+    volume_mgr: &mut VolumeManager<D, T>,
+    dump: &Dump,
+) -> Result<(), embedded_sdmmc::Error<D::Error>> {
+    let uid = dump.uid();
+    let mut stem: String<8> = String::new();
+    for b in &uid[uid.len().saturating_sub(4)..] {
+        write!(stem, "{:02X}", b).unwrap();
+    }
+
+    let mut volume = volume_mgr.open_volume(VolumeIdx(0))?;
+    let mut root_dir = volume.open_root_dir()?;
+
+    let mut name: String<12> = String::new();
+    write!(name, "{}.MFD", stem).unwrap();
+    let mut file = root_dir.open_file_in_dir(name.as_str(), Mode::ReadWriteCreateOrTruncate)?;
+    file.write(dump.mfd())?;
+    file.close()?;
+
+    name.clear();
+    write!(name, "{}.JSN", stem).unwrap();
+    let mut file = root_dir.open_file_in_dir(name.as_str(), Mode::ReadWriteCreateOrTruncate)?;
+    let mut failed = None;
+    let mut writer = BlockWriter::new(|bytes: &[u8]| match file.write(bytes) {
+        Ok(()) => true,
+        Err(e) => {
+            failed = Some(e);
+            false
+        }
+    });
+    let written = dump.write_json(&mut writer).is_ok() && writer.flush();
+    drop(writer);
+    if let Some(e) = failed {
+        return Err(e);
+    }
+    if !written {
+        return Err(embedded_sdmmc::Error::DiskFull);
+    }
+    file.close()
+}
+
+/// Writes the restore dump onto a Classic card and reports what happened.
+fn restore_card<E, SPI: SpiDevice<Error = E>, B: UsbBus>(
     uid: &mfrc522::Uid,
-    rfid: &mut Mfrc522<COMM, mfrc522::Initialized>,
+    dump: &Dump,
+    pcd: &mut Pcd<SPI>,
     serial: &mut SerialPort<B>,
-) -> Result<(), &'static str> {
+) -> Result<(), RfidError<E>> {
+    let card = match card::identify(uid, pcd).map_err(RfidError::Activation)? {
+        Some(Card::Classic(card)) => card,
+        _ => return Err(RfidError::UnsupportedCard),
+    };
+    serial.write("\r\nRestoring onto ".as_bytes()).unwrap();
+    serial.write(card.name().as_bytes()).unwrap();
+    serial.write("\r\n".as_bytes()).unwrap();
+
+    let report = dump::restore(uid, card, dump, &KEYS, RESTORE_OPTIONS, pcd)?;
     let mut buff: String<64> = String::new();
-    for sector in 0..16 {
+    write!(
+        buff,
+        "{} written, {} skipped, {} failed{}\r\n",
+        report.written,
+        report.skipped,
+        report.failed,
+        if report.gen1a { " (Gen1a)" } else { "" }
+    )
+    .unwrap();
+    serial.write(buff.as_bytes()).unwrap();
+    Ok(())
+}
+
+fn dump_memory<E, R, P, B>(
+    uid: &mfrc522::Uid,
+    rfid: &mut Mfrc522<R, mfrc522::Initialized>,
+    pcd: &mut Pcd<P>,
+    serial: &mut SerialPort<B>,
+) -> Result<Option<Dump>, RfidError<E>>
+where
+    R: mfrc522::comm::Interface<Error = E>,
+    P: SpiDevice<Error = E>,
+    B: UsbBus,
+{
+    let card = card::identify(uid, pcd)
+        .map_err(RfidError::Activation)?
+        .ok_or(RfidError::UnsupportedCard)?;
+
+    serial.write("\r\nDumping ".as_bytes()).unwrap();
+    serial.write(card.name().as_bytes()).unwrap();
+    serial.write("\r\n".as_bytes()).unwrap();
+
+    match card {
+        Card::Classic(card) => dump_sectors(uid, card, rfid, pcd, serial).map(Some),
+        Card::Ultralight(tag) => dump_pages(tag, pcd, serial).map(|_| None),
+    }
+}
+
+fn dump_sectors<E, R, P, B>(
+    uid: &mfrc522::Uid,
+    card: CardType,
+    rfid: &mut Mfrc522<R, mfrc522::Initialized>,
+    pcd: &mut Pcd<P>,
+    serial: &mut SerialPort<B>,
+) -> Result<Dump, RfidError<E>>
+where
+    R: mfrc522::comm::Interface<Error = E>,
+    P: SpiDevice<Error = E>,
+    B: UsbBus,
+{
+    let activation = pcd.activate(uid).map_err(RfidError::Activation)?;
+    let mut dump = Dump::new(card, uid.as_bytes(), &activation);
+
+    let mut buff: String<64> = String::new();
+    write!(buff, "{} sectors\r\n", card.sector_count()).unwrap();
+    serial.write(buff.as_bytes()).unwrap();
+    buff.clear();
+
+    let mut unread = 0;
+    for sector in 0..card.sector_count() {
         // Printing the Sector number
         write!(buff, "\r\n-----------SECTOR {}-----------\r\n", sector).unwrap();
         serial.write(buff.as_bytes()).unwrap();
         buff.clear();
-        read_sector(uid, sector, rfid, serial)?;
+
+        // A garbled frame is worth another go; a missing key is not
+        let mut retries = 0;
+        let result = loop {
+            match dump_sector(uid, card, sector, rfid, pcd, &mut dump, serial) {
+                Err(e) if e.is_transient() && retries < SECTOR_RETRIES => {
+                    report(&e, serial);
+                    serial.write("Retrying\r\n".as_bytes()).unwrap();
+                    retries += 1;
+                }
+                result => break result,
+            }
+        };
+        match result {
+            Ok(true) => {}
+            Ok(false) => {
+                serial
+                    .write("No known key opens this sector".as_bytes())
+                    .unwrap();
+                unread += 1;
+            }
+            Err(e) => {
+                report(&e, serial);
+                unread += 1;
+            }
+        }
     }
-    Ok(())
+    write!(
+        buff,
+        "\r\n{} of {} sectors could not be read\r\n",
+        unread,
+        card.sector_count()
+    )
+    .unwrap();
+    serial.write(buff.as_bytes()).unwrap();
+    Ok(dump)
 }
 
-fn read_sector<E, COMM: mfrc522::comm::Interface<Error = E>, B: UsbBus>(
+/// Finds a key for `sector` and dumps it. Returns false if no key opens it.
+fn dump_sector<E, R, P, B>(
     uid: &mfrc522::Uid,
+    card: CardType,
     sector: u8,
-    rfid: &mut Mfrc522<COMM, mfrc522::Initialized>,
+    rfid: &mut Mfrc522<R, mfrc522::Initialized>,
+    pcd: &mut Pcd<P>,
+    dump: &mut Dump,
     serial: &mut SerialPort<B>,
-) -> Result<(), &'static str> {
-    const AUTH_KEY: [u8; 6] = [0xFF; 6];
+) -> Result<bool, RfidError<E>>
+where
+    R: mfrc522::comm::Interface<Error = E>,
+    P: SpiDevice<Error = E>,
+    B: UsbBus,
+{
+    let first_block = card.first_block(sector).ok_or(RfidError::OutOfRange {
+        sector,
+        block: None,
+    })?;
+    // Errors here mean the card is gone, not that the keys were wrong
+    let found = keys::find_key(uid, first_block, &KEYS, pcd).map_err(RfidError::Activation)?;
+    let Some(found) = found else {
+        return Ok(false);
+    };
+    print_found_key(&found, serial);
+
+    read_sector(card, sector, rfid, dump, serial)?;
+    // The key goes in after the data, as the trailer reads back without it
+    dump.set_key(sector, &found);
+    Ok(true)
+}
+
+fn print_found_key<B: UsbBus>(found: &FoundKey, serial: &mut SerialPort<B>) {
+    let key_name = match found.key_type {
+        KeyType::A => "Opened with KEY A: ",
+        KeyType::B => "Opened with KEY B: ",
+    };
+    serial.write(key_name.as_bytes()).unwrap();
+    print_hex_to_serial(&found.key, serial);
+    serial.write("\r\n".as_bytes()).unwrap();
+}
 
+/// Dumps a page based tag: every page, then the CC and the lock bytes.
+fn dump_pages<E, SPI: SpiDevice<Error = E>, B: UsbBus>(
+    tag: TagType,
+    pcd: &mut Pcd<SPI>,
+    serial: &mut SerialPort<B>,
+) -> Result<(), RfidError<E>> {
     let mut buff: String<64> = String::new();
+    let mut header = [0u8; 16];
+    let mut dynamic_lock = None;
+
+    // READ returns four pages at a time
+    for first_page in (0..tag.page_count()).step_by(4) {
+        let data =
+            ultralight::read_pages(first_page, pcd).map_err(|cause| RfidError::ReadPage {
+                page: first_page,
+                cause,
+            })?;
+        if first_page == 0 {
+            header = data;
+        }
+
+        for (i, page_data) in data.chunks(ultralight::PAGE_SIZE).enumerate() {
+            let page = first_page + i as u8;
+            // The last read wraps around to page 0; don't print that twice
+            if page >= tag.page_count() {
+                break;
+            }
+            if Some(page) == tag.dynamic_lock_page() {
+                dynamic_lock = Some([page_data[0], page_data[1], page_data[2]]);
+            }
+
+            write!(buff, "\r\nPAGE {:>3} | ", page).unwrap();
+            serial.write(buff.as_bytes()).unwrap();
+            buff.clear();
+            print_hex_to_serial(page_data, serial);
+            write!(buff, "| {} ", tag.page_kind(page)).unwrap();
+            serial.write(buff.as_bytes()).unwrap();
+            buff.clear();
+        }
+    }
+    serial.write("\r\n".as_bytes()).unwrap();
 
-    let block_offset = sector * 4;
-    rfid.mf_authenticate(uid, block_offset, &AUTH_KEY)
-        .map_err(|_| "Auth failed")?;
+    // Page 3 is the capability container
+    let cc: [u8; 4] = header[12..16].try_into().unwrap();
+    match CapabilityContainer::parse(&cc) {
+        Some(cc) => write!(
+            buff,
+            "\r\nCC: NDEF v{}.{}, {} bytes, {}\r\n",
+            cc.version >> 4,
+            cc.version & 0x0F,
+            cc.data_size,
+            if cc.read_only {
+                "read-only"
+            } else {
+                "writable"
+            }
+        )
+        .unwrap(),
+        None => write!(buff, "\r\nCC: not formatted for NDEF\r\n").unwrap(),
+    }
+    serial.write(buff.as_bytes()).unwrap();
+    buff.clear();
 
-    for abs_block in block_offset..block_offset + 4 {
-        let rel_block = abs_block - block_offset;
-        let data = rfid.mf_read(abs_block).map_err(|_| "Read failed")?;
+    // Bytes 2 and 3 of page 2 are the static lock bytes
+    let lock = [header[10], header[11]];
+    serial.write("LOCKED PAGES:".as_bytes()).unwrap();
+    let mut any_locked = false;
+    for page in ultralight::CC_PAGE..16 {
+        if ultralight::is_statically_locked(&lock, page) {
+            write!(buff, " {}", page).unwrap();
+            serial.write(buff.as_bytes()).unwrap();
+            buff.clear();
+            any_locked = true;
+        }
+    }
+    if !any_locked {
+        serial.write(" none".as_bytes()).unwrap();
+    }
+
+    if let Some(dynamic_lock) = dynamic_lock {
+        serial.write("\r\nDYNAMIC LOCK: ".as_bytes()).unwrap();
+        print_hex_to_serial(&dynamic_lock, serial);
+    }
+    serial.write("\r\n".as_bytes()).unwrap();
+    Ok(())
+}
+
+/// Reads the blocks of a sector that has already been authenticated.
+fn read_sector<E, COMM: mfrc522::comm::Interface<Error = E>, B: UsbBus>(
+    card: CardType,
+    sector: u8,
+    rfid: &mut Mfrc522<COMM, mfrc522::Initialized>,
+    dump: &mut Dump,
+    serial: &mut SerialPort<B>,
+) -> Result<(), RfidError<E>> {
+    let mut buff: String<64> = String::new();
+
+    let out_of_range = || RfidError::OutOfRange {
+        sector,
+        block: None,
+    };
+    let block_offset = card.first_block(sector).ok_or_else(out_of_range)?;
+    let blocks = card.blocks_in_sector(sector).ok_or_else(out_of_range)?;
+    let mut trailer = [0u8; 16];
+    // Iterate relative blocks: the last sector of a 4K card ends at block 255
+    for rel_block in 0..blocks {
+        let abs_block = block_offset + rel_block;
+        let data = rfid.mf_read(abs_block).map_err(|cause| RfidError::Read {
+            sector,
+            block: abs_block,
+            cause,
+        })?;
+        dump.set_block(abs_block, &data);
 
         // Prining the Block absolute and relative numbers
         write!(buff, "\r\nBLOCK {} (REL: {}) | ", abs_block, rel_block).unwrap();
@@ -149,17 +644,98 @@ fn read_sector<E, COMM: mfrc522::comm::Interface<Error = E>, B: UsbBus>(
         write!(buff, "| {} ", block_type).unwrap();
         serial.write(buff.as_bytes()).unwrap();
         buff.clear();
+
+        if geometry::is_trailer(sector, rel_block) {
+            trailer = data;
+        }
     }
-    serial
-        .write("\r\n".as_bytes())
-        .map_err(|_| "Write failed")?;
+    serial.write("\r\n".as_bytes()).unwrap();
+
+    print_trailer(sector, &trailer, serial);
     Ok(())
 }
 
+fn print_trailer<B: UsbBus>(sector: u8, data: &[u8; 16], serial: &mut SerialPort<B>) {
+    let mut buff: String<64> = String::new();
+
+    let trailer = match SectorTrailer::parse(data) {
+        Ok(trailer) => trailer,
+        Err(e) => {
+            write!(buff, "\r\n{}\r\n", e.as_str()).unwrap();
+            serial.write(buff.as_bytes()).unwrap();
+            return;
+        }
+    };
+
+    // Key A always reads back as zeros, so there is nothing worth showing
+    serial
+        .write("\r\nKEY A: ** ** ** ** ** ** \r\nKEY B: ".as_bytes())
+        .unwrap();
+    match trailer.key_b() {
+        Some(key_b) => print_hex_to_serial(key_b, serial),
+        None => {
+            serial.write("not readable".as_bytes()).unwrap();
+        }
+    }
+    write!(buff, "\r\nUSER BYTE: {:02x}\r\n", trailer.user_byte).unwrap();
+    serial.write(buff.as_bytes()).unwrap();
+    buff.clear();
+
+    // Which key may do what with each data block
+    serial
+        .write("\r\nBLOCK | READ  | WRITE | INC   | DEC\r\n".as_bytes())
+        .unwrap();
+    for group in 0..3 {
+        // In the big sectors of a 4K card one row covers five blocks
+        let blocks = geometry::group_blocks(sector, group);
+        let mut label: String<8> = String::new();
+        if blocks.len() == 1 {
+            write!(label, "{}", blocks.start).unwrap();
+        } else {
+            write!(label, "{}-{}", blocks.start, blocks.end - 1).unwrap();
+        }
+
+        let perms = trailer.access.data_permissions(group);
+        write!(
+            buff,
+            "{:<5} | {:<5} | {:<5} | {:<5} | {}\r\n",
+            label,
+            perms.read.as_str(),
+            perms.write.as_str(),
+            perms.increment.as_str(),
+            perms.decrement.as_str()
+        )
+        .unwrap();
+        serial.write(buff.as_bytes()).unwrap();
+        buff.clear();
+    }
+
+    // And with the trailer itself
+    let perms = trailer.access.trailer.trailer();
+    write!(
+        buff,
+        "\r\nTRAILER | KEY A W: {} | ACCESS R: {} W: {}\r\n",
+        perms.key_a_write.as_str(),
+        perms.access_read.as_str(),
+        perms.access_write.as_str()
+    )
+    .unwrap();
+    serial.write(buff.as_bytes()).unwrap();
+    buff.clear();
+    write!(
+        buff,
+        "TRAILER | KEY B R: {} W: {}\r\n",
+        perms.key_b_read.as_str(),
+        perms.key_b_write.as_str()
+    )
+    .unwrap();
+    serial.write(buff.as_bytes()).unwrap();
+}
+
 fn get_block_type(sector: u8, rel_block: u8) -> &'static str {
     match rel_block {
         0 if sector == 0 => "MFD",
-        3 => "TRAILER",
+        _ if geometry::is_trailer(sector, rel_block) => "TRAILER",
         _ => "DATA",
     }
 }
@@ -0,0 +1,769 @@
+//! A simulated MIFARE Classic card: its memory, the ISO 14443-3 states,
+//! Crypto1 authentication and the access conditions in the sector trailers.
+//!
+//! The card sees frames as they go over the air, encrypted once a sector is
+//! authenticated, and answers the same way.
+
+use crate::crc_a;
+use crate::crypto1::{self, Crypto1};
+
+pub const BLOCK_SIZE: usize = 16;
+
+// PICC commands
+const PICC_REQA: u8 = 0x26;
+const PICC_WUPA: u8 = 0x52;
+const PICC_HLTA: u8 = 0x50;
+const PICC_SEL_CL: [u8; 2] = [0x93, 0x95];
+const PICC_AUTH_KEY_A: u8 = 0x60;
+const PICC_AUTH_KEY_B: u8 = 0x61;
+const PICC_READ: u8 = 0x30;
+const PICC_WRITE: u8 = 0xA0;
+const PICC_DECREMENT: u8 = 0xC0;
+const PICC_INCREMENT: u8 = 0xC1;
+const PICC_RESTORE: u8 = 0xC2;
+const PICC_TRANSFER: u8 = 0xB0;
+const MAGIC_UNLOCK_1: u8 = 0x40;
+const MAGIC_UNLOCK_2: u8 = 0x43;
+
+const ACK: u8 = 0x0A;
+/// NAK for a command the card refuses: bad block, no permission, bad format.
+const NAK_NOT_ALLOWED: u8 = 0x04;
+
+/// NVB of a SELECT, as opposed to an ANTICOLLISION.
+const NVB_SELECT: u8 = 0x70;
+const CASCADE_TAG: u8 = 0x88;
+const SAK_UID_INCOMPLETE: u8 = 0x04;
+
+/// Key A, access bits, Key B. Factory default everywhere.
+const TRANSPORT_TRAILER: [u8; BLOCK_SIZE] = [
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x07, 0x80, 0x69, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+/// Who may do something: bit 0 for Key A, bit 1 for Key B.
+type Keys = u8;
+const NEVER: Keys = 0;
+const KEY_A: Keys = 1;
+const KEY_B: Keys = 2;
+const KEY_AB: Keys = 3;
+
+/// Read, write, increment and decrement/transfer/restore of a data block,
+/// indexed by C1 C2 C3.
+const DATA_ACCESS: [[Keys; 4]; 8] = [
+    [KEY_AB, KEY_AB, KEY_AB, KEY_AB], // 000
+    [KEY_AB, NEVER, NEVER, KEY_AB],   // 001
+    [KEY_AB, NEVER, NEVER, NEVER],    // 010
+    [KEY_B, KEY_B, NEVER, NEVER],     // 011
+    [KEY_AB, KEY_B, NEVER, NEVER],    // 100
+    [KEY_B, NEVER, NEVER, NEVER],     // 101
+    [KEY_AB, KEY_B, KEY_B, KEY_AB],   // 110
+    [NEVER, NEVER, NEVER, NEVER],     // 111
+];
+
+/// Write Key A, read and write the access bits, read and write Key B,
+/// indexed by C1 C2 C3.
+const TRAILER_ACCESS: [[Keys; 5]; 8] = [
+    [KEY_A, KEY_A, NEVER, KEY_A, KEY_A],  // 000
+    [KEY_A, KEY_A, KEY_A, KEY_A, KEY_A],  // 001
+    [NEVER, KEY_A, NEVER, KEY_A, NEVER],  // 010
+    [KEY_B, KEY_AB, KEY_B, NEVER, KEY_B], // 011
+    [KEY_B, KEY_AB, NEVER, NEVER, KEY_B], // 100
+    [NEVER, KEY_AB, KEY_B, NEVER, NEVER], // 101
+    [NEVER, KEY_AB, NEVER, NEVER, NEVER], // 110
+    [NEVER, KEY_AB, NEVER, NEVER, NEVER], // 111
+];
+
+/// Clones that let block 0 be written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Magic {
+    #[default]
+    None,
+    /// Unlocked by the 0x40/0x43 backdoor after HLTA; every block can then be
+    /// read and written without authentication.
+    Gen1a,
+    /// Block 0 takes a normal authenticated write.
+    Gen2,
+}
+
+/// What the card sends back for a frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Answer {
+    pub data: Vec<u8>,
+    /// Valid bits in the last byte, 0 meaning all eight.
+    pub last_bits: u8,
+}
+
+impl Answer {
+    fn bytes(data: &[u8]) -> Self {
+        Self {
+            data: data.to_vec(),
+            last_bits: 0,
+        }
+    }
+
+    fn with_crc(data: &[u8]) -> Self {
+        let mut answer = Self::bytes(data);
+        answer.data.extend_from_slice(&crc_a(data));
+        answer
+    }
+
+    fn ack(code: u8) -> Self {
+        Self {
+            data: vec![code],
+            last_bits: 4,
+        }
+    }
+}
+
+/// A command waiting for its second frame.
+#[derive(Debug, Clone, Copy)]
+enum Pending {
+    None,
+    Write(u8),
+    Value { command: u8, block: u8 },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Session {
+    sector: usize,
+    key_b: bool,
+    cipher: Crypto1,
+    pending: Pending,
+    /// The transfer buffer of the value commands: value and address byte.
+    buffer: Option<(i32, u8)>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Idle,
+    Ready {
+        level: usize,
+    },
+    Active,
+    Halt,
+    /// Sent the nonce, waiting for the reader's answer.
+    Authenticating {
+        sector: usize,
+        key_b: bool,
+        cipher: Crypto1,
+        nt: u32,
+    },
+    Authenticated(Session),
+    /// Gen1a: half way through the unlock sequence.
+    Unlocking,
+    Unlocked {
+        pending: Option<u8>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct Card {
+    uid: Vec<u8>,
+    atqa: [u8; 2],
+    sak: u8,
+    magic: Magic,
+    blocks: Vec<[u8; BLOCK_SIZE]>,
+    state: State,
+    nonce: u32,
+}
+
+impl Card {
+    /// A blank Classic Mini, 20 blocks.
+    pub fn mini(uid: &[u8]) -> Self {
+        Self::blank(uid, 20, 0x09, 0x04)
+    }
+
+    /// A blank Classic 1K.
+    pub fn classic_1k(uid: &[u8]) -> Self {
+        Self::blank(uid, 64, 0x08, 0x04)
+    }
+
+    /// A blank Classic 4K.
+    pub fn classic_4k(uid: &[u8]) -> Self {
+        Self::blank(uid, 256, 0x18, 0x02)
+    }
+
+    /// Panics unless `uid` is 4 or 7 bytes.
+    fn blank(uid: &[u8], blocks: usize, sak: u8, atqa: u8) -> Self {
+        assert!(uid.len() == 4 || uid.len() == 7, "UID must be 4 or 7 bytes");
+        // Bit 6 of the ATQA tells a double size UID
+        let atqa = [atqa | if uid.len() == 7 { 0x40 } else { 0x00 }, 0x00];
+        let mut card = Self {
+            uid: uid.to_vec(),
+            atqa,
+            sak,
+            magic: Magic::None,
+            blocks: vec![[0; BLOCK_SIZE]; blocks],
+            state: State::Idle,
+            nonce: uid
+                .iter()
+                .fold(0x0100_0000, |acc, &b| (acc << 3) ^ u32::from(b)),
+        };
+        for block in 0..blocks {
+            if card.is_trailer(block) {
+                card.blocks[block] = TRANSPORT_TRAILER;
+            }
+        }
+        card.blocks[0] = card.manufacturer_block();
+        card
+    }
+
+    pub fn with_magic(mut self, magic: Magic) -> Self {
+        self.magic = magic;
+        self
+    }
+
+    pub fn uid(&self) -> &[u8] {
+        &self.uid
+    }
+
+    pub fn magic(&self) -> Magic {
+        self.magic
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.blocks.len()
+    }
+
+    /// The block as stored, keys included.
+    pub fn block(&self, block: usize) -> &[u8; BLOCK_SIZE] {
+        &self.blocks[block]
+    }
+
+    /// Changes memory directly, as if the card had been prepared elsewhere.
+    pub fn set_block(&mut self, block: usize, data: &[u8; BLOCK_SIZE]) {
+        self.blocks[block] = *data;
+    }
+
+    /// The card left the field and lost power.
+    pub fn power_off(&mut self) {
+        self.state = State::Idle;
+    }
+
+    /// Handles a frame from the reader. `last_bits` is the number of bits in
+    /// its last byte, 0 meaning all eight. `None` means the card stays
+    /// silent.
+    pub fn receive(&mut self, frame: &[u8], last_bits: u8) -> Option<Answer> {
+        if frame.is_empty() {
+            return None;
+        }
+        // Short frames are never encrypted
+        if last_bits == 7 && frame.len() == 1 {
+            return self.short_frame(frame[0]);
+        }
+
+        match self.state {
+            State::Idle | State::Halt => None,
+            State::Ready { level } => self.select(level, frame),
+            State::Active => self.active(frame),
+            State::Authenticating {
+                sector,
+                key_b,
+                cipher,
+                nt,
+            } => self.finish_auth(sector, key_b, cipher, nt, frame),
+            State::Authenticated(session) => self.authenticated(session, frame),
+            State::Unlocking => {
+                if frame == [MAGIC_UNLOCK_2] {
+                    self.state = State::Unlocked { pending: None };
+                    Some(Answer::ack(ACK))
+                } else {
+                    self.state = State::Halt;
+                    None
+                }
+            }
+            State::Unlocked { pending } => self.unlocked(pending, frame),
+        }
+    }
+
+    fn short_frame(&mut self, command: u8) -> Option<Answer> {
+        match (command, self.state) {
+            (PICC_REQA, State::Idle) | (PICC_WUPA, State::Idle | State::Halt) => {
+                self.state = State::Ready { level: 0 };
+                Some(Answer::bytes(&self.atqa))
+            }
+            (MAGIC_UNLOCK_1, State::Halt) if self.magic == Magic::Gen1a => {
+                self.state = State::Unlocking;
+                Some(Answer::ack(ACK))
+            }
+            (_, State::Halt) => None,
+            _ => {
+                self.state = State::Idle;
+                None
+            }
+        }
+    }
+
+    /// ANTICOLLISION and SELECT at cascade `level`.
+    fn select(&mut self, level: usize, frame: &[u8]) -> Option<Answer> {
+        let part = self.level_part(level);
+        if frame.len() < 2 || PICC_SEL_CL.get(level) != Some(&frame[0]) {
+            self.state = State::Idle;
+            return None;
+        }
+
+        let nvb = frame[1];
+        if nvb == NVB_SELECT {
+            if frame.len() != 9 || !crc_ok(frame) || frame[2..7] != part {
+                self.state = State::Idle;
+                return None;
+            }
+            let last = level + 1 == self.levels();
+            let sak = if last {
+                self.state = State::Active;
+                self.sak
+            } else {
+                self.state = State::Ready { level: level + 1 };
+                SAK_UID_INCOMPLETE
+            };
+            return Some(Answer::with_crc(&[sak]));
+        }
+
+        // The reader sends the bits it knows; we send the rest, starting at
+        // the same bit of the byte it stopped in
+        let known = usize::from(nvb >> 4).saturating_sub(2) * 8 + usize::from(nvb & 0x0F);
+        if known >= 8 * part.len() || frame.len() != 2 + known.div_ceil(8) {
+            return None;
+        }
+        let (bytes, bits) = (known / 8, known % 8);
+        let mask = ((1u16 << bits) - 1) as u8;
+        if frame[2..2 + bytes] != part[..bytes]
+            || (bits != 0 && frame[2 + bytes] & mask != part[bytes] & mask)
+        {
+            // Not us: a card with another UID would answer
+            return None;
+        }
+        let mut rest = part[bytes..].to_vec();
+        rest[0] &= !mask;
+        Some(Answer::bytes(&rest))
+    }
+
+    fn active(&mut self, frame: &[u8]) -> Option<Answer> {
+        if !crc_ok(frame) {
+            self.state = State::Idle;
+            return None;
+        }
+        match frame[..frame.len() - 2] {
+            [PICC_HLTA, 0x00] => {
+                self.state = State::Halt;
+                None
+            }
+            [command @ (PICC_AUTH_KEY_A | PICC_AUTH_KEY_B), block] => {
+                self.start_auth(command, block, false)
+            }
+            _ => {
+                self.state = State::Idle;
+                None
+            }
+        }
+    }
+
+    /// First pass: pick a nonce and send it, encrypted if a sector is
+    /// already open.
+    fn start_auth(&mut self, command: u8, block: u8, nested: bool) -> Option<Answer> {
+        let block = usize::from(block);
+        if block >= self.blocks.len() {
+            self.state = State::Idle;
+            return None;
+        }
+        let sector = self.sector_of(block);
+        let trailer = &self.blocks[self.trailer_of(sector)];
+        let key_b = command == PICC_AUTH_KEY_B;
+        let key: [u8; 6] = if key_b {
+            trailer[10..16].try_into().unwrap()
+        } else {
+            trailer[0..6].try_into().unwrap()
+        };
+
+        self.nonce = crypto1::prng_successor(self.nonce, 32);
+        let nt = self.nonce;
+        let uid = self.crypto_uid();
+        let mut cipher = Crypto1::new(&key);
+        let ks = cipher.word(uid ^ nt, false);
+        let sent = if nested { nt ^ ks } else { nt };
+
+        self.state = State::Authenticating {
+            sector,
+            key_b,
+            cipher,
+            nt,
+        };
+        Some(Answer::bytes(&sent.to_be_bytes()))
+    }
+
+    /// Second pass: check the reader's answer to our nonce and prove we know
+    /// the key too.
+    fn finish_auth(
+        &mut self,
+        sector: usize,
+        key_b: bool,
+        mut cipher: Crypto1,
+        nt: u32,
+        frame: &[u8],
+    ) -> Option<Answer> {
+        self.state = State::Idle;
+        let [nr_enc @ .., a0, a1, a2, a3] = frame else {
+            return None;
+        };
+        let Ok(nr_enc) = <[u8; 4]>::try_from(nr_enc) else {
+            return None;
+        };
+        cipher.word(u32::from_be_bytes(nr_enc), true);
+        let ar = u32::from_be_bytes([*a0, *a1, *a2, *a3]) ^ cipher.word(0, false);
+        if ar != crypto1::prng_successor(nt, 64) {
+            return None;
+        }
+
+        let at = crypto1::prng_successor(nt, 96) ^ cipher.word(0, false);
+        self.state = State::Authenticated(Session {
+            sector,
+            key_b,
+            cipher,
+            pending: Pending::None,
+            buffer: None,
+        });
+        Some(Answer::bytes(&at.to_be_bytes()))
+    }
+
+    fn authenticated(&mut self, mut session: Session, frame: &[u8]) -> Option<Answer> {
+        let mut frame = frame.to_vec();
+        session.cipher.crypt(&mut frame, 0);
+        // Plain frames after the reader dropped Crypto1 decrypt to garbage
+        if !crc_ok(&frame) {
+            self.state = State::Idle;
+            return None;
+        }
+        let frame = &frame[..frame.len() - 2];
+
+        let pending = core::mem::replace(&mut session.pending, Pending::None);
+        let code = match (pending, frame) {
+            (Pending::Write(block), data) => match <&[u8; BLOCK_SIZE]>::try_from(data) {
+                Ok(data) => {
+                    self.write(&session, block, data);
+                    ACK
+                }
+                Err(_) => NAK_NOT_ALLOWED,
+            },
+            (Pending::Value { command, block }, &[a, b, c, d]) => {
+                let operand = i32::from_le_bytes([a, b, c, d]);
+                if let Some((value, addr)) = self.value(block) {
+                    let value = match command {
+                        PICC_INCREMENT => value.wrapping_add(operand),
+                        PICC_DECREMENT => value.wrapping_sub(operand),
+                        _ => value,
+                    };
+                    session.buffer = Some((value, addr));
+                }
+                // Success is silence
+                self.state = State::Authenticated(session);
+                return None;
+            }
+            (Pending::Value { .. }, _) => NAK_NOT_ALLOWED,
+            (Pending::None, [PICC_HLTA, 0x00]) => {
+                self.state = State::Halt;
+                return None;
+            }
+            (Pending::None, &[command @ (PICC_AUTH_KEY_A | PICC_AUTH_KEY_B), block]) => {
+                return self.start_auth(command, block, true);
+            }
+            (Pending::None, &[PICC_READ, block]) => match self.read(&session, block) {
+                Some(data) => {
+                    let mut answer = Answer::with_crc(&data);
+                    session.cipher.crypt(&mut answer.data, 0);
+                    self.state = State::Authenticated(session);
+                    return Some(answer);
+                }
+                None => NAK_NOT_ALLOWED,
+            },
+            (Pending::None, &[PICC_WRITE, block]) => {
+                if self.may_write(&session, block) {
+                    session.pending = Pending::Write(block);
+                    ACK
+                } else {
+                    NAK_NOT_ALLOWED
+                }
+            }
+            (
+                Pending::None,
+                &[command @ (PICC_INCREMENT | PICC_DECREMENT | PICC_RESTORE), block],
+            ) => {
+                let right = if command == PICC_INCREMENT { 2 } else { 3 };
+                if self.data_access(&session, block, right) && self.value(block).is_some() {
+                    session.pending = Pending::Value { command, block };
+                    ACK
+                } else {
+                    NAK_NOT_ALLOWED
+                }
+            }
+            (Pending::None, &[PICC_TRANSFER, block]) => match session.buffer {
+                Some((value, addr)) if self.data_access(&session, block, 3) => {
+                    self.blocks[usize::from(block)] = value_block(value, addr);
+                    ACK
+                }
+                _ => NAK_NOT_ALLOWED,
+            },
+            _ => NAK_NOT_ALLOWED,
+        };
+
+        let mut answer = Answer::ack(code);
+        session.cipher.crypt(&mut answer.data, answer.last_bits);
+        // A NAK drops the card out of the session
+        self.state = if code == ACK {
+            State::Authenticated(session)
+        } else {
+            State::Idle
+        };
+        Some(answer)
+    }
+
+    /// The Gen1a backdoor: plain READ and WRITE of any block.
+    fn unlocked(&mut self, pending: Option<u8>, frame: &[u8]) -> Option<Answer> {
+        self.state = State::Unlocked { pending: None };
+        if !crc_ok(frame) {
+            return None;
+        }
+        let frame = &frame[..frame.len() - 2];
+        match (pending, frame) {
+            (Some(block), data) => {
+                let data: &[u8; BLOCK_SIZE] = data.try_into().ok()?;
+                self.store(usize::from(block), data);
+                Some(Answer::ack(ACK))
+            }
+            (None, &[PICC_HLTA, 0x00]) => {
+                self.state = State::Halt;
+                None
+            }
+            (None, &[PICC_READ, block]) if usize::from(block) < self.blocks.len() => {
+                Some(Answer::with_crc(&self.blocks[usize::from(block)]))
+            }
+            (None, &[PICC_WRITE, block]) if usize::from(block) < self.blocks.len() => {
+                self.state = State::Unlocked {
+                    pending: Some(block),
+                };
+                Some(Answer::ack(ACK))
+            }
+            _ => Some(Answer::ack(NAK_NOT_ALLOWED)),
+        }
+    }
+
+    /// A magic card answers with whatever UID block 0 now holds.
+    fn store(&mut self, index: usize, data: &[u8; BLOCK_SIZE]) {
+        self.blocks[index] = *data;
+        if index == 0 {
+            let len = self.uid.len();
+            self.uid.copy_from_slice(&data[..len]);
+        }
+    }
+
+    fn read(&self, session: &Session, block: u8) -> Option<[u8; BLOCK_SIZE]> {
+        let index = usize::from(block);
+        if !self.in_session(session, block) {
+            return None;
+        }
+        if !self.is_trailer(index) {
+            return self
+                .data_access(session, block, 0)
+                .then_some(self.blocks[index]);
+        }
+
+        // Key A never reads back; the rest only as the trailer allows
+        let rights = self.trailer_rights(session.sector)?;
+        let me = self.key_mask(session)?;
+        let stored = &self.blocks[index];
+        let mut data = [0u8; BLOCK_SIZE];
+        if rights[1] & me != 0 {
+            data[6..10].copy_from_slice(&stored[6..10]);
+        }
+        if rights[3] & me != 0 {
+            data[10..16].copy_from_slice(&stored[10..16]);
+        }
+        Some(data)
+    }
+
+    fn may_write(&self, session: &Session, block: u8) -> bool {
+        let index = usize::from(block);
+        if !self.in_session(session, block) || (index == 0 && self.magic != Magic::Gen2) {
+            return false;
+        }
+        if !self.is_trailer(index) {
+            return self.data_access(session, block, 1);
+        }
+        match (self.trailer_rights(session.sector), self.key_mask(session)) {
+            (Some(rights), Some(me)) => [rights[0], rights[2], rights[4]]
+                .iter()
+                .any(|&r| r & me != 0),
+            _ => false,
+        }
+    }
+
+    /// Stores a WRITE that [`Card::may_write`] let through. A trailer only
+    /// takes the parts the key may change.
+    fn write(&mut self, session: &Session, block: u8, data: &[u8; BLOCK_SIZE]) {
+        let index = usize::from(block);
+        if !self.is_trailer(index) {
+            self.store(index, data);
+            return;
+        }
+        let (Some(rights), Some(me)) =
+            (self.trailer_rights(session.sector), self.key_mask(session))
+        else {
+            return;
+        };
+        let stored = &mut self.blocks[index];
+        for (right, range) in [(rights[0], 0..6), (rights[2], 6..10), (rights[4], 10..16)] {
+            if right & me != 0 {
+                stored[range.clone()].copy_from_slice(&data[range]);
+            }
+        }
+    }
+
+    /// Whether the session's key has `right` (read, write, increment,
+    /// decrement) on data block `block`.
+    fn data_access(&self, session: &Session, block: u8, right: usize) -> bool {
+        let index = usize::from(block);
+        if !self.in_session(session, block) || self.is_trailer(index) {
+            return false;
+        }
+        let Some(me) = self.key_mask(session) else {
+            return false;
+        };
+        let trailer = &self.blocks[self.trailer_of(session.sector)];
+        let first = self.first_block(session.sector);
+        let group = if session.sector < 32 {
+            index - first
+        } else {
+            (index - first) / 5
+        };
+        match access_bits(trailer, group) {
+            Some(bits) => DATA_ACCESS[bits][right] & me != 0,
+            None => false,
+        }
+    }
+
+    fn trailer_rights(&self, sector: usize) -> Option<[Keys; 5]> {
+        let trailer = &self.blocks[self.trailer_of(sector)];
+        access_bits(trailer, 3).map(|bits| TRAILER_ACCESS[bits])
+    }
+
+    /// The key the session was opened with. A Key B the trailer lets Key A
+    /// read is just data, and grants nothing.
+    fn key_mask(&self, session: &Session) -> Option<Keys> {
+        let rights = self.trailer_rights(session.sector)?;
+        Some(match session.key_b {
+            false => KEY_A,
+            true if rights[3] & KEY_A != 0 => NEVER,
+            true => KEY_B,
+        })
+    }
+
+    fn value(&self, block: u8) -> Option<(i32, u8)> {
+        let data = self.blocks.get(usize::from(block))?;
+        let word = |i: usize| i32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        let (value, addr) = (word(0), data[12]);
+        let valid = word(4) == !value
+            && word(8) == value
+            && data[13] == !addr
+            && data[14] == addr
+            && data[15] == !addr;
+        valid.then_some((value, addr))
+    }
+
+    fn in_session(&self, session: &Session, block: u8) -> bool {
+        let index = usize::from(block);
+        index < self.blocks.len() && self.sector_of(index) == session.sector
+    }
+
+    fn levels(&self) -> usize {
+        if self.uid.len() == 7 {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// UID bytes of a cascade level, with the cascade tag if the UID goes on,
+    /// and the BCC.
+    fn level_part(&self, level: usize) -> [u8; 5] {
+        let mut part = [0u8; 5];
+        if level + 1 < self.levels() {
+            part[0] = CASCADE_TAG;
+            part[1..4].copy_from_slice(&self.uid[..3]);
+        } else {
+            let offset = self.uid.len() - 4;
+            part[..4].copy_from_slice(&self.uid[offset..]);
+        }
+        part[4] = part[..4].iter().fold(0, |acc, b| acc ^ b);
+        part
+    }
+
+    /// The four UID bytes Crypto1 is seeded with: the last four.
+    fn crypto_uid(&self) -> u32 {
+        let offset = self.uid.len() - 4;
+        u32::from_be_bytes(self.uid[offset..].try_into().unwrap())
+    }
+
+    fn manufacturer_block(&self) -> [u8; BLOCK_SIZE] {
+        let mut block = [0u8; BLOCK_SIZE];
+        let len = self.uid.len();
+        block[..len].copy_from_slice(&self.uid);
+        if len == 4 {
+            block[4] = self.uid.iter().fold(0, |acc, b| acc ^ b);
+            block[5] = self.sak;
+            block[6..8].copy_from_slice(&self.atqa);
+        } else {
+            block[7] = self.sak;
+            block[8..10].copy_from_slice(&self.atqa);
+        }
+        block
+    }
+
+    fn first_block(&self, sector: usize) -> usize {
+        if sector < 32 {
+            sector * 4
+        } else {
+            128 + (sector - 32) * 16
+        }
+    }
+
+    fn sector_of(&self, block: usize) -> usize {
+        if block < 128 {
+            block / 4
+        } else {
+            32 + (block - 128) / 16
+        }
+    }
+
+    fn trailer_of(&self, sector: usize) -> usize {
+        let blocks = if sector < 32 { 4 } else { 16 };
+        self.first_block(sector) + blocks - 1
+    }
+
+    fn is_trailer(&self, block: usize) -> bool {
+        self.trailer_of(self.sector_of(block)) == block
+    }
+}
+
+/// C1 C2 C3 of access group `group` as a table index, or `None` if the
+/// inverted copy doesn't match; such a sector is lost for good.
+fn access_bits(trailer: &[u8; BLOCK_SIZE], group: usize) -> Option<usize> {
+    let (b6, b7, b8) = (trailer[6], trailer[7], trailer[8]);
+    let (c1, c2, c3) = (b7 >> 4, b8 & 0x0F, b8 >> 4);
+    if b6 & 0x0F != !c1 & 0x0F || b6 >> 4 != !c2 & 0x0F || b7 & 0x0F != !c3 & 0x0F {
+        return None;
+    }
+    let bit = |c: u8| usize::from((c >> group) & 1);
+    Some((bit(c1) << 2) | (bit(c2) << 1) | bit(c3))
+}
+
+fn value_block(value: i32, addr: u8) -> [u8; BLOCK_SIZE] {
+    let mut data = [0u8; BLOCK_SIZE];
+    data[0..4].copy_from_slice(&value.to_le_bytes());
+    data[4..8].copy_from_slice(&(!value).to_le_bytes());
+    data[8..12].copy_from_slice(&value.to_le_bytes());
+    data[12..16].copy_from_slice(&[addr, !addr, addr, !addr]);
+    data
+}
+
+fn crc_ok(frame: &[u8]) -> bool {
+    let len = frame.len();
+    len > 2 && crc_a(&frame[..len - 2]) == frame[len - 2..]
+}
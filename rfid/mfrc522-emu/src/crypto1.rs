@@ -0,0 +1,100 @@
+//! The Crypto1 stream cipher of MIFARE Classic.
+//!
+//! Follows the reverse engineered description in crapto1: a 48-bit LFSR kept
+//! as its odd and even bits, a non-linear filter producing one keystream bit
+//! per clock, and the 16-bit PRNG the card draws its nonces from. Parity bits
+//! aren't modelled, so only the bytes are encrypted.
+
+const LF_POLY_ODD: u32 = 0x29CE5C;
+const LF_POLY_EVEN: u32 = 0x870804;
+
+fn bit(x: u32, n: u32) -> u32 {
+    (x >> n) & 1
+}
+
+/// Bit `n` of a word sent most significant byte first, least significant bit
+/// of each byte first.
+fn bebit(x: u32, n: u32) -> u32 {
+    bit(x, n ^ 24)
+}
+
+fn filter(x: u32) -> u32 {
+    let mut f = (0xf22c0 >> (x & 0xf)) & 16;
+    f |= (0x6c9c0 >> ((x >> 4) & 0xf)) & 8;
+    f |= (0x3c8b0 >> ((x >> 8) & 0xf)) & 4;
+    f |= (0x1e458 >> ((x >> 12) & 0xf)) & 2;
+    f |= (0x0d938 >> ((x >> 16) & 0xf)) & 1;
+    bit(0xEC57E80A, f)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Crypto1 {
+    odd: u32,
+    even: u32,
+}
+
+impl Crypto1 {
+    /// Loads a sector key into the LFSR.
+    pub fn new(key: &[u8; 6]) -> Self {
+        let key = key.iter().fold(0u64, |acc, &b| (acc << 8) | u64::from(b));
+        let (mut odd, mut even) = (0u32, 0u32);
+        for i in (1..48).rev().step_by(2) {
+            odd = (odd << 1) | ((key >> ((i - 1) ^ 7)) & 1) as u32;
+            even = (even << 1) | ((key >> (i ^ 7)) & 1) as u32;
+        }
+        Self { odd, even }
+    }
+
+    /// Clocks the LFSR once, shifting in `input`, and returns the keystream
+    /// bit. With `encrypted` set `input` is ciphertext, and the keystream bit
+    /// is taken off it first.
+    pub fn bit(&mut self, input: u32, encrypted: bool) -> u32 {
+        let out = filter(self.odd);
+        let mut feedin = out & u32::from(encrypted);
+        feedin ^= u32::from(input != 0);
+        feedin ^= LF_POLY_ODD & self.odd;
+        feedin ^= LF_POLY_EVEN & self.even;
+        self.even = (self.even << 1) | (feedin.count_ones() & 1);
+        core::mem::swap(&mut self.odd, &mut self.even);
+        out
+    }
+
+    /// Eight clocks, least significant bit first.
+    pub fn byte(&mut self, input: u8, encrypted: bool) -> u8 {
+        (0..8).fold(0, |acc, i| {
+            acc | (self.bit(bit(u32::from(input), i), encrypted) << i) as u8
+        })
+    }
+
+    /// 32 clocks over a word in transmission order.
+    pub fn word(&mut self, input: u32, encrypted: bool) -> u32 {
+        (0..32).fold(0, |acc, i| {
+            acc | (self.bit(bebit(input, i), encrypted) << (i ^ 24))
+        })
+    }
+
+    /// Encrypts or decrypts a frame in place. `last_bits` is the number of
+    /// bits in the last byte, 0 meaning all eight.
+    pub fn crypt(&mut self, data: &mut [u8], last_bits: u8) {
+        let len = data.len();
+        for (i, byte) in data.iter_mut().enumerate() {
+            let bits = if i + 1 == len && last_bits != 0 {
+                last_bits
+            } else {
+                8
+            };
+            for n in 0..bits {
+                *byte ^= (self.bit(0, false) << n) as u8;
+            }
+        }
+    }
+}
+
+/// Clocks the card's nonce generator `n` times on from `x`.
+pub fn prng_successor(x: u32, n: u32) -> u32 {
+    let mut x = x.swap_bytes();
+    for _ in 0..n {
+        x = (x >> 1) | ((x >> 16 ^ x >> 18 ^ x >> 19 ^ x >> 21) << 31);
+    }
+    x.swap_bytes()
+}
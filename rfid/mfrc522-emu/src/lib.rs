@@ -0,0 +1,656 @@
+//! An MFRC522 and a MIFARE Classic card, simulated on the host.
+//!
+//! [`Emulator`] is an embedded-hal [`SpiDevice`] that answers the MFRC522's
+//! SPI protocol at register level: the FIFO, the command register, the
+//! interrupt and error flags, CalcCRC and the digital self-test, Transceive
+//! with bit framing, and MFAuthent with Crypto1. Anything written against
+//! the chip's SPI port, the `mfrc522` driver and the `mifare` crate
+//! included, runs against it unchanged:
+//!
+//! ```ignore
+//! let mut emu = Emulator::with_card(Card::classic_1k(&[0x13, 0x37, 0x73, 0x31]));
+//! emu.init();
+//! let mut pcd = Pcd::new(emu);
+//! ```
+//!
+//! Faults are injected by frame: [`Emulator::frames`] counts the frames sent
+//! to the card so far, and [`Emulator::inject_at`] makes a later one time
+//! out, arrive damaged, or lose the card on the way.
+//!
+//! Not modelled: the chip's own CRC generation (`TxCRCEn`/`RxCRCEn`, which the
+//! driver leaves off), parity bits, timing, and more than one card in the
+//! field.
+
+pub mod card;
+pub mod crypto1;
+
+use embedded_hal::spi::{self, ErrorKind, ErrorType, Operation, SpiDevice};
+
+use crate::card::{Answer, Card};
+use crate::crypto1::Crypto1;
+
+// Register addresses
+const COMMAND: usize = 0x01;
+const COM_IEN: usize = 0x02;
+const DIV_IEN: usize = 0x03;
+const COM_IRQ: usize = 0x04;
+const DIV_IRQ: usize = 0x05;
+const ERROR: usize = 0x06;
+const STATUS1: usize = 0x07;
+const STATUS2: usize = 0x08;
+const FIFO_DATA: usize = 0x09;
+const FIFO_LEVEL: usize = 0x0A;
+const CONTROL: usize = 0x0C;
+const BIT_FRAMING: usize = 0x0D;
+const COLL: usize = 0x0E;
+const TX_CONTROL: usize = 0x14;
+const CRC_RESULT_HIGH: usize = 0x21;
+const CRC_RESULT_LOW: usize = 0x22;
+const T_MODE: usize = 0x2A;
+const AUTO_TEST: usize = 0x36;
+const VERSION: usize = 0x37;
+
+/// Register values after a reset, where they aren't zero.
+const RESET_VALUES: [(usize, u8); 19] = [
+    (COMMAND, 0x20),
+    (COM_IEN, 0x80),
+    (COM_IRQ, 0x14),
+    (STATUS1, 0x21),
+    (0x0B, 0x08), // WaterLevelReg
+    (CONTROL, 0x10),
+    (COLL, 0xA0),
+    (0x11, 0x3F), // ModeReg
+    (TX_CONTROL, 0x80),
+    (0x16, 0x10), // TxSelReg
+    (0x17, 0x84), // RxSelReg
+    (0x18, 0x84), // RxThresholdReg
+    (0x19, 0x4D), // DemodReg
+    (0x1C, 0x62), // MfTxReg
+    (0x1F, 0xEB), // SerialSpeedReg
+    (CRC_RESULT_HIGH, 0xFF),
+    (CRC_RESULT_LOW, 0xFF),
+    (0x24, 0x26), // ModWidthReg
+    (0x26, 0x48), // RFCfgReg
+];
+
+/// What VersionReg reads on an MFRC522 version 2.0.
+pub const CHIP_VERSION: u8 = 0x92;
+
+const FIFO_SIZE: usize = 64;
+/// Bytes the Mem command moves from the FIFO into the internal buffer.
+const INTERNAL_BUFFER: usize = 25;
+
+/// AutoTestReg value that turns CalcCRC into the digital self-test.
+const AUTO_TEST_SELF_TEST: u8 = 0x09;
+/// What the digital self-test leaves in the FIFO on a version 2.0 chip.
+const SELF_TEST: [u8; FIFO_SIZE] = [
+    0x00, 0xEB, 0x66, 0xBA, 0x57, 0xBF, 0x23, 0x95, 0xD0, 0xE3, 0x0D, 0x3D, 0x27, 0x89, 0x5C, 0xDE,
+    0x9D, 0x3B, 0xA7, 0x00, 0x21, 0x5B, 0x89, 0x82, 0x51, 0x3A, 0xEB, 0x02, 0x0C, 0xA5, 0x00, 0x49,
+    0x7C, 0x84, 0x4D, 0xB3, 0xCC, 0xD2, 0x1B, 0x81, 0x5D, 0x48, 0x76, 0xD5, 0x71, 0x61, 0x21, 0xA9,
+    0x86, 0x96, 0x83, 0x38, 0xCF, 0x9D, 0x5B, 0x6D, 0xDC, 0x15, 0xBA, 0x3E, 0x7D, 0x95, 0x3B, 0x2F,
+];
+
+// PCD commands (CommandReg)
+const CMD_MASK: u8 = 0x0F;
+const CMD_IDLE: u8 = 0x00;
+const CMD_MEM: u8 = 0x01;
+const CMD_CALC_CRC: u8 = 0x03;
+const CMD_TRANSCEIVE: u8 = 0x0C;
+const CMD_MF_AUTHENT: u8 = 0x0E;
+const CMD_SOFT_RESET: u8 = 0x0F;
+const COMMAND_POWER_DOWN: u8 = 0x10;
+
+// ComIrqReg and DivIrqReg bits
+const IRQ_SET1: u8 = 0x80;
+const IRQ_TX: u8 = 0x40;
+const IRQ_RX: u8 = 0x20;
+const IRQ_IDLE: u8 = 0x10;
+const IRQ_ERR: u8 = 0x02;
+const IRQ_TIMER: u8 = 0x01;
+const DIV_IRQ_CRC: u8 = 0x04;
+
+// ErrorReg bits
+const ERR_PROTOCOL: u8 = 0x01;
+const ERR_PARITY: u8 = 0x02;
+const ERR_COLLISION: u8 = 0x08;
+const ERR_BUFFER_OVERFLOW: u8 = 0x10;
+
+const STATUS1_CRC_READY: u8 = 0x20;
+const STATUS2_CRYPTO1_ON: u8 = 0x08;
+const FIFO_FLUSH: u8 = 0x80;
+const BIT_FRAMING_START_SEND: u8 = 0x80;
+const COLL_VALUES_AFTER_COLL: u8 = 0x80;
+const TX_RF_EN: u8 = 0x03;
+const T_MODE_AUTO: u8 = 0x80;
+
+/// The ISO 14443-3 type A CRC, in the byte order it is sent.
+pub fn crc_a(data: &[u8]) -> [u8; 2] {
+    let mut crc: u16 = 0x6363;
+    for &b in data {
+        let mut ch = b ^ crc as u8;
+        ch ^= ch << 4;
+        let ch = ch as u16;
+        crc = (crc >> 8) ^ (ch << 8) ^ (ch << 3) ^ (ch >> 4);
+    }
+    crc.to_le_bytes()
+}
+
+/// Something to go wrong with a frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The frame never reaches the card, so nothing comes back.
+    Timeout,
+    /// The answer arrives with a bit flipped in its last byte.
+    Crc,
+    /// The answer arrives with a parity error.
+    Parity,
+    /// The answer collides with another card's.
+    Collision,
+    /// The card leaves the field before the frame reaches it.
+    Removal,
+    /// The SPI transfer that starts the frame fails.
+    Bus,
+}
+
+/// The error of a failed SPI transfer, only ever caused by [`Fault::Bus`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusError;
+
+impl spi::Error for BusError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+/// Where the next byte of an SPI transaction goes. The first byte is an
+/// address; after it each byte of a read addresses the next register to
+/// read, and each byte of a write is data for the addressed register.
+#[derive(Debug, Clone, Copy)]
+enum Access {
+    Address,
+    Read(usize),
+    Write(usize),
+}
+
+pub struct Emulator {
+    regs: [u8; 64],
+    fifo: Vec<u8>,
+    card: Option<Card>,
+    present: bool,
+    /// Our end of Crypto1, while Status2Reg has MFCrypto1On set.
+    cipher: Option<Crypto1>,
+    nonce: u32,
+    frames: u32,
+    faults: Vec<(u32, Fault)>,
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Emulator {
+    /// A chip just out of reset, with an empty field.
+    pub fn new() -> Self {
+        let mut emu = Self {
+            regs: [0; 64],
+            fifo: Vec::with_capacity(FIFO_SIZE),
+            card: None,
+            present: false,
+            cipher: None,
+            nonce: 0x2A2A_2A2A,
+            frames: 0,
+            faults: Vec::new(),
+        };
+        emu.reset();
+        emu
+    }
+
+    pub fn with_card(card: Card) -> Self {
+        let mut emu = Self::new();
+        emu.insert(card);
+        emu
+    }
+
+    /// Sets up what `Mfrc522::init` would: the timer that ends a command
+    /// nobody answers, and the antenna.
+    pub fn init(&mut self) {
+        self.regs[T_MODE] |= T_MODE_AUTO;
+        self.regs[TX_CONTROL] |= TX_RF_EN;
+    }
+
+    /// Puts a card in the field, replacing any other.
+    pub fn insert(&mut self, mut card: Card) {
+        card.power_off();
+        self.card = Some(card);
+        self.present = true;
+    }
+
+    /// Takes the card out of the field. It keeps its memory and can be
+    /// looked at with [`Emulator::card`] or brought back with
+    /// [`Emulator::reinsert`].
+    pub fn remove(&mut self) {
+        self.present = false;
+        if let Some(card) = &mut self.card {
+            card.power_off();
+        }
+    }
+
+    pub fn reinsert(&mut self) {
+        self.present = self.card.is_some();
+    }
+
+    pub fn is_present(&self) -> bool {
+        self.present
+    }
+
+    /// Whether the IRQ pin is asserted: an interrupt flag is set that
+    /// ComIEnReg or DivIEnReg enables. Which level that drives the pin to is
+    /// left to IRqInv.
+    pub fn irq(&self) -> bool {
+        self.regs[COM_IRQ] & self.regs[COM_IEN] & !IRQ_SET1 != 0
+            || self.regs[DIV_IRQ] & self.regs[DIV_IEN] & !IRQ_SET1 != 0
+    }
+
+    /// Whether the chip is in soft power-down.
+    pub fn is_powered_down(&self) -> bool {
+        self.regs[COMMAND] & COMMAND_POWER_DOWN != 0
+    }
+
+    /// The last card inserted, whether or not it is still in the field.
+    pub fn card(&self) -> Option<&Card> {
+        self.card.as_ref()
+    }
+
+    pub fn card_mut(&mut self) -> Option<&mut Card> {
+        self.card.as_mut()
+    }
+
+    /// Frames sent to the card so far. An authentication counts as one.
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// Makes the next frame fail with `fault`.
+    pub fn inject(&mut self, fault: Fault) {
+        self.inject_at(self.frames, fault);
+    }
+
+    /// Makes frame number `frame`, counted like [`Emulator::frames`], fail
+    /// with `fault`.
+    pub fn inject_at(&mut self, frame: u32, fault: Fault) {
+        self.faults.push((frame, fault));
+    }
+
+    /// Drops faults that haven't happened yet.
+    pub fn clear_faults(&mut self) {
+        self.faults.clear();
+    }
+
+    fn reset(&mut self) {
+        self.regs = [0; 64];
+        for (reg, value) in RESET_VALUES {
+            self.regs[reg] = value;
+        }
+        self.regs[VERSION] = CHIP_VERSION;
+        self.fifo.clear();
+        self.cipher = None;
+        self.field_changed();
+    }
+
+    /// Whether the antenna is driving a field.
+    fn field_on(&self) -> bool {
+        self.regs[TX_CONTROL] & TX_RF_EN != 0 && self.regs[COMMAND] & COMMAND_POWER_DOWN == 0
+    }
+
+    /// A card loses power, and its state, whenever the field goes off.
+    fn field_changed(&mut self) {
+        if !self.field_on() {
+            if let Some(card) = &mut self.card {
+                card.power_off();
+            }
+        }
+    }
+
+    /// Clocks one byte through the SPI port: `mosi` goes in, and the byte
+    /// the chip shifts out comes back with where the next byte goes.
+    fn spi_byte(&mut self, access: Access, mosi: u8) -> Result<(Access, u8), BusError> {
+        if let Access::Write(reg) = access {
+            self.write_reg(reg, mosi)?;
+            return Ok((access, 0));
+        }
+        let miso = match access {
+            Access::Read(reg) => self.read_reg(reg),
+            _ => 0,
+        };
+        let reg = usize::from((mosi >> 1) & 0x3F);
+        let next = if mosi & 0x80 != 0 {
+            Access::Read(reg)
+        } else {
+            Access::Write(reg)
+        };
+        Ok((next, miso))
+    }
+
+    fn read_reg(&mut self, reg: usize) -> u8 {
+        match reg {
+            FIFO_DATA => {
+                if self.fifo.is_empty() {
+                    0
+                } else {
+                    self.fifo.remove(0)
+                }
+            }
+            FIFO_LEVEL => self.fifo.len() as u8,
+            _ => self.regs[reg],
+        }
+    }
+
+    fn write_reg(&mut self, reg: usize, val: u8) -> Result<(), BusError> {
+        match reg {
+            COMMAND => {
+                // Only the power down bits stay; the command runs right away
+                self.regs[COMMAND] = (self.regs[COMMAND] & !(CMD_MASK | 0x30)) | (val & 0x30);
+                self.field_changed();
+                self.command(val & CMD_MASK)?;
+            }
+            COM_IRQ | DIV_IRQ => {
+                let bits = val & !IRQ_SET1;
+                if val & IRQ_SET1 != 0 {
+                    self.regs[reg] |= bits;
+                } else {
+                    self.regs[reg] &= !bits;
+                }
+            }
+            // Read only
+            ERROR | STATUS1 => {}
+            STATUS2 => {
+                // MFCrypto1On can only be cleared
+                let crypto = self.regs[STATUS2] & val & STATUS2_CRYPTO1_ON;
+                self.regs[STATUS2] = (val & 0xC0) | crypto | (self.regs[STATUS2] & 0x07);
+                if crypto == 0 {
+                    self.cipher = None;
+                }
+            }
+            FIFO_DATA => {
+                if self.fifo.len() < FIFO_SIZE {
+                    self.fifo.push(val);
+                } else {
+                    self.regs[ERROR] |= ERR_BUFFER_OVERFLOW;
+                }
+            }
+            FIFO_LEVEL => {
+                if val & FIFO_FLUSH != 0 {
+                    self.fifo.clear();
+                    self.regs[ERROR] &= !ERR_BUFFER_OVERFLOW;
+                }
+            }
+            CONTROL => self.regs[CONTROL] = (val & 0xC0) | (self.regs[CONTROL] & 0x07),
+            BIT_FRAMING => {
+                self.regs[BIT_FRAMING] = val & !BIT_FRAMING_START_SEND;
+                if val & BIT_FRAMING_START_SEND != 0
+                    && self.regs[COMMAND] & CMD_MASK == CMD_TRANSCEIVE
+                {
+                    self.transceive()?;
+                }
+            }
+            COLL => {
+                self.regs[COLL] =
+                    (val & COLL_VALUES_AFTER_COLL) | (self.regs[COLL] & !COLL_VALUES_AFTER_COLL);
+            }
+            TX_CONTROL => {
+                self.regs[TX_CONTROL] = val;
+                self.field_changed();
+            }
+            VERSION | CRC_RESULT_HIGH | CRC_RESULT_LOW => {}
+            _ => self.regs[reg] = val,
+        }
+        Ok(())
+    }
+
+    fn command(&mut self, command: u8) -> Result<(), BusError> {
+        match command {
+            CMD_SOFT_RESET => self.reset(),
+            CMD_MEM => {
+                let n = self.fifo.len().min(INTERNAL_BUFFER);
+                self.fifo.drain(..n);
+            }
+            CMD_CALC_CRC if self.regs[AUTO_TEST] & 0x0F == AUTO_TEST_SELF_TEST => {
+                self.fifo = SELF_TEST.to_vec();
+            }
+            CMD_CALC_CRC => {
+                let [low, high] = crc_a(&self.fifo);
+                self.fifo.clear();
+                self.regs[CRC_RESULT_LOW] = low;
+                self.regs[CRC_RESULT_HIGH] = high;
+                self.regs[DIV_IRQ] |= DIV_IRQ_CRC;
+                self.regs[STATUS1] |= STATUS1_CRC_READY;
+                self.regs[COMMAND] |= CMD_CALC_CRC;
+            }
+            // Waits for StartSend
+            CMD_TRANSCEIVE => self.regs[COMMAND] |= CMD_TRANSCEIVE,
+            CMD_MF_AUTHENT => self.authenticate()?,
+            CMD_IDLE => {}
+            // Commands nothing here uses finish at once
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Takes the fault planned for the frame about to go out, if any, and
+    /// counts the frame.
+    fn next_fault(&mut self) -> Option<Fault> {
+        let frame = self.frames;
+        self.frames += 1;
+        let index = self.faults.iter().position(|&(at, _)| at == frame)?;
+        Some(self.faults.remove(index).1)
+    }
+
+    /// Hands a frame to the card. The fault decides whether it gets there.
+    fn send(&mut self, frame: &[u8], last_bits: u8, fault: Option<Fault>) -> Option<Answer> {
+        if !self.field_on() || !self.present {
+            return None;
+        }
+        match fault {
+            Some(Fault::Timeout) => return None,
+            Some(Fault::Removal) => {
+                self.remove();
+                return None;
+            }
+            _ => {}
+        }
+        self.card.as_mut()?.receive(frame, last_bits)
+    }
+
+    /// Applies a fault that damages the answer on its way back.
+    fn damage(&mut self, answer: &mut Answer, fault: Option<Fault>) {
+        match fault {
+            Some(Fault::Crc) => {
+                if let Some(last) = answer.data.last_mut() {
+                    *last ^= 0x01;
+                }
+            }
+            Some(Fault::Parity) => self.regs[ERROR] |= ERR_PARITY,
+            Some(Fault::Collision) => {
+                self.regs[ERROR] |= ERR_COLLISION;
+                // At the first bit, position valid
+                self.regs[COLL] = (self.regs[COLL] & COLL_VALUES_AFTER_COLL) | 0x01;
+            }
+            _ => {}
+        }
+    }
+
+    fn start_frame(&mut self) -> Result<Option<Fault>, BusError> {
+        let fault = self.next_fault();
+        if fault == Some(Fault::Bus) {
+            return Err(BusError);
+        }
+        self.regs[ERROR] &= ERR_BUFFER_OVERFLOW;
+        self.regs[COLL] |= 0x20;
+        Ok(fault)
+    }
+
+    /// Sends the FIFO, encrypted if Crypto1 is on, and puts the answer back in
+    /// it.
+    fn transceive(&mut self) -> Result<(), BusError> {
+        let fault = self.start_frame()?;
+        let tx_last_bits = self.regs[BIT_FRAMING] & 0x07;
+        let mut frame: Vec<u8> = self.fifo.drain(..).collect();
+        if let Some(cipher) = &mut self.cipher {
+            cipher.crypt(&mut frame, tx_last_bits);
+        }
+        self.regs[COM_IRQ] |= IRQ_TX;
+
+        let Some(mut answer) = self.send(&frame, tx_last_bits, fault) else {
+            self.no_answer();
+            return Ok(());
+        };
+        if let Some(cipher) = &mut self.cipher {
+            cipher.crypt(&mut answer.data, answer.last_bits);
+        }
+        self.damage(&mut answer, fault);
+        self.receive(&answer);
+        Ok(())
+    }
+
+    /// Runs the three pass authentication with the command, block, key and
+    /// UID in the FIFO. Nested if a sector is already open.
+    fn authenticate(&mut self) -> Result<(), BusError> {
+        let fault = self.start_frame()?;
+        let fifo: Vec<u8> = self.fifo.drain(..).collect();
+        let nested = self.cipher.take();
+        self.regs[STATUS2] &= !STATUS2_CRYPTO1_ON;
+        if fifo.len() < 12 {
+            self.error(ERR_PROTOCOL);
+            return Ok(());
+        }
+        let key: [u8; 6] = fifo[2..8].try_into().unwrap();
+        let uid = u32::from_be_bytes(fifo[8..12].try_into().unwrap());
+
+        let mut frame = vec![fifo[0], fifo[1]];
+        frame.extend_from_slice(&crc_a(&frame));
+        if let Some(mut cipher) = nested {
+            cipher.crypt(&mut frame, 0);
+        }
+        let Some(mut answer) = self.send(&frame, 0, fault) else {
+            self.no_answer();
+            return Ok(());
+        };
+        self.damage(&mut answer, fault);
+        let Ok(nt) = <[u8; 4]>::try_from(answer.data.as_slice()) else {
+            self.error(ERR_PROTOCOL);
+            return Ok(());
+        };
+        if self.regs[ERROR] != 0 {
+            self.regs[COM_IRQ] |= IRQ_ERR;
+            return Ok(());
+        }
+
+        let mut cipher = Crypto1::new(&key);
+        let nt = u32::from_be_bytes(nt);
+        let nt = if nested.is_some() {
+            nt ^ cipher.word(uid ^ nt, true)
+        } else {
+            cipher.word(uid ^ nt, false);
+            nt
+        };
+        self.nonce = crypto1::prng_successor(self.nonce, 32);
+        let nr = self.nonce;
+        let nr_enc = nr ^ cipher.word(nr, false);
+        let ar_enc = crypto1::prng_successor(nt, 64) ^ cipher.word(0, false);
+        let mut frame = nr_enc.to_be_bytes().to_vec();
+        frame.extend_from_slice(&ar_enc.to_be_bytes());
+
+        let Some(answer) = self.send(&frame, 0, None) else {
+            // Wrong key: the card just stops talking
+            self.no_answer();
+            return Ok(());
+        };
+        let at = <[u8; 4]>::try_from(answer.data.as_slice())
+            .ok()
+            .map(|at| u32::from_be_bytes(at) ^ cipher.word(0, false));
+        if at != Some(crypto1::prng_successor(nt, 96)) {
+            self.error(ERR_PROTOCOL);
+            return Ok(());
+        }
+
+        self.cipher = Some(cipher);
+        self.regs[STATUS2] |= STATUS2_CRYPTO1_ON;
+        self.regs[COM_IRQ] |= IRQ_IDLE;
+        Ok(())
+    }
+
+    fn receive(&mut self, answer: &Answer) {
+        for &b in &answer.data {
+            if self.fifo.len() < FIFO_SIZE {
+                self.fifo.push(b);
+            } else {
+                self.regs[ERROR] |= ERR_BUFFER_OVERFLOW;
+            }
+        }
+        self.regs[CONTROL] = (self.regs[CONTROL] & !0x07) | (answer.last_bits & 0x07);
+        self.regs[COM_IRQ] |= IRQ_RX;
+        if self.regs[ERROR] != 0 {
+            self.regs[COM_IRQ] |= IRQ_ERR;
+        }
+    }
+
+    /// Nobody answered: the timer runs out, if it is set to start on its own.
+    fn no_answer(&mut self) {
+        if self.regs[T_MODE] & T_MODE_AUTO != 0 {
+            self.regs[COM_IRQ] |= IRQ_TIMER;
+        }
+    }
+
+    fn error(&mut self, bits: u8) {
+        self.regs[ERROR] |= bits;
+        self.regs[COM_IRQ] |= IRQ_ERR;
+    }
+}
+
+impl ErrorType for Emulator {
+    type Error = BusError;
+}
+
+impl SpiDevice for Emulator {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), BusError> {
+        let mut access = Access::Address;
+        for op in operations {
+            match op {
+                Operation::Write(bytes) => {
+                    for &b in bytes.iter() {
+                        access = self.spi_byte(access, b)?.0;
+                    }
+                }
+                Operation::TransferInPlace(bytes) => {
+                    for b in bytes.iter_mut() {
+                        let (next, miso) = self.spi_byte(access, *b)?;
+                        access = next;
+                        *b = miso;
+                    }
+                }
+                Operation::Transfer(read, write) => {
+                    let len = read.len().max(write.len());
+                    for i in 0..len {
+                        let mosi = write.get(i).copied().unwrap_or(0);
+                        let (next, miso) = self.spi_byte(access, mosi)?;
+                        access = next;
+                        if let Some(r) = read.get_mut(i) {
+                            *r = miso;
+                        }
+                    }
+                }
+                Operation::Read(bytes) => {
+                    for b in bytes.iter_mut() {
+                        let (next, miso) = self.spi_byte(access, 0)?;
+                        access = next;
+                        *b = miso;
+                    }
+                }
+                Operation::DelayNs(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,701 @@
+//! Runs the `mifare` helpers against the emulator, faults included.
+
+use std::cell::RefCell;
+
+use mfrc522::comm::blocking::spi::SpiInterface;
+use mfrc522::{Error, Mfrc522, Uid};
+
+use mfrc522_emu::card::{Card, Magic};
+use mfrc522_emu::{BusError, Emulator, Fault, CHIP_VERSION};
+
+use mifare::access::{AccessBits, AccessConditions, KeyType};
+use mifare::card;
+use mifare::classic::{self, BLOCK_SIZE};
+use mifare::diag::{self, Gain, PollStats, SelfTest};
+use mifare::dump::{self, Dump, RestoreOptions};
+use mifare::geometry::CardType;
+use mifare::inventory::{self, Tag};
+use mifare::keys::{self, FoundKey, DEFAULT_KEYS, MAD_KEY, NDEF_KEY, TRANSPORT_KEY, VENDOR_KEY_B};
+use mifare::pcd::{Pcd, SharedSpi};
+use mifare::power::{self, CurrentProfile, DutyCycle, Scheduler};
+use mifare::rotation::{self, RotationError, SectorState};
+use mifare::value::ValueBlock;
+use mifare::wallet::{Wallet, WalletError};
+
+type Bench = RefCell<Emulator>;
+type Reader<'a> = Pcd<SharedSpi<'a, Emulator>>;
+
+const UID_4: [u8; 4] = [0x13, 0x37, 0x73, 0x31];
+const UID_7: [u8; 7] = [0x04, 0x62, 0x63, 0x64, 0x65, 0x66, 0x67];
+
+fn bench(card: Card) -> Bench {
+    RefCell::new(Emulator::with_card(card))
+}
+
+fn reader(bench: &Bench) -> Reader<'_> {
+    Pcd::new(SharedSpi::new(bench))
+}
+
+/// Brings the chip up and selects the card the way the firmware does, through
+/// the driver.
+fn connect(bench: &Bench) -> Uid {
+    let driver = Mfrc522::new(SpiInterface::new(SharedSpi::new(bench)));
+    let mut rfid = diag::probe(&mut reader(bench), driver).expect("probe");
+    let atqa = rfid.wupa().expect("WUPA");
+    rfid.select(&atqa).expect("select")
+}
+
+fn stored(bench: &Bench, block: u8) -> [u8; BLOCK_SIZE] {
+    *bench.borrow().card().unwrap().block(usize::from(block))
+}
+
+fn open(uid: &Uid, block: u8, pcd: &mut Reader) -> FoundKey {
+    keys::find_key(uid, block, &DEFAULT_KEYS, pcd)
+        .expect("find_key")
+        .unwrap_or_else(|| panic!("no key opens block {}", block))
+}
+
+fn pattern(block: u8) -> [u8; BLOCK_SIZE] {
+    core::array::from_fn(|i| block.wrapping_mul(16).wrapping_add(i as u8))
+}
+
+#[test]
+fn select() {
+    for card in [Card::classic_1k(&UID_4), Card::classic_4k(&UID_7)] {
+        let bench = bench(card);
+        let uid = connect(&bench);
+        assert_eq!(uid.as_bytes(), bench.borrow().card().unwrap().uid());
+
+        let mut pcd = reader(&bench);
+        let activation = pcd.activate(&uid).expect("activate");
+        let expected = if uid.as_bytes().len() == 4 {
+            CardType::Classic1K
+        } else {
+            CardType::Classic4K
+        };
+        assert_eq!(activation.card_type(), Some(expected), "{:?}", activation);
+        let detected = card::detect_classic(&uid, &mut pcd);
+        assert!(
+            matches!(detected, Ok(found) if found == expected),
+            "{:?}",
+            detected
+        );
+
+        // And once more without knowing the UID
+        pcd.halt().expect("halt");
+        pcd.request(true).expect("WUPA");
+        let selected = pcd.anticollision().expect("anticollision");
+        assert_eq!(selected.uid(), uid.as_bytes());
+    }
+}
+
+#[test]
+fn inventory() {
+    let bench = bench(Card::classic_4k(&UID_7));
+    // A selected card wouldn't answer the first WUPA
+    bench.borrow_mut().init();
+    let mut pcd = reader(&bench);
+    let mut tags = [Tag::default(); 4];
+    let found = inventory::inventory(&mut pcd, &mut tags).expect("inventory");
+    assert_eq!(found, 1);
+    assert_eq!(tags[0].uid(), UID_7);
+
+    bench.borrow_mut().remove();
+    let found = inventory::inventory(&mut pcd, &mut tags).expect("inventory");
+    assert_eq!(found, 0, "tags in an empty field");
+}
+
+#[test]
+fn read_write() {
+    let bench = bench(Card::classic_1k(&UID_4));
+    let uid = connect(&bench);
+    let mut pcd = reader(&bench);
+
+    let found = open(&uid, 4, &mut pcd);
+    assert_eq!((found.key_type, found.key), (KeyType::A, TRANSPORT_KEY));
+    let data = pattern(5);
+    classic::write_block(5, &data, &mut pcd).expect("write");
+    let read = classic::read_block(5, &mut pcd).expect("read");
+    assert_eq!(read, data);
+    assert_eq!(stored(&bench, 5), data);
+
+    // Key A reads as zeros, the readable Key B as itself
+    let trailer = classic::read_block(7, &mut pcd).expect("read trailer");
+    assert_eq!(trailer[..6], [0; 6]);
+    assert_eq!(trailer[6..10], [0xFF, 0x07, 0x80, 0x69]);
+    assert_eq!(trailer[10..], [0xFF; 6]);
+
+    // Nested authentication, straight from one sector to the next
+    pcd.authenticate(&uid, 8, KeyType::A, &TRANSPORT_KEY)
+        .expect("nested auth");
+    classic::write_block(8, &data, &mut pcd).expect("write after nested auth");
+    assert_eq!(stored(&bench, 8), data, "nested write lost");
+
+    // Block 0 belongs to the manufacturer
+    pcd.authenticate(&uid, 0, KeyType::A, &TRANSPORT_KEY)
+        .expect("auth sector 0");
+    let result = classic::write_block(0, &data, &mut pcd);
+    assert!(matches!(result, Err(Error::Nak)), "{:?}", result);
+}
+
+#[test]
+fn access() {
+    let bench = bench(Card::classic_1k(&UID_4));
+    let conditions = AccessConditions {
+        blocks: [AccessBits::DATA_WRITE_KEY_B; 3],
+        trailer: AccessBits::TRAILER_KEY_B,
+    };
+    let trailer = conditions
+        .to_trailer(&MAD_KEY, &VENDOR_KEY_B, 0x69)
+        .expect("trailer");
+    bench
+        .borrow_mut()
+        .card_mut()
+        .unwrap()
+        .set_block(11, &trailer);
+
+    let uid = connect(&bench);
+    let mut pcd = reader(&bench);
+    let found = open(&uid, 8, &mut pcd);
+    assert_eq!((found.key_type, found.key), (KeyType::A, MAD_KEY));
+    let result = classic::write_block(8, &pattern(8), &mut pcd);
+    assert!(
+        matches!(result, Err(Error::Nak)),
+        "Key A write gave {:?}",
+        result
+    );
+
+    pcd.activate(&uid).expect("activate");
+    pcd.authenticate(&uid, 8, KeyType::B, &VENDOR_KEY_B)
+        .expect("auth with Key B");
+    classic::write_block(8, &pattern(8), &mut pcd).expect("Key B write");
+    let read = classic::read_block(11, &mut pcd).expect("read trailer");
+    assert_eq!(read[10..], [0; 6]);
+    assert_eq!(read[6..10], trailer[6..10]);
+
+    // A wrong key gets no answer at all
+    pcd.activate(&uid).expect("activate");
+    let result = pcd.authenticate(&uid, 8, KeyType::B, &TRANSPORT_KEY);
+    assert!(matches!(result, Err(Error::Timeout)), "{:?}", result);
+}
+
+/// Reads every block the default keys open, like memory-dump does.
+fn read_dump(uid: &Uid, card: CardType, pcd: &mut Reader) -> Dump {
+    let activation = pcd.activate(uid).expect("activate");
+    let mut dump = Dump::new(card, uid.as_bytes(), &activation);
+    for sector in 0..card.sector_count() {
+        let first = card.first_block(sector).unwrap_or_default();
+        let found = open(uid, first, pcd);
+        for rel_block in 0..card.blocks_in_sector(sector).unwrap_or_default() {
+            let block = first + rel_block;
+            let data = classic::read_block(block, pcd).expect("read");
+            dump.set_block(block, &data);
+        }
+        dump.set_key(sector, &found);
+    }
+    dump
+}
+
+fn fill(bench: &Bench) {
+    let mut emu = bench.borrow_mut();
+    let card = emu.card_mut().unwrap();
+    for block in 1..card.block_count() {
+        let trailer = if block < 128 {
+            block % 4 == 3
+        } else {
+            block % 16 == 15
+        };
+        if !trailer {
+            card.set_block(block, &pattern(block as u8));
+        }
+    }
+}
+
+#[test]
+fn dump_4k_with_7_byte_uid() {
+    let bench = bench(Card::classic_4k(&UID_7));
+    fill(&bench);
+    let uid = connect(&bench);
+    let mut pcd = reader(&bench);
+    let dump = read_dump(&uid, CardType::Classic4K, &mut pcd);
+
+    for block in 0..=255u8 {
+        assert_eq!(
+            dump.block(block),
+            Some(&stored(&bench, block)),
+            "block {}",
+            block
+        );
+    }
+    let copy = Dump::from_mfd(dump.mfd()).expect("mfd");
+    assert_eq!(copy.uid(), UID_7);
+    assert!(copy.diff(&dump).next().is_none(), "mfd round trip differs");
+}
+
+#[test]
+fn wallet() {
+    let bench = bench(Card::classic_1k(&UID_4));
+    let uid = connect(&bench);
+    let mut pcd = reader(&bench);
+    let found = open(&uid, 4, &mut pcd);
+    let wallet: Wallet =
+        Wallet::new::<BusError>(&uid, CardType::Classic1K, 1, found).expect("wallet");
+
+    wallet.format(100, &mut pcd).expect("format");
+    assert_eq!(wallet.debit(30, &mut pcd).expect("debit"), 70);
+    assert_eq!(wallet.credit(50, &mut pcd).expect("credit"), 120);
+    let result = wallet.debit(500, &mut pcd);
+    assert!(
+        matches!(result, Err(WalletError::InsufficientFunds { balance: 120 })),
+        "overdraft gave {:?}",
+        result
+    );
+
+    // A damaged balance comes back from the backup, which holds the balance
+    // from before the last change
+    bench
+        .borrow_mut()
+        .card_mut()
+        .unwrap()
+        .set_block(4, &[0; BLOCK_SIZE]);
+    let balance = wallet.balance(&mut pcd).expect("balance");
+    assert_eq!(balance, 70);
+    let repaired = ValueBlock::decode(&stored(&bench, 4));
+    assert_eq!(repaired.map(|v| v.value), Some(70));
+}
+
+#[test]
+fn wallet_recovery() {
+    let bench = bench(Card::classic_1k(&UID_4));
+    let uid = connect(&bench);
+    let mut pcd = reader(&bench);
+    let found = open(&uid, 4, &mut pcd);
+    let wallet: Wallet =
+        Wallet::new::<BusError>(&uid, CardType::Classic1K, 1, found).expect("wallet");
+    wallet.format(100, &mut pcd).expect("format");
+
+    // Flip a bit of the value so it no longer matches its inverted copy
+    let mut corrupt = stored(&bench, 4);
+    corrupt[0] ^= 0x01;
+    bench
+        .borrow_mut()
+        .card_mut()
+        .unwrap()
+        .set_block(4, &corrupt);
+    assert_eq!(wallet.balance(&mut pcd).expect("balance"), 100);
+
+    // The repaired block carries its own address, not the backup's
+    assert_eq!(stored(&bench, 4), ValueBlock::new(100, 4).encode());
+    assert_eq!(stored(&bench, 5), ValueBlock::new(100, 5).encode());
+    assert_eq!(wallet.debit(40, &mut pcd).expect("debit"), 60);
+}
+
+#[test]
+fn timeouts_and_damaged_frames() {
+    let bench = bench(Card::classic_1k(&UID_4));
+    let uid = connect(&bench);
+    let mut pcd = reader(&bench);
+    open(&uid, 4, &mut pcd);
+
+    // A damaged answer leaves the card where it was
+    for (fault, expected) in [(Fault::Crc, "Crc"), (Fault::Parity, "Parity")] {
+        bench.borrow_mut().inject(fault);
+        let result = classic::read_block(4, &mut pcd);
+        assert_eq!(format!("{:?}", result), format!("Err({})", expected));
+        classic::read_block(4, &mut pcd).expect("read after a damaged answer");
+    }
+
+    // A lost frame needs the sector opened again
+    bench.borrow_mut().inject(Fault::Timeout);
+    let result = classic::read_block(4, &mut pcd);
+    assert!(matches!(result, Err(Error::Timeout)), "{:?}", result);
+    open(&uid, 4, &mut pcd);
+    classic::read_block(4, &mut pcd).expect("read after reopening");
+
+    pcd.halt().expect("halt");
+    bench.borrow_mut().inject(Fault::Collision);
+    let result = pcd.request(true);
+    assert!(matches!(result, Err(Error::Collision)), "{:?}", result);
+}
+
+#[test]
+fn card_removed_mid_write() {
+    let before = Card::classic_1k(&UID_4);
+    let old = *before.block(4);
+    let new = pattern(4);
+    // WRITE takes two frames: pull the card before each, then not at all
+    for frame in 0..3 {
+        let bench = bench(before.clone());
+        let uid = connect(&bench);
+        let mut pcd = reader(&bench);
+        open(&uid, 4, &mut pcd);
+        let at = bench.borrow().frames() + frame;
+        bench.borrow_mut().inject_at(at, Fault::Removal);
+
+        let result = classic::write_block(4, &new, &mut pcd);
+        assert_eq!(result.is_ok(), frame == 2, "frame {}: {:?}", frame, result);
+        let expected = if frame < 2 { old } else { new };
+        assert_eq!(stored(&bench, 4), expected, "frame {}", frame);
+    }
+}
+
+/// Transport access conditions with `key_a` and the transport Key B.
+fn transport_trailer(key_a: &[u8; 6]) -> [u8; BLOCK_SIZE] {
+    AccessConditions::TRANSPORT
+        .to_trailer(key_a, &TRANSPORT_KEY, 0x69)
+        .expect("trailer")
+}
+
+const TRANSPORT: FoundKey = FoundKey {
+    key_type: KeyType::A,
+    key: TRANSPORT_KEY,
+};
+
+#[test]
+fn rotation_refusals() {
+    let bench = bench(Card::classic_1k(&UID_4));
+    let uid = connect(&bench);
+    let mut pcd = reader(&bench);
+    let change = |sector, trailer: &[u8; BLOCK_SIZE], old: &FoundKey, pcd: &mut Reader| {
+        rotation::change_trailer(&uid, CardType::Classic1K, sector, old, trailer, false, pcd)
+    };
+
+    // Refused before anything goes over the air
+    let frames = bench.borrow().frames();
+    let result = change(0, &transport_trailer(&MAD_KEY), &TRANSPORT, &mut pcd);
+    assert!(
+        matches!(result, Err(RotationError::Sector0)),
+        "{:?}",
+        result
+    );
+    let frozen = AccessConditions {
+        blocks: [AccessBits::DATA_TRANSPORT; 3],
+        trailer: AccessBits::TRAILER_FROZEN,
+    }
+    .to_trailer(&MAD_KEY, &VENDOR_KEY_B, 0x69)
+    .expect("trailer");
+    let result = change(1, &frozen, &TRANSPORT, &mut pcd);
+    assert!(
+        matches!(result, Err(RotationError::SelfLocking)),
+        "{:?}",
+        result
+    );
+    assert_eq!(bench.borrow().frames(), frames, "refusals sent frames");
+
+    // Only Key B may write this trailer, so Key A gets nowhere
+    let key_b_only = AccessConditions {
+        blocks: [AccessBits::DATA_WRITE_KEY_B; 3],
+        trailer: AccessBits::TRAILER_KEY_B,
+    }
+    .to_trailer(&MAD_KEY, &VENDOR_KEY_B, 0x69)
+    .expect("trailer");
+    bench
+        .borrow_mut()
+        .card_mut()
+        .unwrap()
+        .set_block(11, &key_b_only);
+    let old = FoundKey {
+        key_type: KeyType::A,
+        key: MAD_KEY,
+    };
+    let result = change(2, &transport_trailer(&NDEF_KEY), &old, &mut pcd);
+    assert!(
+        matches!(result, Err(RotationError::NotPermitted)),
+        "{:?}",
+        result
+    );
+    assert_eq!(stored(&bench, 11), key_b_only);
+    assert_eq!(stored(&bench, 3), transport_trailer(&TRANSPORT_KEY));
+    assert_eq!(stored(&bench, 7), transport_trailer(&TRANSPORT_KEY));
+}
+
+#[test]
+fn rotation() {
+    let bench = bench(Card::classic_1k(&UID_4));
+    let uid = connect(&bench);
+    let mut pcd = reader(&bench);
+    let new = transport_trailer(&MAD_KEY);
+    rotation::change_trailer(
+        &uid,
+        CardType::Classic1K,
+        1,
+        &TRANSPORT,
+        &new,
+        false,
+        &mut pcd,
+    )
+    .expect("change_trailer");
+    assert_eq!(stored(&bench, 7), new);
+
+    // The new Key A opens the sector, the old one no longer does
+    pcd.activate(&uid).expect("activate");
+    pcd.authenticate(&uid, 4, KeyType::A, &MAD_KEY)
+        .expect("auth with the new Key A");
+    classic::write_block(4, &pattern(4), &mut pcd).expect("write with the new Key A");
+    pcd.activate(&uid).expect("activate");
+    let result = pcd.authenticate(&uid, 4, KeyType::A, &TRANSPORT_KEY);
+    assert!(matches!(result, Err(Error::Timeout)), "{:?}", result);
+}
+
+#[test]
+fn rotation_write_lost() {
+    let before = Card::classic_1k(&UID_4);
+    let old = *before.block(7);
+    let new = transport_trailer(&MAD_KEY);
+
+    // Frames change_trailer sends before writing: activate, authenticate and
+    // read the current trailer
+    let bench = bench(before.clone());
+    let uid = connect(&bench);
+    let mut pcd = reader(&bench);
+    let start = bench.borrow().frames();
+    pcd.activate(&uid).expect("activate");
+    pcd.authenticate(&uid, 7, KeyType::A, &TRANSPORT_KEY)
+        .expect("auth");
+    classic::read_block(7, &mut pcd).expect("read trailer");
+    let checks = bench.borrow().frames() - start;
+
+    // WRITE takes two frames; lose either and the old keys still work
+    for (frame, fault, expected) in [
+        (0, Fault::Timeout, SectorState::OldKeys),
+        (1, Fault::Timeout, SectorState::OldKeys),
+        (0, Fault::Removal, SectorState::Unknown),
+    ] {
+        let bench = self::bench(before.clone());
+        let uid = connect(&bench);
+        let mut pcd = reader(&bench);
+        let at = bench.borrow().frames() + checks + frame;
+        bench.borrow_mut().inject_at(at, fault);
+
+        let result = rotation::change_trailer(
+            &uid,
+            CardType::Classic1K,
+            1,
+            &TRANSPORT,
+            &new,
+            false,
+            &mut pcd,
+        );
+        assert!(
+            matches!(result, Err(RotationError::Failed { state, .. }) if state == expected),
+            "{:?} at frame {}: {:?}",
+            fault,
+            frame,
+            result
+        );
+        assert_eq!(stored(&bench, 7), old, "{:?} at frame {}", fault, frame);
+    }
+}
+
+#[test]
+fn wallet_survives_removal() {
+    let bench = bench(Card::classic_1k(&UID_4));
+    let uid = connect(&bench);
+    let mut pcd = reader(&bench);
+    let found = open(&uid, 4, &mut pcd);
+    Wallet::new::<BusError>(&uid, CardType::Classic1K, 1, found)
+        .expect("wallet")
+        .format(100, &mut pcd)
+        .expect("format");
+    let formatted = bench.borrow().card().unwrap().clone();
+
+    // Count the frames of an undisturbed debit
+    let start = bench.borrow().frames();
+    Wallet::new::<BusError>(&uid, CardType::Classic1K, 1, found)
+        .expect("wallet")
+        .debit(30, &mut pcd)
+        .expect("debit");
+    let frames = bench.borrow().frames() - start;
+
+    for frame in 0..frames {
+        let bench = self::bench(formatted.clone());
+        let uid = connect(&bench);
+        let mut pcd = reader(&bench);
+        let wallet = Wallet::new::<BusError>(&uid, CardType::Classic1K, 1, found).expect("wallet");
+        let at = bench.borrow().frames() + frame;
+        bench.borrow_mut().inject_at(at, Fault::Removal);
+        // The debit may or may not have gone through; either is fine
+        let _ = wallet.debit(30, &mut pcd);
+
+        bench.borrow_mut().reinsert();
+        let uid = connect(&bench);
+        let wallet = Wallet::new::<BusError>(&uid, CardType::Classic1K, 1, found).expect("wallet");
+        let balance = wallet.balance(&mut pcd);
+        assert!(
+            matches!(balance, Ok(100 | 70)),
+            "removal at frame {} of {} left {:?}",
+            frame,
+            frames,
+            balance
+        );
+    }
+}
+
+#[test]
+fn restore() {
+    let source = bench(Card::classic_1k(&UID_4));
+    fill(&source);
+    let uid = connect(&source);
+    let mut pcd = reader(&source);
+    let dump = read_dump(&uid, CardType::Classic1K, &mut pcd);
+
+    // A Gen1a clone takes everything, UID included
+    let target = bench(Card::classic_1k(&[0xDE, 0xAD, 0xBE, 0xEF]).with_magic(Magic::Gen1a));
+    let uid = connect(&target);
+    let mut pcd = reader(&target);
+    let options = RestoreOptions {
+        block0: true,
+        trailers: true,
+    };
+    let report = dump::restore(
+        &uid,
+        CardType::Classic1K,
+        &dump,
+        &DEFAULT_KEYS,
+        options,
+        &mut pcd,
+    )
+    .expect("restore");
+    assert!(report.gen1a, "{:?}", report);
+    assert_eq!((report.failed, report.written), (0, 64), "{:?}", report);
+    for block in 0..64 {
+        assert_eq!(
+            stored(&target, block),
+            stored(&source, block),
+            "block {}",
+            block
+        );
+    }
+    assert_eq!(connect(&target).as_bytes(), UID_4, "UID not cloned");
+
+    // A genuine card takes the data blocks only
+    let target = bench(Card::classic_1k(&[0xDE, 0xAD, 0xBE, 0xEF]));
+    let uid = connect(&target);
+    let mut pcd = reader(&target);
+    let report = dump::restore(
+        &uid,
+        CardType::Classic1K,
+        &dump,
+        &DEFAULT_KEYS,
+        RestoreOptions::default(),
+        &mut pcd,
+    )
+    .expect("restore");
+    assert!(!report.gen1a, "{:?}", report);
+    assert_eq!((report.failed, report.written), (0, 47), "{:?}", report);
+}
+
+#[test]
+fn bus_errors() {
+    let bench = bench(Card::classic_1k(&UID_4));
+    let uid = connect(&bench);
+    let mut pcd = reader(&bench);
+    open(&uid, 4, &mut pcd);
+    bench.borrow_mut().inject(Fault::Bus);
+    let result = classic::read_block(4, &mut pcd);
+    assert!(matches!(result, Err(Error::Comm(BusError))), "{:?}", result);
+}
+
+#[test]
+fn reader_diagnostics() {
+    let bench = bench(Card::classic_1k(&UID_4));
+    connect(&bench);
+    let mut pcd = reader(&bench);
+    let version = diag::version(&mut pcd).expect("version");
+    assert_eq!(version.0, CHIP_VERSION);
+    assert!(version.is_present());
+
+    diag::set_gain(&mut pcd, Gain::Db43).expect("set_gain");
+    assert_eq!(diag::gain(&mut pcd).expect("gain"), Gain::Db43);
+
+    // The self-test resets the chip; the settings have to survive it
+    assert_eq!(
+        diag::self_test(&mut pcd).expect("self_test"),
+        SelfTest::Passed
+    );
+    assert_eq!(diag::gain(&mut pcd).expect("gain"), Gain::Db43);
+
+    let stats = diag::poll(&mut pcd, 10).expect("poll");
+    assert_eq!(stats.ok, 10, "{:?}", stats);
+    assert_eq!(stats.success_rate(), 100);
+
+    // Each fault lands on a different step of a single poll
+    let expect = |stats: PollStats, no_answer: u16, errors: u16| {
+        assert_eq!(
+            (stats.polls, stats.no_answer, stats.errors),
+            (1, no_answer, errors),
+            "{:?}",
+            stats
+        );
+    };
+    bench.borrow_mut().inject(Fault::Timeout);
+    expect(diag::poll(&mut pcd, 1).expect("poll"), 1, 0);
+    bench.borrow_mut().inject(Fault::Parity);
+    expect(diag::poll(&mut pcd, 1).expect("poll"), 0, 1);
+    let next = bench.borrow().frames() + 1;
+    bench.borrow_mut().inject_at(next, Fault::Crc);
+    expect(diag::poll(&mut pcd, 1).expect("poll"), 0, 1);
+
+    bench.borrow_mut().remove();
+    let stats = diag::poll(&mut pcd, 3).expect("poll");
+    assert_eq!(stats.no_answer, 3, "{:?}", stats);
+    assert_eq!(stats.success_rate(), 0);
+}
+
+#[test]
+fn low_power_polling() {
+    let bench = bench(Card::classic_1k(&UID_4));
+    connect(&bench);
+    let mut pcd = reader(&bench);
+    power::enable_irq(&mut pcd).expect("enable_irq");
+
+    // The selected card ignores REQA, until power-down takes the field away
+    let probe = power::probe(&mut pcd, 1000).expect("probe");
+    assert!(
+        !probe.finish(&mut pcd).expect("finish"),
+        "selected card answered REQA"
+    );
+    power::power_down(&mut pcd).expect("power_down");
+    assert!(bench.borrow().is_powered_down());
+    power::power_up(&mut pcd).expect("power_up");
+
+    // The probe raises IRQ when the card answers and leaves it ready to select
+    let probe = power::probe(&mut pcd, 1000).expect("probe");
+    assert!(bench.borrow().irq(), "no IRQ after the answer");
+    assert!(
+        probe.finish(&mut pcd).expect("finish"),
+        "probe missed the card"
+    );
+    assert!(!bench.borrow().irq());
+    let selected = pcd.anticollision().expect("anticollision");
+    assert_eq!(selected.uid(), UID_4);
+
+    // An empty field raises IRQ through the timer instead
+    bench.borrow_mut().remove();
+    let probe = power::probe(&mut pcd, 1000).expect("probe");
+    assert!(bench.borrow().irq(), "no IRQ on timeout");
+    assert!(!probe.finish(&mut pcd).expect("finish"));
+
+    // Ten seconds of an empty field with 1 ms polls, then a card for one
+    let mut scheduler = Scheduler::new(DutyCycle::DOOR, 0);
+    while scheduler.next_poll_us() < 10_000_000 {
+        let now = scheduler.next_poll_us();
+        scheduler.record(now, now + 1000, false);
+    }
+    let stats = scheduler.stats(10_000_000);
+    assert_eq!(stats.poll_rate_centihz(), 400, "{:?}", stats);
+    assert_eq!(stats.duty_ppm(), 4000, "{:?}", stats);
+    let current = stats.average_current_ua(&CurrentProfile::TYPICAL);
+    assert!(
+        (8_100..8_300).contains(&current),
+        "idle current {} uA",
+        current
+    );
+    let now = scheduler.next_poll_us();
+    scheduler.record(now, now + 1000, true);
+    assert_eq!(
+        scheduler.next_poll_us(),
+        now + 50_000,
+        "no faster polling after a card"
+    );
+}
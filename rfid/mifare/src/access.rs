@@ -0,0 +1,534 @@
+//! Sector trailer access conditions.
+//!
+//! Bytes 6, 7 and 8 of a sector trailer hold the access bits C1, C2 and C3 for
+//! every block of the sector. Each bit is stored twice, once as-is and once
+//! inverted, so the card can tell a corrupted trailer from a valid one:
+//!
+//! ```text
+//!           bit 7 .. 4     bit 3 .. 0
+//! byte 6:   !C2 (3..0)     !C1 (3..0)
+//! byte 7:    C1 (3..0)     !C3 (3..0)
+//! byte 8:    C3 (3..0)      C2 (3..0)
+//! ```
+//!
+//! Bit 3 of each group belongs to the trailer, bits 0..2 to the data blocks.
+//! Byte 9 is a free user byte.
+
+/// Which of the two sector keys is used to authenticate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyType {
+    A,
+    B,
+}
+
+/// Which key, if any, is allowed to perform an operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    Never,
+    KeyA,
+    KeyB,
+    KeyAOrB,
+}
+
+impl Permission {
+    /// Returns true if authenticating with `key` grants this permission.
+    pub const fn allows(self, key: KeyType) -> bool {
+        matches!(
+            (self, key),
+            (Permission::KeyAOrB, _)
+                | (Permission::KeyA, KeyType::A)
+                | (Permission::KeyB, KeyType::B)
+        )
+    }
+
+    /// Drops Key B from the permission; used when Key B is readable and
+    /// therefore can't be used to authenticate.
+    const fn without_key_b(self) -> Self {
+        match self {
+            Permission::KeyB => Permission::Never,
+            Permission::KeyAOrB => Permission::KeyA,
+            other => other,
+        }
+    }
+
+    const fn key_b_only(self) -> bool {
+        matches!(self, Permission::KeyB)
+    }
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Permission::Never => "never",
+            Permission::KeyA => "A",
+            Permission::KeyB => "B",
+            Permission::KeyAOrB => "A|B",
+        }
+    }
+}
+
+/// What each key may do with a data block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataPermissions {
+    pub read: Permission,
+    pub write: Permission,
+    pub increment: Permission,
+    /// Covers DECREMENT, TRANSFER and RESTORE.
+    pub decrement: Permission,
+}
+
+/// What each key may do with the parts of a sector trailer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrailerPermissions {
+    pub key_a_write: Permission,
+    pub access_read: Permission,
+    pub access_write: Permission,
+    pub key_b_read: Permission,
+    pub key_b_write: Permission,
+}
+
+impl TrailerPermissions {
+    /// When Key B can be read it is plain data and can't authenticate.
+    pub const fn key_b_readable(&self) -> bool {
+        !matches!(self.key_b_read, Permission::Never)
+    }
+}
+
+/// The C1, C2 and C3 access bits of a single block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessBits {
+    pub c1: bool,
+    pub c2: bool,
+    pub c3: bool,
+}
+
+impl AccessBits {
+    pub const fn new(c1: bool, c2: bool, c3: bool) -> Self {
+        Self { c1, c2, c3 }
+    }
+
+    /// Data block: read, write, increment and decrement with Key A or B.
+    pub const DATA_TRANSPORT: Self = Self::new(false, false, false);
+    /// Data block: read with Key A or B, nothing else.
+    pub const DATA_READ_ONLY: Self = Self::new(false, true, false);
+    /// Data block: read with Key A or B, write with Key B.
+    pub const DATA_WRITE_KEY_B: Self = Self::new(true, false, false);
+    /// Value block: Key B may recharge, Key A or B may debit.
+    pub const VALUE_RECHARGEABLE: Self = Self::new(true, true, false);
+    /// Value block: debit only, with Key A or B.
+    pub const VALUE_DEBIT_ONLY: Self = Self::new(false, false, true);
+    /// Data block: read and write with Key B only.
+    pub const DATA_KEY_B: Self = Self::new(false, true, true);
+    /// Data block: read with Key B only.
+    pub const DATA_READ_KEY_B: Self = Self::new(true, false, true);
+    /// Data block: no access at all.
+    pub const DATA_LOCKED: Self = Self::new(true, true, true);
+
+    /// Trailer: Key A manages everything and Key B is readable. Factory default.
+    pub const TRAILER_TRANSPORT: Self = Self::new(false, false, true);
+    /// Trailer: Key A rewrites the keys, access bits are frozen.
+    pub const TRAILER_KEY_A: Self = Self::new(false, false, false);
+    /// Trailer: nothing writable, Key B readable with Key A.
+    pub const TRAILER_READ_ONLY: Self = Self::new(false, true, false);
+    /// Trailer: Key B rewrites the keys, access bits are frozen.
+    pub const TRAILER_KEY_B_KEYS: Self = Self::new(true, false, false);
+    /// Trailer: Key B rewrites the keys and the access bits.
+    pub const TRAILER_KEY_B: Self = Self::new(false, true, true);
+    /// Trailer: Key B rewrites the access bits only.
+    pub const TRAILER_KEY_B_ACCESS: Self = Self::new(true, false, true);
+    /// Trailer: nothing can ever be written again.
+    pub const TRAILER_FROZEN: Self = Self::new(true, true, true);
+
+    /// Picks the bits of block `n` out of the C1, C2 and C3 nibbles.
+    const fn from_groups(c1: u8, c2: u8, c3: u8, n: u8) -> Self {
+        Self::new(c1 & (1 << n) != 0, c2 & (1 << n) != 0, c3 & (1 << n) != 0)
+    }
+
+    /// Packs the bits as `C1 C2 C3`, the order used by the datasheet tables.
+    const fn index(self) -> usize {
+        ((self.c1 as usize) << 2) | ((self.c2 as usize) << 1) | self.c3 as usize
+    }
+
+    /// Interprets the bits as the access condition of a data block.
+    pub const fn data(self) -> DataPermissions {
+        use Permission::*;
+        let (read, write, increment, decrement) = match self.index() {
+            0b000 => (KeyAOrB, KeyAOrB, KeyAOrB, KeyAOrB),
+            0b010 => (KeyAOrB, Never, Never, Never),
+            0b100 => (KeyAOrB, KeyB, Never, Never),
+            0b110 => (KeyAOrB, KeyB, KeyB, KeyAOrB),
+            0b001 => (KeyAOrB, Never, Never, KeyAOrB),
+            0b011 => (KeyB, KeyB, Never, Never),
+            0b101 => (KeyB, Never, Never, Never),
+            _ => (Never, Never, Never, Never),
+        };
+        DataPermissions {
+            read,
+            write,
+            increment,
+            decrement,
+        }
+    }
+
+    /// Interprets the bits as the access condition of a sector trailer.
+    ///
+    /// Key A itself can never be read back.
+    pub const fn trailer(self) -> TrailerPermissions {
+        use Permission::*;
+        let (key_a_write, access_read, access_write, key_b_read, key_b_write) = match self.index() {
+            0b000 => (KeyA, KeyA, Never, KeyA, KeyA),
+            0b010 => (Never, KeyA, Never, KeyA, Never),
+            0b100 => (KeyB, KeyAOrB, Never, Never, KeyB),
+            0b110 => (Never, KeyAOrB, Never, Never, Never),
+            0b001 => (KeyA, KeyA, KeyA, KeyA, KeyA),
+            0b011 => (KeyB, KeyAOrB, KeyB, Never, KeyB),
+            0b101 => (Never, KeyAOrB, KeyB, Never, Never),
+            _ => (Never, KeyAOrB, Never, Never, Never),
+        };
+        TrailerPermissions {
+            key_a_write,
+            access_read,
+            access_write,
+            key_b_read,
+            key_b_write,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessError {
+    /// The inverted copy of the access bits doesn't match. A card treats such
+    /// a trailer as corrupt and locks the sector.
+    Redundancy,
+    /// The trailer leaves Key B readable, but the given data block only grants
+    /// rights to Key B, so nobody could ever use them.
+    KeyBUnusable { group: u8 },
+}
+
+impl AccessError {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            AccessError::Redundancy => "Access bits are corrupt",
+            AccessError::KeyBUnusable { .. } => "Block needs Key B but Key B is readable",
+        }
+    }
+}
+
+/// Access conditions of a whole sector: three data block groups and the trailer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessConditions {
+    pub blocks: [AccessBits; 3],
+    pub trailer: AccessBits,
+}
+
+impl AccessConditions {
+    /// The conditions a blank card ships with: `FF 07 80`.
+    pub const TRANSPORT: Self = Self {
+        blocks: [AccessBits::DATA_TRANSPORT; 3],
+        trailer: AccessBits::TRAILER_TRANSPORT,
+    };
+
+    /// Decodes bytes 6..9 of a sector trailer, checking the inverted copy.
+    pub const fn decode(bytes: &[u8; 3]) -> Result<Self, AccessError> {
+        let c1 = bytes[1] >> 4;
+        let c2 = bytes[2] & 0x0F;
+        let c3 = bytes[2] >> 4;
+        let inv_c1 = bytes[0] & 0x0F;
+        let inv_c2 = bytes[0] >> 4;
+        let inv_c3 = bytes[1] & 0x0F;
+
+        if c1 != !inv_c1 & 0x0F || c2 != !inv_c2 & 0x0F || c3 != !inv_c3 & 0x0F {
+            return Err(AccessError::Redundancy);
+        }
+
+        Ok(Self {
+            blocks: [
+                AccessBits::from_groups(c1, c2, c3, 0),
+                AccessBits::from_groups(c1, c2, c3, 1),
+                AccessBits::from_groups(c1, c2, c3, 2),
+            ],
+            trailer: AccessBits::from_groups(c1, c2, c3, 3),
+        })
+    }
+
+    /// Decodes the access bits of a full 16-byte sector trailer.
+    pub const fn from_trailer(trailer: &[u8; 16]) -> Result<Self, AccessError> {
+        Self::decode(&[trailer[6], trailer[7], trailer[8]])
+    }
+
+    /// Encodes the access bits into bytes 6..9 of a sector trailer.
+    ///
+    /// Refuses combinations where a data block could only be used with Key B
+    /// while the trailer exposes Key B as readable data.
+    pub const fn encode(&self) -> Result<[u8; 3], AccessError> {
+        if let Err(e) = self.validate() {
+            return Err(e);
+        }
+
+        let mut c1 = 0u8;
+        let mut c2 = 0u8;
+        let mut c3 = 0u8;
+        let mut n = 0;
+        while n < 4 {
+            let bits = self.bits(n as u8);
+            c1 |= (bits.c1 as u8) << n;
+            c2 |= (bits.c2 as u8) << n;
+            c3 |= (bits.c3 as u8) << n;
+            n += 1;
+        }
+
+        Ok([
+            (!c2 << 4) | (!c1 & 0x0F),
+            (c1 << 4) | (!c3 & 0x0F),
+            (c3 << 4) | c2,
+        ])
+    }
+
+    /// Builds a complete sector trailer from the keys and these conditions.
+    pub const fn to_trailer(
+        &self,
+        key_a: &[u8; 6],
+        key_b: &[u8; 6],
+        user_byte: u8,
+    ) -> Result<[u8; 16], AccessError> {
+        let access = match self.encode() {
+            Ok(access) => access,
+            Err(e) => return Err(e),
+        };
+
+        let mut trailer = [0u8; 16];
+        let mut i = 0;
+        while i < 6 {
+            trailer[i] = key_a[i];
+            trailer[10 + i] = key_b[i];
+            i += 1;
+        }
+        trailer[6] = access[0];
+        trailer[7] = access[1];
+        trailer[8] = access[2];
+        trailer[9] = user_byte;
+        Ok(trailer)
+    }
+
+    /// Access bits of an access group (0..=3). In 4-block sectors the group is
+    /// the block's position in the sector; see [`crate::geometry::access_group`].
+    pub const fn bits(&self, group: u8) -> AccessBits {
+        match group {
+            0..=2 => self.blocks[group as usize],
+            _ => self.trailer,
+        }
+    }
+
+    /// Effective permissions for the data blocks of an access group, taking
+    /// into account that a readable Key B can't be used to authenticate.
+    pub const fn data_permissions(&self, group: u8) -> DataPermissions {
+        let perms = self.bits(group).data();
+        if !self.trailer.trailer().key_b_readable() {
+            return perms;
+        }
+        DataPermissions {
+            read: perms.read.without_key_b(),
+            write: perms.write.without_key_b(),
+            increment: perms.increment.without_key_b(),
+            decrement: perms.decrement.without_key_b(),
+        }
+    }
+
+    const fn validate(&self) -> Result<(), AccessError> {
+        if !self.trailer.trailer().key_b_readable() {
+            return Ok(());
+        }
+
+        let mut n = 0;
+        while n < 3 {
+            let perms = self.blocks[n].data();
+            if perms.read.key_b_only()
+                || perms.write.key_b_only()
+                || perms.increment.key_b_only()
+                || perms.decrement.key_b_only()
+            {
+                return Err(AccessError::KeyBUnusable { group: n as u8 });
+            }
+            n += 1;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Permission::*;
+
+    const DATA_MODES: [(AccessBits, DataPermissions); 8] = [
+        (
+            AccessBits::DATA_TRANSPORT,
+            data(KeyAOrB, KeyAOrB, KeyAOrB, KeyAOrB),
+        ),
+        (
+            AccessBits::DATA_READ_ONLY,
+            data(KeyAOrB, Never, Never, Never),
+        ),
+        (
+            AccessBits::DATA_WRITE_KEY_B,
+            data(KeyAOrB, KeyB, Never, Never),
+        ),
+        (
+            AccessBits::VALUE_RECHARGEABLE,
+            data(KeyAOrB, KeyB, KeyB, KeyAOrB),
+        ),
+        (
+            AccessBits::VALUE_DEBIT_ONLY,
+            data(KeyAOrB, Never, Never, KeyAOrB),
+        ),
+        (AccessBits::DATA_KEY_B, data(KeyB, KeyB, Never, Never)),
+        (AccessBits::DATA_READ_KEY_B, data(KeyB, Never, Never, Never)),
+        (AccessBits::DATA_LOCKED, data(Never, Never, Never, Never)),
+    ];
+
+    const TRAILER_MODES: [(AccessBits, TrailerPermissions); 8] = [
+        (
+            AccessBits::TRAILER_KEY_A,
+            trailer(KeyA, KeyA, Never, KeyA, KeyA),
+        ),
+        (
+            AccessBits::TRAILER_READ_ONLY,
+            trailer(Never, KeyA, Never, KeyA, Never),
+        ),
+        (
+            AccessBits::TRAILER_KEY_B_KEYS,
+            trailer(KeyB, KeyAOrB, Never, Never, KeyB),
+        ),
+        (
+            AccessBits::TRAILER_FROZEN,
+            trailer(Never, KeyAOrB, Never, Never, Never),
+        ),
+        (
+            AccessBits::TRAILER_TRANSPORT,
+            trailer(KeyA, KeyA, KeyA, KeyA, KeyA),
+        ),
+        (
+            AccessBits::TRAILER_KEY_B,
+            trailer(KeyB, KeyAOrB, KeyB, Never, KeyB),
+        ),
+        (
+            AccessBits::TRAILER_KEY_B_ACCESS,
+            trailer(Never, KeyAOrB, KeyB, Never, Never),
+        ),
+        (
+            AccessBits::new(true, true, false),
+            trailer(Never, KeyAOrB, Never, Never, Never),
+        ),
+    ];
+
+    const fn data(
+        read: Permission,
+        write: Permission,
+        increment: Permission,
+        decrement: Permission,
+    ) -> DataPermissions {
+        DataPermissions {
+            read,
+            write,
+            increment,
+            decrement,
+        }
+    }
+
+    const fn trailer(
+        key_a_write: Permission,
+        access_read: Permission,
+        access_write: Permission,
+        key_b_read: Permission,
+        key_b_write: Permission,
+    ) -> TrailerPermissions {
+        TrailerPermissions {
+            key_a_write,
+            access_read,
+            access_write,
+            key_b_read,
+            key_b_write,
+        }
+    }
+
+    #[test]
+    fn data_modes_round_trip() {
+        for (bits, perms) in DATA_MODES {
+            assert_eq!(bits.data(), perms, "{:?}", bits);
+            for group in 0..3 {
+                // A trailer that keeps Key B secret, so every mode is allowed
+                let mut access = AccessConditions {
+                    blocks: [AccessBits::DATA_TRANSPORT; 3],
+                    trailer: AccessBits::TRAILER_KEY_B,
+                };
+                access.blocks[group] = bits;
+                let bytes = access.encode().unwrap();
+                assert_eq!(AccessConditions::decode(&bytes), Ok(access));
+            }
+        }
+    }
+
+    #[test]
+    fn trailer_modes_round_trip() {
+        for (bits, perms) in TRAILER_MODES {
+            assert_eq!(bits.trailer(), perms, "{:?}", bits);
+            let access = AccessConditions {
+                blocks: [AccessBits::DATA_TRANSPORT; 3],
+                trailer: bits,
+            };
+            let bytes = access.encode().unwrap();
+            assert_eq!(AccessConditions::decode(&bytes), Ok(access));
+            assert_eq!(access.bits(3), bits);
+        }
+    }
+
+    #[test]
+    fn transport_vector() {
+        assert_eq!(AccessConditions::TRANSPORT.encode(), Ok([0xFF, 0x07, 0x80]));
+        assert_eq!(
+            AccessConditions::decode(&[0xFF, 0x07, 0x80]),
+            Ok(AccessConditions::TRANSPORT)
+        );
+
+        let trailer = AccessConditions::TRANSPORT
+            .to_trailer(&[0xFF; 6], &[0xFF; 6], 0x69)
+            .unwrap();
+        assert_eq!(
+            trailer,
+            [
+                0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x07, 0x80, 0x69, 0xFF, 0xFF, 0xFF, 0xFF,
+                0xFF, 0xFF
+            ]
+        );
+        assert_eq!(
+            AccessConditions::from_trailer(&trailer),
+            Ok(AccessConditions::TRANSPORT)
+        );
+    }
+
+    #[test]
+    fn corrupt_copy_is_rejected() {
+        assert_eq!(
+            AccessConditions::decode(&[0xFF, 0x07, 0x81]),
+            Err(AccessError::Redundancy)
+        );
+        assert_eq!(
+            AccessConditions::decode(&[0x00, 0x00, 0x00]),
+            Err(AccessError::Redundancy)
+        );
+    }
+
+    #[test]
+    fn key_b_only_block_needs_secret_key_b() {
+        let access = AccessConditions {
+            blocks: [
+                AccessBits::DATA_TRANSPORT,
+                AccessBits::DATA_KEY_B,
+                AccessBits::DATA_TRANSPORT,
+            ],
+            trailer: AccessBits::TRAILER_TRANSPORT,
+        };
+        assert_eq!(access.encode(), Err(AccessError::KeyBUnusable { group: 1 }));
+        // Decoding such a trailer still works; Key B just loses its rights
+        assert_eq!(access.data_permissions(1).read, Never);
+        assert_eq!(access.data_permissions(0).write, KeyA);
+    }
+}
@@ -0,0 +1,136 @@
+//! Converts and compares Classic card dumps on the host.
+//!
+//! ```text
+//! cargo run --bin dump-tool -- convert 13377331.MFD card.json
+//! cargo run --bin dump-tool -- diff before.mfd after.json
+//! cargo run --bin dump-tool -- show card.json
+//! ```
+//!
+//! Files ending in `.json` or `.jsn` are JSON, anything else is `.mfd`.
+
+use std::path::Path;
+use std::process::ExitCode;
+
+use mifare::dump::Dump;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let result = match args.as_slice() {
+        ["convert", input, output] => convert(input, output),
+        ["diff", a, b] => diff(a, b),
+        ["show", input] => show(input),
+        _ => {
+            eprintln!("usage: dump-tool convert <in> <out>");
+            eprintln!("       dump-tool diff <a> <b>");
+            eprintln!("       dump-tool show <file>");
+            return ExitCode::FAILURE;
+        }
+    };
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn is_json(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("json") || e.eq_ignore_ascii_case("jsn"))
+}
+
+fn load(path: &str) -> Result<Box<Dump>, String> {
+    let data = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+    let dump = if is_json(path) {
+        let text = std::str::from_utf8(&data).map_err(|e| format!("{}: {}", path, e))?;
+        Dump::from_json(text)
+    } else {
+        Dump::from_mfd(&data)
+    };
+    dump.map(Box::new)
+        .map_err(|e| format!("{}: {}", path, e.as_str()))
+}
+
+fn convert(input: &str, output: &str) -> Result<ExitCode, String> {
+    let dump = load(input)?;
+    let data = if is_json(output) {
+        let mut text = String::new();
+        dump.write_json(&mut text).unwrap();
+        text.into_bytes()
+    } else {
+        dump.mfd().to_vec()
+    };
+    std::fs::write(output, data).map_err(|e| format!("{}: {}", output, e))?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Prints the blocks that differ, with a caret under every changed byte.
+/// Exits with 1 if there are any, like `diff`.
+fn diff(a: &str, b: &str) -> Result<ExitCode, String> {
+    let (left, right) = (load(a)?, load(b)?);
+    if left.card != right.card {
+        println!("card: {} / {}", left.card.name(), right.card.name());
+    }
+    if left.uid() != right.uid() {
+        println!("UID: {} / {}", hex(left.uid()), hex(right.uid()));
+    }
+
+    let mut blocks = 0;
+    for block in left.diff(&right) {
+        blocks += 1;
+        let (l, r) = (left.block(block), right.block(block));
+        println!("block {:3} - {}", block, l.map_or("unread".into(), |d| hex(d)));
+        println!("          + {}", r.map_or("unread".into(), |d| hex(d)));
+        if let (Some(l), Some(r)) = (l, r) {
+            let marks: Vec<&str> = l
+                .iter()
+                .zip(r)
+                .map(|(x, y)| if x == y { "  " } else { "^^" })
+                .collect();
+            println!("            {}", marks.join(" ").trim_end());
+        }
+    }
+
+    if blocks == 0 && left.card == right.card && left.uid() == right.uid() {
+        println!("identical");
+        Ok(ExitCode::SUCCESS)
+    } else {
+        println!("{} blocks differ", blocks);
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+fn show(input: &str) -> Result<ExitCode, String> {
+    let dump = load(input)?;
+    println!(
+        "{}, UID {}, ATQA {}, SAK {:02x}",
+        dump.card.name(),
+        hex(dump.uid()),
+        hex(&dump.atqa),
+        dump.sak
+    );
+    for sector in 0..dump.card.sector_count() {
+        println!("-- sector {}", sector);
+        let first = dump.card.first_block(sector).unwrap_or_default();
+        let blocks = dump.card.blocks_in_sector(sector).unwrap_or_default();
+        // Relative blocks: the last sector of a 4K ends at block 255
+        for rel_block in 0..blocks {
+            let block = first + rel_block;
+            let data = dump.block(block).map_or("unread".into(), |d| hex(d));
+            println!("{:3}  {}", block, data);
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,71 @@
+//! Telling the supported tag families apart.
+
+use embedded_hal::spi::SpiDevice;
+use mfrc522::{Error, Uid};
+
+use crate::error::RfidError;
+use crate::geometry::CardType;
+use crate::pcd::Pcd;
+use crate::ultralight::{self, TagType};
+
+/// SAK sent by Ultralight and NTAG tags, which have neither Crypto1 nor
+/// ISO 14443-4 support.
+const SAK_ULTRALIGHT: u8 = 0x00;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Card {
+    /// Sector based, Crypto1 protected.
+    Classic(CardType),
+    /// Page based, no authentication.
+    Ultralight(TagType),
+}
+
+impl Card {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Card::Classic(card) => card.name(),
+            Card::Ultralight(tag) => tag.name(),
+        }
+    }
+}
+
+/// Re-selects the card and works out what it is from its SAK, ATQA and, for
+/// page based tags, GET_VERSION. Returns `None` for cards we don't support.
+///
+/// The card is left selected.
+pub fn identify<E, SPI: SpiDevice<Error = E>>(
+    uid: &Uid,
+    pcd: &mut Pcd<SPI>,
+) -> Result<Option<Card>, Error<E>> {
+    let activation = pcd.activate(uid)?;
+    if let Some(card) = activation.card_type() {
+        return Ok(Some(Card::Classic(card)));
+    }
+    if activation.sak == SAK_ULTRALIGHT {
+        let tag = ultralight::identify(uid, pcd)?;
+        return Ok(Some(Card::Ultralight(tag)));
+    }
+    Ok(None)
+}
+
+/// [`identify`], with an unsupported card as an error like any other.
+pub fn detect<E, SPI: SpiDevice<Error = E>>(
+    uid: &Uid,
+    pcd: &mut Pcd<SPI>,
+) -> Result<Card, RfidError<E>> {
+    identify(uid, pcd)
+        .map_err(RfidError::Activation)?
+        .ok_or(RfidError::UnsupportedCard)
+}
+
+/// Like [`detect`] for tools that only handle Classic cards. Skips
+/// GET_VERSION, so page based tags come back as unsupported.
+pub fn detect_classic<E, SPI: SpiDevice<Error = E>>(
+    uid: &Uid,
+    pcd: &mut Pcd<SPI>,
+) -> Result<CardType, RfidError<E>> {
+    pcd.activate(uid)
+        .map_err(RfidError::Activation)?
+        .card_type()
+        .ok_or(RfidError::UnsupportedCard)
+}
@@ -0,0 +1,234 @@
+//! Block access and NDEF storage on MIFARE Classic cards.
+//!
+//! An NDEF formatted Classic card has a MAD in sector 0 (and sector 16 on a
+//! 4K) that assigns sectors to the NDEF application. The message TLV runs
+//! through the data blocks of those sectors in order, skipping the trailers.
+
+use embedded_hal::spi::SpiDevice;
+use mfrc522::{Error, Uid};
+
+use crate::access::{AccessConditions, KeyType};
+use crate::geometry::{self, CardType};
+use crate::keys::{FoundKey, MAD_KEY, NDEF_KEY};
+use crate::mad::{self, Mad, MAD2_SECTOR, MAD_SECTOR, NDEF_AID};
+use crate::ndef::{self, NdefError, TagError};
+use crate::pcd::Pcd;
+
+// PICC commands
+const PICC_READ: u8 = 0x30;
+const PICC_WRITE: u8 = 0xA0;
+const ACK: u8 = 0x0A;
+
+pub const BLOCK_SIZE: usize = 16;
+
+/// Reads a block of a sector that is already authenticated.
+pub fn read_block<E, SPI: SpiDevice<Error = E>>(
+    block: u8,
+    pcd: &mut Pcd<SPI>,
+) -> Result<[u8; BLOCK_SIZE], Error<E>> {
+    let mut data = [0u8; BLOCK_SIZE];
+    match pcd.transceive_crc(&[PICC_READ, block], &mut data)? {
+        BLOCK_SIZE => Ok(data),
+        1 => Err(Error::Nak),
+        _ => Err(Error::IncompleteFrame),
+    }
+}
+
+/// Writes a block of a sector that is already authenticated. The card
+/// acknowledges the command and the data separately.
+pub fn write_block<E, SPI: SpiDevice<Error = E>>(
+    block: u8,
+    data: &[u8; BLOCK_SIZE],
+    pcd: &mut Pcd<SPI>,
+) -> Result<(), Error<E>> {
+    let mut ack = [0u8; 1];
+    for frame in [&[PICC_WRITE, block][..], &data[..]] {
+        let n = pcd.transceive_crc(frame, &mut ack)?;
+        if n != 1 || ack[0] & 0x0F != ACK {
+            return Err(Error::Nak);
+        }
+    }
+    Ok(())
+}
+
+/// Wakes the card up again and authenticates the sector that `block`
+/// belongs to.
+fn open_sector<E, SPI: SpiDevice<Error = E>>(
+    uid: &Uid,
+    block: u8,
+    key_type: KeyType,
+    key: &[u8; 6],
+    pcd: &mut Pcd<SPI>,
+) -> Result<(), Error<E>> {
+    pcd.activate(uid)?;
+    pcd.authenticate(uid, block, key_type, key)
+}
+
+/// Reads the MAD. Fails with `NotFormatted` if sector 0 doesn't open with
+/// the public MAD key or doesn't announce a MAD.
+pub fn read_mad<E, SPI: SpiDevice<Error = E>>(
+    uid: &Uid,
+    card: CardType,
+    pcd: &mut Pcd<SPI>,
+) -> Result<Mad, TagError<E>> {
+    open_sector(uid, 0, KeyType::A, &MAD_KEY, pcd).map_err(|_| NdefError::NotFormatted)?;
+    let mut data = [0u8; mad::MAD1_SIZE];
+    data[..BLOCK_SIZE].copy_from_slice(&read_block(1, pcd)?);
+    data[BLOCK_SIZE..].copy_from_slice(&read_block(2, pcd)?);
+    let trailer = read_block(3, pcd)?;
+
+    let version = mad::version(trailer[9]).ok_or(NdefError::NotFormatted)?;
+    let mut directory = Mad::parse_v1(&data)?;
+
+    if version >= 2 && card == CardType::Classic4K {
+        let first = card.first_block(MAD2_SECTOR).unwrap_or_default();
+        open_sector(uid, first, KeyType::A, &MAD_KEY, pcd).map_err(|_| NdefError::NotFormatted)?;
+        let mut data = [0u8; mad::MAD2_SIZE];
+        for (i, chunk) in data.chunks_mut(BLOCK_SIZE).enumerate() {
+            chunk.copy_from_slice(&read_block(first + i as u8, pcd)?);
+        }
+        directory.parse_v2(&data)?;
+    }
+    Ok(directory)
+}
+
+/// Reads the NDEF message into `buf` and returns it.
+pub fn read_ndef<'b, E, SPI: SpiDevice<Error = E>>(
+    uid: &Uid,
+    card: CardType,
+    pcd: &mut Pcd<SPI>,
+    buf: &'b mut [u8],
+) -> Result<&'b [u8], TagError<E>> {
+    let directory = read_mad(uid, card, pcd)?;
+
+    let mut len = 0;
+    for sector in directory.sectors(NDEF_AID) {
+        let first = card.first_block(sector).unwrap_or_default();
+        let blocks = card.blocks_in_sector(sector).unwrap_or_default();
+        open_sector(uid, first, KeyType::A, &NDEF_KEY, pcd)?;
+
+        for rel_block in 0..blocks - 1 {
+            if len + BLOCK_SIZE > buf.len() {
+                return Err(NdefError::NoRoom.into());
+            }
+            let data = read_block(first + rel_block, pcd)?;
+            buf[len..len + BLOCK_SIZE].copy_from_slice(&data);
+            len += BLOCK_SIZE;
+
+            // Stop as soon as the whole message is in
+            match ndef::message_range(&buf[..len]) {
+                Ok(range) => return Ok(&buf[range]),
+                Err(NdefError::Incomplete) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+    Err(NdefError::NoMessage.into())
+}
+
+/// Writes `message` as the NDEF message of a formatted card.
+pub fn write_ndef<E, SPI: SpiDevice<Error = E>>(
+    uid: &Uid,
+    card: CardType,
+    message: &[u8],
+    pcd: &mut Pcd<SPI>,
+) -> Result<(), TagError<E>> {
+    let directory = read_mad(uid, card, pcd)?;
+
+    let capacity: usize = directory
+        .sectors(NDEF_AID)
+        .map(|s| (card.blocks_in_sector(s).unwrap_or_default() as usize - 1) * BLOCK_SIZE)
+        .sum();
+    if ndef::tlv_size(message.len())? > capacity {
+        return Err(NdefError::NoRoom.into());
+    }
+
+    let mut bytes = ndef::tlv_bytes(message)?.peekable();
+    for sector in directory.sectors(NDEF_AID) {
+        let first = card.first_block(sector).unwrap_or_default();
+        let blocks = card.blocks_in_sector(sector).unwrap_or_default();
+        open_sector(uid, first, KeyType::A, &NDEF_KEY, pcd)?;
+
+        // A read-only NDEF sector doesn't let Key A write its data blocks
+        let trailer = read_block(first + (blocks - 1), pcd)?;
+        let access = AccessConditions::from_trailer(&trailer).map_err(|_| NdefError::Malformed)?;
+
+        for rel_block in 0..blocks - 1 {
+            let group = geometry::access_group(sector, rel_block);
+            if !access.data_permissions(group).write.allows(KeyType::A) {
+                return Err(NdefError::ReadOnly.into());
+            }
+
+            let mut data = [0u8; BLOCK_SIZE];
+            for (byte, value) in data.iter_mut().zip(&mut bytes) {
+                *byte = value;
+            }
+            write_block(first + rel_block, &data, pcd)?;
+
+            if bytes.peek().is_none() {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Formats the card for NDEF: writes a MAD giving every sector to NDEF, an
+/// empty message, and trailers with the public MAD and NDEF keys.
+///
+/// `auth` must open every sector, as a fresh card's transport key does. It
+/// becomes Key B of every sector, so the same key can reformat the card as
+/// Key B later. A failure half way leaves the card partly formatted.
+pub fn format_ndef<E, SPI: SpiDevice<Error = E>>(
+    uid: &Uid,
+    card: CardType,
+    auth: &FoundKey,
+    pcd: &mut Pcd<SPI>,
+) -> Result<(), TagError<E>> {
+    let directory = Mad::ndef(card);
+    let gpb = if card == CardType::Classic4K {
+        mad::GPB_MAD2
+    } else {
+        mad::GPB_MAD1
+    };
+    let mad_trailer = mad::MAD_ACCESS
+        .to_trailer(&MAD_KEY, &auth.key, gpb)
+        .map_err(|_| NdefError::Malformed)?;
+    let ndef_trailer = mad::NDEF_ACCESS
+        .to_trailer(&NDEF_KEY, &auth.key, mad::GPB_NDEF)
+        .map_err(|_| NdefError::Malformed)?;
+
+    // An empty NDEF message, so readers don't find leftovers
+    let mut empty = [0u8; BLOCK_SIZE];
+    empty[..3].copy_from_slice(&[ndef::TLV_NDEF, 0x00, ndef::TLV_TERMINATOR]);
+
+    for sector in 0..card.sector_count() {
+        let first = card.first_block(sector).unwrap_or_default();
+        let trailer_block = card.trailer_block(sector).unwrap_or_default();
+        open_sector(uid, first, auth.key_type, &auth.key, pcd)?;
+
+        match sector {
+            MAD_SECTOR => {
+                let data = directory.encode_v1();
+                write_block(1, data[..BLOCK_SIZE].try_into().unwrap(), pcd)?;
+                write_block(2, data[BLOCK_SIZE..].try_into().unwrap(), pcd)?;
+                write_block(trailer_block, &mad_trailer, pcd)?;
+            }
+            MAD2_SECTOR => {
+                let data = directory.encode_v2();
+                for (i, chunk) in data.chunks(BLOCK_SIZE).enumerate() {
+                    write_block(first + i as u8, chunk.try_into().unwrap(), pcd)?;
+                }
+                write_block(trailer_block, &mad_trailer, pcd)?;
+            }
+            _ => {
+                if sector == 1 {
+                    write_block(first, &empty, pcd)?;
+                }
+                write_block(trailer_block, &ndef_trailer, pcd)?;
+            }
+        }
+    }
+    pcd.activate(uid)?;
+    Ok(())
+}
@@ -0,0 +1,364 @@
+//! Checking the reader rather than the card: which chip is fitted, whether its
+//! digital self-test passes, how much receiver gain it uses, and how reliably
+//! it reads a card held in the field.
+//!
+//! A chip that isn't there reads back 0x00 or 0xFF from every register, so
+//! [`version`] is a safe first call before starting the driver, and [`probe`]
+//! makes it the boot check.
+
+use embedded_hal::spi::SpiDevice;
+use mfrc522::comm::Interface;
+use mfrc522::{Error, Initialized, Mfrc522, Uninitialized};
+
+use crate::pcd::{Pcd, Register, Selected};
+
+// PCD commands (CommandReg)
+const CMD_IDLE: u8 = 0x00;
+const CMD_MEM: u8 = 0x01;
+const CMD_CALC_CRC: u8 = 0x03;
+const CMD_SOFT_RESET: u8 = 0x0F;
+
+/// CommandReg bit set while the chip is powered down or still resetting.
+const COMMAND_POWER_DOWN: u8 = 0x10;
+/// FIFOLevelReg bit that empties the FIFO.
+const FIFO_FLUSH: u8 = 0x80;
+/// AutoTestReg value that turns CalcCRC into the digital self-test.
+const AUTO_TEST_SELF_TEST: u8 = 0x09;
+/// RxGain field of RFCfgReg.
+const RF_CFG_RX_GAIN: u8 = 0x70;
+
+/// Bytes the Mem command moves into the internal buffer.
+const INTERNAL_BUFFER: usize = 25;
+/// Bytes the self-test leaves in the FIFO.
+const SELF_TEST_SIZE: usize = 64;
+
+/// How many register reads to wait for a reset or the self-test to finish.
+const MAX_POLLS: u16 = 2000;
+
+/// Registers the driver sets up in `init` that a soft reset clears. TxControlReg
+/// comes last so the antenna only comes back on once everything else is set.
+const CONFIG: [Register; 13] = [
+    Register::ComIEnReg,
+    Register::DivIEnReg,
+    Register::ModeReg,
+    Register::TxModeReg,
+    Register::RxModeReg,
+    Register::TxASKReg,
+    Register::ModWidthReg,
+    Register::RFCfgReg,
+    Register::TModeReg,
+    Register::TPrescalerReg,
+    Register::TReloadRegHigh,
+    Register::TReloadRegLow,
+    Register::TxControlReg,
+];
+
+// Self-test results from the datasheet (MFRC522 section 16.1.1) and, for the
+// FM17522, from the widely used Arduino library.
+const SELF_TEST_V0_0: [u8; SELF_TEST_SIZE] = [
+    0x00, 0x87, 0x98, 0x0F, 0x49, 0xFF, 0x07, 0x19, 0xBF, 0x22, 0x30, 0x49, 0x59, 0x63, 0xAD, 0xCA,
+    0x7F, 0xE3, 0x4E, 0x03, 0x5C, 0x4E, 0x49, 0x50, 0x47, 0x9A, 0x37, 0x61, 0xE7, 0xE2, 0xC6, 0x2E,
+    0x75, 0x5A, 0xED, 0x04, 0x3D, 0x02, 0x4B, 0x78, 0x32, 0xFF, 0x58, 0x3B, 0x7C, 0xE9, 0x00, 0x94,
+    0xB4, 0x4A, 0x59, 0x5B, 0xFD, 0xC9, 0x29, 0xDF, 0x35, 0x96, 0x98, 0x9E, 0x4F, 0x30, 0x32, 0x8D,
+];
+const SELF_TEST_V1_0: [u8; SELF_TEST_SIZE] = [
+    0x00, 0xC6, 0x37, 0xD5, 0x32, 0xB7, 0x57, 0x5C, 0xC2, 0xD8, 0x7C, 0x4D, 0xD9, 0x70, 0xC7, 0x73,
+    0x10, 0xE6, 0xD2, 0xAA, 0x5E, 0xA1, 0x3E, 0x5A, 0x14, 0xAF, 0x30, 0x61, 0xC9, 0x70, 0xDB, 0x2E,
+    0x64, 0x22, 0x72, 0xB5, 0xBD, 0x65, 0xF4, 0xEC, 0x22, 0xBC, 0xD3, 0x72, 0x35, 0xCD, 0xAA, 0x41,
+    0x1F, 0xA7, 0xF3, 0x53, 0x14, 0xDE, 0x7E, 0x02, 0xD9, 0x0F, 0xB5, 0x5E, 0x25, 0x1D, 0x29, 0x79,
+];
+const SELF_TEST_V2_0: [u8; SELF_TEST_SIZE] = [
+    0x00, 0xEB, 0x66, 0xBA, 0x57, 0xBF, 0x23, 0x95, 0xD0, 0xE3, 0x0D, 0x3D, 0x27, 0x89, 0x5C, 0xDE,
+    0x9D, 0x3B, 0xA7, 0x00, 0x21, 0x5B, 0x89, 0x82, 0x51, 0x3A, 0xEB, 0x02, 0x0C, 0xA5, 0x00, 0x49,
+    0x7C, 0x84, 0x4D, 0xB3, 0xCC, 0xD2, 0x1B, 0x81, 0x5D, 0x48, 0x76, 0xD5, 0x71, 0x61, 0x21, 0xA9,
+    0x86, 0x96, 0x83, 0x38, 0xCF, 0x9D, 0x5B, 0x6D, 0xDC, 0x15, 0xBA, 0x3E, 0x7D, 0x95, 0x3B, 0x2F,
+];
+const SELF_TEST_FM17522: [u8; SELF_TEST_SIZE] = [
+    0x00, 0xD6, 0x78, 0x8C, 0xE2, 0xAA, 0x0C, 0x18, 0x2A, 0xB8, 0x7A, 0x7F, 0xD3, 0x6A, 0xCF, 0x0B,
+    0xB1, 0x37, 0x63, 0x4B, 0x69, 0xAE, 0x91, 0xC7, 0xC3, 0x97, 0xAE, 0x77, 0xF4, 0x37, 0xD7, 0x9B,
+    0x7C, 0xF5, 0x3C, 0x11, 0x8F, 0x15, 0xC3, 0xD7, 0xC1, 0x5B, 0x00, 0x2A, 0xD0, 0x75, 0xDE, 0x9E,
+    0x51, 0x64, 0xAB, 0x3E, 0xE9, 0x15, 0xB5, 0xAB, 0x56, 0x9A, 0x98, 0x82, 0x26, 0xEA, 0x2A, 0x62,
+];
+
+/// The contents of VersionReg.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChipVersion(pub u8);
+
+impl ChipVersion {
+    /// Whether anything answered. A missing module or a broken MISO line reads
+    /// as all zeros or all ones.
+    pub const fn is_present(self) -> bool {
+        !matches!(self.0, 0x00 | 0xFF)
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self.0 {
+            0x88 => "FM17522",
+            0x90 => "MFRC522 v0.0",
+            0x91 => "MFRC522 v1.0",
+            0x92 => "MFRC522 v2.0",
+            0x00 | 0xFF => "no chip",
+            _ => "unknown chip",
+        }
+    }
+
+    /// What the self-test should produce on this chip, if we know.
+    const fn self_test(self) -> Option<&'static [u8; SELF_TEST_SIZE]> {
+        match self.0 {
+            0x88 => Some(&SELF_TEST_FM17522),
+            0x90 => Some(&SELF_TEST_V0_0),
+            0x91 => Some(&SELF_TEST_V1_0),
+            0x92 => Some(&SELF_TEST_V2_0),
+            _ => None,
+        }
+    }
+}
+
+/// Outcome of [`self_test`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SelfTest {
+    Passed,
+    /// The output differed from the reference, first at this byte.
+    Failed {
+        offset: u8,
+    },
+    /// The test never finished.
+    NoResult,
+    /// There's no reference output for this chip version.
+    Unknown,
+}
+
+impl SelfTest {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            SelfTest::Passed => "passed",
+            SelfTest::Failed { .. } => "FAILED",
+            SelfTest::NoResult => "no result",
+            SelfTest::Unknown => "no reference for this chip",
+        }
+    }
+}
+
+/// Receiver gain, the RxGain field of RFCfgReg. Two of the eight codes repeat
+/// 18 and 23 dB; reading them back gives the canonical code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gain {
+    Db18,
+    Db23,
+    Db33,
+    Db38,
+    Db43,
+    Db48,
+}
+
+impl Gain {
+    pub const ALL: [Gain; 6] = [
+        Gain::Db18,
+        Gain::Db23,
+        Gain::Db33,
+        Gain::Db38,
+        Gain::Db43,
+        Gain::Db48,
+    ];
+
+    pub const fn db(self) -> u8 {
+        match self {
+            Gain::Db18 => 18,
+            Gain::Db23 => 23,
+            Gain::Db33 => 33,
+            Gain::Db38 => 38,
+            Gain::Db43 => 43,
+            Gain::Db48 => 48,
+        }
+    }
+
+    pub fn from_db(db: u8) -> Option<Gain> {
+        Gain::ALL.into_iter().find(|gain| gain.db() == db)
+    }
+
+    const fn bits(self) -> u8 {
+        match self {
+            Gain::Db18 => 0b000,
+            Gain::Db23 => 0b001,
+            Gain::Db33 => 0b100,
+            Gain::Db38 => 0b101,
+            Gain::Db43 => 0b110,
+            Gain::Db48 => 0b111,
+        }
+    }
+
+    const fn from_bits(bits: u8) -> Gain {
+        match bits & 0b111 {
+            0b000 | 0b010 => Gain::Db18,
+            0b001 | 0b011 => Gain::Db23,
+            0b100 => Gain::Db33,
+            0b101 => Gain::Db38,
+            0b110 => Gain::Db43,
+            _ => Gain::Db48,
+        }
+    }
+}
+
+/// Tally of [`poll`]. With the card held still, `errors` points at the
+/// antenna or the gain and `no_answer` at the distance; a card that fails on
+/// one reader and not another is the card.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PollStats {
+    pub polls: u16,
+    /// Full UID read and matching the first one.
+    pub ok: u16,
+    /// Nothing answered the wake-up.
+    pub no_answer: u16,
+    /// Something answered but the frame was damaged: CRC, parity, collision
+    /// or a timeout halfway through anticollision.
+    pub errors: u16,
+    /// A clean read of a UID other than the first one.
+    pub wrong_uid: u16,
+}
+
+impl PollStats {
+    /// Successful reads, in whole percent.
+    pub fn success_rate(&self) -> u8 {
+        if self.polls == 0 {
+            return 0;
+        }
+        (u32::from(self.ok) * 100 / u32::from(self.polls)) as u8
+    }
+}
+
+/// Reads VersionReg.
+pub fn version<E, SPI: SpiDevice<Error = E>>(pcd: &mut Pcd<SPI>) -> Result<ChipVersion, Error<E>> {
+    pcd.read(Register::VersionReg).map(ChipVersion)
+}
+
+/// Looks for the chip before starting `driver`, so firmware can say what's
+/// wrong instead of hanging in `init` or panicking. The error is the message
+/// to show.
+pub fn probe<E, SPI: SpiDevice<Error = E>, COMM: Interface>(
+    pcd: &mut Pcd<SPI>,
+    driver: Mfrc522<COMM, Uninitialized>,
+) -> Result<Mfrc522<COMM, Initialized>, &'static str> {
+    match version(pcd) {
+        Ok(version) if version.is_present() => {
+            driver.init().map_err(|_| "Reader failed to initialise")
+        }
+        Ok(_) => Err("No reader found, check the wiring"),
+        Err(_) => Err("SPI error talking to the reader"),
+    }
+}
+
+/// Runs the digital self-test and compares its output with the reference for
+/// the chip version.
+///
+/// The test needs a soft reset, so the configuration registers are saved first
+/// and written back afterwards; the driver can carry on as before.
+pub fn self_test<E, SPI: SpiDevice<Error = E>>(pcd: &mut Pcd<SPI>) -> Result<SelfTest, Error<E>> {
+    let version = version(pcd)?;
+    let mut config = [0u8; CONFIG.len()];
+    for (value, &reg) in config.iter_mut().zip(CONFIG.iter()) {
+        *value = pcd.read(reg)?;
+    }
+
+    soft_reset(pcd)?;
+    pcd.write(Register::FIFOLevelReg, FIFO_FLUSH)?;
+    pcd.write_fifo(&[0; INTERNAL_BUFFER])?;
+    pcd.write(Register::CommandReg, CMD_MEM)?;
+    pcd.write(Register::AutoTestReg, AUTO_TEST_SELF_TEST)?;
+    pcd.write_fifo(&[0])?;
+    pcd.write(Register::CommandReg, CMD_CALC_CRC)?;
+
+    let mut finished = false;
+    for _ in 0..MAX_POLLS {
+        if pcd.read(Register::FIFOLevelReg)? as usize >= SELF_TEST_SIZE {
+            finished = true;
+            break;
+        }
+    }
+    pcd.write(Register::CommandReg, CMD_IDLE)?;
+    let mut output = [0u8; SELF_TEST_SIZE];
+    if finished {
+        pcd.read_fifo(&mut output)?;
+    }
+    pcd.write(Register::AutoTestReg, 0)?;
+
+    soft_reset(pcd)?;
+    for (&value, &reg) in config.iter().zip(CONFIG.iter()) {
+        pcd.write(reg, value)?;
+    }
+
+    let result = match version.self_test() {
+        _ if !finished => SelfTest::NoResult,
+        None => SelfTest::Unknown,
+        Some(expected) => match output.iter().zip(expected.iter()).position(|(a, b)| a != b) {
+            None => SelfTest::Passed,
+            Some(offset) => SelfTest::Failed {
+                offset: offset as u8,
+            },
+        },
+    };
+    Ok(result)
+}
+
+/// Reads the receiver gain.
+pub fn gain<E, SPI: SpiDevice<Error = E>>(pcd: &mut Pcd<SPI>) -> Result<Gain, Error<E>> {
+    let cfg = pcd.read(Register::RFCfgReg)?;
+    Ok(Gain::from_bits((cfg & RF_CFG_RX_GAIN) >> 4))
+}
+
+/// Sets the receiver gain, leaving the rest of RFCfgReg alone.
+pub fn set_gain<E, SPI: SpiDevice<Error = E>>(
+    pcd: &mut Pcd<SPI>,
+    gain: Gain,
+) -> Result<(), Error<E>> {
+    let cfg = pcd.read(Register::RFCfgReg)?;
+    pcd.write(
+        Register::RFCfgReg,
+        (cfg & !RF_CFG_RX_GAIN) | (gain.bits() << 4),
+    )
+}
+
+/// Wakes, selects and halts the card in the field `polls` times and counts
+/// how each attempt went. Only bus errors stop the run.
+pub fn poll<E, SPI: SpiDevice<Error = E>>(
+    pcd: &mut Pcd<SPI>,
+    polls: u16,
+) -> Result<PollStats, Error<E>> {
+    let mut stats = PollStats::default();
+    let mut first: Option<Selected> = None;
+    for _ in 0..polls {
+        stats.polls += 1;
+        match pcd.request(true) {
+            Ok(_) => match pcd.anticollision() {
+                Ok(selected) => match first {
+                    Some(first) if first.uid() != selected.uid() => stats.wrong_uid += 1,
+                    Some(_) => stats.ok += 1,
+                    None => {
+                        first = Some(selected);
+                        stats.ok += 1;
+                    }
+                },
+                Err(Error::Comm(e)) => return Err(Error::Comm(e)),
+                Err(_) => stats.errors += 1,
+            },
+            Err(Error::Timeout) => stats.no_answer += 1,
+            Err(Error::Comm(e)) => return Err(Error::Comm(e)),
+            Err(_) => stats.errors += 1,
+        }
+        // Puts an active card to sleep and sends a half-selected one back
+        // to idle, so the next wake-up starts from scratch either way
+        if let Err(Error::Comm(e)) = pcd.halt() {
+            return Err(Error::Comm(e));
+        }
+    }
+    Ok(stats)
+}
+
+fn soft_reset<E, SPI: SpiDevice<Error = E>>(pcd: &mut Pcd<SPI>) -> Result<(), Error<E>> {
+    pcd.write(Register::CommandReg, CMD_SOFT_RESET)?;
+    for _ in 0..MAX_POLLS {
+        if pcd.read(Register::CommandReg)? & COMMAND_POWER_DOWN == 0 {
+            return Ok(());
+        }
+    }
+    Err(Error::Timeout)
+}
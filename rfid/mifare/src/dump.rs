@@ -0,0 +1,448 @@
+//! Whole-card dumps of Classic cards, as `.mfd` files and as JSON.
+//!
+//! An `.mfd` file is the card's blocks back to back, 1024 bytes for a 1K. Key
+//! A never reads back, so the keys that opened a sector are filled into its
+//! trailer, as other tools do. Blocks that couldn't be read are zeros.
+//!
+//! The JSON form follows the layout of Proxmark3 dumps, with unread blocks
+//! left out:
+//!
+//! ```text
+//! {
+//!   "Created": "pico-pico",
+//!   "FileType": "mfcard",
+//!   "Card": { "UID": "13377331", "ATQA": "0400", "SAK": "08" },
+//!   "blocks": {
+//!     "0": "13377331660804006263646566676869",
+//!     ...
+//!   }
+//! }
+//! ```
+
+use core::fmt;
+
+use embedded_hal::spi::SpiDevice;
+use mfrc522::Uid;
+
+use crate::access::{AccessConditions, KeyType};
+use crate::classic::{self, BLOCK_SIZE};
+use crate::error::RfidError;
+use crate::geometry::{self, CardType};
+use crate::keys::{self, FoundKey};
+use crate::magic;
+use crate::pcd::{Activation, Pcd};
+
+/// Blocks of the largest card, a 4K.
+pub const MAX_BLOCKS: usize = 256;
+
+const MAX_UID_LEN: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpError {
+    /// The size doesn't match any Classic card.
+    Size,
+    Malformed,
+}
+
+impl DumpError {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            DumpError::Size => "Dump size doesn't match a Classic card",
+            DumpError::Malformed => "Malformed dump",
+        }
+    }
+}
+
+pub struct Dump {
+    pub card: CardType,
+    uid: [u8; MAX_UID_LEN],
+    uid_len: u8,
+    pub atqa: [u8; 2],
+    pub sak: u8,
+    blocks: [[u8; BLOCK_SIZE]; MAX_BLOCKS],
+    read: [bool; MAX_BLOCKS],
+}
+
+impl Dump {
+    /// An empty dump of a card, to be filled with [`Dump::set_block`].
+    pub fn new(card: CardType, uid: &[u8], activation: &Activation) -> Self {
+        let mut dump = Self {
+            card,
+            uid: [0; MAX_UID_LEN],
+            uid_len: 0,
+            atqa: activation.atqa,
+            sak: activation.sak,
+            blocks: [[0; BLOCK_SIZE]; MAX_BLOCKS],
+            read: [false; MAX_BLOCKS],
+        };
+        dump.set_uid(uid);
+        dump
+    }
+
+    pub fn uid(&self) -> &[u8] {
+        &self.uid[..self.uid_len as usize]
+    }
+
+    fn set_uid(&mut self, uid: &[u8]) {
+        let len = uid.len().min(MAX_UID_LEN);
+        self.uid[..len].copy_from_slice(&uid[..len]);
+        self.uid_len = len as u8;
+    }
+
+    pub fn block_count(&self) -> usize {
+        self.card.block_count() as usize
+    }
+
+    /// The contents of `block`, or `None` if it wasn't read.
+    pub fn block(&self, block: u8) -> Option<&[u8; BLOCK_SIZE]> {
+        let i = block as usize;
+        (i < self.block_count() && self.read[i]).then(|| &self.blocks[i])
+    }
+
+    pub fn set_block(&mut self, block: u8, data: &[u8; BLOCK_SIZE]) {
+        let i = block as usize;
+        if i < self.block_count() {
+            self.blocks[i] = *data;
+            self.read[i] = true;
+        }
+    }
+
+    /// Fills the key that opened `sector` into its trailer.
+    pub fn set_key(&mut self, sector: u8, found: &FoundKey) {
+        let Some(trailer) = self.card.trailer_block(sector) else {
+            return;
+        };
+        let trailer = &mut self.blocks[trailer as usize];
+        match found.key_type {
+            KeyType::A => trailer[..6].copy_from_slice(&found.key),
+            KeyType::B => trailer[10..].copy_from_slice(&found.key),
+        }
+    }
+
+    /// The keys stored in the trailer of `sector`, Key A first.
+    pub fn keys(&self, sector: u8) -> Option<[[u8; 6]; 2]> {
+        let trailer = self.block(self.card.trailer_block(sector)?)?;
+        Some([
+            trailer[..6].try_into().unwrap(),
+            trailer[10..].try_into().unwrap(),
+        ])
+    }
+
+    /// The dump in `.mfd` layout.
+    pub fn mfd(&self) -> &[u8] {
+        &self.blocks.as_flattened()[..self.block_count() * BLOCK_SIZE]
+    }
+
+    /// Reads an `.mfd` file. The UID, ATQA and SAK come from block 0 as NXP
+    /// lays it out.
+    pub fn from_mfd(data: &[u8]) -> Result<Self, DumpError> {
+        let card = match data.len() {
+            320 => CardType::ClassicMini,
+            1024 => CardType::Classic1K,
+            4096 => CardType::Classic4K,
+            _ => return Err(DumpError::Size),
+        };
+        let mut dump = Self::new(
+            card,
+            &[],
+            &Activation {
+                atqa: [0; 2],
+                sak: 0,
+            },
+        );
+        for (i, chunk) in data.chunks_exact(BLOCK_SIZE).enumerate() {
+            dump.set_block(i as u8, chunk.try_into().unwrap());
+        }
+
+        let b0 = dump.blocks[0];
+        // A 4-byte UID is followed by its BCC; a 7-byte one isn't
+        if b0[..4].iter().fold(0, |acc, b| acc ^ b) == b0[4] {
+            dump.set_uid(&b0[..4]);
+            dump.sak = b0[5];
+            dump.atqa = [b0[6], b0[7]];
+        } else {
+            dump.set_uid(&b0[..7]);
+            dump.sak = b0[7];
+            dump.atqa = [b0[8], b0[9]];
+        }
+        Ok(dump)
+    }
+
+    /// Writes the dump as JSON.
+    pub fn write_json(&self, w: &mut impl fmt::Write) -> fmt::Result {
+        w.write_str("{\n  \"Created\": \"pico-pico\",\n  \"FileType\": \"mfcard\",\n")?;
+        w.write_str("  \"Card\": {\n    \"UID\": \"")?;
+        write_hex(w, self.uid())?;
+        w.write_str("\",\n    \"ATQA\": \"")?;
+        write_hex(w, &self.atqa)?;
+        write!(
+            w,
+            "\",\n    \"SAK\": \"{:02X}\"\n  }},\n  \"blocks\": {{",
+            self.sak
+        )?;
+
+        let mut first = true;
+        for block in 0..self.block_count() {
+            let Some(data) = self.block(block as u8) else {
+                continue;
+            };
+            let separator = if first { "" } else { "," };
+            write!(w, "{}\n    \"{}\": \"", separator, block)?;
+            write_hex(w, data)?;
+            w.write_str("\"")?;
+            first = false;
+        }
+        w.write_str("\n  }\n}\n")
+    }
+
+    /// Reads a dump written by [`Dump::write_json`] or a Proxmark3 client.
+    /// Only the `Card` fields and `blocks` are looked at.
+    pub fn from_json(text: &str) -> Result<Self, DumpError> {
+        let mut uid = [0u8; MAX_UID_LEN];
+        let uid_len = parse_hex(json_string(text, "UID")?, &mut uid)?;
+        let mut atqa = [0u8; 2];
+        if parse_hex(json_string(text, "ATQA")?, &mut atqa)? != atqa.len() {
+            return Err(DumpError::Malformed);
+        }
+        let mut sak = [0u8; 1];
+        if parse_hex(json_string(text, "SAK")?, &mut sak)? != sak.len() {
+            return Err(DumpError::Malformed);
+        }
+        let activation = Activation { atqa, sak: sak[0] };
+
+        // Parse the blocks first, the card size follows from them
+        let mut blocks = [[0u8; BLOCK_SIZE]; MAX_BLOCKS];
+        let mut read = [false; MAX_BLOCKS];
+        let mut last = 0;
+        for entry in json_object(text, "blocks")? {
+            let (key, value) = entry?;
+            let block: usize = key.parse().map_err(|_| DumpError::Malformed)?;
+            if block >= MAX_BLOCKS || parse_hex(value, &mut blocks[block])? != BLOCK_SIZE {
+                return Err(DumpError::Malformed);
+            }
+            read[block] = true;
+            last = last.max(block);
+        }
+
+        let card = match last {
+            0..20 => CardType::ClassicMini,
+            20..64 => CardType::Classic1K,
+            _ => CardType::Classic4K,
+        };
+        // Trust the SAK if it names a card big enough for the blocks
+        let card = match activation.card_type() {
+            Some(named) if named.block_count() >= card.block_count() => named,
+            _ => card,
+        };
+
+        let mut dump = Self::new(card, &uid[..uid_len], &activation);
+        dump.blocks = blocks;
+        dump.read = read;
+        Ok(dump)
+    }
+
+    /// The blocks that differ between two dumps, including blocks read in
+    /// one but not the other.
+    pub fn diff<'a>(&'a self, other: &'a Dump) -> impl Iterator<Item = u8> + 'a {
+        let count = self.block_count().max(other.block_count());
+        (0..count)
+            .map(|b| b as u8)
+            .filter(move |&b| self.block(b) != other.block(b))
+    }
+}
+
+fn write_hex(w: &mut impl fmt::Write, data: &[u8]) -> fmt::Result {
+    for b in data {
+        write!(w, "{:02X}", b)?;
+    }
+    Ok(())
+}
+
+/// Parses hex digits into `out` and returns the number of bytes.
+fn parse_hex(s: &str, out: &mut [u8]) -> Result<usize, DumpError> {
+    let digits = s.as_bytes();
+    if !digits.len().is_multiple_of(2) || digits.len() / 2 > out.len() {
+        return Err(DumpError::Malformed);
+    }
+    for (byte, pair) in out.iter_mut().zip(digits.chunks_exact(2)) {
+        let pair = core::str::from_utf8(pair).map_err(|_| DumpError::Malformed)?;
+        *byte = u8::from_str_radix(pair, 16).map_err(|_| DumpError::Malformed)?;
+    }
+    Ok(digits.len() / 2)
+}
+
+/// Everything after `"key"` and its colon.
+fn json_value<'t>(text: &'t str, key: &str) -> Result<&'t str, DumpError> {
+    let mut rest = text;
+    while let Some(start) = rest.find('"') {
+        let after = &rest[start + 1..];
+        if let Some(tail) = after.strip_prefix(key).and_then(|t| t.strip_prefix('"')) {
+            if let Some(value) = tail.trim_start().strip_prefix(':') {
+                return Ok(value.trim_start());
+            }
+        }
+        rest = after;
+    }
+    Err(DumpError::Malformed)
+}
+
+/// The string value of `"key"`.
+fn json_string<'t>(text: &'t str, key: &str) -> Result<&'t str, DumpError> {
+    let (value, _) = quoted(json_value(text, key)?)?;
+    Ok(value)
+}
+
+/// The entries of the object `"key"`, whose values must be strings.
+fn json_object<'t>(
+    text: &'t str,
+    key: &str,
+) -> Result<impl Iterator<Item = Result<(&'t str, &'t str), DumpError>>, DumpError> {
+    let mut rest = json_value(text, key)?
+        .strip_prefix('{')
+        .ok_or(DumpError::Malformed)?;
+    Ok(core::iter::from_fn(move || {
+        rest = rest.trim_start().trim_start_matches(',').trim_start();
+        if rest.starts_with('}') {
+            return None;
+        }
+        let entry = quoted(rest).and_then(|(key, tail)| {
+            let tail = tail
+                .trim_start()
+                .strip_prefix(':')
+                .ok_or(DumpError::Malformed)?;
+            let (value, tail) = quoted(tail.trim_start())?;
+            Ok((key, value, tail))
+        });
+        match entry {
+            Ok((key, value, tail)) => {
+                rest = tail;
+                Some(Ok((key, value)))
+            }
+            Err(e) => {
+                // Stop after reporting it
+                rest = "}";
+                Some(Err(e))
+            }
+        }
+    }))
+}
+
+/// Splits a leading `"string"` off `s`. No escapes; dumps don't need them.
+fn quoted(s: &str) -> Result<(&str, &str), DumpError> {
+    let s = s.strip_prefix('"').ok_or(DumpError::Malformed)?;
+    let end = s.find('"').ok_or(DumpError::Malformed)?;
+    Ok((&s[..end], &s[end + 1..]))
+}
+
+/// What [`restore`] may write besides the data blocks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RestoreOptions {
+    /// Write block 0. Only magic cards accept it.
+    pub block0: bool,
+    /// Write the sector trailers, keys and access bits included.
+    pub trailers: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RestoreReport {
+    pub written: u16,
+    /// Left out by the options, or not in the dump.
+    pub skipped: u16,
+    /// Refused by the card, not opened by any key, or trailers with invalid
+    /// access bits.
+    pub failed: u16,
+    /// The card took the Gen1a unlock, so no keys were needed.
+    pub gen1a: bool,
+}
+
+/// Writes `dump` onto the card with `uid`, usually a blank card or a magic
+/// clone. Sectors are opened with the keys in the dump first, then with
+/// `keys`. A trailer is only written if its access bits are valid, and last
+/// in its sector since it may change the keys.
+///
+/// Failing blocks are counted and skipped; only losing the card stops the
+/// restore.
+pub fn restore<E, SPI: SpiDevice<Error = E>>(
+    uid: &Uid,
+    card: CardType,
+    dump: &Dump,
+    keys: &[[u8; 6]],
+    options: RestoreOptions,
+    pcd: &mut Pcd<SPI>,
+) -> Result<RestoreReport, RfidError<E>> {
+    if card.block_count() < dump.card.block_count() {
+        return Err(RfidError::Refused {
+            block: 0,
+            reason: "Card is smaller than the dump",
+        });
+    }
+
+    let mut report = RestoreReport {
+        gen1a: magic::unlock_gen1a(pcd).map_err(RfidError::Activation)?,
+        ..Default::default()
+    };
+    if !report.gen1a {
+        pcd.activate(uid).map_err(RfidError::Activation)?;
+    }
+
+    for sector in 0..dump.card.sector_count() {
+        let first = dump.card.first_block(sector).unwrap_or_default();
+        let blocks = dump.card.blocks_in_sector(sector).unwrap_or_default();
+
+        let key = if report.gen1a {
+            None
+        } else {
+            let dump_keys = dump.keys(sector).unwrap_or([keys::TRANSPORT_KEY; 2]);
+            let found = match keys::find_key(uid, first, &dump_keys, pcd) {
+                Ok(None) => keys::find_key(uid, first, keys, pcd),
+                found => found,
+            };
+            match found.map_err(RfidError::Activation)? {
+                Some(found) => Some(found),
+                None => {
+                    report.failed += blocks as u16;
+                    continue;
+                }
+            }
+        };
+
+        for rel_block in 0..blocks {
+            let block = first + rel_block;
+            let trailer = geometry::is_trailer(sector, rel_block);
+            let data = match dump.block(block) {
+                Some(data) if (block != 0 || options.block0) && (!trailer || options.trailers) => {
+                    data
+                }
+                _ => {
+                    report.skipped += 1;
+                    continue;
+                }
+            };
+            // A trailer with bad access bits would lock the sector for good
+            if trailer
+                && AccessConditions::from_trailer(data)
+                    .and_then(|access| access.encode())
+                    .is_err()
+            {
+                report.failed += 1;
+                continue;
+            }
+
+            match classic::write_block(block, data, pcd) {
+                Ok(()) => report.written += 1,
+                Err(_) => {
+                    report.failed += 1;
+                    // A NAK drops the card out of the authenticated state
+                    if let Some(key) = &key {
+                        pcd.activate(uid).map_err(RfidError::Activation)?;
+                        let _ = pcd.authenticate(uid, first, key.key_type, &key.key);
+                    }
+                }
+            }
+        }
+    }
+    if !report.gen1a {
+        pcd.activate(uid).map_err(RfidError::Activation)?;
+    }
+    Ok(report)
+}
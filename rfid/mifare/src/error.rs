@@ -0,0 +1,222 @@
+//! A single error type for the RFID tools.
+//!
+//! It keeps the `mfrc522` error that caused the failure along with the sector
+//! and block involved, so a caller can retry a garbled frame but give up on a
+//! wrong key, and still print something useful either way.
+
+use core::fmt;
+
+use mfrc522::Error;
+
+use crate::access::{AccessError, KeyType};
+
+#[derive(Debug)]
+pub enum RfidError<E> {
+    /// Waking up or selecting the card failed.
+    Activation(Error<E>),
+    Auth {
+        sector: u8,
+        key_type: KeyType,
+        cause: Error<E>,
+    },
+    Read {
+        sector: u8,
+        block: u8,
+        cause: Error<E>,
+    },
+    Write {
+        sector: u8,
+        block: u8,
+        cause: Error<E>,
+    },
+    /// Ultralight and NTAG tags have pages instead of sectors and blocks.
+    ReadPage {
+        page: u8,
+        cause: Error<E>,
+    },
+    WritePage {
+        page: u8,
+        cause: Error<E>,
+    },
+    /// The card doesn't have this sector or block.
+    OutOfRange {
+        sector: u8,
+        block: Option<u8>,
+    },
+    /// The access bits don't allow the operation with the key we hold.
+    AccessDenied {
+        sector: u8,
+        block: u8,
+    },
+    /// We refuse to touch this block, like a trailer through a data write.
+    Refused {
+        block: u8,
+        reason: &'static str,
+    },
+    InvalidTrailer {
+        sector: u8,
+        error: AccessError,
+    },
+    UnsupportedCard,
+}
+
+impl<E> RfidError<E> {
+    /// The `mfrc522` error behind this one, if any.
+    pub fn cause(&self) -> Option<&Error<E>> {
+        match self {
+            RfidError::Activation(cause)
+            | RfidError::Auth { cause, .. }
+            | RfidError::Read { cause, .. }
+            | RfidError::Write { cause, .. }
+            | RfidError::ReadPage { cause, .. }
+            | RfidError::WritePage { cause, .. } => Some(cause),
+            _ => None,
+        }
+    }
+
+    /// Is this worth trying again? Garbled or missing frames are, usually
+    /// because the card sat at the edge of the field. A rejected key or a
+    /// refused operation will fail the same way every time.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            // A failed authentication also shows up as a timeout, so only a
+            // garbled frame suggests the key might still be right
+            RfidError::Auth { cause, .. } => {
+                matches!(cause, Error::Crc | Error::Parity | Error::Collision)
+            }
+            RfidError::Activation(cause)
+            | RfidError::Read { cause, .. }
+            | RfidError::Write { cause, .. }
+            | RfidError::ReadPage { cause, .. }
+            | RfidError::WritePage { cause, .. } => matches!(
+                cause,
+                Error::Crc
+                    | Error::Bcc
+                    | Error::Parity
+                    | Error::Collision
+                    | Error::Timeout
+                    | Error::IncompleteFrame
+            ),
+            _ => false,
+        }
+    }
+}
+
+/// Short name of an `mfrc522` error.
+pub fn cause_str<E>(cause: &Error<E>) -> &'static str {
+    match cause {
+        Error::Comm(_) => "bus error",
+        Error::Bcc => "BCC error",
+        Error::BufferOverflow => "FIFO overflow",
+        Error::Collision => "collision",
+        Error::Crc => "CRC error",
+        Error::IncompleteFrame => "incomplete frame",
+        Error::NoRoom => "no room",
+        Error::Overheating => "antenna overheating",
+        Error::Parity => "parity error",
+        Error::Protocol => "protocol error",
+        Error::Timeout => "timeout",
+        Error::Wr => "write error",
+        Error::Nak => "NAK",
+        Error::Proprietary => "proprietary error",
+    }
+}
+
+impl<E> fmt::Display for RfidError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RfidError::Activation(cause) => write!(f, "Activation failed: {}", cause_str(cause)),
+            RfidError::Auth {
+                sector,
+                key_type,
+                cause,
+            } => write!(
+                f,
+                "Auth failed: sector {}, Key {:?}: {}",
+                sector,
+                key_type,
+                cause_str(cause)
+            ),
+            RfidError::Read {
+                sector,
+                block,
+                cause,
+            } => write!(
+                f,
+                "Read failed: sector {}, block {}: {}",
+                sector,
+                block,
+                cause_str(cause)
+            ),
+            RfidError::Write {
+                sector,
+                block,
+                cause,
+            } => write!(
+                f,
+                "Write failed: sector {}, block {}: {}",
+                sector,
+                block,
+                cause_str(cause)
+            ),
+            RfidError::ReadPage { page, cause } => {
+                write!(f, "Read failed: page {}: {}", page, cause_str(cause))
+            }
+            RfidError::WritePage { page, cause } => {
+                write!(f, "Write failed: page {}: {}", page, cause_str(cause))
+            }
+            RfidError::OutOfRange {
+                sector,
+                block: Some(block),
+            } => write!(f, "Block {} of sector {} out of range", block, sector),
+            RfidError::OutOfRange {
+                sector,
+                block: None,
+            } => write!(f, "Sector {} out of range", sector),
+            RfidError::AccessDenied { sector, block } => write!(
+                f,
+                "Access bits deny this on sector {}, block {}",
+                sector, block
+            ),
+            RfidError::Refused { block, reason } => write!(f, "Block {}: {}", block, reason),
+            RfidError::InvalidTrailer { sector, error } => {
+                write!(f, "Sector {}: {}", sector, error.as_str())
+            }
+            RfidError::UnsupportedCard => f.write_str("Unsupported card type"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn garbled_frames_are_transient() {
+        for cause in [Error::Bcc, Error::Crc, Error::Timeout] {
+            assert!(RfidError::<()>::Activation(cause).is_transient());
+        }
+        let read = RfidError::<()>::Read {
+            sector: 1,
+            block: 4,
+            cause: Error::Bcc,
+        };
+        assert!(read.is_transient());
+    }
+
+    #[test]
+    fn rejected_key_is_not_transient() {
+        let auth = RfidError::<()>::Auth {
+            sector: 1,
+            key_type: KeyType::A,
+            cause: Error::Timeout,
+        };
+        assert!(!auth.is_transient());
+        assert!(!RfidError::<()>::Activation(Error::Comm(())).is_transient());
+    }
+
+    #[test]
+    fn bcc_has_a_name() {
+        assert_eq!(cause_str(&Error::<()>::Bcc), "BCC error");
+    }
+}
@@ -0,0 +1,254 @@
+//! Sector and block layout of the MIFARE Classic family.
+//!
+//! Mini and 1K cards, and the first 32 sectors of a 4K card, have 4 blocks per
+//! sector. The last 8 sectors of a 4K card have 16 blocks each; there one
+//! group of access bits covers five data blocks instead of one.
+
+use core::ops::Range;
+
+/// Sectors with 4 blocks come first on every card.
+const SMALL_SECTORS: u8 = 32;
+const SMALL_SECTOR_BLOCKS: u8 = 4;
+const LARGE_SECTOR_BLOCKS: u8 = 16;
+/// Data blocks covered by one access group in a 16-block sector.
+const LARGE_GROUP_BLOCKS: u8 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CardType {
+    ClassicMini,
+    Classic1K,
+    Classic4K,
+}
+
+impl CardType {
+    /// Identifies the card from the SAK and ATQA it sent while being selected.
+    ///
+    /// Returns `None` for anything that isn't a MIFARE Classic.
+    pub fn from_sak_atqa(sak: u8, atqa: [u8; 2]) -> Option<Self> {
+        match sak {
+            0x09 => Some(CardType::ClassicMini),
+            // 0x28 and 0x38 are SmartMX chips emulating a Classic, 0x88 is the
+            // Infineon variant of the 1K
+            0x08 | 0x28 | 0x88 => Some(CardType::Classic1K),
+            0x18 | 0x38 => Some(CardType::Classic4K),
+            // Some clones send an odd SAK but still set the Classic bit; the
+            // ATQA then tells the size
+            sak if sak & 0x08 != 0 => match atqa[0] & 0x0F {
+                0x02 => Some(CardType::Classic4K),
+                0x04 => Some(CardType::Classic1K),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            CardType::ClassicMini => "MIFARE Classic Mini",
+            CardType::Classic1K => "MIFARE Classic 1K",
+            CardType::Classic4K => "MIFARE Classic 4K",
+        }
+    }
+
+    pub const fn sector_count(self) -> u8 {
+        match self {
+            CardType::ClassicMini => 5,
+            CardType::Classic1K => 16,
+            CardType::Classic4K => 40,
+        }
+    }
+
+    pub const fn block_count(self) -> u16 {
+        match self {
+            CardType::ClassicMini => 20,
+            CardType::Classic1K => 64,
+            CardType::Classic4K => 256,
+        }
+    }
+
+    /// Number of blocks in `sector`, trailer included.
+    pub const fn blocks_in_sector(self, sector: u8) -> Option<u8> {
+        if sector >= self.sector_count() {
+            None
+        } else if sector < SMALL_SECTORS {
+            Some(SMALL_SECTOR_BLOCKS)
+        } else {
+            Some(LARGE_SECTOR_BLOCKS)
+        }
+    }
+
+    /// Absolute number of the first block of `sector`.
+    pub const fn first_block(self, sector: u8) -> Option<u8> {
+        if sector >= self.sector_count() {
+            None
+        } else if sector < SMALL_SECTORS {
+            Some(sector * SMALL_SECTOR_BLOCKS)
+        } else {
+            Some(
+                SMALL_SECTORS * SMALL_SECTOR_BLOCKS
+                    + (sector - SMALL_SECTORS) * LARGE_SECTOR_BLOCKS,
+            )
+        }
+    }
+
+    /// Absolute block number of `rel_block` within `sector`.
+    pub const fn abs_block(self, sector: u8, rel_block: u8) -> Option<u8> {
+        match (self.first_block(sector), self.blocks_in_sector(sector)) {
+            (Some(first), Some(blocks)) if rel_block < blocks => Some(first + rel_block),
+            _ => None,
+        }
+    }
+
+    /// Absolute number of the trailer block of `sector`.
+    pub const fn trailer_block(self, sector: u8) -> Option<u8> {
+        match (self.first_block(sector), self.blocks_in_sector(sector)) {
+            (Some(first), Some(blocks)) => Some(first + (blocks - 1)),
+            _ => None,
+        }
+    }
+
+    /// The sector an absolute block belongs to.
+    pub const fn sector_of(self, block: u8) -> Option<u8> {
+        if block as u16 >= self.block_count() {
+            None
+        } else if block < SMALL_SECTORS * SMALL_SECTOR_BLOCKS {
+            Some(block / SMALL_SECTOR_BLOCKS)
+        } else {
+            Some(
+                SMALL_SECTORS + (block - SMALL_SECTORS * SMALL_SECTOR_BLOCKS) / LARGE_SECTOR_BLOCKS,
+            )
+        }
+    }
+}
+
+/// Is `rel_block` the trailer of its sector?
+pub const fn is_trailer(sector: u8, rel_block: u8) -> bool {
+    if sector < SMALL_SECTORS {
+        rel_block == SMALL_SECTOR_BLOCKS - 1
+    } else {
+        rel_block == LARGE_SECTOR_BLOCKS - 1
+    }
+}
+
+/// The access bit group (0..=3) that governs `rel_block` within `sector`.
+/// Group 3 is always the trailer.
+pub const fn access_group(sector: u8, rel_block: u8) -> u8 {
+    if sector < SMALL_SECTORS {
+        rel_block
+    } else {
+        rel_block / LARGE_GROUP_BLOCKS
+    }
+}
+
+/// The data blocks of `sector`, relative to its start, that access group
+/// `group` (0..=2) governs.
+pub const fn group_blocks(sector: u8, group: u8) -> Range<u8> {
+    if sector < SMALL_SECTORS {
+        group..group + 1
+    } else {
+        group * LARGE_GROUP_BLOCKS..(group + 1) * LARGE_GROUP_BLOCKS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use CardType::*;
+
+    #[test]
+    fn small_sectors() {
+        for card in [ClassicMini, Classic1K, Classic4K] {
+            assert_eq!(card.first_block(0), Some(0));
+            assert_eq!(card.trailer_block(0), Some(3));
+            assert_eq!(card.first_block(4), Some(16));
+            assert_eq!(card.trailer_block(4), Some(19));
+            assert_eq!(card.abs_block(4, 2), Some(18));
+            assert_eq!(card.abs_block(4, 4), None);
+            assert_eq!(card.sector_of(19), Some(4));
+        }
+    }
+
+    #[test]
+    fn card_ends() {
+        assert_eq!(ClassicMini.trailer_block(4), Some(19));
+        assert_eq!(ClassicMini.first_block(5), None);
+        assert_eq!(ClassicMini.sector_of(20), None);
+
+        assert_eq!(Classic1K.trailer_block(15), Some(63));
+        assert_eq!(Classic1K.first_block(16), None);
+        assert_eq!(Classic1K.abs_block(16, 0), None);
+        assert_eq!(Classic1K.sector_of(63), Some(15));
+        assert_eq!(Classic1K.sector_of(64), None);
+
+        assert_eq!(Classic4K.first_block(40), None);
+        assert_eq!(Classic4K.trailer_block(40), None);
+        assert_eq!(Classic4K.blocks_in_sector(40), None);
+    }
+
+    #[test]
+    fn large_sectors_of_4k() {
+        assert_eq!(Classic4K.blocks_in_sector(31), Some(4));
+        assert_eq!(Classic4K.trailer_block(31), Some(127));
+        assert_eq!(Classic4K.sector_of(127), Some(31));
+
+        assert_eq!(Classic4K.blocks_in_sector(32), Some(16));
+        assert_eq!(Classic4K.first_block(32), Some(128));
+        assert_eq!(Classic4K.trailer_block(32), Some(143));
+        assert_eq!(Classic4K.abs_block(32, 15), Some(143));
+        assert_eq!(Classic4K.abs_block(32, 16), None);
+        assert_eq!(Classic4K.sector_of(128), Some(32));
+        assert_eq!(Classic4K.sector_of(143), Some(32));
+        assert_eq!(Classic4K.sector_of(144), Some(33));
+
+        assert_eq!(Classic4K.first_block(39), Some(240));
+        assert_eq!(Classic4K.trailer_block(39), Some(255));
+        for block in 240..=255 {
+            assert_eq!(Classic4K.sector_of(block), Some(39));
+        }
+    }
+
+    #[test]
+    fn sector_of_inverts_abs_block() {
+        for card in [ClassicMini, Classic1K, Classic4K] {
+            for sector in 0..card.sector_count() {
+                let blocks = card.blocks_in_sector(sector).unwrap();
+                for rel in 0..blocks {
+                    let block = card.abs_block(sector, rel).unwrap();
+                    assert_eq!(card.sector_of(block), Some(sector));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn access_groups_of_large_sectors() {
+        assert_eq!(access_group(32, 0), 0);
+        assert_eq!(access_group(32, 4), 0);
+        assert_eq!(access_group(32, 5), 1);
+        assert_eq!(access_group(32, 14), 2);
+        assert_eq!(access_group(32, 15), 3);
+        assert!(is_trailer(32, 15));
+        assert!(!is_trailer(32, 3));
+        assert_eq!(group_blocks(32, 2), 10..15);
+        assert_eq!(group_blocks(5, 2), 2..3);
+    }
+
+    #[test]
+    fn from_sak_atqa() {
+        assert_eq!(
+            CardType::from_sak_atqa(0x09, [0x04, 0x00]),
+            Some(ClassicMini)
+        );
+        assert_eq!(CardType::from_sak_atqa(0x08, [0x04, 0x00]), Some(Classic1K));
+        assert_eq!(CardType::from_sak_atqa(0x88, [0x04, 0x00]), Some(Classic1K));
+        assert_eq!(CardType::from_sak_atqa(0x18, [0x02, 0x00]), Some(Classic4K));
+        assert_eq!(CardType::from_sak_atqa(0x38, [0x02, 0x00]), Some(Classic4K));
+        // Clones with an odd SAK fall back to the ATQA
+        assert_eq!(CardType::from_sak_atqa(0x98, [0x02, 0x00]), Some(Classic4K));
+        assert_eq!(CardType::from_sak_atqa(0x98, [0x44, 0x00]), Some(Classic1K));
+        assert_eq!(CardType::from_sak_atqa(0x98, [0x08, 0x00]), None);
+        // Ultralight and ISO 14443-4 cards aren't Classic
+        assert_eq!(CardType::from_sak_atqa(0x00, [0x44, 0x00]), None);
+        assert_eq!(CardType::from_sak_atqa(0x20, [0x04, 0x03]), None);
+    }
+}
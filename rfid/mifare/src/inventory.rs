@@ -0,0 +1,250 @@
+//! Finding every card in the field, and noticing when they come and go.
+//!
+//! A round starts with WUPA so cards halted in the last round answer again.
+//! Each card that wins anticollision is selected and halted, and REQA brings
+//! out the rest until nobody answers. [`Tracker`] compares rounds and reports
+//! arrivals and departures.
+
+use embedded_hal::spi::SpiDevice;
+use mfrc522::Error;
+
+use crate::geometry::CardType;
+use crate::pcd::{Pcd, Selected};
+
+/// SAK bit of cards that speak ISO 14443-4.
+const SAK_ISO14443_4: u8 = 0x20;
+/// SAK sent by Ultralight and NTAG tags.
+const SAK_ULTRALIGHT: u8 = 0x00;
+
+/// Extra requests per round, on top of one per slot, before giving up on a
+/// field that keeps producing answers.
+const SPARE_REQUESTS: usize = 4;
+
+/// A card found by [`inventory`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Tag {
+    pub selected: Selected,
+    /// ATQA as received; garbled when several cards answered together.
+    pub atqa: [u8; 2],
+}
+
+impl Tag {
+    pub fn uid(&self) -> &[u8] {
+        self.selected.uid()
+    }
+
+    pub fn sak(&self) -> u8 {
+        self.selected.sak
+    }
+
+    /// What the SAK says the card is.
+    pub fn kind(&self) -> &'static str {
+        if let Some(card) = CardType::from_sak_atqa(self.sak(), self.atqa) {
+            return card.name();
+        }
+        match self.sak() {
+            SAK_ULTRALIGHT => "Ultralight/NTAG",
+            sak if sak & SAK_ISO14443_4 != 0 => "ISO 14443-4",
+            _ => "Unknown",
+        }
+    }
+}
+
+/// Finds the cards in the field and stores them in `tags`. Returns how many
+/// were found; a full `tags` means there may be more.
+///
+/// Every card found is left halted.
+pub fn inventory<E, SPI: SpiDevice<Error = E>>(
+    pcd: &mut Pcd<SPI>,
+    tags: &mut [Tag],
+) -> Result<usize, Error<E>> {
+    let mut found = 0;
+    for attempt in 0..tags.len() + SPARE_REQUESTS {
+        if found == tags.len() {
+            break;
+        }
+        let atqa = match pcd.request(attempt == 0) {
+            Ok(atqa) => atqa,
+            // Several cards answered at once
+            Err(Error::Collision) => [0, 0],
+            Err(Error::Timeout) => break,
+            Err(e) => return Err(e),
+        };
+
+        // A garbled frame loses this card for now, but the rest may still be
+        // there; it's back in the next round
+        let Ok(selected) = pcd.anticollision() else {
+            continue;
+        };
+        pcd.halt()?;
+
+        if tags[..found].iter().any(|t| t.selected == selected) {
+            continue;
+        }
+        tags[found] = Tag { selected, atqa };
+        found += 1;
+    }
+    Ok(found)
+}
+
+/// A change between two inventory rounds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Arrived(Tag),
+    Departed(Tag),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Present {
+    tag: Tag,
+    misses: u8,
+}
+
+/// The cards in the field, as seen over several inventory rounds.
+///
+/// A card at the edge of the field drops out of a round now and then, so it
+/// only counts as gone after `misses` rounds in a row without it.
+pub struct Tracker<const N: usize> {
+    present: [Option<Present>; N],
+    misses: u8,
+}
+
+impl<const N: usize> Tracker<N> {
+    pub const fn new(misses: u8) -> Self {
+        Self {
+            present: [None; N],
+            misses,
+        }
+    }
+
+    /// The cards currently in the field.
+    pub fn tags(&self) -> impl Iterator<Item = &Tag> {
+        self.present.iter().flatten().map(|p| &p.tag)
+    }
+
+    /// Takes the cards found in one round and calls `on_event` for every
+    /// card that arrived or left. Arrivals that don't fit are dropped, and
+    /// reported again once there is room.
+    pub fn update(&mut self, seen: &[Tag], mut on_event: impl FnMut(Event)) {
+        for slot in self.present.iter_mut() {
+            let Some(present) = slot else {
+                continue;
+            };
+            if seen.iter().any(|t| t.uid() == present.tag.uid()) {
+                present.misses = 0;
+                continue;
+            }
+            present.misses += 1;
+            if present.misses >= self.misses {
+                on_event(Event::Departed(present.tag));
+                *slot = None;
+            }
+        }
+
+        for tag in seen {
+            if self.tags().any(|t| t.uid() == tag.uid()) {
+                continue;
+            }
+            if let Some(slot) = self.present.iter_mut().find(|s| s.is_none()) {
+                *slot = Some(Present {
+                    tag: *tag,
+                    misses: 0,
+                });
+                on_event(Event::Arrived(*tag));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tag(last: u8) -> Tag {
+        Tag {
+            selected: Selected::new(&[0x13, 0x37, 0x73, last], 0x08),
+            atqa: [0x04, 0x00],
+        }
+    }
+
+    /// Runs one round and returns its events in order.
+    fn round<const N: usize>(tracker: &mut Tracker<N>, seen: &[Tag]) -> [Option<Event>; 4] {
+        let mut events = [None; 4];
+        let mut count = 0;
+        tracker.update(seen, |event| {
+            events[count] = Some(event);
+            count += 1;
+        });
+        events
+    }
+
+    #[test]
+    fn arrival() {
+        let mut tracker = Tracker::<4>::new(3);
+        let a = tag(1);
+        assert_eq!(
+            round(&mut tracker, &[a]),
+            [Some(Event::Arrived(a)), None, None, None]
+        );
+        assert_eq!(round(&mut tracker, &[a]), [None; 4], "still there");
+        assert!(tracker.tags().eq([&a]));
+    }
+
+    #[test]
+    fn missed_rounds() {
+        let mut tracker = Tracker::<4>::new(3);
+        let a = tag(1);
+        round(&mut tracker, &[a]);
+        // Two misses aren't enough, and coming back starts the count again
+        for _ in 0..2 {
+            for _ in 0..2 {
+                assert_eq!(round(&mut tracker, &[]), [None; 4]);
+                assert!(tracker.tags().eq([&a]));
+            }
+            assert_eq!(round(&mut tracker, &[a]), [None; 4], "no second arrival");
+        }
+    }
+
+    #[test]
+    fn departure() {
+        let mut tracker = Tracker::<4>::new(3);
+        let a = tag(1);
+        let b = tag(2);
+        round(&mut tracker, &[a, b]);
+        for _ in 0..2 {
+            assert_eq!(round(&mut tracker, &[b]), [None; 4]);
+        }
+        assert_eq!(
+            round(&mut tracker, &[b]),
+            [Some(Event::Departed(a)), None, None, None]
+        );
+        assert!(tracker.tags().eq([&b]));
+        assert_eq!(
+            round(&mut tracker, &[a, b]),
+            [Some(Event::Arrived(a)), None, None, None],
+            "back again"
+        );
+    }
+
+    #[test]
+    fn full() {
+        let mut tracker = Tracker::<2>::new(1);
+        let [a, b, c] = [tag(1), tag(2), tag(3)];
+        assert_eq!(
+            round(&mut tracker, &[a, b, c]),
+            [Some(Event::Arrived(a)), Some(Event::Arrived(b)), None, None]
+        );
+        assert_eq!(round(&mut tracker, &[a, b, c]), [None; 4], "no room yet");
+        // Departures go first, so the room a leaves is c's straight away
+        assert_eq!(
+            round(&mut tracker, &[b, c]),
+            [
+                Some(Event::Departed(a)),
+                Some(Event::Arrived(c)),
+                None,
+                None
+            ]
+        );
+        assert!(tracker.tags().eq([&c, &b]));
+    }
+}
@@ -0,0 +1,122 @@
+//! Finding the key that opens a sector.
+
+use embedded_hal::spi::SpiDevice;
+use mfrc522::{Error, Uid};
+
+use crate::access::KeyType;
+use crate::pcd::Pcd;
+
+/// Factory default for both keys.
+pub const TRANSPORT_KEY: [u8; 6] = [0xFF; 6];
+/// Key A of the MIFARE Application Directory sector.
+pub const MAD_KEY: [u8; 6] = [0xA0, 0xA1, 0xA2, 0xA3, 0xA4, 0xA5];
+/// Key A of sectors holding NDEF data.
+pub const NDEF_KEY: [u8; 6] = [0xD3, 0xF7, 0xD3, 0xF7, 0xD3, 0xF7];
+/// A Key B many vendors ship with.
+pub const VENDOR_KEY_B: [u8; 6] = [0xB0, 0xB1, 0xB2, 0xB3, 0xB4, 0xB5];
+/// Blank key, seen on some cheap cards.
+pub const ZERO_KEY: [u8; 6] = [0x00; 6];
+
+/// The well-known keys, in the order worth trying them.
+pub const DEFAULT_KEYS: [[u8; 6]; 5] = [TRANSPORT_KEY, MAD_KEY, NDEF_KEY, VENDOR_KEY_B, ZERO_KEY];
+
+/// A key that successfully authenticated a sector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FoundKey {
+    pub key_type: KeyType,
+    pub key: [u8; 6],
+}
+
+/// Tries every key in `keys`, first as Key A and then as Key B, against the
+/// sector that `block` belongs to.
+///
+/// A failed authentication knocks the card out of the ACTIVE state, so it is
+/// woken up and selected again before each attempt. On success the sector is
+/// left authenticated. `Ok(None)` means no key opened it.
+pub fn find_key<E, SPI: SpiDevice<Error = E>>(
+    uid: &Uid,
+    block: u8,
+    keys: &[[u8; 6]],
+    pcd: &mut Pcd<SPI>,
+) -> Result<Option<FoundKey>, Error<E>> {
+    for key_type in [KeyType::A, KeyType::B] {
+        for key in keys {
+            pcd.activate(uid)?;
+            if pcd.authenticate(uid, block, key_type, key).is_ok() {
+                return Ok(Some(FoundKey {
+                    key_type,
+                    key: *key,
+                }));
+            }
+        }
+    }
+    // Don't leave the card stuck after the last failed attempt
+    pcd.activate(uid)?;
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use mfrc522::GenericUid;
+
+    use super::*;
+    use crate::mock::MockReader;
+
+    const UID: [u8; 4] = [0x13, 0x37, 0x73, 0x31];
+    const OWN_KEY: [u8; 6] = *b"Rusted";
+
+    fn uid() -> Uid {
+        Uid::Single(GenericUid::new(UID, 0x08))
+    }
+
+    #[test]
+    fn finds_key_a_after_failures() {
+        let mut pcd = Pcd::new(MockReader::new(UID, NDEF_KEY, OWN_KEY));
+        let found = find_key(&uid(), 4, &DEFAULT_KEYS, &mut pcd).unwrap();
+        assert_eq!(
+            found,
+            Some(FoundKey {
+                key_type: KeyType::A,
+                key: NDEF_KEY
+            })
+        );
+    }
+
+    #[test]
+    fn falls_back_to_key_b() {
+        let mut pcd = Pcd::new(MockReader::new(UID, OWN_KEY, VENDOR_KEY_B));
+        let found = find_key(&uid(), 4, &DEFAULT_KEYS, &mut pcd).unwrap();
+        assert_eq!(
+            found,
+            Some(FoundKey {
+                key_type: KeyType::B,
+                key: VENDOR_KEY_B
+            })
+        );
+    }
+
+    #[test]
+    fn tries_every_key_as_a_then_b() {
+        let mut pcd = Pcd::new(MockReader::new(UID, OWN_KEY, OWN_KEY));
+        assert_eq!(find_key(&uid(), 4, &DEFAULT_KEYS, &mut pcd), Ok(None));
+
+        let reader = pcd.release();
+        assert_eq!(reader.attempt_count, 2 * DEFAULT_KEYS.len());
+        let expected = [KeyType::A, KeyType::B]
+            .into_iter()
+            .flat_map(|key_type| DEFAULT_KEYS.iter().map(move |key| (key_type, *key)));
+        for (attempt, expected) in reader.attempts.iter().zip(expected) {
+            assert_eq!(*attempt, Some(expected));
+        }
+        // Left selected for whatever the caller does next
+        assert!(reader.is_active());
+    }
+
+    #[test]
+    fn locked_sector_leaves_card_usable() {
+        let mut pcd = Pcd::new(MockReader::new(UID, OWN_KEY, OWN_KEY));
+        assert_eq!(find_key(&uid(), 4, &DEFAULT_KEYS, &mut pcd), Ok(None));
+        let found = find_key(&uid(), 8, &[OWN_KEY], &mut pcd).unwrap();
+        assert_eq!(found.map(|f| f.key_type), Some(KeyType::A));
+    }
+}
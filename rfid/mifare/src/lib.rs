@@ -0,0 +1,31 @@
+//! MIFARE helpers shared by the RFID projects.
+//!
+//! Everything in here is plain `no_std` code with no dependency on the HAL, so
+//! it can be built and checked on the host as well as on the Pico. With the
+//! `serial` feature, [`serial`] reports errors over the USB serial port.
+#![no_std]
+
+pub mod access;
+pub mod card;
+pub mod classic;
+pub mod diag;
+pub mod dump;
+pub mod error;
+pub mod geometry;
+pub mod inventory;
+pub mod keys;
+pub mod mad;
+pub mod magic;
+#[cfg(test)]
+mod mock;
+pub mod ndef;
+pub mod pcd;
+pub mod power;
+pub mod rotation;
+#[cfg(feature = "serial")]
+pub mod serial;
+pub mod shell;
+pub mod trailer;
+pub mod ultralight;
+pub mod value;
+pub mod wallet;
@@ -0,0 +1,299 @@
+//! MIFARE Application Directory (MAD).
+//!
+//! The MAD tells a reader which application owns each sector of a Classic
+//! card. Version 1 lives in blocks 1 and 2 of sector 0 and covers sectors
+//! 1..=15; a 4K card adds version 2 in sector 16 for sectors 17..=39. Each
+//! area starts with a CRC byte and an info byte, followed by one 2-byte
+//! application ID (AID) per sector, little endian.
+//!
+//! The general purpose byte (byte 9) of the sector 0 trailer says whether a
+//! MAD is present and which version it is.
+
+use crate::access::{AccessBits, AccessConditions};
+use crate::geometry::CardType;
+use crate::ndef::NdefError;
+
+/// Application ID of an NFC Forum NDEF sector.
+pub const NDEF_AID: u16 = 0xE103;
+pub const AID_FREE: u16 = 0x0000;
+/// Marks MAD entries for sectors the card doesn't have.
+pub const AID_NOT_APPLICABLE: u16 = 0x0005;
+
+pub const MAD_SECTOR: u8 = 0;
+/// Sector holding MAD version 2 on a 4K card.
+pub const MAD2_SECTOR: u8 = 16;
+
+pub const MAD1_SIZE: usize = 32;
+pub const MAD2_SIZE: usize = 48;
+const MAD1_SECTORS: u8 = 15;
+const MAD2_SECTORS: u8 = 23;
+
+// General purpose byte
+const GPB_DA: u8 = 0x80;
+const GPB_MA: u8 = 0x40;
+const GPB_ADV: u8 = 0x03;
+/// General purpose byte of the sector 0 trailer with MAD version 1.
+pub const GPB_MAD1: u8 = GPB_DA | GPB_MA | 0x01;
+/// General purpose byte of the sector 0 trailer with MAD version 2.
+pub const GPB_MAD2: u8 = GPB_DA | GPB_MA | 0x02;
+/// General purpose byte of a read/write NDEF sector trailer (mapping 1.0).
+pub const GPB_NDEF: u8 = 0x40;
+
+/// Info byte written by NFC Forum formatters.
+const DEFAULT_INFO: u8 = 0x01;
+
+/// Access conditions of the MAD sectors: anyone may read the directory, only
+/// Key B may change it. Encodes to `78 77 88`.
+pub const MAD_ACCESS: AccessConditions = AccessConditions {
+    blocks: [AccessBits::DATA_WRITE_KEY_B; 3],
+    trailer: AccessBits::TRAILER_KEY_B,
+};
+
+/// Access conditions of a read/write NDEF sector: Key A reads and writes the
+/// data, Key B manages the trailer. Encodes to `7F 07 88`.
+pub const NDEF_ACCESS: AccessConditions = AccessConditions {
+    blocks: [AccessBits::DATA_TRANSPORT; 3],
+    trailer: AccessBits::TRAILER_KEY_B,
+};
+
+const _: () = assert!(MAD_ACCESS.encode().is_ok() && NDEF_ACCESS.encode().is_ok());
+
+/// The MAD version announced by the general purpose byte of the sector 0
+/// trailer, or `None` if the card has no MAD.
+pub const fn version(gpb: u8) -> Option<u8> {
+    if gpb & GPB_DA == 0 {
+        return None;
+    }
+    match gpb & GPB_ADV {
+        0 => None,
+        v => Some(v),
+    }
+}
+
+/// CRC-8 of a MAD area, over everything but the CRC byte itself.
+pub const fn crc8(data: &[u8]) -> u8 {
+    let mut crc: u8 = 0xC7;
+    let mut i = 0;
+    while i < data.len() {
+        crc ^= data[i];
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x1D
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        i += 1;
+    }
+    crc
+}
+
+/// The application directory of a card, indexed by sector.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mad {
+    /// Points at the card publisher sector, if any.
+    pub info: u8,
+    aids: [u16; 40],
+}
+
+impl Mad {
+    /// An empty directory for `card`, with entries for missing sectors
+    /// marked as not applicable.
+    pub fn new(card: CardType) -> Self {
+        let mut aids = [AID_NOT_APPLICABLE; 40];
+        for aid in aids.iter_mut().take(card.sector_count() as usize) {
+            *aid = AID_FREE;
+        }
+        Self {
+            info: DEFAULT_INFO,
+            aids,
+        }
+    }
+
+    /// A directory that gives every sector but the MAD sectors to NDEF.
+    pub fn ndef(card: CardType) -> Self {
+        let mut mad = Self::new(card);
+        for sector in 1..card.sector_count() {
+            mad.set_aid(sector, NDEF_AID);
+        }
+        mad
+    }
+
+    /// The application owning `sector`. The MAD sectors themselves have no
+    /// entry.
+    pub fn aid(&self, sector: u8) -> Option<u16> {
+        match sector {
+            MAD_SECTOR | MAD2_SECTOR => None,
+            _ => self.aids.get(sector as usize).copied(),
+        }
+    }
+
+    /// Assigns `sector` to `aid`. Returns false for the MAD sectors and
+    /// sectors out of range.
+    pub fn set_aid(&mut self, sector: u8, aid: u16) -> bool {
+        match sector {
+            MAD_SECTOR | MAD2_SECTOR => false,
+            _ => match self.aids.get_mut(sector as usize) {
+                Some(entry) => {
+                    *entry = aid;
+                    true
+                }
+                None => false,
+            },
+        }
+    }
+
+    /// The sectors belonging to `aid`, in ascending order.
+    pub fn sectors(&self, aid: u16) -> impl Iterator<Item = u8> + '_ {
+        (0..self.aids.len() as u8).filter(move |&s| self.aid(s) == Some(aid))
+    }
+
+    /// Decodes MAD version 1 from blocks 1 and 2 of sector 0, checking the
+    /// CRC.
+    pub fn parse_v1(data: &[u8; MAD1_SIZE]) -> Result<Self, NdefError> {
+        if crc8(&data[1..]) != data[0] {
+            return Err(NdefError::Malformed);
+        }
+        let mut mad = Self {
+            info: data[1],
+            aids: [AID_NOT_APPLICABLE; 40],
+        };
+        for i in 0..MAD1_SECTORS {
+            mad.aids[1 + i as usize] = read_aid(data, i);
+        }
+        Ok(mad)
+    }
+
+    /// Adds the entries of MAD version 2, from blocks 0..=2 of sector 16.
+    pub fn parse_v2(&mut self, data: &[u8; MAD2_SIZE]) -> Result<(), NdefError> {
+        if crc8(&data[1..]) != data[0] {
+            return Err(NdefError::Malformed);
+        }
+        for i in 0..MAD2_SECTORS {
+            self.aids[(MAD2_SECTOR + 1 + i) as usize] = read_aid(data, i);
+        }
+        Ok(())
+    }
+
+    /// Encodes MAD version 1, ready to be written to blocks 1 and 2.
+    pub fn encode_v1(&self) -> [u8; MAD1_SIZE] {
+        let mut data = [0u8; MAD1_SIZE];
+        data[1] = self.info;
+        for i in 0..MAD1_SECTORS {
+            write_aid(&mut data, i, self.aids[1 + i as usize]);
+        }
+        data[0] = crc8(&data[1..]);
+        data
+    }
+
+    /// Encodes MAD version 2, ready to be written to blocks 0..=2 of sector
+    /// 16. The info byte of version 2 doesn't point at a publisher sector.
+    pub fn encode_v2(&self) -> [u8; MAD2_SIZE] {
+        let mut data = [0u8; MAD2_SIZE];
+        data[1] = DEFAULT_INFO;
+        for i in 0..MAD2_SECTORS {
+            write_aid(&mut data, i, self.aids[(MAD2_SECTOR + 1 + i) as usize]);
+        }
+        data[0] = crc8(&data[1..]);
+        data
+    }
+}
+
+/// The `i`th AID of a MAD area.
+fn read_aid(data: &[u8], i: u8) -> u16 {
+    let offset = 2 + 2 * i as usize;
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn write_aid(data: &mut [u8], i: u8, aid: u16) {
+    let offset = 2 + 2 * i as usize;
+    data[offset..offset + 2].copy_from_slice(&aid.to_le_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Compares what a reader can see: the raw array also holds filler for
+    /// the MAD sectors, which differs between `new` and `parse_v1`.
+    fn assert_same_entries(a: &Mad, b: &Mad) {
+        assert_eq!(a.info, b.info);
+        for sector in 0..40 {
+            assert_eq!(a.aid(sector), b.aid(sector), "sector {sector}");
+        }
+    }
+
+    #[test]
+    fn crc_of_ndef_formatted_1k() {
+        // Sector 0 of a 1K card formatted by a phone: block 1 reads
+        // `14 01 03 E1 03 E1 ...`.
+        let data = Mad::ndef(CardType::Classic1K).encode_v1();
+        assert_eq!(data[..4], [0x14, 0x01, 0x03, 0xE1]);
+        assert_eq!(crc8(&data[1..]), 0x14);
+    }
+
+    #[test]
+    fn v1_round_trip() {
+        let mut mad = Mad::new(CardType::Classic1K);
+        mad.info = 0x05;
+        assert!(mad.set_aid(1, NDEF_AID));
+        assert!(mad.set_aid(7, 0x4801));
+        assert!(mad.set_aid(15, 0x4801));
+
+        let parsed = Mad::parse_v1(&mad.encode_v1()).unwrap();
+        assert_same_entries(&parsed, &mad);
+        assert!(parsed.sectors(0x4801).eq([7, 15]));
+        assert!(parsed.sectors(NDEF_AID).eq([1]));
+    }
+
+    #[test]
+    fn v2_round_trip() {
+        let mad = Mad::ndef(CardType::Classic4K);
+        let mut parsed = Mad::parse_v1(&mad.encode_v1()).unwrap();
+        assert_eq!(parsed.aid(17), Some(AID_NOT_APPLICABLE));
+        parsed.parse_v2(&mad.encode_v2()).unwrap();
+        assert_same_entries(&parsed, &mad);
+        assert_eq!(parsed.sectors(NDEF_AID).count(), 38);
+        assert_eq!(parsed.aid(39), Some(NDEF_AID));
+    }
+
+    #[test]
+    fn corrupt_crc_is_rejected() {
+        let mut v1 = Mad::ndef(CardType::Classic1K).encode_v1();
+        v1[20] ^= 0x01;
+        assert_eq!(Mad::parse_v1(&v1), Err(NdefError::Malformed));
+
+        let mut v1 = Mad::ndef(CardType::Classic1K).encode_v1();
+        v1[0] ^= 0x01;
+        assert_eq!(Mad::parse_v1(&v1), Err(NdefError::Malformed));
+
+        let mut mad = Mad::new(CardType::Classic4K);
+        let mut v2 = Mad::ndef(CardType::Classic4K).encode_v2();
+        v2[47] ^= 0x80;
+        assert_eq!(mad.parse_v2(&v2), Err(NdefError::Malformed));
+    }
+
+    #[test]
+    fn mad_sectors_have_no_entry() {
+        let mut mad = Mad::new(CardType::Classic4K);
+        assert!(!mad.set_aid(MAD_SECTOR, NDEF_AID));
+        assert!(!mad.set_aid(MAD2_SECTOR, NDEF_AID));
+        assert!(!mad.set_aid(40, NDEF_AID));
+        assert_eq!(mad.aid(MAD_SECTOR), None);
+        assert_eq!(mad.aid(MAD2_SECTOR), None);
+        assert_eq!(
+            Mad::new(CardType::Classic1K).aid(17),
+            Some(AID_NOT_APPLICABLE)
+        );
+    }
+
+    #[test]
+    fn gpb_versions() {
+        assert_eq!(version(GPB_MAD1), Some(1));
+        assert_eq!(version(GPB_MAD2), Some(2));
+        assert_eq!(version(GPB_NDEF), None);
+        assert_eq!(version(GPB_DA), None);
+    }
+}
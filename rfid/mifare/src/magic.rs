@@ -0,0 +1,34 @@
+//! "Magic" Classic clones with a writable block 0.
+//!
+//! Gen1a cards open a backdoor on a special unlock sequence sent after HLTA;
+//! after that every block, trailers included, can be read and written without
+//! authentication. Gen2 (CUID) cards need nothing special: block 0 simply
+//! accepts a normal authenticated write.
+
+use embedded_hal::spi::SpiDevice;
+use mfrc522::Error;
+
+use crate::pcd::Pcd;
+
+// Gen1a backdoor commands
+const MAGIC_UNLOCK_1: u8 = 0x40;
+const MAGIC_UNLOCK_2: u8 = 0x43;
+const ACK: u8 = 0x0A;
+
+/// Tries the Gen1a unlock sequence on the card in the field. Returns true if
+/// the card took it; every block can then be written until the next HLTA or
+/// WUPA.
+///
+/// A genuine card ignores the sequence and has to be activated again.
+pub fn unlock_gen1a<E, SPI: SpiDevice<Error = E>>(pcd: &mut Pcd<SPI>) -> Result<bool, Error<E>> {
+    pcd.halt()?;
+    let mut ack = [0u8; 1];
+    for (command, bits) in [(MAGIC_UNLOCK_1, 7), (MAGIC_UNLOCK_2, 0)] {
+        match pcd.transceive(&[command], bits, &mut ack) {
+            Ok(1) if ack[0] & 0x0F == ACK => {}
+            Ok(_) | Err(Error::Timeout) => return Ok(false),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,234 @@
+//! A scripted MFRC522 for unit tests: just enough of the register interface
+//! for [`Pcd`](crate::pcd::Pcd) to wake, select and authenticate one Classic
+//! card with a single UID. Frames after authentication aren't modelled; the
+//! `mfrc522-emu` crate does the whole card.
+
+use core::convert::Infallible;
+
+use embedded_hal::spi::{ErrorType, Operation, SpiDevice};
+
+use crate::access::KeyType;
+use crate::pcd::{crc_a, Register};
+
+const CMD_MASK: u8 = 0x0F;
+const CMD_TRANSCEIVE: u8 = 0x0C;
+const CMD_MF_AUTHENT: u8 = 0x0E;
+const START_SEND: u8 = 0x80;
+const IRQ_RX: u8 = 0x20;
+const IRQ_IDLE: u8 = 0x10;
+const IRQ_TIMER: u8 = 0x01;
+const CRYPTO1_ON: u8 = 0x08;
+const FIFO_FLUSH: u8 = 0x80;
+
+const ATQA: [u8; 2] = [0x04, 0x00];
+const SAK_1K: u8 = 0x08;
+
+/// Where the next byte of an SPI transaction goes.
+#[derive(Clone, Copy)]
+enum Access {
+    None,
+    Read(u8),
+    Write(u8),
+}
+
+pub struct MockReader {
+    regs: [u8; 64],
+    fifo: [u8; 64],
+    fifo_len: usize,
+    uid: [u8; 4],
+    key_a: [u8; 6],
+    key_b: [u8; 6],
+    /// Selected and able to authenticate. A failed authentication drops the
+    /// card back to idle, as a real one does.
+    active: bool,
+    /// Authentications tried, in order.
+    pub attempts: [Option<(KeyType, [u8; 6])>; 32],
+    pub attempt_count: usize,
+}
+
+impl MockReader {
+    pub fn new(uid: [u8; 4], key_a: [u8; 6], key_b: [u8; 6]) -> Self {
+        Self {
+            regs: [0; 64],
+            fifo: [0; 64],
+            fifo_len: 0,
+            uid,
+            key_a,
+            key_b,
+            active: false,
+            attempts: [None; 32],
+            attempt_count: 0,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    fn byte(&mut self, access: Access, mosi: u8) -> (Access, u8) {
+        match access {
+            Access::None | Access::Read(_) => {
+                let miso = match access {
+                    Access::Read(reg) => self.read(reg),
+                    _ => 0,
+                };
+                let reg = (mosi >> 1) & 0x3F;
+                let next = if mosi & 0x80 != 0 {
+                    Access::Read(reg)
+                } else {
+                    Access::Write(reg)
+                };
+                (next, miso)
+            }
+            Access::Write(reg) => {
+                self.write(reg, mosi);
+                (access, 0)
+            }
+        }
+    }
+
+    fn read(&mut self, reg: u8) -> u8 {
+        if reg == Register::FIFODataReg as u8 {
+            let b = self.fifo[0];
+            self.fifo.copy_within(1..self.fifo_len.max(1), 0);
+            self.fifo_len = self.fifo_len.saturating_sub(1);
+            b
+        } else if reg == Register::FIFOLevelReg as u8 {
+            self.fifo_len as u8
+        } else {
+            self.regs[reg as usize]
+        }
+    }
+
+    fn write(&mut self, reg: u8, val: u8) {
+        match reg {
+            r if r == Register::FIFODataReg as u8 => {
+                self.fifo[self.fifo_len] = val;
+                self.fifo_len += 1;
+            }
+            r if r == Register::FIFOLevelReg as u8 => {
+                if val & FIFO_FLUSH != 0 {
+                    self.fifo_len = 0;
+                }
+            }
+            r if r == Register::ComIrqReg as u8 => self.regs[r as usize] &= !val,
+            r if r == Register::CommandReg as u8 => {
+                self.regs[r as usize] = val;
+                if val & CMD_MASK == CMD_MF_AUTHENT {
+                    self.authenticate();
+                }
+            }
+            r if r == Register::BitFramingReg as u8 => {
+                self.regs[r as usize] = val & !START_SEND;
+                let command = self.regs[Register::CommandReg as usize] & CMD_MASK;
+                if val & START_SEND != 0 && command == CMD_TRANSCEIVE {
+                    self.transceive();
+                }
+            }
+            r => self.regs[r as usize] = val,
+        }
+    }
+
+    fn answer(&mut self, data: &[u8]) {
+        self.fifo[..data.len()].copy_from_slice(data);
+        self.fifo_len = data.len();
+        self.regs[Register::ComIrqReg as usize] |= IRQ_RX | IRQ_IDLE;
+    }
+
+    fn silence(&mut self) {
+        self.fifo_len = 0;
+        self.regs[Register::ComIrqReg as usize] |= IRQ_TIMER;
+    }
+
+    fn transceive(&mut self) {
+        let mut frame = [0u8; 64];
+        let len = self.fifo_len;
+        frame[..len].copy_from_slice(&self.fifo[..len]);
+        match &frame[..len] {
+            // REQA and WUPA
+            [0x26] | [0x52] => self.answer(&ATQA),
+            // HLTA
+            [0x50, 0x00, ..] => {
+                self.active = false;
+                self.silence();
+            }
+            [0x93, 0x70, rest @ ..] if rest[..4] == self.uid => {
+                self.active = true;
+                let crc = crc_a(&[SAK_1K]);
+                self.answer(&[SAK_1K, crc[0], crc[1]]);
+            }
+            _ => self.silence(),
+        }
+    }
+
+    fn authenticate(&mut self) {
+        let fifo = self.fifo;
+        self.fifo_len = 0;
+        let key_type = if fifo[0] == 0x61 {
+            KeyType::B
+        } else {
+            KeyType::A
+        };
+        let key: [u8; 6] = fifo[2..8].try_into().unwrap();
+        if let Some(slot) = self.attempts.get_mut(self.attempt_count) {
+            *slot = Some((key_type, key));
+        }
+        self.attempt_count += 1;
+
+        let expected = match key_type {
+            KeyType::A => self.key_a,
+            KeyType::B => self.key_b,
+        };
+        if self.active && fifo[8..12] == self.uid && key == expected {
+            self.regs[Register::Status2Reg as usize] |= CRYPTO1_ON;
+            self.regs[Register::ComIrqReg as usize] |= IRQ_IDLE;
+        } else {
+            self.active = false;
+            self.silence();
+        }
+    }
+}
+
+impl ErrorType for MockReader {
+    type Error = Infallible;
+}
+
+impl SpiDevice for MockReader {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Infallible> {
+        let mut access = Access::None;
+        for op in operations {
+            match op {
+                Operation::Write(bytes) => {
+                    for &b in bytes.iter() {
+                        access = self.byte(access, b).0;
+                    }
+                }
+                Operation::TransferInPlace(bytes) => {
+                    for b in bytes.iter_mut() {
+                        let (next, miso) = self.byte(access, *b);
+                        access = next;
+                        *b = miso;
+                    }
+                }
+                Operation::Transfer(read, write) => {
+                    for (i, &b) in write.iter().enumerate() {
+                        let (next, miso) = self.byte(access, b);
+                        access = next;
+                        if let Some(r) = read.get_mut(i) {
+                            *r = miso;
+                        }
+                    }
+                }
+                Operation::Read(bytes) => {
+                    for b in bytes.iter_mut() {
+                        let (next, miso) = self.byte(access, 0);
+                        access = next;
+                        *b = miso;
+                    }
+                }
+                Operation::DelayNs(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
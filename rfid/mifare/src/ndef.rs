@@ -0,0 +1,781 @@
+//! NDEF messages, as phones read and write them.
+//!
+//! On a tag the message sits inside a TLV block, usually followed by a
+//! terminator:
+//!
+//! ```text
+//! 03 len message... FE       len < 255
+//! 03 FF hi lo message... FE  longer messages
+//! ```
+//!
+//! A message is a list of records. Each record has a header byte with the MB
+//! (first record), ME (last record), CF (chunked), SR (short record) and IL
+//! (ID present) flags plus a 3-bit TNF, followed by the type, ID and payload
+//! lengths and the three fields themselves.
+
+use core::ops::Range;
+
+// TLV types
+const TLV_NULL: u8 = 0x00;
+pub const TLV_NDEF: u8 = 0x03;
+pub const TLV_TERMINATOR: u8 = 0xFE;
+/// First length byte announcing a 3-byte length.
+const TLV_LONG_LENGTH: u8 = 0xFF;
+
+// Record header flags
+const FLAG_MB: u8 = 0x80;
+const FLAG_ME: u8 = 0x40;
+const FLAG_CF: u8 = 0x20;
+const FLAG_SR: u8 = 0x10;
+const FLAG_IL: u8 = 0x08;
+const TNF_MASK: u8 = 0x07;
+
+// Text record status byte
+const TEXT_UTF16: u8 = 0x80;
+const TEXT_LANG_MASK: u8 = 0x3F;
+
+/// Abbreviations for the start of a URI, by identifier code.
+const URI_PREFIXES: [&str; 36] = [
+    "",
+    "http://www.",
+    "https://www.",
+    "http://",
+    "https://",
+    "tel:",
+    "mailto:",
+    "ftp://anonymous:anonymous@",
+    "ftp://ftp.",
+    "ftps://",
+    "sftp://",
+    "smb://",
+    "nfs://",
+    "ftp://",
+    "dav://",
+    "news:",
+    "telnet://",
+    "imap:",
+    "rtsp://",
+    "urn:",
+    "pop:",
+    "sip:",
+    "sips:",
+    "tftp:",
+    "btspp://",
+    "btl2cap://",
+    "btgoep://",
+    "tcpobex://",
+    "irdaobex://",
+    "file://",
+    "urn:epc:id:",
+    "urn:epc:tag:",
+    "urn:epc:pat:",
+    "urn:epc:raw:",
+    "urn:epc:",
+    "urn:nfc:",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NdefError {
+    /// The data ends in the middle of a TLV or record.
+    Incomplete,
+    /// A length or flag doesn't make sense.
+    Malformed,
+    /// There is no NDEF TLV on the tag.
+    NoMessage,
+    /// The output buffer or the tag is too small.
+    NoRoom,
+    /// The tag isn't formatted for NDEF.
+    NotFormatted,
+    /// The tag is formatted read-only.
+    ReadOnly,
+}
+
+impl NdefError {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            NdefError::Incomplete => "NDEF data is truncated",
+            NdefError::Malformed => "NDEF data is malformed",
+            NdefError::NoMessage => "No NDEF message on the tag",
+            NdefError::NoRoom => "NDEF message doesn't fit",
+            NdefError::NotFormatted => "Tag is not formatted for NDEF",
+            NdefError::ReadOnly => "Tag is read-only",
+        }
+    }
+}
+
+/// Reading or writing NDEF on a tag fails either at the radio or in the data.
+#[derive(Debug)]
+pub enum TagError<E> {
+    Card(mfrc522::Error<E>),
+    Ndef(NdefError),
+}
+
+impl<E> From<mfrc522::Error<E>> for TagError<E> {
+    fn from(e: mfrc522::Error<E>) -> Self {
+        TagError::Card(e)
+    }
+}
+
+impl<E> From<NdefError> for TagError<E> {
+    fn from(e: NdefError) -> Self {
+        TagError::Ndef(e)
+    }
+}
+
+impl<E> TagError<E> {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            TagError::Card(_) => "Card communication failed",
+            TagError::Ndef(e) => e.as_str(),
+        }
+    }
+}
+
+/// Type Name Format: how to interpret the record type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tnf {
+    Empty,
+    /// NFC Forum well-known type, like `T` (text) or `U` (URI).
+    WellKnown,
+    /// MIME media type, like `text/plain`.
+    Media,
+    AbsoluteUri,
+    /// NFC Forum external type, like `example.com:thing`.
+    External,
+    Unknown,
+    /// Continuation of a chunked record.
+    Unchanged,
+    Reserved,
+}
+
+impl Tnf {
+    const fn from_bits(bits: u8) -> Self {
+        match bits & TNF_MASK {
+            0 => Tnf::Empty,
+            1 => Tnf::WellKnown,
+            2 => Tnf::Media,
+            3 => Tnf::AbsoluteUri,
+            4 => Tnf::External,
+            5 => Tnf::Unknown,
+            6 => Tnf::Unchanged,
+            _ => Tnf::Reserved,
+        }
+    }
+
+    const fn bits(self) -> u8 {
+        self as u8
+    }
+}
+
+/// A decoded record, borrowing from the message it came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record<'a> {
+    pub tnf: Tnf,
+    pub record_type: &'a [u8],
+    pub id: &'a [u8],
+    pub payload: &'a [u8],
+    /// More chunks of the same payload follow, in records with TNF
+    /// `Unchanged`.
+    pub chunked: bool,
+}
+
+/// Payload of a well-known Text record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Text<'a> {
+    /// IANA language code, like `en`.
+    pub lang: &'a str,
+    pub text: &'a str,
+}
+
+/// Payload of a well-known URI record. The full URI is `prefix` followed by
+/// `rest`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Uri<'a> {
+    pub prefix: &'static str,
+    pub rest: &'a str,
+}
+
+impl<'a> Record<'a> {
+    /// Decodes a Text record. UTF-16 text isn't supported and gives `None`.
+    pub fn as_text(&self) -> Option<Text<'a>> {
+        if self.tnf != Tnf::WellKnown || self.record_type != b"T" {
+            return None;
+        }
+        let (&status, rest) = self.payload.split_first()?;
+        if status & TEXT_UTF16 != 0 {
+            return None;
+        }
+        let lang_len = (status & TEXT_LANG_MASK) as usize;
+        if lang_len > rest.len() {
+            return None;
+        }
+        let (lang, text) = rest.split_at(lang_len);
+        Some(Text {
+            lang: core::str::from_utf8(lang).ok()?,
+            text: core::str::from_utf8(text).ok()?,
+        })
+    }
+
+    /// Decodes a URI record, expanding the abbreviated prefix.
+    pub fn as_uri(&self) -> Option<Uri<'a>> {
+        if self.tnf != Tnf::WellKnown || self.record_type != b"U" {
+            return None;
+        }
+        let (&code, rest) = self.payload.split_first()?;
+        Some(Uri {
+            prefix: URI_PREFIXES.get(code as usize)?,
+            rest: core::str::from_utf8(rest).ok()?,
+        })
+    }
+
+    /// Returns the MIME type and the raw payload of a media record.
+    pub fn as_mime(&self) -> Option<(&'a str, &'a [u8])> {
+        if self.tnf != Tnf::Media {
+            return None;
+        }
+        Some((core::str::from_utf8(self.record_type).ok()?, self.payload))
+    }
+}
+
+impl core::fmt::Display for Uri<'_> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.prefix)?;
+        f.write_str(self.rest)
+    }
+}
+
+/// Iterates over the records of a message.
+pub struct Records<'a> {
+    data: &'a [u8],
+    done: bool,
+}
+
+/// Starts decoding a message. Errors are reported by the iterator, which
+/// stops after the first one.
+pub fn records(message: &[u8]) -> Records<'_> {
+    Records {
+        data: message,
+        done: message.is_empty(),
+    }
+}
+
+impl<'a> Records<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], NdefError> {
+        if n > self.data.len() {
+            return Err(NdefError::Incomplete);
+        }
+        let (head, rest) = self.data.split_at(n);
+        self.data = rest;
+        Ok(head)
+    }
+
+    fn next_record(&mut self) -> Result<Record<'a>, NdefError> {
+        let header = self.take(1)?[0];
+        let type_len = self.take(1)?[0] as usize;
+        let payload_len = if header & FLAG_SR != 0 {
+            self.take(1)?[0] as usize
+        } else {
+            let len = self.take(4)?;
+            u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize
+        };
+        let id_len = if header & FLAG_IL != 0 {
+            self.take(1)?[0] as usize
+        } else {
+            0
+        };
+
+        let record = Record {
+            tnf: Tnf::from_bits(header),
+            record_type: self.take(type_len)?,
+            id: self.take(id_len)?,
+            payload: self.take(payload_len)?,
+            chunked: header & FLAG_CF != 0,
+        };
+        if record.tnf == Tnf::Empty && !(record.record_type.is_empty() && record.payload.is_empty())
+        {
+            return Err(NdefError::Malformed);
+        }
+
+        if header & FLAG_ME != 0 {
+            self.done = true;
+        } else if self.data.is_empty() {
+            return Err(NdefError::Incomplete);
+        }
+        Ok(record)
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = Result<Record<'a>, NdefError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let record = self.next_record();
+        if record.is_err() {
+            self.done = true;
+        }
+        Some(record)
+    }
+}
+
+/// Builds a message record by record into a caller supplied buffer.
+///
+/// Short records are used whenever the payload fits in 255 bytes. The MB flag
+/// goes on the first record and `finish` sets ME on the last one.
+pub struct MessageWriter<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+    /// Offset of the header byte of the last record written.
+    last_header: Option<usize>,
+}
+
+impl<'b> MessageWriter<'b> {
+    pub fn new(buf: &'b mut [u8]) -> Self {
+        Self {
+            buf,
+            len: 0,
+            last_header: None,
+        }
+    }
+
+    /// Adds a UTF-8 Text record.
+    pub fn text(&mut self, lang: &str, text: &str) -> Result<(), NdefError> {
+        if lang.len() > TEXT_LANG_MASK as usize {
+            return Err(NdefError::Malformed);
+        }
+        let status = [lang.len() as u8];
+        self.record_parts(
+            Tnf::WellKnown,
+            b"T",
+            &[],
+            &[&status, lang.as_bytes(), text.as_bytes()],
+        )
+    }
+
+    /// Adds a URI record, abbreviating the longest known prefix.
+    pub fn uri(&mut self, uri: &str) -> Result<(), NdefError> {
+        let (code, prefix) = URI_PREFIXES
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter(|(_, p)| uri.starts_with(*p))
+            .max_by_key(|(_, p)| p.len())
+            .unwrap_or((0, &""));
+        self.record_parts(
+            Tnf::WellKnown,
+            b"U",
+            &[],
+            &[&[code as u8], &uri.as_bytes()[prefix.len()..]],
+        )
+    }
+
+    /// Adds a MIME media record.
+    pub fn mime(&mut self, mime_type: &str, payload: &[u8]) -> Result<(), NdefError> {
+        self.record_parts(Tnf::Media, mime_type.as_bytes(), &[], &[payload])
+    }
+
+    /// Adds an arbitrary record.
+    pub fn record(
+        &mut self,
+        tnf: Tnf,
+        record_type: &[u8],
+        id: &[u8],
+        payload: &[u8],
+    ) -> Result<(), NdefError> {
+        self.record_parts(tnf, record_type, id, &[payload])
+    }
+
+    /// Sets ME on the last record and returns the message.
+    pub fn finish(self) -> Result<&'b [u8], NdefError> {
+        let last = self.last_header.ok_or(NdefError::NoMessage)?;
+        self.buf[last] |= FLAG_ME;
+        Ok(&self.buf[..self.len])
+    }
+
+    fn record_parts(
+        &mut self,
+        tnf: Tnf,
+        record_type: &[u8],
+        id: &[u8],
+        payload: &[&[u8]],
+    ) -> Result<(), NdefError> {
+        if record_type.len() > u8::MAX as usize || id.len() > u8::MAX as usize {
+            return Err(NdefError::Malformed);
+        }
+        let payload_len: usize = payload.iter().map(|p| p.len()).sum();
+        let short = payload_len <= u8::MAX as usize;
+
+        let mut header = tnf.bits();
+        if self.last_header.is_none() {
+            header |= FLAG_MB;
+        }
+        if short {
+            header |= FLAG_SR;
+        }
+        if !id.is_empty() {
+            header |= FLAG_IL;
+        }
+
+        let start = self.len;
+        self.push(&[header, record_type.len() as u8])?;
+        if short {
+            self.push(&[payload_len as u8])?;
+        } else {
+            let len = u32::try_from(payload_len).map_err(|_| NdefError::NoRoom)?;
+            self.push(&len.to_be_bytes())?;
+        }
+        if !id.is_empty() {
+            self.push(&[id.len() as u8])?;
+        }
+        self.push(record_type)?;
+        self.push(id)?;
+        for part in payload {
+            self.push(part)?;
+        }
+
+        self.last_header = Some(start);
+        Ok(())
+    }
+
+    fn push(&mut self, data: &[u8]) -> Result<(), NdefError> {
+        let end = self.len + data.len();
+        if end > self.buf.len() {
+            return Err(NdefError::NoRoom);
+        }
+        self.buf[self.len..end].copy_from_slice(data);
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// The NDEF TLV header for a message of `len` bytes: the header bytes and how
+/// many of them to use.
+pub fn tlv_header(len: usize) -> Result<([u8; 4], usize), NdefError> {
+    if len < TLV_LONG_LENGTH as usize {
+        Ok(([TLV_NDEF, len as u8, 0, 0], 2))
+    } else if len <= u16::MAX as usize {
+        let [hi, lo] = (len as u16).to_be_bytes();
+        Ok(([TLV_NDEF, TLV_LONG_LENGTH, hi, lo], 4))
+    } else {
+        Err(NdefError::NoRoom)
+    }
+}
+
+/// Bytes needed to store a message of `len` bytes as a TLV with terminator.
+pub fn tlv_size(len: usize) -> Result<usize, NdefError> {
+    Ok(tlv_header(len)?.1 + len + 1)
+}
+
+/// Yields the TLV bytes of `message`: header, message and terminator.
+///
+/// Tag writers pull blocks or pages out of this, so the message never needs
+/// to be copied into a second buffer.
+pub fn tlv_bytes(message: &[u8]) -> Result<impl Iterator<Item = u8> + '_, NdefError> {
+    let (header, n) = tlv_header(message.len())?;
+    Ok(header
+        .into_iter()
+        .take(n)
+        .chain(message.iter().copied())
+        .chain([TLV_TERMINATOR]))
+}
+
+/// Finds the first NDEF message in the data area of a tag.
+///
+/// NULL TLVs are skipped, as are lock control, memory control and
+/// proprietary TLVs. `Incomplete` means the area ends before the message
+/// does, so the caller should read more and try again.
+pub fn find_message(area: &[u8]) -> Result<&[u8], NdefError> {
+    message_range(area).map(|range| &area[range])
+}
+
+/// Like [`find_message`], but returns where the message is in `area`.
+pub fn message_range(area: &[u8]) -> Result<Range<usize>, NdefError> {
+    let mut i = 0;
+    while i < area.len() {
+        let tlv_type = area[i];
+        i += 1;
+        match tlv_type {
+            TLV_NULL => continue,
+            TLV_TERMINATOR => return Err(NdefError::NoMessage),
+            _ => {}
+        }
+
+        let len = match area.get(i) {
+            None => return Err(NdefError::Incomplete),
+            Some(&TLV_LONG_LENGTH) => {
+                let len = area.get(i + 1..i + 3).ok_or(NdefError::Incomplete)?;
+                i += 3;
+                u16::from_be_bytes([len[0], len[1]]) as usize
+            }
+            Some(&len) => {
+                i += 1;
+                len as usize
+            }
+        };
+
+        if i + len > area.len() {
+            return Err(NdefError::Incomplete);
+        }
+        if tlv_type == TLV_NDEF {
+            return Ok(i..i + len);
+        }
+        i += len;
+    }
+    Err(NdefError::Incomplete)
+}
+
+/// Writes a one-line description of a record, for dumps.
+pub fn describe<W: core::fmt::Write>(record: &Record, out: &mut W) -> core::fmt::Result {
+    if let Some(text) = record.as_text() {
+        write!(out, "Text [{}]: {}", text.lang, text.text)
+    } else if let Some(uri) = record.as_uri() {
+        write!(out, "URI: {}", uri)
+    } else if let Some((mime, payload)) = record.as_mime() {
+        write!(out, "{} ({} bytes)", mime, payload.len())
+    } else {
+        write!(
+            out,
+            "{:?} record ({} bytes)",
+            record.tnf,
+            record.payload.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn only_record(message: &[u8]) -> Record<'_> {
+        let mut iter = records(message);
+        let record = iter.next().unwrap().unwrap();
+        assert!(iter.next().is_none());
+        record
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let mut buf = [0u8; 64];
+        let mut w = MessageWriter::new(&mut buf);
+        w.text("en", "Hello").unwrap();
+        let message = w.finish().unwrap();
+        assert_eq!(
+            message,
+            [0xD1, 0x01, 0x08, b'T', 0x02, b'e', b'n', b'H', b'e', b'l', b'l', b'o']
+        );
+
+        let text = only_record(message).as_text().unwrap();
+        assert_eq!(text.lang, "en");
+        assert_eq!(text.text, "Hello");
+    }
+
+    #[test]
+    fn utf16_text_is_not_decoded() {
+        let message = [0xD1, 0x01, 0x03, b'T', 0x80 | 0x02, b'e', b'n'];
+        assert_eq!(only_record(&message).as_text(), None);
+    }
+
+    #[test]
+    fn uri_round_trip() {
+        let mut buf = [0u8; 64];
+        let mut w = MessageWriter::new(&mut buf);
+        w.uri("https://www.example.com").unwrap();
+        let message = w.finish().unwrap();
+        // The longest matching prefix wins: "https://www." over "https://".
+        assert_eq!(message[..5], [0xD1, 0x01, 0x0C, b'U', 0x02]);
+
+        let uri = only_record(message).as_uri().unwrap();
+        assert_eq!(uri.prefix, "https://www.");
+        assert_eq!(uri.rest, "example.com");
+    }
+
+    #[test]
+    fn uri_without_known_prefix() {
+        let mut buf = [0u8; 64];
+        let mut w = MessageWriter::new(&mut buf);
+        w.uri("geo:52.0,4.3").unwrap();
+        let message = w.finish().unwrap();
+        let uri = only_record(message).as_uri().unwrap();
+        assert_eq!(uri.prefix, "");
+        assert_eq!(uri.rest, "geo:52.0,4.3");
+    }
+
+    #[test]
+    fn mime_round_trip() {
+        let mut buf = [0u8; 64];
+        let mut w = MessageWriter::new(&mut buf);
+        w.mime("text/plain", b"hi").unwrap();
+        let message = w.finish().unwrap();
+        assert_eq!(message[..3], [0xD2, 0x0A, 0x02]);
+
+        let (mime, payload) = only_record(message).as_mime().unwrap();
+        assert_eq!(mime, "text/plain");
+        assert_eq!(payload, b"hi");
+    }
+
+    #[test]
+    fn flags_on_several_records() {
+        let mut buf = [0u8; 64];
+        let mut w = MessageWriter::new(&mut buf);
+        w.text("en", "a").unwrap();
+        w.record(Tnf::External, b"x:y", b"id", b"z").unwrap();
+        w.uri("tel:123").unwrap();
+        let message = w.finish().unwrap();
+
+        let headers: [u8; 3] = [message[0], message[8], message[18]];
+        assert_eq!(headers[0], FLAG_MB | FLAG_SR | 0x01);
+        assert_eq!(headers[1], FLAG_SR | FLAG_IL | 0x04);
+        assert_eq!(headers[2], FLAG_ME | FLAG_SR | 0x01);
+
+        let mut iter = records(message);
+        assert_eq!(iter.next().unwrap().unwrap().as_text().unwrap().text, "a");
+        let external = iter.next().unwrap().unwrap();
+        assert_eq!(external.tnf, Tnf::External);
+        assert_eq!(external.record_type, b"x:y");
+        assert_eq!(external.id, b"id");
+        assert_eq!(external.payload, b"z");
+        assert_eq!(iter.next().unwrap().unwrap().as_uri().unwrap().rest, "123");
+        assert!(iter.next().is_none());
+    }
+
+    #[test]
+    fn short_record_up_to_255_bytes() {
+        let payload = [0xAB; 255];
+        let mut buf = [0u8; 300];
+        let mut w = MessageWriter::new(&mut buf);
+        w.mime("a/b", &payload).unwrap();
+        let message = w.finish().unwrap();
+        assert_eq!(message[0] & FLAG_SR, FLAG_SR);
+        assert_eq!(message.len(), 3 + 3 + 255);
+        assert_eq!(only_record(message).payload, payload);
+    }
+
+    #[test]
+    fn long_record_above_255_bytes() {
+        let payload = [0xCD; 256];
+        let mut buf = [0u8; 300];
+        let mut w = MessageWriter::new(&mut buf);
+        w.mime("a/b", &payload).unwrap();
+        let message = w.finish().unwrap();
+        assert_eq!(message[0] & FLAG_SR, 0);
+        assert_eq!(message[2..6], [0x00, 0x00, 0x01, 0x00]);
+        assert_eq!(message.len(), 6 + 3 + 256);
+        assert_eq!(only_record(message).payload, payload);
+    }
+
+    #[test]
+    fn long_record_with_short_payload_decodes() {
+        // Writers may use a 4-byte length even when one byte would do.
+        let message = [0xC1, 0x01, 0x00, 0x00, 0x00, 0x03, b'T', 0x00, b'o', b'k'];
+        assert_eq!(only_record(&message).as_text().unwrap().text, "ok");
+    }
+
+    #[test]
+    fn writer_rejects_bad_input() {
+        let mut buf = [0u8; 8];
+        let mut w = MessageWriter::new(&mut buf);
+        assert_eq!(w.text(&"x".repeat(64), ""), Err(NdefError::Malformed));
+        assert_eq!(w.mime("a/b", &[0; 8]), Err(NdefError::NoRoom));
+
+        let empty = MessageWriter::new(&mut buf);
+        assert_eq!(empty.finish(), Err(NdefError::NoMessage));
+    }
+
+    #[test]
+    fn truncated_records() {
+        let message = [
+            0xD1, 0x01, 0x08, b'T', 0x02, b'e', b'n', b'H', b'e', b'l', b'l', b'o',
+        ];
+        for len in 1..message.len() {
+            let mut iter = records(&message[..len]);
+            assert_eq!(iter.next(), Some(Err(NdefError::Incomplete)), "{len}");
+            assert!(iter.next().is_none());
+        }
+        // A record without ME that is the last one in the data.
+        let unterminated = [0x91, 0x01, 0x01, b'T', 0x00];
+        assert_eq!(
+            records(&unterminated).next(),
+            Some(Err(NdefError::Incomplete))
+        );
+    }
+
+    #[test]
+    fn empty_record_with_payload_is_malformed() {
+        let message = [0xD0, 0x00, 0x01, 0xAA];
+        assert_eq!(records(&message).next(), Some(Err(NdefError::Malformed)));
+    }
+
+    #[test]
+    fn tlv_header_lengths() {
+        assert_eq!(tlv_header(0), Ok(([TLV_NDEF, 0, 0, 0], 2)));
+        assert_eq!(tlv_header(254), Ok(([TLV_NDEF, 254, 0, 0], 2)));
+        assert_eq!(tlv_header(255), Ok(([TLV_NDEF, 0xFF, 0x00, 0xFF], 4)));
+        assert_eq!(tlv_header(0x1234), Ok(([TLV_NDEF, 0xFF, 0x12, 0x34], 4)));
+        assert_eq!(tlv_header(0xFFFF), Ok(([TLV_NDEF, 0xFF, 0xFF, 0xFF], 4)));
+        assert_eq!(tlv_header(0x10000), Err(NdefError::NoRoom));
+
+        assert_eq!(tlv_size(254), Ok(2 + 254 + 1));
+        assert_eq!(tlv_size(255), Ok(4 + 255 + 1));
+    }
+
+    #[test]
+    fn tlv_round_trip() {
+        for len in [0, 1, 254, 255, 300] {
+            let message = [0x5A; 300];
+            let message = &message[..len];
+            let mut area = [0u8; 320];
+            let mut n = 0;
+            for (slot, b) in area.iter_mut().zip(tlv_bytes(message).unwrap()) {
+                *slot = b;
+                n += 1;
+            }
+            assert_eq!(Ok(n), tlv_size(len));
+            assert_eq!(area[n - 1], TLV_TERMINATOR);
+            assert_eq!(find_message(&area), Ok(message), "{len}");
+        }
+    }
+
+    #[test]
+    fn message_range_skips_other_tlvs() {
+        // NULL, lock control TLV, then the message.
+        let area = [
+            0x00, 0x01, 0x03, 0xA0, 0x10, 0x44, 0x03, 0x02, 0xAA, 0xBB, 0xFE,
+        ];
+        assert_eq!(message_range(&area), Ok(8..10));
+        assert_eq!(
+            message_range(&[0x00, 0xFE, 0x03]),
+            Err(NdefError::NoMessage)
+        );
+        assert_eq!(message_range(&[]), Err(NdefError::Incomplete));
+    }
+
+    #[test]
+    fn message_range_on_truncated_input() {
+        let short = [TLV_NDEF, 0x03, 0xAA, 0xBB, 0xCC, TLV_TERMINATOR];
+        for len in 0..4 {
+            assert_eq!(
+                message_range(&short[..len]),
+                Err(NdefError::Incomplete),
+                "{len}"
+            );
+        }
+        assert_eq!(message_range(&short[..5]), Ok(2..5));
+
+        let long = [TLV_NDEF, 0xFF, 0x01, 0x00];
+        for len in 1..=long.len() {
+            assert_eq!(
+                message_range(&long[..len]),
+                Err(NdefError::Incomplete),
+                "{len}"
+            );
+        }
+
+        // A TLV before the message that runs off the end.
+        assert_eq!(
+            message_range(&[0x01, 0x05, 0x00]),
+            Err(NdefError::Incomplete)
+        );
+    }
+}
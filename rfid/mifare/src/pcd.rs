@@ -0,0 +1,589 @@
+//! Register-level MFRC522 commands the `mfrc522` driver doesn't expose.
+//!
+//! The driver keeps its register map private, so [`Pcd`] talks to the chip
+//! over SPI itself, framing each access the way the driver's `SpiInterface`
+//! does. The driver takes ownership of its SPI device, so [`SharedSpi`] lets
+//! it and [`Pcd`] use the same one:
+//!
+//! ```ignore
+//! let spi = RefCell::new(spi);
+//! let mut rfid = Mfrc522::new(SpiInterface::new(SharedSpi::new(&spi))).init().unwrap();
+//! let mut pcd = Pcd::new(SharedSpi::new(&spi));
+//! ```
+
+use core::cell::RefCell;
+
+use embedded_hal::spi::{ErrorType, Operation, SpiDevice};
+use mfrc522::{Error, Uid};
+
+use crate::access::KeyType;
+use crate::geometry::CardType;
+
+/// The MFRC522 registers we use, at their datasheet addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Register {
+    CommandReg = 0x01,
+    ComIEnReg = 0x02,
+    DivIEnReg = 0x03,
+    ComIrqReg = 0x04,
+    ErrorReg = 0x06,
+    Status2Reg = 0x08,
+    FIFODataReg = 0x09,
+    FIFOLevelReg = 0x0A,
+    ControlReg = 0x0C,
+    BitFramingReg = 0x0D,
+    CollReg = 0x0E,
+    ModeReg = 0x11,
+    TxModeReg = 0x12,
+    RxModeReg = 0x13,
+    TxControlReg = 0x14,
+    TxASKReg = 0x15,
+    ModWidthReg = 0x24,
+    RFCfgReg = 0x26,
+    TModeReg = 0x2A,
+    TPrescalerReg = 0x2B,
+    TReloadRegHigh = 0x2C,
+    TReloadRegLow = 0x2D,
+    AutoTestReg = 0x36,
+    VersionReg = 0x37,
+}
+
+impl Register {
+    /// The first byte of an SPI read of this register.
+    const fn read_address(self) -> u8 {
+        ((self as u8) << 1) | 0x80
+    }
+
+    /// The first byte of an SPI write to this register.
+    const fn write_address(self) -> u8 {
+        (self as u8) << 1
+    }
+}
+
+// PCD commands (CommandReg)
+const CMD_IDLE: u8 = 0x00;
+const CMD_TRANSCEIVE: u8 = 0x0C;
+const CMD_MF_AUTHENT: u8 = 0x0E;
+
+// PICC commands
+const PICC_REQA: u8 = 0x26;
+const PICC_WUPA: u8 = 0x52;
+const PICC_HLTA: u8 = 0x50;
+const PICC_SEL_CL: [u8; 3] = [0x93, 0x95, 0x97];
+const PICC_AUTH_KEY_A: u8 = 0x60;
+const PICC_AUTH_KEY_B: u8 = 0x61;
+
+/// Cascade tag, prefixed to UID parts that don't complete the UID.
+const CASCADE_TAG: u8 = 0x88;
+/// SAK bit telling that the UID continues at the next cascade level.
+const SAK_UID_INCOMPLETE: u8 = 0x04;
+
+// ComIrqReg bits
+const IRQ_ALL: u8 = 0x7F;
+const IRQ_RX: u8 = 0x20;
+const IRQ_IDLE: u8 = 0x10;
+const IRQ_ERR: u8 = 0x02;
+const IRQ_TIMER: u8 = 0x01;
+
+// ErrorReg bits
+const ERR_PROTOCOL: u8 = 0x01;
+const ERR_PARITY: u8 = 0x02;
+const ERR_COLLISION: u8 = 0x08;
+const ERR_BUFFER_OVERFLOW: u8 = 0x10;
+
+// Status2Reg bits
+const STATUS2_CRYPTO1_ON: u8 = 0x08;
+
+// FIFOLevelReg bits
+const FIFO_FLUSH: u8 = 0x80;
+
+// BitFramingReg bits
+const BIT_FRAMING_START_SEND: u8 = 0x80;
+
+// CollReg bits
+const COLL_VALUES_AFTER_COLL: u8 = 0x80;
+const COLL_POS_NOT_VALID: u8 = 0x20;
+const COLL_POS: u8 = 0x1F;
+
+// ControlReg bits
+const CONTROL_RX_LAST_BITS: u8 = 0x07;
+
+/// Bytes of UID, or cascade tag and UID, per cascade level, plus the BCC.
+const LEVEL_SIZE: usize = 5;
+
+/// Largest frame we send or receive, CRC included.
+const MAX_FRAME: usize = 24;
+
+/// How many times to poll ComIrqReg before giving up on a command. The chip's
+/// own timer normally fires long before this runs out.
+const MAX_POLLS: u16 = 2000;
+
+/// What a card answered while being woken up and selected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Activation {
+    pub atqa: [u8; 2],
+    pub sak: u8,
+}
+
+impl Activation {
+    pub fn card_type(&self) -> Option<CardType> {
+        CardType::from_sak_atqa(self.sak, self.atqa)
+    }
+}
+
+/// A card picked out of the field by the anticollision loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Selected {
+    uid: [u8; 10],
+    uid_len: u8,
+    pub sak: u8,
+}
+
+impl Selected {
+    /// The full UID, 4, 7 or 10 bytes.
+    pub fn uid(&self) -> &[u8] {
+        &self.uid[..self.uid_len as usize]
+    }
+
+    #[cfg(test)]
+    pub(crate) fn new(uid: &[u8], sak: u8) -> Self {
+        let mut selected = Self {
+            uid: [0; 10],
+            uid_len: uid.len() as u8,
+            sak,
+        };
+        selected.uid[..uid.len()].copy_from_slice(uid);
+        selected
+    }
+}
+
+/// The ISO 14443-3 type A CRC, in the byte order it is sent.
+pub fn crc_a(data: &[u8]) -> [u8; 2] {
+    let mut crc: u16 = 0x6363;
+    for &b in data {
+        let mut ch = b ^ crc as u8;
+        ch ^= ch << 4;
+        let ch = ch as u16;
+        crc = (crc >> 8) ^ (ch << 8) ^ (ch << 3) ^ (ch >> 4);
+    }
+    crc.to_le_bytes()
+}
+
+/// An [`SpiDevice`] that borrows a shared one for every transaction.
+pub struct SharedSpi<'a, SPI> {
+    spi: &'a RefCell<SPI>,
+}
+
+impl<'a, SPI> SharedSpi<'a, SPI> {
+    pub fn new(spi: &'a RefCell<SPI>) -> Self {
+        Self { spi }
+    }
+}
+
+impl<SPI: ErrorType> ErrorType for SharedSpi<'_, SPI> {
+    type Error = SPI::Error;
+}
+
+impl<SPI: SpiDevice> SpiDevice for SharedSpi<'_, SPI> {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+        self.spi.borrow_mut().transaction(operations)
+    }
+}
+
+/// Direct access to the MFRC522 for commands the driver lacks.
+pub struct Pcd<SPI> {
+    spi: SPI,
+}
+
+impl<E, SPI: SpiDevice<Error = E>> Pcd<SPI> {
+    pub fn new(spi: SPI) -> Self {
+        Self { spi }
+    }
+
+    /// Gives the SPI device back.
+    pub fn release(self) -> SPI {
+        self.spi
+    }
+
+    /// Authenticates a sector with either key. The `mfrc522` driver only
+    /// knows about Key A.
+    ///
+    /// On success Crypto1 is switched on; call `stop_crypto1` when done.
+    pub fn authenticate(
+        &mut self,
+        uid: &Uid,
+        block: u8,
+        key_type: KeyType,
+        key: &[u8; 6],
+    ) -> Result<(), Error<E>> {
+        let mut frame = [0u8; 12];
+        frame[0] = match key_type {
+            KeyType::A => PICC_AUTH_KEY_A,
+            KeyType::B => PICC_AUTH_KEY_B,
+        };
+        frame[1] = block;
+        frame[2..8].copy_from_slice(key);
+        // Cards with 7 or 10 byte UIDs authenticate with the last four bytes
+        let uid = uid.as_bytes();
+        frame[8..12].copy_from_slice(&uid[uid.len() - 4..]);
+
+        self.execute(CMD_MF_AUTHENT, &frame, IRQ_IDLE | IRQ_ERR)?;
+
+        if self.read(Register::Status2Reg)? & STATUS2_CRYPTO1_ON == 0 {
+            return Err(Error::Protocol);
+        }
+        Ok(())
+    }
+
+    /// Wakes the card up again, selects it by its known UID and returns what
+    /// it answered on the way: the ATQA to WUPA and the final SAK.
+    ///
+    /// Works from any state, so it is also the way back after a failed
+    /// authentication or a NAK.
+    pub fn activate(&mut self, uid: &Uid) -> Result<Activation, Error<E>> {
+        self.halt()?;
+        let atqa = self.request(true)?;
+        let sak = self.select(uid.as_bytes())?;
+        Ok(Activation { atqa, sak })
+    }
+
+    /// Runs the SELECT cascade for a known UID of 4, 7 or 10 bytes and
+    /// returns the SAK of the last level.
+    pub fn select(&mut self, uid: &[u8]) -> Result<u8, Error<E>> {
+        let levels = match uid.len() {
+            4 => 1,
+            7 => 2,
+            10 => 3,
+            _ => return Err(Error::Protocol),
+        };
+
+        let mut sak = 0;
+        let mut offset = 0;
+        for (level, &sel) in PICC_SEL_CL.iter().enumerate().take(levels) {
+            let last = level == levels - 1;
+            let mut part = [0u8; 4];
+            if last {
+                part.copy_from_slice(&uid[offset..offset + 4]);
+            } else {
+                part[0] = CASCADE_TAG;
+                part[1..].copy_from_slice(&uid[offset..offset + 3]);
+                offset += 3;
+            }
+            let bcc = part.iter().fold(0, |acc, b| acc ^ b);
+
+            let mut frame = [sel, 0x70, 0, 0, 0, 0, bcc];
+            frame[2..6].copy_from_slice(&part);
+            let mut answer = [0u8; 1];
+            if self.transceive_crc(&frame, &mut answer)? != 1 {
+                return Err(Error::IncompleteFrame);
+            }
+            sak = answer[0];
+
+            if last == (sak & SAK_UID_INCOMPLETE != 0) {
+                return Err(Error::Protocol);
+            }
+        }
+        Ok(sak)
+    }
+
+    /// Sends REQA, or WUPA if `wake` is set, and returns the ATQA. REQA only
+    /// reaches idle cards, WUPA halted ones as well.
+    ///
+    /// Several cards answering at once garble the ATQA; that shows up as
+    /// `Error::Collision` and still means there are cards to select.
+    pub fn request(&mut self, wake: bool) -> Result<[u8; 2], Error<E>> {
+        let command = if wake { PICC_WUPA } else { PICC_REQA };
+        let mut atqa = [0u8; 2];
+        if self.transceive(&[command], 7, &mut atqa)? != atqa.len() {
+            return Err(Error::IncompleteFrame);
+        }
+        Ok(atqa)
+    }
+
+    /// Picks one card out of those that answered a request, whatever its UID,
+    /// and selects it. Where UIDs differ the card with a 1 at the first
+    /// differing bit wins, so the others stay ready for the next round.
+    pub fn anticollision(&mut self) -> Result<Selected, Error<E>> {
+        let mut uid = [0u8; 10];
+        let mut len = 0;
+        for &sel in PICC_SEL_CL.iter() {
+            let part = self.anticollision_level(sel)?;
+
+            let mut frame = [sel, 0x70, 0, 0, 0, 0, 0];
+            frame[2..].copy_from_slice(&part);
+            let mut answer = [0u8; 1];
+            if self.transceive_crc(&frame, &mut answer)? != 1 {
+                return Err(Error::IncompleteFrame);
+            }
+            let sak = answer[0];
+
+            if sak & SAK_UID_INCOMPLETE == 0 {
+                uid[len..len + 4].copy_from_slice(&part[..4]);
+                return Ok(Selected {
+                    uid,
+                    uid_len: (len + 4) as u8,
+                    sak,
+                });
+            }
+            if part[0] != CASCADE_TAG {
+                return Err(Error::Protocol);
+            }
+            uid[len..len + 3].copy_from_slice(&part[1..4]);
+            len += 3;
+        }
+        Err(Error::Protocol)
+    }
+
+    /// Sends HLTA to the selected card. It won't answer REQA until it leaves
+    /// the field or is woken with WUPA.
+    pub fn halt(&mut self) -> Result<(), Error<E>> {
+        self.stop_crypto1()?;
+        // A card that obeys doesn't answer, so the timeout is what we expect
+        match self.transceive_crc(&[PICC_HLTA, 0x00], &mut []) {
+            Ok(_) | Err(Error::Timeout) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Resolves one cascade level bit by bit and returns its four UID bytes
+    /// and the BCC.
+    fn anticollision_level(&mut self, sel: u8) -> Result<[u8; LEVEL_SIZE], Error<E>> {
+        let mut part = [0u8; LEVEL_SIZE];
+        // Number of leading UID bits we have settled on
+        let mut known = 0;
+        loop {
+            let bytes = known / 8;
+            let bits = (known % 8) as u8;
+            let sent = bytes + usize::from(bits != 0);
+            let mut frame = [0u8; 2 + LEVEL_SIZE];
+            frame[0] = sel;
+            // NVB: bytes and bits sent, including SEL and NVB themselves
+            frame[1] = ((2 + bytes as u8) << 4) | bits;
+            frame[2..2 + sent].copy_from_slice(&part[..sent]);
+
+            let mut rx = [0u8; LEVEL_SIZE];
+            let (received, collision) =
+                self.transceive_anticollision(&frame[..2 + sent], bits, &mut rx)?;
+
+            // The first byte received completes the partial byte we sent
+            let kept = (1u8 << bits) - 1;
+            for (i, &b) in rx[..received].iter().enumerate() {
+                let Some(byte) = part.get_mut(bytes + i) else {
+                    break;
+                };
+                *byte = if i == 0 {
+                    (*byte & kept) | (b & !kept)
+                } else {
+                    b
+                };
+            }
+
+            let Some(pos) = collision else {
+                if bytes + received != LEVEL_SIZE {
+                    return Err(Error::IncompleteFrame);
+                }
+                if part.iter().fold(0, |acc, b| acc ^ b) != 0 {
+                    return Err(Error::Bcc);
+                }
+                return Ok(part);
+            };
+            // Take the branch with a 1 and ask again for the rest
+            let bit = known + pos;
+            if bit > 8 * LEVEL_SIZE {
+                return Err(Error::Protocol);
+            }
+            part[(bit - 1) / 8] |= 1 << ((bit - 1) % 8);
+            known = bit;
+        }
+    }
+
+    /// Like [`Pcd::transceive`] but for ANTICOLLISION frames: the answer
+    /// starts at bit `tx_last_bits` of its first byte, and a collision isn't
+    /// an error. Returns the bytes received and, after a collision, its
+    /// position counted from 1 at the first bit received.
+    fn transceive_anticollision(
+        &mut self,
+        data: &[u8],
+        tx_last_bits: u8,
+        rx: &mut [u8],
+    ) -> Result<(usize, Option<usize>), Error<E>> {
+        // Bits after a collision read as 0, so our choice of 1 stands out
+        let coll = self.read(Register::CollReg)?;
+        self.write(Register::CollReg, coll & !COLL_VALUES_AFTER_COLL)?;
+
+        let tx_last_bits = tx_last_bits & 0x07;
+        self.write(Register::BitFramingReg, (tx_last_bits << 4) | tx_last_bits)?;
+        // Wait for the whole answer, not just the first collision
+        let result = self.execute(CMD_TRANSCEIVE, data, IRQ_RX | IRQ_IDLE);
+        self.write(Register::BitFramingReg, 0)?;
+        let collided = match result {
+            Ok(()) => false,
+            Err(Error::Collision) => true,
+            Err(e) => return Err(e),
+        };
+
+        let received = self.read(Register::FIFOLevelReg)? as usize;
+        if received > rx.len() {
+            return Err(Error::BufferOverflow);
+        }
+        if received > 0 {
+            self.read_fifo(&mut rx[..received])?;
+        }
+        if !collided {
+            return Ok((received, None));
+        }
+
+        let coll = self.read(Register::CollReg)?;
+        if coll & COLL_POS_NOT_VALID != 0 {
+            return Err(Error::Collision);
+        }
+        let pos = match coll & COLL_POS {
+            0 => 32,
+            pos => pos as usize,
+        };
+        Ok((received, Some(pos)))
+    }
+
+    /// Switches off Crypto1 so the next frames go out in plain text.
+    pub fn stop_crypto1(&mut self) -> Result<(), Error<E>> {
+        let status = self.read(Register::Status2Reg)?;
+        self.write(Register::Status2Reg, status & !STATUS2_CRYPTO1_ON)
+    }
+
+    /// Sends `data` followed by its CRC_A and receives the answer into `rx`,
+    /// checking and stripping its CRC. Returns the number of bytes received.
+    ///
+    /// Short answers without a CRC, like the 4-bit ACK/NAK, are returned as
+    /// they are.
+    pub fn transceive_crc(&mut self, data: &[u8], rx: &mut [u8]) -> Result<usize, Error<E>> {
+        let mut frame = [0u8; MAX_FRAME];
+        let len = data.len();
+        if len + 2 > MAX_FRAME || rx.len() + 2 > MAX_FRAME {
+            return Err(Error::BufferOverflow);
+        }
+        frame[..len].copy_from_slice(data);
+        frame[len..len + 2].copy_from_slice(&crc_a(data));
+
+        let mut answer = [0u8; MAX_FRAME];
+        let received = self.transceive(&frame[..len + 2], 0, &mut answer[..rx.len() + 2])?;
+        if received < 3 {
+            let n = received.min(rx.len());
+            rx[..n].copy_from_slice(&answer[..n]);
+            return Ok(n);
+        }
+
+        let n = received - 2;
+        if crc_a(&answer[..n]) != answer[n..received] {
+            return Err(Error::Crc);
+        }
+        rx[..n].copy_from_slice(&answer[..n]);
+        Ok(n)
+    }
+
+    /// Sends a raw frame and receives the answer into `rx`. `tx_last_bits` is
+    /// the number of bits to send from the last byte, 0 meaning all eight.
+    /// Returns the number of bytes received.
+    pub fn transceive(
+        &mut self,
+        data: &[u8],
+        tx_last_bits: u8,
+        rx: &mut [u8],
+    ) -> Result<usize, Error<E>> {
+        self.write(Register::BitFramingReg, tx_last_bits & 0x07)?;
+        let result = self.execute(CMD_TRANSCEIVE, data, IRQ_RX | IRQ_IDLE | IRQ_ERR);
+        self.write(Register::BitFramingReg, 0)?;
+        result?;
+
+        let received = self.read(Register::FIFOLevelReg)? as usize;
+        if received > rx.len() {
+            return Err(Error::BufferOverflow);
+        }
+        if received > 0 {
+            self.read_fifo(&mut rx[..received])?;
+        }
+        // Only a 4-bit ACK/NAK is expected to end in a partial byte
+        let last_bits = self.read(Register::ControlReg)? & CONTROL_RX_LAST_BITS;
+        if last_bits != 0 && received != 1 {
+            return Err(Error::IncompleteFrame);
+        }
+        Ok(received)
+    }
+
+    /// Loads `data` into the FIFO, runs `command` and waits until one of the
+    /// `done` interrupts fires. Include `IRQ_ERR` to stop at the first error
+    /// instead of the end of the frame.
+    fn execute(&mut self, command: u8, data: &[u8], done: u8) -> Result<(), Error<E>> {
+        self.write(Register::CommandReg, CMD_IDLE)?;
+        self.write(Register::ComIrqReg, IRQ_ALL)?;
+        self.write(Register::FIFOLevelReg, FIFO_FLUSH)?;
+        self.write_fifo(data)?;
+        self.write(Register::CommandReg, command)?;
+        if command == CMD_TRANSCEIVE {
+            let framing = self.read(Register::BitFramingReg)?;
+            self.write(Register::BitFramingReg, framing | BIT_FRAMING_START_SEND)?;
+        }
+
+        let mut polls = 0;
+        loop {
+            let irq = self.read(Register::ComIrqReg)?;
+            if irq & done != 0 {
+                break;
+            }
+            if irq & IRQ_TIMER != 0 || polls == MAX_POLLS {
+                self.write(Register::CommandReg, CMD_IDLE)?;
+                return Err(Error::Timeout);
+            }
+            polls += 1;
+        }
+
+        let err = self.read(Register::ErrorReg)?;
+        if err & ERR_BUFFER_OVERFLOW != 0 {
+            Err(Error::BufferOverflow)
+        } else if err & ERR_PARITY != 0 {
+            Err(Error::Parity)
+        } else if err & ERR_COLLISION != 0 {
+            Err(Error::Collision)
+        } else if err & ERR_PROTOCOL != 0 {
+            Err(Error::Protocol)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn read(&mut self, reg: Register) -> Result<u8, Error<E>> {
+        let mut buf = [reg.read_address(), 0];
+        self.spi.transfer_in_place(&mut buf).map_err(Error::Comm)?;
+        Ok(buf[1])
+    }
+
+    pub(crate) fn write(&mut self, reg: Register, val: u8) -> Result<(), Error<E>> {
+        self.spi
+            .write(&[reg.write_address(), val])
+            .map_err(Error::Comm)
+    }
+
+    /// Reads `buf.len()` bytes from the FIFO in one transaction: every byte
+    /// clocked out but the last repeats the address.
+    pub(crate) fn read_fifo(&mut self, buf: &mut [u8]) -> Result<(), Error<E>> {
+        let address = Register::FIFODataReg.read_address();
+        buf.fill(address);
+        if let Some(last) = buf.last_mut() {
+            *last = 0;
+        }
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&[address]),
+                Operation::TransferInPlace(buf),
+            ])
+            .map_err(Error::Comm)
+    }
+
+    pub(crate) fn write_fifo(&mut self, data: &[u8]) -> Result<(), Error<E>> {
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&[Register::FIFODataReg.write_address()]),
+                Operation::Write(data),
+            ])
+            .map_err(Error::Comm)
+    }
+}
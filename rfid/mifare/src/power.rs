@@ -0,0 +1,310 @@
+//! Polling for cards on a battery budget.
+//!
+//! The MFRC522 can't notice a card on its own; the field has to be on and the
+//! reader has to ask. So the chip spends the time between polls in soft
+//! power-down, [`Scheduler`] decides when the next poll is due, and [`probe`]
+//! keeps each poll short: one REQA with a timeout of about a millisecond
+//! rather than the driver's longer default. With [`enable_irq`] the chip pulls its IRQ
+//! pin low when the probe ends, so the MCU can sleep through it too.
+//!
+//! [`PowerStats`] turns the measured timings into a poll rate and an average
+//! current estimate.
+
+use embedded_hal::spi::SpiDevice;
+use mfrc522::Error;
+
+use crate::pcd::{Pcd, Register};
+
+// PCD commands (CommandReg)
+const CMD_IDLE: u8 = 0x00;
+const CMD_TRANSCEIVE: u8 = 0x0C;
+
+/// CommandReg bit for soft power-down. It reads back set until the
+/// oscillator is running again after power-up.
+const COMMAND_POWER_DOWN: u8 = 0x10;
+
+// ComIEnReg, ComIrqReg and DivIEnReg bits
+const IRQ_INV: u8 = 0x80;
+const IRQ_PUSH_PULL: u8 = 0x80;
+const IRQ_ALL: u8 = 0x7F;
+const IRQ_RX: u8 = 0x20;
+const IRQ_ERR: u8 = 0x02;
+const IRQ_TIMER: u8 = 0x01;
+
+const FIFO_FLUSH: u8 = 0x80;
+const BIT_FRAMING_START_SEND: u8 = 0x80;
+
+/// REQA is a short frame of 7 bits.
+const PICC_REQA: u8 = 0x26;
+const SHORT_FRAME_BITS: u8 = 7;
+
+/// The chip's clock, in cycles per millisecond.
+const CLOCK_PER_MS: u64 = 13_560;
+
+/// How many register reads to wait for the oscillator after power-up.
+const MAX_POLLS: u16 = 2000;
+
+/// Puts the chip into soft power-down. The field goes off, so every card in
+/// it loses power and starts over at the next poll; registers are kept.
+pub fn power_down<E, SPI: SpiDevice<Error = E>>(pcd: &mut Pcd<SPI>) -> Result<(), Error<E>> {
+    pcd.write(Register::CommandReg, COMMAND_POWER_DOWN | CMD_IDLE)
+}
+
+/// Brings the chip out of soft power-down and waits for its oscillator.
+pub fn power_up<E, SPI: SpiDevice<Error = E>>(pcd: &mut Pcd<SPI>) -> Result<(), Error<E>> {
+    pcd.write(Register::CommandReg, CMD_IDLE)?;
+    for _ in 0..MAX_POLLS {
+        if pcd.read(Register::CommandReg)? & COMMAND_POWER_DOWN == 0 {
+            return Ok(());
+        }
+    }
+    Err(Error::Timeout)
+}
+
+/// Makes the IRQ pin a push-pull output that goes low when a frame arrives,
+/// a receive error happens or the timer runs out. It stays low until the
+/// interrupt flags are cleared, which [`Probe::finish`] and every command in
+/// [`Pcd`] do.
+pub fn enable_irq<E, SPI: SpiDevice<Error = E>>(pcd: &mut Pcd<SPI>) -> Result<(), Error<E>> {
+    pcd.write(Register::DivIEnReg, IRQ_PUSH_PULL)?;
+    pcd.write(Register::ComIEnReg, IRQ_INV | IRQ_RX | IRQ_ERR | IRQ_TIMER)
+}
+
+/// A REQA on its way; see [`probe`].
+#[must_use]
+pub struct Probe {
+    /// TReloadReg as it was before the probe shortened it.
+    reload: [u8; 2],
+}
+
+/// Sends REQA with the timer set to give up after `timeout_us`, and returns
+/// without waiting. Wait for the IRQ pin or [`Probe::is_done`], then call
+/// [`Probe::finish`].
+///
+/// A card that answers is left in the READY state, ready for
+/// [`Pcd::anticollision`]; sending it REQA or WUPA again would put it back to
+/// sleep.
+pub fn probe<E, SPI: SpiDevice<Error = E>>(
+    pcd: &mut Pcd<SPI>,
+    timeout_us: u32,
+) -> Result<Probe, Error<E>> {
+    let reload = [
+        pcd.read(Register::TReloadRegHigh)?,
+        pcd.read(Register::TReloadRegLow)?,
+    ];
+    let prescaler = (u64::from(pcd.read(Register::TModeReg)? & 0x0F) << 8)
+        | u64::from(pcd.read(Register::TPrescalerReg)?);
+    let ticks = (u64::from(timeout_us) * CLOCK_PER_MS).div_ceil(1000 * (2 * prescaler + 1));
+    let ticks = ticks.clamp(1, 0xFFFF) as u16;
+    pcd.write(Register::TReloadRegHigh, (ticks >> 8) as u8)?;
+    pcd.write(Register::TReloadRegLow, ticks as u8)?;
+
+    pcd.write(Register::CommandReg, CMD_IDLE)?;
+    pcd.write(Register::ComIrqReg, IRQ_ALL)?;
+    pcd.write(Register::FIFOLevelReg, FIFO_FLUSH)?;
+    pcd.write_fifo(&[PICC_REQA])?;
+    pcd.write(Register::CommandReg, CMD_TRANSCEIVE)?;
+    pcd.write(
+        Register::BitFramingReg,
+        BIT_FRAMING_START_SEND | SHORT_FRAME_BITS,
+    )?;
+    Ok(Probe { reload })
+}
+
+impl Probe {
+    /// Whether the card answered or the timer ran out, for readers without
+    /// the IRQ pin wired.
+    pub fn is_done<E, SPI: SpiDevice<Error = E>>(
+        &self,
+        pcd: &mut Pcd<SPI>,
+    ) -> Result<bool, Error<E>> {
+        Ok(pcd.read(Register::ComIrqReg)? & (IRQ_RX | IRQ_ERR | IRQ_TIMER) != 0)
+    }
+
+    /// Stops the probe, puts the timer back and releases the IRQ pin. Returns
+    /// whether anything answered.
+    pub fn finish<E, SPI: SpiDevice<Error = E>>(
+        self,
+        pcd: &mut Pcd<SPI>,
+    ) -> Result<bool, Error<E>> {
+        let irq = pcd.read(Register::ComIrqReg)?;
+        pcd.write(Register::CommandReg, CMD_IDLE)?;
+        pcd.write(Register::BitFramingReg, 0)?;
+        pcd.write(Register::ComIrqReg, IRQ_ALL)?;
+        pcd.write(Register::TReloadRegHigh, self.reload[0])?;
+        pcd.write(Register::TReloadRegLow, self.reload[1])?;
+        // A garbled answer, from a collision or a card at the edge of the
+        // field, still means something is there
+        Ok(irq & (IRQ_RX | IRQ_ERR) != 0)
+    }
+}
+
+/// How often to poll. A short `active_period_us` after a card keeps a second
+/// tap, or the card leaving, from going unnoticed for long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DutyCycle {
+    /// Time from one poll to the next while the field is empty.
+    pub idle_period_us: u32,
+    /// Time from one poll to the next while a card is around.
+    pub active_period_us: u32,
+    /// Polls to stay at the active rate after the last card was seen.
+    pub linger: u16,
+}
+
+impl DutyCycle {
+    /// Four polls a second, twenty while a card is around: a quarter second
+    /// at most before a door reacts.
+    pub const DOOR: DutyCycle = DutyCycle {
+        idle_period_us: 250_000,
+        active_period_us: 50_000,
+        linger: 20,
+    };
+}
+
+/// Supply currents for [`PowerStats::average_current_ua`], in µA.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CurrentProfile {
+    /// MFRC522 with the field on.
+    pub reader_active_ua: u32,
+    /// MFRC522 in soft power-down.
+    pub reader_sleep_ua: u32,
+    /// MCU running.
+    pub mcu_active_ua: u32,
+    /// MCU waiting for an interrupt.
+    pub mcu_sleep_ua: u32,
+}
+
+impl CurrentProfile {
+    /// Rough figures for a common MFRC522 module and an RP2350 at 150 MHz.
+    /// Measure your own board and adjust.
+    pub const TYPICAL: CurrentProfile = CurrentProfile {
+        reader_active_ua: 26_000,
+        reader_sleep_ua: 10,
+        mcu_active_ua: 25_000,
+        mcu_sleep_ua: 8_000,
+    };
+}
+
+/// What the reader has been doing since the [`Scheduler`] started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PowerStats {
+    pub polls: u32,
+    /// Polls that found a card.
+    pub cards: u32,
+    pub since_us: u64,
+    pub until_us: u64,
+    /// Time spent powered up, polling and handling cards.
+    pub awake_us: u64,
+}
+
+impl PowerStats {
+    pub fn elapsed_us(&self) -> u64 {
+        self.until_us - self.since_us
+    }
+
+    /// Polls per 100 seconds, for printing as polls per second with two
+    /// decimals.
+    pub fn poll_rate_centihz(&self) -> u32 {
+        match self.elapsed_us() {
+            0 => 0,
+            elapsed => (u64::from(self.polls) * 100_000_000 / elapsed) as u32,
+        }
+    }
+
+    /// Share of the time spent awake, in parts per million.
+    pub fn duty_ppm(&self) -> u32 {
+        match self.elapsed_us() {
+            0 => 0,
+            elapsed => (self.awake_us * 1_000_000 / elapsed) as u32,
+        }
+    }
+
+    /// Average supply current, weighting `profile` by the time spent awake
+    /// and asleep.
+    pub fn average_current_ua(&self, profile: &CurrentProfile) -> u32 {
+        let elapsed = self.elapsed_us();
+        if elapsed == 0 {
+            return 0;
+        }
+        let awake = self.awake_us.min(elapsed);
+        let asleep = elapsed - awake;
+        let active = u64::from(profile.reader_active_ua + profile.mcu_active_ua);
+        let sleep = u64::from(profile.reader_sleep_ua + profile.mcu_sleep_ua);
+        ((awake * active + asleep * sleep) / elapsed) as u32
+    }
+}
+
+/// Decides when to poll next and keeps the [`PowerStats`]. Times are in
+/// microseconds from any monotonic clock.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    duty: DutyCycle,
+    next_us: u64,
+    /// Active-rate polls left.
+    linger: u16,
+    stats: PowerStats,
+}
+
+impl Scheduler {
+    /// Starts with a poll due straight away.
+    pub fn new(duty: DutyCycle, now_us: u64) -> Self {
+        Self {
+            duty,
+            next_us: now_us,
+            linger: 0,
+            stats: PowerStats {
+                since_us: now_us,
+                until_us: now_us,
+                ..PowerStats::default()
+            },
+        }
+    }
+
+    /// When the next poll is due.
+    pub fn next_poll_us(&self) -> u64 {
+        self.next_us
+    }
+
+    pub fn is_due(&self, now_us: u64) -> bool {
+        now_us >= self.next_us
+    }
+
+    /// Records a poll that kept the reader awake from `started_us` to
+    /// `ended_us`, card handling included, and plans the next one.
+    pub fn record(&mut self, started_us: u64, ended_us: u64, card: bool) {
+        self.stats.polls += 1;
+        self.stats.awake_us += ended_us.saturating_sub(started_us);
+        self.stats.until_us = self.stats.until_us.max(ended_us);
+        if card {
+            self.stats.cards += 1;
+            self.linger = self.duty.linger;
+        } else {
+            self.linger = self.linger.saturating_sub(1);
+        }
+
+        let period = if card || self.linger > 0 {
+            self.duty.active_period_us
+        } else {
+            self.duty.idle_period_us
+        };
+        // A poll that overran its period is followed by the next one at once
+        self.next_us = (started_us + u64::from(period)).max(ended_us);
+    }
+
+    /// Stats up to `now_us`, sleep since the last poll included.
+    pub fn stats(&self, now_us: u64) -> PowerStats {
+        PowerStats {
+            until_us: self.stats.until_us.max(now_us),
+            ..self.stats
+        }
+    }
+
+    /// Starts counting afresh from `now_us`, to report stats per interval.
+    pub fn reset_stats(&mut self, now_us: u64) {
+        self.stats = PowerStats {
+            since_us: now_us,
+            until_us: now_us,
+            ..PowerStats::default()
+        };
+    }
+}
@@ -0,0 +1,179 @@
+//! Changing the keys and access bits of a sector without losing it.
+//!
+//! A trailer write that goes wrong, because of a typo in a key, a bad
+//! combination of access bits or the card leaving the field, can lock a
+//! sector for good. [`change_trailer`] checks everything it can before
+//! writing, verifies the result with the new key afterwards and, if that
+//! fails, finds out which keys still open the sector.
+
+use embedded_hal::spi::SpiDevice;
+use mfrc522::{Error, Uid};
+
+use crate::access::{AccessConditions, AccessError, KeyType, Permission};
+use crate::classic;
+use crate::geometry::CardType;
+use crate::keys::FoundKey;
+use crate::pcd::Pcd;
+
+/// Which keys open the sector after a failed change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectorState {
+    /// The old keys still work; the trailer wasn't changed.
+    OldKeys,
+    /// The new Key A works but the trailer doesn't read back as written.
+    NewKeysMismatch,
+    /// Neither key opens the sector, or the card is gone.
+    Unknown,
+}
+
+impl SectorState {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            SectorState::OldKeys => "old keys still valid",
+            SectorState::NewKeysMismatch => "new Key A works but trailer differs",
+            SectorState::Unknown => "sector state unknown, try both keys",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RotationError<E> {
+    OutOfRange,
+    /// Sector 0 holds the manufacturer block and the MAD; needs `force`.
+    Sector0,
+    /// The access bits could never be changed again; needs `force`.
+    SelfLocking,
+    InvalidTrailer(AccessError),
+    /// The old key doesn't authenticate the sector.
+    OldKeyRejected(Error<E>),
+    /// The current access conditions don't let the old key write the parts
+    /// of the trailer that change.
+    NotPermitted,
+    /// Writing or verifying failed; `state` is what the sector looks like now.
+    Failed {
+        state: SectorState,
+        cause: Option<Error<E>>,
+    },
+}
+
+impl<E> RotationError<E> {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            RotationError::OutOfRange => "Sector out of range",
+            RotationError::Sector0 => "Refusing to change sector 0 without force",
+            RotationError::SelfLocking => "Refusing to freeze the access bits without force",
+            RotationError::InvalidTrailer(e) => e.as_str(),
+            RotationError::OldKeyRejected(_) => "Old key rejected, nothing written",
+            RotationError::NotPermitted => "Old key may not write this trailer, nothing written",
+            RotationError::Failed { state, .. } => state.as_str(),
+        }
+    }
+}
+
+/// Would these conditions leave the access bits unchangeable forever?
+pub const fn is_self_locking(access: &AccessConditions) -> bool {
+    matches!(access.trailer.trailer().access_write, Permission::Never)
+}
+
+/// Replaces the trailer of `sector` with `new_trailer`, authenticating with
+/// `old`.
+///
+/// Nothing is written unless the new access bits are valid, the old key
+/// opens the sector and may write every part of the trailer that changes.
+/// Sector 0 and trailers that freeze their own access bits are refused
+/// unless `force` is set.
+///
+/// After writing, the sector is opened with the new Key A and the trailer read
+/// back. If that fails the old key is tried again and the error says which
+/// keys work.
+pub fn change_trailer<E, SPI: SpiDevice<Error = E>>(
+    uid: &Uid,
+    card: CardType,
+    sector: u8,
+    old: &FoundKey,
+    new_trailer: &[u8; 16],
+    force: bool,
+    pcd: &mut Pcd<SPI>,
+) -> Result<(), RotationError<E>> {
+    let trailer_block = card
+        .trailer_block(sector)
+        .ok_or(RotationError::OutOfRange)?;
+    let new_access =
+        AccessConditions::from_trailer(new_trailer).map_err(RotationError::InvalidTrailer)?;
+    // Catches data groups that only a readable, and so unusable, Key B opens
+    new_access.encode().map_err(RotationError::InvalidTrailer)?;
+    if !force {
+        if sector == 0 {
+            return Err(RotationError::Sector0);
+        }
+        if is_self_locking(&new_access) {
+            return Err(RotationError::SelfLocking);
+        }
+    }
+
+    // Pre-authenticate with the old key and check what it may write
+    pcd.activate(uid).map_err(RotationError::OldKeyRejected)?;
+    pcd.authenticate(uid, trailer_block, old.key_type, &old.key)
+        .map_err(RotationError::OldKeyRejected)?;
+    let current = classic::read_block(trailer_block, pcd).map_err(RotationError::OldKeyRejected)?;
+    let current_access =
+        AccessConditions::from_trailer(&current).map_err(RotationError::InvalidTrailer)?;
+    let perms = current_access.trailer.trailer();
+    if old.key_type == KeyType::B && perms.key_b_readable() {
+        return Err(RotationError::NotPermitted);
+    }
+    // Key A can't be read back, so assume it changes
+    let access_changes = current[6..9] != new_trailer[6..9];
+    let key_b_changes = !perms.key_b_readable() || current[10..16] != new_trailer[10..16];
+    if !perms.key_a_write.allows(old.key_type)
+        || (access_changes && !perms.access_write.allows(old.key_type))
+        || (key_b_changes && !perms.key_b_write.allows(old.key_type))
+    {
+        return Err(RotationError::NotPermitted);
+    }
+
+    // A lost ACK doesn't mean the write didn't happen, so verify either way
+    let written = classic::write_block(trailer_block, new_trailer, pcd).err();
+
+    let state = match read_with_new_key(uid, trailer_block, new_trailer, pcd) {
+        Ok(actual) if matches(&actual, new_trailer, &new_access) => return Ok(()),
+        // Key A didn't change and the trailer is untouched
+        Ok(actual) if matches(&actual, &current, &current_access) => SectorState::OldKeys,
+        Ok(_) => SectorState::NewKeysMismatch,
+        Err(_) => {
+            let reopened = pcd
+                .activate(uid)
+                .and_then(|_| pcd.authenticate(uid, trailer_block, old.key_type, &old.key));
+            match reopened {
+                Ok(()) => SectorState::OldKeys,
+                Err(_) => SectorState::Unknown,
+            }
+        }
+    };
+    let _ = pcd.activate(uid);
+    Err(RotationError::Failed {
+        state,
+        cause: written,
+    })
+}
+
+/// Opens the sector with the new Key A and reads the trailer back.
+fn read_with_new_key<E, SPI: SpiDevice<Error = E>>(
+    uid: &Uid,
+    trailer_block: u8,
+    new_trailer: &[u8; 16],
+    pcd: &mut Pcd<SPI>,
+) -> Result<[u8; 16], Error<E>> {
+    let key_a: [u8; 6] = new_trailer[..6].try_into().unwrap();
+    pcd.activate(uid)?;
+    pcd.authenticate(uid, trailer_block, KeyType::A, &key_a)?;
+    classic::read_block(trailer_block, pcd)
+}
+
+/// Compares what a trailer read reveals: the access bits, the user byte and
+/// Key B if `access` makes it readable.
+fn matches(actual: &[u8; 16], expected: &[u8; 16], access: &AccessConditions) -> bool {
+    let key_b_matches =
+        !access.trailer.trailer().key_b_readable() || actual[10..16] == expected[10..16];
+    actual[6..10] == expected[6..10] && key_b_matches
+}
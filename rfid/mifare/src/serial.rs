@@ -0,0 +1,39 @@
+//! Reporting over the USB serial port, for the firmware.
+
+use core::fmt::{self, Write};
+
+use usb_device::bus::UsbBus;
+use usb_device::device::UsbDevice;
+use usbd_serial::SerialPort;
+
+/// Writes `e` and a line break. Whatever the port has no room for is dropped
+/// rather than waited for.
+pub fn report<B: UsbBus>(e: &impl fmt::Display, serial: &mut SerialPort<B>) {
+    let _ = write!(Text(serial), "{}\r\n", e);
+}
+
+/// Repeats `message` over serial every few seconds and stops there.
+pub fn halt<B: UsbBus>(usb_dev: &mut UsbDevice<B>, serial: &mut SerialPort<B>, message: &str) -> ! {
+    let mut polls = 0u32;
+    loop {
+        if usb_dev.poll(&mut [&mut *serial]) {
+            let mut buf = [0u8; 64];
+            let _ = serial.read(&mut buf);
+        }
+        polls = polls.wrapping_add(1);
+        if polls.is_multiple_of(2_000_000) {
+            let _ = serial.write(message.as_bytes());
+            let _ = serial.write(b"\r\n");
+        }
+    }
+}
+
+/// Formats straight onto the port.
+struct Text<'s, 'a, B: UsbBus>(&'s mut SerialPort<'a, B>);
+
+impl<B: UsbBus> Write for Text<'_, '_, B> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let _ = self.0.write(s.as_bytes());
+        Ok(())
+    }
+}
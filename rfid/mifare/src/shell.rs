@@ -0,0 +1,353 @@
+//! Parser for the command lines of `rfid-shell`.
+
+use crate::access::KeyType;
+use crate::diag::Gain;
+
+pub const HELP: &str = "\
+uid                      print the UID of the card in the field\r\n\
+dump                     dump every sector or page\r\n\
+read <blk>               read a block (Classic) or four pages (Ultralight)\r\n\
+write <blk> <hex>        write 16 bytes to a block or 4 bytes to a page\r\n\
+auth <sector> A|B <key>  check a key against a sector\r\n\
+setkey A|B <key>         key used by read, write and dump\r\n\
+diag                     chip version, self-test and receiver gain\r\n\
+gain [dB]                show or set receiver gain: 18, 23, 33, 38, 43, 48\r\n\
+stats [n]                poll the card n times and count failures\r\n\
+help                     this text\r\n";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Help,
+    Uid,
+    Dump,
+    Read {
+        block: u8,
+    },
+    /// `len` is 16 for a Classic block or 4 for an Ultralight page.
+    Write {
+        block: u8,
+        data: [u8; 16],
+        len: usize,
+    },
+    Auth {
+        sector: u8,
+        key_type: KeyType,
+        key: [u8; 6],
+    },
+    SetKey {
+        key_type: KeyType,
+        key: [u8; 6],
+    },
+    Diag,
+    /// Shows the gain, or sets it if given.
+    Gain {
+        gain: Option<Gain>,
+    },
+    Stats {
+        polls: u8,
+    },
+}
+
+/// Polls run by `stats` without an argument.
+pub const DEFAULT_POLLS: u8 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    Empty,
+    UnknownCommand,
+    MissingArgument,
+    TooManyArguments,
+    BadNumber,
+    BadKeyType,
+    BadHex,
+    BadGain,
+    /// Write data must be 4 or 16 bytes, keys 6 bytes.
+    BadLength,
+}
+
+impl ParseError {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            ParseError::Empty => "",
+            ParseError::UnknownCommand => "Unknown command, try help",
+            ParseError::MissingArgument => "Missing argument",
+            ParseError::TooManyArguments => "Too many arguments",
+            ParseError::BadNumber => "Expected a number from 0 to 255",
+            ParseError::BadKeyType => "Key type must be A or B",
+            ParseError::BadHex => "Expected hex digits",
+            ParseError::BadGain => "Gain must be 18, 23, 33, 38, 43 or 48 dB",
+            ParseError::BadLength => "Wrong number of bytes",
+        }
+    }
+}
+
+/// Parses one line. Words are separated by spaces; commands and key types
+/// are case insensitive.
+pub fn parse(line: &str) -> Result<Command, ParseError> {
+    let mut args = line.split_whitespace();
+    let name = args.next().ok_or(ParseError::Empty)?;
+
+    let command = if name.eq_ignore_ascii_case("help") || name == "?" {
+        Command::Help
+    } else if name.eq_ignore_ascii_case("uid") {
+        Command::Uid
+    } else if name.eq_ignore_ascii_case("dump") {
+        Command::Dump
+    } else if name.eq_ignore_ascii_case("read") {
+        Command::Read {
+            block: number(next(&mut args)?)?,
+        }
+    } else if name.eq_ignore_ascii_case("write") {
+        let block = number(next(&mut args)?)?;
+        let mut data = [0u8; 16];
+        let len = hex(next(&mut args)?, &mut data)?;
+        if len != 4 && len != 16 {
+            return Err(ParseError::BadLength);
+        }
+        Command::Write { block, data, len }
+    } else if name.eq_ignore_ascii_case("auth") {
+        Command::Auth {
+            sector: number(next(&mut args)?)?,
+            key_type: key_type(next(&mut args)?)?,
+            key: key(next(&mut args)?)?,
+        }
+    } else if name.eq_ignore_ascii_case("setkey") {
+        Command::SetKey {
+            key_type: key_type(next(&mut args)?)?,
+            key: key(next(&mut args)?)?,
+        }
+    } else if name.eq_ignore_ascii_case("diag") {
+        Command::Diag
+    } else if name.eq_ignore_ascii_case("gain") {
+        let gain = match args.next() {
+            Some(word) => Some(Gain::from_db(number(word)?).ok_or(ParseError::BadGain)?),
+            None => None,
+        };
+        Command::Gain { gain }
+    } else if name.eq_ignore_ascii_case("stats") {
+        let polls = match args.next() {
+            Some(word) => number(word)?,
+            None => DEFAULT_POLLS,
+        };
+        if polls == 0 {
+            return Err(ParseError::BadNumber);
+        }
+        Command::Stats { polls }
+    } else {
+        return Err(ParseError::UnknownCommand);
+    };
+
+    if args.next().is_some() {
+        return Err(ParseError::TooManyArguments);
+    }
+    Ok(command)
+}
+
+fn next<'a>(args: &mut impl Iterator<Item = &'a str>) -> Result<&'a str, ParseError> {
+    args.next().ok_or(ParseError::MissingArgument)
+}
+
+/// A decimal number, or hex with a `0x` prefix.
+fn number(word: &str) -> Result<u8, ParseError> {
+    let parsed = match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+        Some(digits) => u8::from_str_radix(digits, 16),
+        None => word.parse(),
+    };
+    parsed.map_err(|_| ParseError::BadNumber)
+}
+
+fn key_type(word: &str) -> Result<KeyType, ParseError> {
+    if word.eq_ignore_ascii_case("a") {
+        Ok(KeyType::A)
+    } else if word.eq_ignore_ascii_case("b") {
+        Ok(KeyType::B)
+    } else {
+        Err(ParseError::BadKeyType)
+    }
+}
+
+fn key(word: &str) -> Result<[u8; 6], ParseError> {
+    let mut key = [0u8; 6];
+    if hex(word, &mut key)? != key.len() {
+        return Err(ParseError::BadLength);
+    }
+    Ok(key)
+}
+
+/// Decodes hex digits into `out` and returns the number of bytes. Colons
+/// between bytes are allowed, as in `ff:ff:ff:ff:ff:ff`.
+fn hex(word: &str, out: &mut [u8]) -> Result<usize, ParseError> {
+    let mut len = 0;
+    let mut high = None;
+    for c in word.chars().filter(|&c| c != ':') {
+        let nibble = c.to_digit(16).ok_or(ParseError::BadHex)? as u8;
+        match high.take() {
+            None => high = Some(nibble),
+            Some(h) => {
+                let byte = out.get_mut(len).ok_or(ParseError::BadLength)?;
+                *byte = (h << 4) | nibble;
+                len += 1;
+            }
+        }
+    }
+    if high.is_some() {
+        return Err(ParseError::BadHex);
+    }
+    Ok(len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 6] = [0xA0, 0xA1, 0xA2, 0xA3, 0xA4, 0xA5];
+
+    #[test]
+    fn commands_without_arguments() {
+        assert_eq!(parse("help"), Ok(Command::Help));
+        assert_eq!(parse("?"), Ok(Command::Help));
+        assert_eq!(parse("uid"), Ok(Command::Uid));
+        assert_eq!(parse("dump"), Ok(Command::Dump));
+        assert_eq!(parse("diag"), Ok(Command::Diag));
+        assert_eq!(parse("  UID \t"), Ok(Command::Uid));
+        assert_eq!(parse("Dump"), Ok(Command::Dump));
+    }
+
+    #[test]
+    fn read() {
+        assert_eq!(parse("read 4"), Ok(Command::Read { block: 4 }));
+        assert_eq!(parse("read 0xff"), Ok(Command::Read { block: 255 }));
+        assert_eq!(parse("READ 0X10"), Ok(Command::Read { block: 16 }));
+        assert_eq!(parse("read"), Err(ParseError::MissingArgument));
+        assert_eq!(parse("read 256"), Err(ParseError::BadNumber));
+        assert_eq!(parse("read -1"), Err(ParseError::BadNumber));
+        assert_eq!(parse("read x"), Err(ParseError::BadNumber));
+        assert_eq!(parse("read 4 5"), Err(ParseError::TooManyArguments));
+    }
+
+    #[test]
+    fn write_block_and_page() {
+        let mut data = [0u8; 16];
+        for (i, b) in data.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        assert_eq!(
+            parse("write 8 000102030405060708090a0b0c0d0e0f"),
+            Ok(Command::Write {
+                block: 8,
+                data,
+                len: 16
+            })
+        );
+
+        let mut page = [0u8; 16];
+        page[..4].copy_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(
+            parse("write 5 de:ad:BE:EF"),
+            Ok(Command::Write {
+                block: 5,
+                data: page,
+                len: 4
+            })
+        );
+    }
+
+    #[test]
+    fn write_rejects_bad_data() {
+        assert_eq!(parse("write"), Err(ParseError::MissingArgument));
+        assert_eq!(parse("write 4"), Err(ParseError::MissingArgument));
+        assert_eq!(parse("write 4 0102"), Err(ParseError::BadLength));
+        assert_eq!(
+            parse("write 4 01020304050607080910111213141516ff"),
+            Err(ParseError::BadLength)
+        );
+        assert_eq!(parse("write 4 0102030"), Err(ParseError::BadHex));
+        assert_eq!(parse("write 4 0102030g"), Err(ParseError::BadHex));
+        assert_eq!(
+            parse("write 4 01020304 00"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn auth() {
+        assert_eq!(
+            parse("auth 1 a a0a1a2a3a4a5"),
+            Ok(Command::Auth {
+                sector: 1,
+                key_type: KeyType::A,
+                key: KEY
+            })
+        );
+        assert_eq!(
+            parse("auth 39 B A0:A1:A2:A3:A4:A5"),
+            Ok(Command::Auth {
+                sector: 39,
+                key_type: KeyType::B,
+                key: KEY
+            })
+        );
+        assert_eq!(parse("auth 1 a"), Err(ParseError::MissingArgument));
+        assert_eq!(parse("auth 1 c a0a1a2a3a4a5"), Err(ParseError::BadKeyType));
+        assert_eq!(parse("auth 1 a a0a1a2a3a4"), Err(ParseError::BadLength));
+        assert_eq!(parse("auth 1 a a0a1a2a3a4a5a6"), Err(ParseError::BadLength));
+        assert_eq!(parse("auth x a a0a1a2a3a4a5"), Err(ParseError::BadNumber));
+    }
+
+    #[test]
+    fn setkey() {
+        assert_eq!(
+            parse("setkey b a0a1a2a3a4a5"),
+            Ok(Command::SetKey {
+                key_type: KeyType::B,
+                key: KEY
+            })
+        );
+        assert_eq!(parse("setkey"), Err(ParseError::MissingArgument));
+        assert_eq!(parse("setkey a"), Err(ParseError::MissingArgument));
+        assert_eq!(parse("setkey ab a0a1a2a3a4a5"), Err(ParseError::BadKeyType));
+        assert_eq!(parse("setkey a zz"), Err(ParseError::BadHex));
+        assert_eq!(
+            parse("setkey a a0a1a2a3a4a5 x"),
+            Err(ParseError::TooManyArguments)
+        );
+    }
+
+    #[test]
+    fn gain() {
+        assert_eq!(parse("gain"), Ok(Command::Gain { gain: None }));
+        let lines = [
+            "gain 18", "gain 23", "gain 33", "gain 38", "gain 43", "gain 48",
+        ];
+        for (line, gain) in lines.into_iter().zip(Gain::ALL) {
+            assert_eq!(parse(line), Ok(Command::Gain { gain: Some(gain) }));
+        }
+        assert_eq!(parse("gain 20"), Err(ParseError::BadGain));
+        assert_eq!(parse("gain loud"), Err(ParseError::BadNumber));
+        assert_eq!(parse("gain 33 38"), Err(ParseError::TooManyArguments));
+    }
+
+    #[test]
+    fn stats() {
+        assert_eq!(
+            parse("stats"),
+            Ok(Command::Stats {
+                polls: DEFAULT_POLLS
+            })
+        );
+        assert_eq!(parse("stats 7"), Ok(Command::Stats { polls: 7 }));
+        assert_eq!(parse("stats 0"), Err(ParseError::BadNumber));
+        assert_eq!(parse("stats 1000"), Err(ParseError::BadNumber));
+        assert_eq!(parse("stats 1 2"), Err(ParseError::TooManyArguments));
+    }
+
+    #[test]
+    fn empty_and_unknown_lines() {
+        assert_eq!(parse(""), Err(ParseError::Empty));
+        assert_eq!(parse("   "), Err(ParseError::Empty));
+        assert_eq!(parse("format"), Err(ParseError::UnknownCommand));
+        assert_eq!(parse("uid2"), Err(ParseError::UnknownCommand));
+        assert_eq!(parse("uid now"), Err(ParseError::TooManyArguments));
+        assert_eq!(parse("help me"), Err(ParseError::TooManyArguments));
+    }
+}
@@ -0,0 +1,36 @@
+//! Decoded view of a sector trailer as read back from the card.
+
+use crate::access::{AccessConditions, AccessError};
+
+/// The last block of every sector: Key A, access bits, a user byte and Key B.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SectorTrailer {
+    pub access: AccessConditions,
+    pub user_byte: u8,
+    key_b: [u8; 6],
+}
+
+impl SectorTrailer {
+    /// Parses a trailer block. Fails if the access bits are corrupt.
+    pub fn parse(block: &[u8; 16]) -> Result<Self, AccessError> {
+        let access = AccessConditions::from_trailer(block)?;
+        let mut key_b = [0u8; 6];
+        key_b.copy_from_slice(&block[10..16]);
+        Ok(Self {
+            access,
+            user_byte: block[9],
+            key_b,
+        })
+    }
+
+    /// Key B, if the access conditions let it be read.
+    ///
+    /// Key A is never readable; the card always returns zeros in its place.
+    pub fn key_b(&self) -> Option<&[u8; 6]> {
+        if self.access.trailer.trailer().key_b_readable() {
+            Some(&self.key_b)
+        } else {
+            None
+        }
+    }
+}
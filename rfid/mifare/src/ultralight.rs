@@ -0,0 +1,330 @@
+//! MIFARE Ultralight and NTAG21x tags.
+//!
+//! These tags have no sectors and no Crypto1. Memory is split into 4-byte
+//! pages: READ returns four pages at once, WRITE stores a single page.
+//!
+//! ```text
+//! page 0..2   UID, page 2 also holds the two static lock bytes
+//! page 3      capability container (CC), one-time programmable
+//! page 4..    user memory
+//! then        dynamic lock bytes and configuration pages (EV1 and NTAG)
+//! ```
+
+use core::ops::Range;
+
+use embedded_hal::spi::SpiDevice;
+use mfrc522::{Error, Uid};
+
+use crate::ndef::{self, NdefError, TagError};
+use crate::pcd::Pcd;
+
+pub const PAGE_SIZE: usize = 4;
+/// First page of user memory on every tag of the family.
+pub const FIRST_USER_PAGE: u8 = 4;
+pub const CC_PAGE: u8 = 3;
+const STATIC_LOCK_PAGE: u8 = 2;
+
+// PICC commands
+const PICC_GET_VERSION: u8 = 0x60;
+const PICC_READ: u8 = 0x30;
+const PICC_WRITE: u8 = 0xA2;
+const ACK: u8 = 0x0A;
+
+/// Magic number in the first CC byte of a tag formatted for NDEF.
+const CC_MAGIC: u8 = 0xE1;
+/// NDEF mapping version 1.0.
+const CC_VERSION: u8 = 0x10;
+/// Access byte of a CC that allows writing.
+const CC_WRITABLE: u8 = 0x00;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagType {
+    /// The original Ultralight, or anything that doesn't answer GET_VERSION.
+    Ultralight,
+    /// Ultralight EV1 with 48 bytes of user memory (MF0UL11).
+    UltralightEv1_11,
+    /// Ultralight EV1 with 128 bytes of user memory (MF0UL21).
+    UltralightEv1_21,
+    Ntag213,
+    Ntag215,
+    Ntag216,
+}
+
+impl TagType {
+    /// Identifies the tag from its 8-byte GET_VERSION answer: product type in
+    /// byte 2, storage size in byte 6.
+    pub fn from_version(version: &[u8; 8]) -> Option<Self> {
+        match (version[2], version[6]) {
+            (0x03, 0x0B) => Some(TagType::UltralightEv1_11),
+            (0x03, 0x0E) => Some(TagType::UltralightEv1_21),
+            (0x04, 0x0F) => Some(TagType::Ntag213),
+            (0x04, 0x11) => Some(TagType::Ntag215),
+            (0x04, 0x13) => Some(TagType::Ntag216),
+            _ => None,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            TagType::Ultralight => "MIFARE Ultralight",
+            TagType::UltralightEv1_11 => "MIFARE Ultralight EV1 (MF0UL11)",
+            TagType::UltralightEv1_21 => "MIFARE Ultralight EV1 (MF0UL21)",
+            TagType::Ntag213 => "NTAG213",
+            TagType::Ntag215 => "NTAG215",
+            TagType::Ntag216 => "NTAG216",
+        }
+    }
+
+    /// Total number of pages, configuration included.
+    pub const fn page_count(self) -> u8 {
+        match self {
+            TagType::Ultralight => 16,
+            TagType::UltralightEv1_11 => 20,
+            TagType::UltralightEv1_21 => 41,
+            TagType::Ntag213 => 45,
+            TagType::Ntag215 => 135,
+            TagType::Ntag216 => 231,
+        }
+    }
+
+    /// Pages free for user data.
+    pub const fn user_pages(self) -> Range<u8> {
+        let end = match self {
+            TagType::Ultralight | TagType::UltralightEv1_11 => 16,
+            TagType::UltralightEv1_21 => 36,
+            TagType::Ntag213 => 40,
+            TagType::Ntag215 => 130,
+            TagType::Ntag216 => 226,
+        };
+        FIRST_USER_PAGE..end
+    }
+
+    /// The page holding the dynamic lock bytes, on tags that have them.
+    pub const fn dynamic_lock_page(self) -> Option<u8> {
+        match self {
+            TagType::Ultralight | TagType::UltralightEv1_11 => None,
+            TagType::UltralightEv1_21 => Some(36),
+            TagType::Ntag213 => Some(40),
+            TagType::Ntag215 => Some(130),
+            TagType::Ntag216 => Some(226),
+        }
+    }
+
+    /// What a page is used for, for labelling dumps.
+    pub const fn page_kind(self, page: u8) -> &'static str {
+        let user = self.user_pages();
+        match page {
+            0..=1 => "UID",
+            STATIC_LOCK_PAGE => "UID/LOCK",
+            CC_PAGE => "CC",
+            _ if page >= user.start && page < user.end => "DATA",
+            _ if matches!(self.dynamic_lock_page(), Some(p) if p == page) => "DYN LOCK",
+            _ => "CONFIG",
+        }
+    }
+}
+
+/// The capability container in page 3, which tells an NDEF reader how much
+/// memory there is and whether it may write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CapabilityContainer {
+    pub version: u8,
+    /// Size of the data area in bytes.
+    pub data_size: u16,
+    pub read_only: bool,
+}
+
+impl CapabilityContainer {
+    /// Parses page 3. Returns `None` if the tag isn't formatted for NDEF.
+    pub fn parse(page: &[u8; PAGE_SIZE]) -> Option<Self> {
+        if page[0] != CC_MAGIC {
+            return None;
+        }
+        Some(Self {
+            version: page[1],
+            data_size: page[2] as u16 * 8,
+            read_only: page[3] != CC_WRITABLE,
+        })
+    }
+}
+
+/// Is `page` write-protected by the static lock bytes (bytes 2 and 3 of page
+/// 2)? Only pages 3..=15 can be locked this way; higher pages use the dynamic
+/// lock bytes.
+pub fn is_statically_locked(lock: &[u8; 2], page: u8) -> bool {
+    match page {
+        CC_PAGE => lock[0] & 0x08 != 0,
+        4..=7 => lock[0] & (1 << page) != 0,
+        8..=15 => lock[1] & (1 << (page - 8)) != 0,
+        _ => false,
+    }
+}
+
+/// Finds out which tag of the family this is. Expects a tag that answered
+/// with SAK 0x00 and leaves it selected.
+pub fn identify<E, SPI: SpiDevice<Error = E>>(
+    uid: &Uid,
+    pcd: &mut Pcd<SPI>,
+) -> Result<TagType, Error<E>> {
+    match get_version(pcd) {
+        Ok(version) => Ok(TagType::from_version(&version).unwrap_or(TagType::Ultralight)),
+        // The original Ultralight doesn't know GET_VERSION and drops back to
+        // IDLE, so wake it up again
+        Err(_) => {
+            pcd.activate(uid)?;
+            Ok(TagType::Ultralight)
+        }
+    }
+}
+
+pub fn get_version<E, SPI: SpiDevice<Error = E>>(pcd: &mut Pcd<SPI>) -> Result<[u8; 8], Error<E>> {
+    let mut version = [0u8; 8];
+    if pcd.transceive_crc(&[PICC_GET_VERSION], &mut version)? != version.len() {
+        return Err(Error::IncompleteFrame);
+    }
+    Ok(version)
+}
+
+/// Reads four pages starting at `page`. Reads past the last page wrap around
+/// to page 0.
+pub fn read_pages<E, SPI: SpiDevice<Error = E>>(
+    page: u8,
+    pcd: &mut Pcd<SPI>,
+) -> Result<[u8; 16], Error<E>> {
+    let mut data = [0u8; 16];
+    match pcd.transceive_crc(&[PICC_READ, page], &mut data)? {
+        16 => Ok(data),
+        1 => Err(Error::Nak),
+        _ => Err(Error::IncompleteFrame),
+    }
+}
+
+/// Writes a single page.
+pub fn write_page<E, SPI: SpiDevice<Error = E>>(
+    page: u8,
+    data: &[u8; PAGE_SIZE],
+    pcd: &mut Pcd<SPI>,
+) -> Result<(), Error<E>> {
+    let frame = [PICC_WRITE, page, data[0], data[1], data[2], data[3]];
+    let mut ack = [0u8; 1];
+    let n = pcd.transceive_crc(&frame, &mut ack)?;
+    if n != 1 || ack[0] & 0x0F != ACK {
+        return Err(Error::Nak);
+    }
+    Ok(())
+}
+
+/// Writes `data` page by page starting at `page`, padding the last page with
+/// zeros.
+pub fn write_pages<E, SPI: SpiDevice<Error = E>>(
+    page: u8,
+    data: &[u8],
+    pcd: &mut Pcd<SPI>,
+) -> Result<(), Error<E>> {
+    for (i, chunk) in data.chunks(PAGE_SIZE).enumerate() {
+        let mut buf = [0u8; PAGE_SIZE];
+        buf[..chunk.len()].copy_from_slice(chunk);
+        write_page(page + i as u8, &buf, pcd)?;
+    }
+    Ok(())
+}
+
+/// The NDEF data area described by the CC, as a range of pages. Never extends
+/// past the user memory of `tag`.
+fn ndef_pages(tag: TagType, cc: &CapabilityContainer) -> Range<u8> {
+    let user = tag.user_pages();
+    let pages = (cc.data_size as usize / PAGE_SIZE).min(user.len()) as u8;
+    FIRST_USER_PAGE..FIRST_USER_PAGE + pages
+}
+
+fn read_cc<E, SPI: SpiDevice<Error = E>>(
+    pcd: &mut Pcd<SPI>,
+) -> Result<CapabilityContainer, TagError<E>> {
+    let data = read_pages(CC_PAGE, pcd)?;
+    let cc = data[..PAGE_SIZE].try_into().unwrap();
+    Ok(CapabilityContainer::parse(&cc).ok_or(NdefError::NotFormatted)?)
+}
+
+/// Reads the NDEF message into `buf` and returns it.
+pub fn read_ndef<'b, E, SPI: SpiDevice<Error = E>>(
+    tag: TagType,
+    pcd: &mut Pcd<SPI>,
+    buf: &'b mut [u8],
+) -> Result<&'b [u8], TagError<E>> {
+    let cc = read_cc(pcd)?;
+    let area = ndef_pages(tag, &cc);
+
+    let mut len = 0;
+    for page in area.clone().step_by(4) {
+        let data = read_pages(page, pcd)?;
+        // The last read may run past the data area
+        let pages = (area.end - page).min(4) as usize;
+        let n = pages * PAGE_SIZE;
+        if len + n > buf.len() {
+            return Err(NdefError::NoRoom.into());
+        }
+        buf[len..len + n].copy_from_slice(&data[..n]);
+        len += n;
+
+        match ndef::message_range(&buf[..len]) {
+            Ok(range) => return Ok(&buf[range]),
+            Err(NdefError::Incomplete) => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Err(NdefError::NoMessage.into())
+}
+
+/// Writes `message` as the NDEF message of a formatted tag.
+pub fn write_ndef<E, SPI: SpiDevice<Error = E>>(
+    tag: TagType,
+    message: &[u8],
+    pcd: &mut Pcd<SPI>,
+) -> Result<(), TagError<E>> {
+    let cc = read_cc(pcd)?;
+    if cc.read_only {
+        return Err(NdefError::ReadOnly.into());
+    }
+    let area = ndef_pages(tag, &cc);
+    if ndef::tlv_size(message.len())? > area.len() * PAGE_SIZE {
+        return Err(NdefError::NoRoom.into());
+    }
+
+    let mut bytes = ndef::tlv_bytes(message)?.peekable();
+    for page in area {
+        let mut data = [0u8; PAGE_SIZE];
+        for (byte, value) in data.iter_mut().zip(&mut bytes) {
+            *byte = value;
+        }
+        write_page(page, &data, pcd)?;
+
+        if bytes.peek().is_none() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Formats a blank tag for NDEF by writing the CC and an empty message.
+///
+/// The CC page is one-time programmable: bits can be set but never cleared,
+/// so this refuses to touch a CC that isn't all zeros.
+pub fn format_ndef<E, SPI: SpiDevice<Error = E>>(
+    tag: TagType,
+    pcd: &mut Pcd<SPI>,
+) -> Result<(), TagError<E>> {
+    let data = read_pages(CC_PAGE, pcd)?;
+    if data[..PAGE_SIZE] != [0; PAGE_SIZE] {
+        return Err(NdefError::Malformed.into());
+    }
+
+    let data_size = tag.user_pages().len() * PAGE_SIZE;
+    let cc = [CC_MAGIC, CC_VERSION, (data_size / 8) as u8, CC_WRITABLE];
+    write_page(
+        FIRST_USER_PAGE,
+        &[ndef::TLV_NDEF, 0x00, ndef::TLV_TERMINATOR, 0x00],
+        pcd,
+    )?;
+    write_page(CC_PAGE, &cc, pcd)?;
+    Ok(())
+}
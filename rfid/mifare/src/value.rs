@@ -0,0 +1,206 @@
+//! MIFARE Classic value blocks.
+//!
+//! A value block stores a signed 32-bit value three times, the middle copy
+//! inverted, followed by an address byte four times, alternately inverted:
+//!
+//! ```text
+//! value | !value | value | addr !addr addr !addr
+//! ```
+//!
+//! INCREMENT, DECREMENT and RESTORE load a value block into the card's
+//! transfer buffer and change it there; TRANSFER writes the buffer to a block
+//! of the same sector. The card checks the format before it touches a block.
+
+use embedded_hal::spi::SpiDevice;
+use mfrc522::Error;
+
+use crate::classic::BLOCK_SIZE;
+use crate::pcd::Pcd;
+
+// PICC commands
+const PICC_DECREMENT: u8 = 0xC0;
+const PICC_INCREMENT: u8 = 0xC1;
+const PICC_RESTORE: u8 = 0xC2;
+const PICC_TRANSFER: u8 = 0xB0;
+const ACK: u8 = 0x0A;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueBlock {
+    pub value: i32,
+    /// Free for the application; usually the block's own number, so a copy
+    /// in the wrong place can be spotted.
+    pub addr: u8,
+}
+
+impl ValueBlock {
+    pub const fn new(value: i32, addr: u8) -> Self {
+        Self { value, addr }
+    }
+
+    pub fn encode(&self) -> [u8; BLOCK_SIZE] {
+        let value = self.value.to_le_bytes();
+        let inverted = (!self.value).to_le_bytes();
+        let mut data = [0u8; BLOCK_SIZE];
+        data[0..4].copy_from_slice(&value);
+        data[4..8].copy_from_slice(&inverted);
+        data[8..12].copy_from_slice(&value);
+        data[12..16].copy_from_slice(&[self.addr, !self.addr, self.addr, !self.addr]);
+        data
+    }
+
+    /// Decodes a value block, or returns `None` if any of the copies
+    /// disagree.
+    pub fn decode(data: &[u8; BLOCK_SIZE]) -> Option<Self> {
+        let word = |i: usize| i32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        let value = word(0);
+        let addr = data[12];
+        if word(4) != !value
+            || word(8) != value
+            || data[13] != !addr
+            || data[14] != addr
+            || data[15] != !addr
+        {
+            return None;
+        }
+        Some(Self { value, addr })
+    }
+}
+
+/// Whether `data` is in value block format.
+pub fn is_value_block(data: &[u8; BLOCK_SIZE]) -> bool {
+    ValueBlock::decode(data).is_some()
+}
+
+/// Loads value block `block` into the transfer buffer and adds `delta`.
+/// Nothing is stored until [`transfer`].
+pub fn increment<E, SPI: SpiDevice<Error = E>>(
+    block: u8,
+    delta: u32,
+    pcd: &mut Pcd<SPI>,
+) -> Result<(), Error<E>> {
+    value_command(PICC_INCREMENT, block, delta.to_le_bytes(), pcd)
+}
+
+/// Loads value block `block` into the transfer buffer and subtracts
+/// `delta`. Nothing is stored until [`transfer`].
+pub fn decrement<E, SPI: SpiDevice<Error = E>>(
+    block: u8,
+    delta: u32,
+    pcd: &mut Pcd<SPI>,
+) -> Result<(), Error<E>> {
+    value_command(PICC_DECREMENT, block, delta.to_le_bytes(), pcd)
+}
+
+/// Loads value block `block` into the transfer buffer unchanged, to copy it
+/// with [`transfer`].
+pub fn restore<E, SPI: SpiDevice<Error = E>>(
+    block: u8,
+    pcd: &mut Pcd<SPI>,
+) -> Result<(), Error<E>> {
+    value_command(PICC_RESTORE, block, [0; 4], pcd)
+}
+
+/// Writes the transfer buffer to `block`.
+pub fn transfer<E, SPI: SpiDevice<Error = E>>(
+    block: u8,
+    pcd: &mut Pcd<SPI>,
+) -> Result<(), Error<E>> {
+    let mut ack = [0u8; 1];
+    let n = pcd.transceive_crc(&[PICC_TRANSFER, block], &mut ack)?;
+    if n != 1 || ack[0] & 0x0F != ACK {
+        return Err(Error::Nak);
+    }
+    Ok(())
+}
+
+/// Sends a value command and its operand. The card acknowledges the command
+/// but stays silent after the operand unless it has to NAK it.
+fn value_command<E, SPI: SpiDevice<Error = E>>(
+    command: u8,
+    block: u8,
+    operand: [u8; 4],
+    pcd: &mut Pcd<SPI>,
+) -> Result<(), Error<E>> {
+    let mut ack = [0u8; 1];
+    let n = pcd.transceive_crc(&[command, block], &mut ack)?;
+    if n != 1 || ack[0] & 0x0F != ACK {
+        return Err(Error::Nak);
+    }
+    match pcd.transceive_crc(&operand, &mut ack) {
+        Err(Error::Timeout) => Ok(()),
+        Ok(_) => Err(Error::Nak),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_positive_value() {
+        assert_eq!(
+            ValueBlock::new(100, 4).encode(),
+            [
+                0x64, 0x00, 0x00, 0x00, 0x9B, 0xFF, 0xFF, 0xFF, 0x64, 0x00, 0x00, 0x00, 0x04, 0xFB,
+                0x04, 0xFB,
+            ]
+        );
+    }
+
+    #[test]
+    fn encodes_negative_value() {
+        assert_eq!(
+            ValueBlock::new(-1, 0x3E).encode(),
+            [
+                0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x3E, 0xC1,
+                0x3E, 0xC1,
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        for value in [0, 1, -1, 100, -100, i32::MAX, i32::MIN] {
+            for addr in [0x00, 0x04, 0x80, 0xFF] {
+                let block = ValueBlock::new(value, addr);
+                assert_eq!(ValueBlock::decode(&block.encode()), Some(block));
+                assert!(is_value_block(&block.encode()));
+            }
+        }
+    }
+
+    #[test]
+    fn any_changed_copy_is_rejected() {
+        let data = ValueBlock::new(-100, 9).encode();
+        for i in 0..BLOCK_SIZE {
+            let mut bad = data;
+            bad[i] ^= 0x01;
+            assert_eq!(ValueBlock::decode(&bad), None, "byte {i}");
+        }
+    }
+
+    #[test]
+    fn inverted_copy_must_be_inverted() {
+        // Three plain copies, as a careless writer might store them
+        let mut data = ValueBlock::new(5, 4).encode();
+        data[4..8].copy_from_slice(&5i32.to_le_bytes());
+        assert!(!is_value_block(&data));
+    }
+
+    #[test]
+    fn address_bytes_must_alternate() {
+        let mut data = ValueBlock::new(5, 4).encode();
+        data[12..16].copy_from_slice(&[4, 4, 4, 4]);
+        assert!(!is_value_block(&data));
+
+        data[12..16].copy_from_slice(&[4, 0xFB, 5, 0xFA]);
+        assert!(!is_value_block(&data));
+    }
+
+    #[test]
+    fn blank_blocks_are_not_values() {
+        assert!(!is_value_block(&[0x00; BLOCK_SIZE]));
+        assert!(!is_value_block(&[0xFF; BLOCK_SIZE]));
+    }
+}
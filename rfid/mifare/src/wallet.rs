@@ -0,0 +1,272 @@
+//! A stored-value wallet in one sector of a Classic card.
+//!
+//! Block 0 of the sector holds the balance as a value block and block 1 a
+//! backup. Every change first copies the balance to the backup, then changes
+//! the balance in place with DECREMENT or INCREMENT and TRANSFER. A card
+//! pulled away half way leaves at least one good copy, and the next
+//! operation restores a damaged balance from the backup.
+
+use core::fmt;
+
+use embedded_hal::spi::SpiDevice;
+use mfrc522::{Error, Uid};
+
+use crate::access::AccessConditions;
+use crate::classic::{self, BLOCK_SIZE};
+use crate::error::RfidError;
+use crate::geometry::{self, CardType};
+use crate::keys::FoundKey;
+use crate::pcd::Pcd;
+use crate::value::{self, ValueBlock};
+
+#[derive(Debug)]
+pub enum WalletError<E> {
+    Card(RfidError<E>),
+    /// Neither the balance nor its backup is a value block.
+    NotFormatted,
+    InsufficientFunds {
+        balance: i32,
+    },
+    /// The new balance doesn't fit in 32 bits.
+    Overflow,
+}
+
+impl<E> From<RfidError<E>> for WalletError<E> {
+    fn from(e: RfidError<E>) -> Self {
+        WalletError::Card(e)
+    }
+}
+
+impl<E> fmt::Display for WalletError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WalletError::Card(e) => e.fmt(f),
+            WalletError::NotFormatted => f.write_str("Not a wallet sector"),
+            WalletError::InsufficientFunds { balance } => {
+                write!(f, "Insufficient funds, balance {}", balance)
+            }
+            WalletError::Overflow => f.write_str("Balance out of range"),
+        }
+    }
+}
+
+/// The wallet in `sector`, opened with `key`.
+pub struct Wallet<'a> {
+    uid: &'a Uid,
+    sector: u8,
+    first: u8,
+    trailer: u8,
+    key: FoundKey,
+}
+
+impl<'a> Wallet<'a> {
+    /// Sector 0 is refused, as its block 0 is the manufacturer block.
+    pub fn new<E>(
+        uid: &'a Uid,
+        card: CardType,
+        sector: u8,
+        key: FoundKey,
+    ) -> Result<Self, WalletError<E>> {
+        let (Some(first), Some(trailer)) = (card.first_block(sector), card.trailer_block(sector))
+        else {
+            return Err(RfidError::OutOfRange {
+                sector,
+                block: None,
+            }
+            .into());
+        };
+        if sector == 0 {
+            return Err(RfidError::Refused {
+                block: first,
+                reason: "Sector 0 can't hold a wallet",
+            }
+            .into());
+        }
+        Ok(Self {
+            uid,
+            sector,
+            first,
+            trailer,
+            key,
+        })
+    }
+
+    pub fn balance_block(&self) -> u8 {
+        self.first
+    }
+
+    pub fn backup_block(&self) -> u8 {
+        self.first + 1
+    }
+
+    /// Writes `balance` to the balance and backup blocks. Needs write access
+    /// to both.
+    pub fn format<E, SPI: SpiDevice<Error = E>>(
+        &self,
+        balance: i32,
+        pcd: &mut Pcd<SPI>,
+    ) -> Result<(), WalletError<E>> {
+        self.open(pcd)?;
+        for block in [self.balance_block(), self.backup_block()] {
+            let data = ValueBlock::new(balance, block).encode();
+            classic::write_block(block, &data, pcd)
+                .map_err(|cause| self.write_error(block, cause))?;
+        }
+        Ok(())
+    }
+
+    /// Reads the balance, repairing it from the backup if it is damaged.
+    pub fn balance<E, SPI: SpiDevice<Error = E>>(
+        &self,
+        pcd: &mut Pcd<SPI>,
+    ) -> Result<i32, WalletError<E>> {
+        self.open(pcd)?;
+        self.recover(pcd)
+    }
+
+    /// Takes `amount` off the balance and returns the new balance. Refuses
+    /// to go below zero.
+    pub fn debit<E, SPI: SpiDevice<Error = E>>(
+        &self,
+        amount: u32,
+        pcd: &mut Pcd<SPI>,
+    ) -> Result<i32, WalletError<E>> {
+        self.change(amount, false, pcd)
+    }
+
+    /// Adds `amount` to the balance and returns the new balance.
+    pub fn credit<E, SPI: SpiDevice<Error = E>>(
+        &self,
+        amount: u32,
+        pcd: &mut Pcd<SPI>,
+    ) -> Result<i32, WalletError<E>> {
+        self.change(amount, true, pcd)
+    }
+
+    fn change<E, SPI: SpiDevice<Error = E>>(
+        &self,
+        amount: u32,
+        credit: bool,
+        pcd: &mut Pcd<SPI>,
+    ) -> Result<i32, WalletError<E>> {
+        self.open(pcd)?;
+        self.check_access(credit, pcd)?;
+        let balance = self.recover(pcd)?;
+
+        let delta = i32::try_from(amount).map_err(|_| WalletError::Overflow)?;
+        let new = if credit {
+            balance.checked_add(delta)
+        } else {
+            balance.checked_sub(delta)
+        }
+        .ok_or(WalletError::Overflow)?;
+        if new < 0 {
+            return Err(WalletError::InsufficientFunds { balance });
+        }
+
+        let (balance_block, backup_block) = (self.balance_block(), self.backup_block());
+        // Keep the old balance in the backup until the new one is in
+        value::restore(balance_block, pcd)
+            .map_err(|cause| self.write_error(balance_block, cause))?;
+        value::transfer(backup_block, pcd)
+            .map_err(|cause| self.write_error(backup_block, cause))?;
+
+        let result = if credit {
+            value::increment(balance_block, amount, pcd)
+        } else {
+            value::decrement(balance_block, amount, pcd)
+        };
+        result
+            .and_then(|_| value::transfer(balance_block, pcd))
+            .map_err(|cause| self.write_error(balance_block, cause))?;
+
+        // The ACK says the card took it; reading back says what it stored
+        match ValueBlock::decode(&self.read(balance_block, pcd)?) {
+            Some(stored) if stored.value == new => Ok(new),
+            _ => Err(self.write_error(balance_block, Error::Wr).into()),
+        }
+    }
+
+    /// Returns the balance, restoring it from the backup if it doesn't
+    /// decode. The sector must be open.
+    fn recover<E, SPI: SpiDevice<Error = E>>(
+        &self,
+        pcd: &mut Pcd<SPI>,
+    ) -> Result<i32, WalletError<E>> {
+        let (balance_block, backup_block) = (self.balance_block(), self.backup_block());
+        if let Some(balance) = ValueBlock::decode(&self.read(balance_block, pcd)?) {
+            return Ok(balance.value);
+        }
+        let backup =
+            ValueBlock::decode(&self.read(backup_block, pcd)?).ok_or(WalletError::NotFormatted)?;
+        // Written whole rather than restored, which would copy the backup's
+        // address byte too
+        let data = ValueBlock::new(backup.value, balance_block).encode();
+        classic::write_block(balance_block, &data, pcd)
+            .map_err(|cause| self.write_error(balance_block, cause))?;
+        Ok(backup.value)
+    }
+
+    /// Checks that our key may change the balance and copy it to the backup.
+    fn check_access<E, SPI: SpiDevice<Error = E>>(
+        &self,
+        credit: bool,
+        pcd: &mut Pcd<SPI>,
+    ) -> Result<(), WalletError<E>> {
+        let trailer = self.read(self.trailer, pcd)?;
+        let access = AccessConditions::from_trailer(&trailer).map_err(|error| {
+            RfidError::InvalidTrailer {
+                sector: self.sector,
+                error,
+            }
+        })?;
+
+        for block in [self.balance_block(), self.backup_block()] {
+            let group = geometry::access_group(self.sector, block - self.first);
+            let perms = access.data_permissions(group);
+            // TRANSFER and RESTORE fall under the decrement permission
+            let mut allowed = perms.decrement.allows(self.key.key_type);
+            if credit && block == self.balance_block() {
+                allowed &= perms.increment.allows(self.key.key_type);
+            }
+            if !allowed {
+                return Err(RfidError::AccessDenied {
+                    sector: self.sector,
+                    block,
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    fn open<E, SPI: SpiDevice<Error = E>>(&self, pcd: &mut Pcd<SPI>) -> Result<(), RfidError<E>> {
+        pcd.activate(self.uid).map_err(RfidError::Activation)?;
+        pcd.authenticate(self.uid, self.first, self.key.key_type, &self.key.key)
+            .map_err(|cause| RfidError::Auth {
+                sector: self.sector,
+                key_type: self.key.key_type,
+                cause,
+            })
+    }
+
+    fn read<E, SPI: SpiDevice<Error = E>>(
+        &self,
+        block: u8,
+        pcd: &mut Pcd<SPI>,
+    ) -> Result<[u8; BLOCK_SIZE], RfidError<E>> {
+        classic::read_block(block, pcd).map_err(|cause| RfidError::Read {
+            sector: self.sector,
+            block,
+            cause,
+        })
+    }
+
+    fn write_error<E>(&self, block: u8, cause: Error<E>) -> RfidError<E> {
+        RfidError::Write {
+            sector: self.sector,
+            block,
+            cause,
+        }
+    }
+}
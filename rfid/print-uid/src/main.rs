@@ -1,7 +1,6 @@
 #![no_std]
 #![no_main]
 
-use embedded_hal::delay::DelayNs;
 use hal::block::ImageDef;
 use heapless::String;
 use panic_halt as _;
@@ -10,12 +9,19 @@ use rp235x_hal::{self as hal, Clock};
 use usb_device::{class_prelude::*, prelude::*};
 use usbd_serial::SerialPort;
 
+use core::cell::RefCell;
 use core::fmt::Write;
 
 use embedded_hal_bus::spi::ExclusiveDevice;
 
 use mfrc522::{comm::blocking::spi::SpiInterface, Mfrc522};
 
+use mifare::diag;
+use mifare::inventory::{self, Event, Tag, Tracker};
+use mifare::pcd::{Pcd, SharedSpi};
+use mifare::power::{self, CurrentProfile, DutyCycle, Scheduler};
+use mifare::serial::halt;
+
 use hal::fugit::RateExtU32;
 
 #[link_section = ".start_block"]
@@ -24,6 +30,25 @@ pub static IMAGE_DEF: ImageDef = hal::block::ImageDef::secure_exe();
 
 const XTAL_FREQ_HZ: u32 = 12_000_000u32;
 
+/// Most cards reported at once.
+const MAX_TAGS: usize = 8;
+/// Rounds a card may be missing before it counts as gone.
+const MISSES: u8 = 3;
+/// Ten rounds a second. USB keeps the core awake, so only the reader sleeps
+/// in between.
+const DUTY: DutyCycle = DutyCycle {
+    idle_period_us: 100_000,
+    active_period_us: 100_000,
+    linger: 0,
+};
+const STATS_INTERVAL_US: u64 = 10_000_000;
+/// The core never sleeps here, so only the reader's share is estimated.
+const READER_ONLY: CurrentProfile = CurrentProfile {
+    mcu_active_ua: 0,
+    mcu_sleep_ua: 0,
+    ..CurrentProfile::TYPICAL
+};
+
 #[hal::entry]
 fn main() -> ! {
     // Boiler Plate
@@ -47,7 +72,7 @@ fn main() -> ! {
         sio.gpio_bank0,
         &mut pac.RESETS,
     );
-    let mut timer = hal::Timer::new_timer0(pac.TIMER0, &mut pac.RESETS, &clocks);
+    let timer = hal::Timer::new_timer0(pac.TIMER0, &mut pac.RESETS, &clocks);
 
     // For USB Serial
     let usb_bus = UsbBusAllocator::new(hal::usb::UsbBus::new(
@@ -86,21 +111,79 @@ fn main() -> ! {
     );
 
     let spi = ExclusiveDevice::new(spi, spi_cs, timer).unwrap();
-    let itf = SpiInterface::new(spi);
-    let mut rfid = Mfrc522::new(itf).init().unwrap();
+    let spi = RefCell::new(spi);
+    // The driver sets the chip up; the inventory goes through Pcd
+    let mut pcd = Pcd::new(SharedSpi::new(&spi));
+    let _rfid = diag::probe(
+        &mut pcd,
+        Mfrc522::new(SpiInterface::new(SharedSpi::new(&spi))),
+    )
+    .unwrap_or_else(|message| halt(&mut usb_dev, &mut serial, message));
+
+    let mut tracker = Tracker::<MAX_TAGS>::new(MISSES);
+    let mut tags = [Tag::default(); MAX_TAGS];
+
+    let _ = power::power_down(&mut pcd);
+    let start = timer.get_counter().ticks();
+    let mut scheduler = Scheduler::new(DUTY, start);
+    let mut next_stats = start + STATS_INTERVAL_US;
 
     loop {
         let _ = usb_dev.poll(&mut [&mut serial]);
-        if let Ok(atqa) = rfid.reqa() {
-            if let Ok(uid) = rfid.select(&atqa) {
-                serial.write("\r\nUID: \r\n".as_bytes()).unwrap();
-                print_hex_to_serial(uid.as_bytes(), &mut serial);
-                timer.delay_ms(500);
-            }
+        let now = timer.get_counter().ticks();
+        if now >= next_stats {
+            print_stats(&scheduler, now, &mut serial);
+            scheduler.reset_stats(now);
+            next_stats = now + STATS_INTERVAL_US;
+        }
+        if !scheduler.is_due(now) {
+            continue;
         }
+
+        // A failed round is treated like an empty one; the misses allowance
+        // keeps it from reporting departures straight away
+        let found = match power::power_up(&mut pcd) {
+            Ok(()) => inventory::inventory(&mut pcd, &mut tags).unwrap_or(0),
+            Err(_) => 0,
+        };
+        let _ = power::power_down(&mut pcd);
+        scheduler.record(now, timer.get_counter().ticks(), found > 0);
+        tracker.update(&tags[..found], |event| print_event(&event, &mut serial));
     }
 }
 
+fn print_stats<B: UsbBus>(scheduler: &Scheduler, now: u64, serial: &mut SerialPort<B>) {
+    let stats = scheduler.stats(now);
+    let rate = stats.poll_rate_centihz();
+    let duty = stats.duty_ppm();
+    let mut buff: String<96> = String::new();
+    write!(
+        buff,
+        "\r\n{}.{:02} polls/s, field on {}.{:02}%, reader about {} uA\r\n",
+        rate / 100,
+        rate % 100,
+        duty / 10_000,
+        duty / 100 % 100,
+        stats.average_current_ua(&READER_ONLY),
+    )
+    .unwrap();
+    // Dropped when nobody has the port open
+    let _ = serial.write(buff.as_bytes());
+}
+
+fn print_event<B: UsbBus>(event: &Event, serial: &mut SerialPort<B>) {
+    let (label, tag) = match event {
+        Event::Arrived(tag) => ("\r\n+ ", tag),
+        Event::Departed(tag) => ("\r\n- ", tag),
+    };
+    serial.write(label.as_bytes()).unwrap();
+    print_hex_to_serial(tag.uid(), serial);
+
+    let mut buff: String<64> = String::new();
+    write!(buff, "SAK {:02x} {}\r\n", tag.sak(), tag.kind()).unwrap();
+    serial.write(buff.as_bytes()).unwrap();
+}
+
 fn print_hex_to_serial<B: UsbBus>(data: &[u8], serial: &mut SerialPort<B>) {
     let mut buff: String<64> = String::new();
     for &d in data.iter() {
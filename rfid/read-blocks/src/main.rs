@@ -9,12 +9,21 @@ use rp235x_hal::{self as hal, Clock};
 use usb_device::{class_prelude::*, prelude::*};
 use usbd_serial::SerialPort;
 
+use core::cell::RefCell;
 use core::fmt::Write;
 
 use embedded_hal_bus::spi::ExclusiveDevice;
 
 use mfrc522::{comm::blocking::spi::SpiInterface, Mfrc522};
 
+use mifare::access::KeyType;
+use mifare::card;
+use mifare::diag;
+use mifare::error::RfidError;
+use mifare::geometry::CardType;
+use mifare::pcd::{Pcd, SharedSpi};
+use mifare::serial::{halt, report};
+
 use hal::fugit::RateExtU32;
 
 #[link_section = ".start_block"]
@@ -85,15 +94,29 @@ fn main() -> ! {
     );
 
     let spi = ExclusiveDevice::new(spi, spi_cs, timer).unwrap();
-    let itf = SpiInterface::new(spi);
-    let mut rfid = Mfrc522::new(itf).init().unwrap();
+    // Pcd shares the SPI device with the driver to read the card's SAK
+    let spi = RefCell::new(spi);
+    let mut pcd = Pcd::new(SharedSpi::new(&spi));
+    let mut rfid = diag::probe(
+        &mut pcd,
+        Mfrc522::new(SpiInterface::new(SharedSpi::new(&spi))),
+    )
+    .unwrap_or_else(|message| halt(&mut usb_dev, &mut serial, message));
 
     loop {
         let _ = usb_dev.poll(&mut [&mut serial]);
         if let Ok(atqa) = rfid.reqa() {
             if let Ok(uid) = rfid.select(&atqa) {
-                if let Err(e) = read_sector(&uid, 0, &mut rfid, &mut serial) {
-                    serial.write(e.as_bytes()).unwrap();
+                let card = match card::detect_classic(&uid, &mut pcd) {
+                    Ok(card) => card,
+                    Err(e) => {
+                        report(&e, &mut serial);
+                        rfid.hlta().unwrap();
+                        continue;
+                    }
+                };
+                if let Err(e) = read_sector(&uid, card, 0, &mut rfid, &mut serial) {
+                    report(&e, &mut serial);
                 }
                 rfid.hlta().unwrap();
                 rfid.stop_crypto1().unwrap();
@@ -104,22 +127,35 @@ fn main() -> ! {
 
 fn read_sector<E, COMM: mfrc522::comm::Interface<Error = E>, B: UsbBus>(
     uid: &mfrc522::Uid,
+    card: CardType,
     sector: u8,
     rfid: &mut Mfrc522<COMM, mfrc522::Initialized>,
     serial: &mut SerialPort<B>,
-) -> Result<(), &'static str> {
+) -> Result<(), RfidError<E>> {
     const AUTH_KEY: [u8; 6] = [0xFF; 6];
 
-    let block_offset = sector * 4;
+    let out_of_range = || RfidError::OutOfRange {
+        sector,
+        block: None,
+    };
+    let block_offset = card.first_block(sector).ok_or_else(out_of_range)?;
+    let blocks = card.blocks_in_sector(sector).ok_or_else(out_of_range)?;
     rfid.mf_authenticate(uid, block_offset, &AUTH_KEY)
-        .map_err(|_| "Auth failed")?;
-
-    for abs_block in block_offset..block_offset + 4 {
-        let data = rfid.mf_read(abs_block).map_err(|_| "Read failed")?;
+        .map_err(|cause| RfidError::Auth {
+            sector,
+            key_type: KeyType::A,
+            cause,
+        })?;
+
+    for rel_block in 0..blocks {
+        let block = block_offset + rel_block;
+        let data = rfid.mf_read(block).map_err(|cause| RfidError::Read {
+            sector,
+            block,
+            cause,
+        })?;
         print_hex_to_serial(&data, serial);
-        serial
-            .write("\r\n".as_bytes())
-            .map_err(|_| "Write failed")?;
+        serial.write("\r\n".as_bytes()).unwrap();
     }
     Ok(())
 }
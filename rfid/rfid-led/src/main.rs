@@ -1,17 +1,40 @@
 #![no_std]
 #![no_main]
 
-use embedded_hal::{delay::DelayNs, digital::OutputPin};
+use embedded_hal::{
+    delay::DelayNs,
+    digital::{InputPin, OutputPin},
+    spi::SpiDevice,
+};
 use hal::block::ImageDef;
+use hal::gpio::Interrupt::EdgeLow;
+use hal::gpio::{FunctionI2C, Pin};
+use hal::pac::interrupt;
+use hal::timer::Alarm;
+use heapless::String;
 use panic_halt as _;
 use rp235x_hal::{self as hal, Clock};
 
-use hal::fugit::RateExtU32;
+use core::cell::RefCell;
+use core::fmt::Write;
+
+use hal::fugit::{MicrosDurationU32, RateExtU32};
 
 use embedded_hal_bus::spi::ExclusiveDevice;
 
 use mfrc522::{comm::blocking::spi::SpiInterface, Mfrc522};
 
+use mifare::diag;
+use mifare::pcd::{Pcd, Selected, SharedSpi};
+use mifare::power::{self, CurrentProfile, DutyCycle, Scheduler};
+
+use embedded_sdmmc::{BlockDevice, Mode, SdCard, TimeSource, Timestamp, VolumeIdx, VolumeManager};
+
+use access_log::{Chain, Reason, Record, LOG_FILE, PADDING, RECORD_SIZE};
+use allowlist::Allowlist;
+use sdcard_clock::aon::AonClock;
+use sdcard_clock::ds3231::Ds3231;
+
 /// Tell the Boot ROM about our application
 #[link_section = ".start_block"]
 #[used]
@@ -19,6 +42,56 @@ pub static IMAGE_DEF: ImageDef = hal::block::ImageDef::secure_exe();
 
 const XTAL_FREQ_HZ: u32 = 12_000_000u32;
 
+/// The allowlist in the root of the SD card, one card per line:
+///
+/// ```text
+/// # uid, name, valid from, valid until
+/// 13:37:73:31, Front desk
+/// 04A1B2C3D4E5F6, Alice, 2026-01-01, 2026-12-31
+/// ```
+///
+/// Cards with a validity window need the clock, which comes from a DS3231 on
+/// I2C1 (GPIO18 SDA, GPIO19 SCL) after a power cycle. Without one they're
+/// refused.
+const ALLOWLIST_FILE: &str = "ALLOW.TXT";
+const MAX_ENTRIES: usize = 128;
+/// Longer lines are skipped as malformed.
+const MAX_LINE: usize = 96;
+
+/// A card held to the reader is logged once, and again only after it has
+/// been away this long.
+const REPEAT_US: u64 = 2_000_000;
+
+/// How often to poll for cards. The reader sleeps in between.
+const DUTY: DutyCycle = DutyCycle::DOOR;
+/// A card answers REQA within a few hundred microseconds.
+const PROBE_TIMEOUT_US: u32 = 1_000;
+/// Poll rate and current estimate, rewritten every `STATS_INTERVAL_US`.
+const STATS_FILE: &str = "POWER.TXT";
+const STATS_INTERVAL_US: u64 = 600_000_000;
+
+/// The MFRC522 IRQ output on GPIO 8, low while an enabled interrupt is
+/// pending. Left unconnected, polls just take the alarm's backstop time.
+type IrqPin =
+    hal::gpio::Pin<hal::gpio::bank0::Gpio8, hal::gpio::FunctionSioInput, hal::gpio::PullUp>;
+
+/// Records carry their own timestamp, so the file times don't matter.
+#[derive(Default)]
+pub struct DummyTimesource();
+
+impl TimeSource for DummyTimesource {
+    fn get_timestamp(&self) -> Timestamp {
+        Timestamp {
+            year_since_1970: 0,
+            zero_indexed_month: 0,
+            zero_indexed_day: 0,
+            hours: 0,
+            minutes: 0,
+            seconds: 0,
+        }
+    }
+}
+
 #[hal::entry]
 fn main() -> ! {
     // Grab our singleton objects
@@ -54,6 +127,31 @@ fn main() -> ! {
     );
 
     let mut timer = hal::Timer::new_timer0(pac.TIMER0, &mut pac.RESETS, &clocks);
+    // Wakes the core from sleep between polls
+    let mut alarm = timer.alarm_0().unwrap();
+    alarm.enable_interrupt();
+
+    // An optional DS3231 RTC module on I2C1
+    let sda_pin: Pin<_, FunctionI2C, _> = pins.gpio18.reconfigure();
+    let scl_pin: Pin<_, FunctionI2C, _> = pins.gpio19.reconfigure();
+    let i2c = hal::I2C::i2c1(
+        pac.I2C1,
+        sda_pin,
+        scl_pin,
+        400.kHz(),
+        &mut pac.RESETS,
+        &clocks.system_clock,
+    );
+    let mut ds3231 = Ds3231::new(i2c);
+
+    // The AON timer keeps the time across resets. After a power cycle, take
+    // it from the DS3231 if there is one and it's been set.
+    let aon = AonClock::new(pac.POWMAN, XTAL_FREQ_HZ);
+    if !aon.is_set() {
+        if let Ok(Some(now)) = ds3231.now() {
+            aon.set(&now);
+        }
+    }
 
     let mut led = pins.gpio25.into_push_pull_output();
     // RFID Setup
@@ -71,26 +169,320 @@ fn main() -> ! {
     );
 
     let spi = ExclusiveDevice::new(spi, spi_cs, timer).unwrap();
-    let itf = SpiInterface::new(spi);
-    let mut rfid = Mfrc522::new(itf).init().unwrap();
+    // The driver sets the chip up; polling goes through Pcd
+    let spi = RefCell::new(spi);
+    let mut pcd = Pcd::new(SharedSpi::new(&spi));
+    // Look for the chip before starting the driver. There's no serial port
+    // to say what's wrong, so a missing or dead reader gets a slow blink
+    // for good.
+    let rfid = diag::probe(
+        &mut pcd,
+        Mfrc522::new(SpiInterface::new(SharedSpi::new(&spi))),
+    )
+    .ok();
+    if rfid.is_none() || power::enable_irq(&mut pcd).is_err() {
+        loop {
+            led.set_high().unwrap();
+            timer.delay_ms(1000);
+            led.set_low().unwrap();
+            timer.delay_ms(1000);
+        }
+    }
+    let mut rfid_irq: IrqPin = pins.gpio8.into_pull_up_input();
+    rfid_irq.set_interrupt_enabled(EdgeLow, true);
+
+    // SD card Setup, on SPI1 since the reader has SPI0
+    let sd_cs = pins.gpio13.into_push_pull_output();
+    let sd_sck = pins.gpio10.into_function::<hal::gpio::FunctionSpi>();
+    let sd_mosi = pins.gpio11.into_function::<hal::gpio::FunctionSpi>();
+    let sd_miso = pins.gpio12.into_function::<hal::gpio::FunctionSpi>();
+    let sd_bus = hal::spi::Spi::<_, _, _, 8>::new(pac.SPI1, (sd_mosi, sd_miso, sd_sck));
 
-    // Replace the UID Bytes with your tag UID
-    const TAG_UID: [u8; 4] = [0x13, 0x37, 0x73, 0x31];
+    let sd_spi = sd_bus.init(
+        &mut pac.RESETS,
+        clocks.peripheral_clock.freq(),
+        400.kHz(), // card initialization happens at low baud rate
+        embedded_hal::spi::MODE_0,
+    );
+
+    let sd_spi = ExclusiveDevice::new(sd_spi, sd_cs, timer).unwrap();
+    let sdcard = SdCard::new(sd_spi, timer);
+    let mut volume_mgr = VolumeManager::new(sdcard, DummyTimesource::default());
+
+    let mut allowlist = Allowlist::<MAX_ENTRIES>::new();
+    // Flash a few times if the file is missing or has lines we skipped
+    let loaded = load_allowlist(&mut volume_mgr, &mut allowlist);
+    let mut chain = open_log(&mut volume_mgr);
+    if let Ok(chain) = chain.as_mut() {
+        let boot = chain.seal(&Record::boot(timer.get_counter().ticks()));
+        let _ = append_record(&mut volume_mgr, &boot);
+    }
+    if !matches!(loaded, Ok(0)) || chain.is_err() {
+        for _ in 0..5 {
+            led.set_high().unwrap();
+            timer.delay_ms(100);
+            led.set_low().unwrap();
+            timer.delay_ms(100);
+        }
+    }
+
+    // UID of the card in front of the reader and when it was last seen
+    let mut last_tap: Option<([u8; 10], usize, u64)> = None;
+
+    let _ = power::power_down(&mut pcd);
+    let start = timer.get_counter().ticks();
+    let mut scheduler = Scheduler::new(DUTY, start);
+    let mut next_stats = start + STATS_INTERVAL_US;
 
     loop {
         led.set_low().unwrap();
 
-        if let Ok(atqa) = rfid.reqa() {
-            if let Ok(uid) = rfid.select(&atqa) {
-                if *uid.as_bytes() == TAG_UID {
-                    led.set_high().unwrap();
-                    timer.delay_ms(500);
+        let now = timer.get_counter().ticks();
+        if now >= next_stats {
+            let _ = write_stats(&mut volume_mgr, &scheduler, now);
+            scheduler.reset_stats(now);
+            next_stats = now + STATS_INTERVAL_US;
+        }
+        if !scheduler.is_due(now) {
+            let wait = (scheduler.next_poll_us() - now).min(STATS_INTERVAL_US) as u32;
+            sleep(&mut alarm, wait, || false);
+            continue;
+        }
+
+        let started = now;
+        let selected = power::power_up(&mut pcd)
+            .ok()
+            .and_then(|()| poll(&mut pcd, &mut alarm, &mut rfid_irq));
+        let mut granted = false;
+        if let Some(selected) = selected {
+            let uid = selected.uid();
+            let now = timer.get_counter().ticks();
+            // Cards with a validity window are refused until the clock is set
+            let decision = allowlist.check(uid, aon.now().map(|time| time.to_unix()));
+
+            let repeat = last_tap
+                .is_some_and(|(last, len, seen)| last[..len] == *uid && now - seen < REPEAT_US);
+            let mut last = [0u8; 10];
+            last[..uid.len()].copy_from_slice(uid);
+            last_tap = Some((last, uid.len(), now));
+
+            if !repeat {
+                if let Ok(chain) = chain.as_mut() {
+                    let reason = match decision {
+                        Ok(_) => Reason::Granted,
+                        Err(denial) => denial.into(),
+                    };
+                    let record = chain.seal(&Record::tap(now, uid, reason));
+                    let _ = append_record(&mut volume_mgr, &record);
                 }
             }
+
+            granted = decision.is_ok();
+        }
+        // Power-down takes the field away, so a card left on the reader
+        // answers the next REQA afresh
+        let _ = power::power_down(&mut pcd);
+        scheduler.record(started, timer.get_counter().ticks(), selected.is_some());
+
+        if granted {
+            led.set_high().unwrap();
+            sleep(&mut alarm, 500_000, || false);
         }
     }
 }
 
+/// One poll: REQA with a short timeout, sleeping until the reader's IRQ pin
+/// says it's over, then anticollision if anything answered.
+fn poll<E, SPI: SpiDevice<Error = E>, A: Alarm>(
+    pcd: &mut Pcd<SPI>,
+    alarm: &mut A,
+    irq: &mut IrqPin,
+) -> Option<Selected> {
+    let probe = power::probe(pcd, PROBE_TIMEOUT_US).ok()?;
+    // The alarm only matters if the IRQ pin isn't wired
+    sleep(alarm, 2 * PROBE_TIMEOUT_US, || {
+        irq.clear_interrupt(EdgeLow);
+        irq.is_low().unwrap()
+    });
+    if !probe.finish(pcd).ok()? {
+        return None;
+    }
+    pcd.anticollision().ok()
+}
+
+/// Sleeps for `us` microseconds, or until `wake` returns true. `wake` is
+/// checked with interrupts off, so an interrupt between the check and the
+/// sleep still wakes the core.
+fn sleep<A: Alarm>(alarm: &mut A, us: u32, mut wake: impl FnMut() -> bool) {
+    alarm.clear_interrupt();
+    if alarm.schedule(MicrosDurationU32::micros(us)).is_err() {
+        return;
+    }
+    loop {
+        hal::arch::interrupt_disable();
+        let done = alarm.finished() || wake();
+        if !done {
+            // The handlers mask their interrupt again once it has woken us
+            unsafe {
+                hal::arch::interrupt_unmask(hal::pac::Interrupt::TIMER0_IRQ_0);
+                hal::arch::interrupt_unmask(hal::pac::Interrupt::IO_IRQ_BANK0);
+            }
+            hal::arch::wfi();
+        }
+        unsafe { hal::arch::interrupt_enable() };
+        if done {
+            break;
+        }
+    }
+    alarm.clear_interrupt();
+}
+
+// The alarm and the IRQ pin only need to wake the core, and the main loop
+// clears them, so the handlers just mask themselves until the next sleep.
+#[interrupt]
+fn TIMER0_IRQ_0() {
+    hal::arch::interrupt_mask(hal::pac::Interrupt::TIMER0_IRQ_0);
+}
+
+#[interrupt]
+fn IO_IRQ_BANK0() {
+    hal::arch::interrupt_mask(hal::pac::Interrupt::IO_IRQ_BANK0);
+}
+
+/// Replaces the stats file with the poll rate, the time spent awake and the
+/// average current since the last report.
+fn write_stats<D: BlockDevice, T: TimeSource>(
+    volume_mgr: &mut VolumeManager<D, T>,
+    scheduler: &Scheduler,
+    now: u64,
+) -> Result<(), embedded_sdmmc::Error<D::Error>> {
+    let stats = scheduler.stats(now);
+    let rate = stats.poll_rate_centihz();
+    let duty = stats.duty_ppm();
+    let mut text: String<128> = String::new();
+    write!(
+        text,
+        "{} polls in {} s, {}.{:02} polls/s, {} with a card\r\n\
+         awake {}.{:02}%, about {} uA average\r\n",
+        stats.polls,
+        stats.elapsed_us() / 1_000_000,
+        rate / 100,
+        rate % 100,
+        stats.cards,
+        duty / 10_000,
+        duty / 100 % 100,
+        stats.average_current_ua(&CurrentProfile::TYPICAL),
+    )
+    .unwrap();
+
+    let mut volume = volume_mgr.open_volume(VolumeIdx(0))?;
+    let mut root_dir = volume.open_root_dir()?;
+    let mut file = root_dir.open_file_in_dir(STATS_FILE, Mode::ReadWriteCreateOrTruncate)?;
+    file.write(text.as_bytes())?;
+    file.close()
+}
+
+/// Picks up the chain where the log on the card ends. A record cut short by
+/// a power loss is padded out so the next one starts on a record boundary.
+fn open_log<D: BlockDevice, T: TimeSource>(
+    volume_mgr: &mut VolumeManager<D, T>,
+) -> Result<Chain, embedded_sdmmc::Error<D::Error>> {
+    let mut volume = volume_mgr.open_volume(VolumeIdx(0))?;
+    let mut root_dir = volume.open_root_dir()?;
+    let mut file = root_dir.open_file_in_dir(LOG_FILE, Mode::ReadWriteCreateOrAppend)?;
+
+    let len = file.length() as usize;
+    let whole = len - len % RECORD_SIZE;
+    let mut chain = Chain::new();
+    if whole > 0 {
+        let mut last = [0u8; RECORD_SIZE];
+        file.seek_from_start((whole - RECORD_SIZE) as u32)?;
+        let mut read = 0;
+        while read < RECORD_SIZE {
+            match file.read(&mut last[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        chain = Chain::resume(&last);
+    }
+    if len != whole {
+        file.seek_from_end(0)?;
+        file.write(&[PADDING; RECORD_SIZE][..RECORD_SIZE - (len - whole)])?;
+    }
+    file.close()?;
+    Ok(chain)
+}
+
+/// Appends one record and closes the file again, so a power loss costs at
+/// most the record being written.
+fn append_record<D: BlockDevice, T: TimeSource>(
+    volume_mgr: &mut VolumeManager<D, T>,
+    record: &[u8; RECORD_SIZE],
+) -> Result<(), embedded_sdmmc::Error<D::Error>> {
+    let mut volume = volume_mgr.open_volume(VolumeIdx(0))?;
+    let mut root_dir = volume.open_root_dir()?;
+    let mut file = root_dir.open_file_in_dir(LOG_FILE, Mode::ReadWriteCreateOrAppend)?;
+    file.write(record)?;
+    file.close()
+}
+
+/// Reads the allowlist file into `allowlist`. Malformed lines, duplicates
+/// and lines that don't fit are skipped; returns how many.
+fn load_allowlist<D: BlockDevice, T: TimeSource>(
+    volume_mgr: &mut VolumeManager<D, T>,
+    allowlist: &mut Allowlist<MAX_ENTRIES>,
+) -> Result<usize, embedded_sdmmc::Error<D::Error>> {
+    let mut volume = volume_mgr.open_volume(VolumeIdx(0))?;
+    let mut root_dir = volume.open_root_dir()?;
+    let mut file = root_dir.open_file_in_dir(ALLOWLIST_FILE, Mode::ReadOnly)?;
+
+    let mut line = [0u8; MAX_LINE];
+    let mut len = 0;
+    let mut line_no = 1;
+    let mut overlong = false;
+    let mut skipped = 0;
+
+    while !file.is_eof() {
+        let mut buffer = [0u8; 32];
+        let num_read = file.read(&mut buffer)?;
+        for &b in &buffer[..num_read] {
+            if b != b'\n' {
+                if len < line.len() {
+                    line[len] = b;
+                    len += 1;
+                } else {
+                    overlong = true;
+                }
+                continue;
+            }
+            if !add_line(allowlist, line_no, &line[..len], overlong) {
+                skipped += 1;
+            }
+            line_no += 1;
+            len = 0;
+            overlong = false;
+        }
+    }
+    // The last line may not end in a newline
+    if !add_line(allowlist, line_no, &line[..len], overlong) {
+        skipped += 1;
+    }
+    Ok(skipped)
+}
+
+fn add_line(
+    allowlist: &mut Allowlist<MAX_ENTRIES>,
+    line_no: usize,
+    line: &[u8],
+    overlong: bool,
+) -> bool {
+    let Ok(line) = core::str::from_utf8(line) else {
+        return false;
+    };
+    !overlong && allowlist.add_line(line_no, line).is_ok()
+}
+
 // Program metadata for `picotool info`.
 // This isn't needed, but it's recomended to have these minimal entries.
 #[link_section = ".bi_entries"]
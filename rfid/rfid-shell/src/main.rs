@@ -0,0 +1,480 @@
+#![no_std]
+#![no_main]
+
+use hal::block::ImageDef;
+use heapless::String;
+use panic_halt as _;
+use rp235x_hal::{self as hal, Clock};
+
+use usb_device::{class_prelude::*, prelude::*};
+use usbd_serial::SerialPort;
+
+use core::cell::RefCell;
+use core::fmt::Write;
+
+use embedded_hal::spi::SpiDevice;
+use embedded_hal_bus::spi::ExclusiveDevice;
+
+use mfrc522::{comm::blocking::spi::SpiInterface, Mfrc522};
+
+use mifare::access::KeyType;
+use mifare::card::{self, Card};
+use mifare::classic;
+use mifare::diag::{self, SelfTest};
+use mifare::geometry::{self, CardType};
+use mifare::keys;
+use mifare::pcd::{Pcd, SharedSpi};
+use mifare::shell::{self, Command, ParseError};
+use mifare::ultralight::{self, TagType};
+
+use hal::fugit::RateExtU32;
+
+#[link_section = ".start_block"]
+#[used]
+pub static IMAGE_DEF: ImageDef = hal::block::ImageDef::secure_exe();
+
+const XTAL_FREQ_HZ: u32 = 12_000_000u32;
+
+/// Longest command line we accept.
+const LINE_LEN: usize = 128;
+
+#[derive(Debug, Clone, Copy)]
+struct Session {
+    /// Key used by read, write and dump; set with `setkey`.
+    key_type: KeyType,
+    key: [u8; 6],
+}
+
+#[hal::entry]
+fn main() -> ! {
+    // Boiler Plate
+    let mut pac = hal::pac::Peripherals::take().unwrap();
+    let mut watchdog = hal::Watchdog::new(pac.WATCHDOG);
+    let clocks = hal::clocks::init_clocks_and_plls(
+        XTAL_FREQ_HZ,
+        pac.XOSC,
+        pac.CLOCKS,
+        pac.PLL_SYS,
+        pac.PLL_USB,
+        &mut pac.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+    let sio = hal::Sio::new(pac.SIO);
+    let pins = hal::gpio::Pins::new(
+        pac.IO_BANK0,
+        pac.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut pac.RESETS,
+    );
+    let timer = hal::Timer::new_timer0(pac.TIMER0, &mut pac.RESETS, &clocks);
+
+    // For USB Serial
+    let usb_bus = UsbBusAllocator::new(hal::usb::UsbBus::new(
+        pac.USB,
+        pac.USB_DPRAM,
+        clocks.usb_clock,
+        true,
+        &mut pac.RESETS,
+    ));
+
+    // Set up the USB Communications Class Device driver
+    let mut serial = SerialPort::new(&usb_bus);
+
+    // Create a USB device with a fake VID and PID
+    let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x16c0, 0x27dd))
+        .strings(&[StringDescriptors::default()
+            .manufacturer("Fake company")
+            .product("Serial port")
+            .serial_number("TEST")])
+        .unwrap()
+        .device_class(2) // from: https://www.usb.org/defined-class-codes
+        .build();
+
+    // RFID Setup
+    let spi_mosi = pins.gpio7.into_function::<hal::gpio::FunctionSpi>();
+    let spi_miso = pins.gpio4.into_function::<hal::gpio::FunctionSpi>();
+    let spi_sclk = pins.gpio6.into_function::<hal::gpio::FunctionSpi>();
+    let spi_bus = hal::spi::Spi::<_, _, _, 8>::new(pac.SPI0, (spi_mosi, spi_miso, spi_sclk));
+
+    let spi_cs = pins.gpio5.into_push_pull_output();
+    let spi = spi_bus.init(
+        &mut pac.RESETS,
+        clocks.peripheral_clock.freq(),
+        1_000.kHz(),
+        embedded_hal::spi::MODE_0,
+    );
+
+    let spi = ExclusiveDevice::new(spi, spi_cs, timer).unwrap();
+    // The driver finds the card; Pcd does everything after that
+    let spi = RefCell::new(spi);
+    let mut pcd = Pcd::new(SharedSpi::new(&spi));
+    // Look for the chip before starting the driver, so a missing module is
+    // reported over serial rather than halting before USB comes up
+    let mut rfid = diag::probe(
+        &mut pcd,
+        Mfrc522::new(SpiInterface::new(SharedSpi::new(&spi))),
+    );
+
+    let mut session = Session {
+        key_type: KeyType::A,
+        key: keys::TRANSPORT_KEY,
+    };
+    let mut line: String<LINE_LEN> = String::new();
+    let mut last_byte = 0u8;
+
+    loop {
+        if !usb_dev.poll(&mut [&mut serial]) {
+            continue;
+        }
+        let mut buf = [0u8; 64];
+        let Ok(count) = serial.read(&mut buf) else {
+            continue;
+        };
+
+        for &byte in &buf[..count] {
+            match byte {
+                // Terminals send \r, \n or both
+                b'\n' if last_byte == b'\r' => {}
+                b'\r' | b'\n' => {
+                    serial.write(b"\r\n").unwrap();
+                    match shell::parse(&line) {
+                        Ok(command) => {
+                            if let Err(e) =
+                                run(command, &mut session, &mut rfid, &mut pcd, &mut serial)
+                            {
+                                serial.write(e.as_bytes()).unwrap();
+                                serial.write(b"\r\n").unwrap();
+                            }
+                        }
+                        Err(ParseError::Empty) => {}
+                        Err(e) => {
+                            serial.write(e.as_str().as_bytes()).unwrap();
+                            serial.write(b"\r\n").unwrap();
+                        }
+                    }
+                    line.clear();
+                    serial.write(b"> ").unwrap();
+                }
+                // Backspace and DEL
+                0x08 | 0x7F => {
+                    if line.pop().is_some() {
+                        serial.write(b"\x08 \x08").unwrap();
+                    }
+                }
+                b' '..=b'~' => {
+                    if line.push(byte as char).is_ok() {
+                        serial.write(&[byte]).unwrap();
+                    }
+                }
+                _ => {}
+            }
+            last_byte = byte;
+        }
+    }
+}
+
+/// Runs one command against the card in the field. `rfid` holds the reason
+/// the driver didn't start, if it didn't.
+fn run<E, R, P, B>(
+    command: Command,
+    session: &mut Session,
+    rfid: &mut Result<Mfrc522<R, mfrc522::Initialized>, &'static str>,
+    pcd: &mut Pcd<P>,
+    serial: &mut SerialPort<B>,
+) -> Result<(), &'static str>
+where
+    R: mfrc522::comm::Interface<Error = E>,
+    P: SpiDevice<Error = E>,
+    B: UsbBus,
+{
+    // These don't need a card
+    match command {
+        Command::Help => {
+            serial.write(shell::HELP.as_bytes()).unwrap();
+            return Ok(());
+        }
+        Command::SetKey { key_type, key } => {
+            session.key_type = key_type;
+            session.key = key;
+            serial.write(b"OK\r\n").unwrap();
+            return Ok(());
+        }
+        // These talk to the chip directly, so they work when the driver didn't start
+        Command::Diag => return run_diag(rfid.as_ref().err().copied(), pcd, serial),
+        Command::Gain { gain } => {
+            if let Some(gain) = gain {
+                diag::set_gain(pcd, gain).map_err(|_| "Reader not responding")?;
+            }
+            let gain = diag::gain(pcd).map_err(|_| "Reader not responding")?;
+            let mut text: String<32> = String::new();
+            write!(text, "Gain: {} dB\r\n", gain.db()).unwrap();
+            serial.write(text.as_bytes()).unwrap();
+            return Ok(());
+        }
+        Command::Stats { polls } => {
+            let stats = diag::poll(pcd, u16::from(polls)).map_err(|_| "Reader not responding")?;
+            let mut text: String<96> = String::new();
+            write!(
+                text,
+                "{} polls: {} ok ({}%), {} no answer, {} errors, {} wrong UID\r\n",
+                stats.polls,
+                stats.ok,
+                stats.success_rate(),
+                stats.no_answer,
+                stats.errors,
+                stats.wrong_uid
+            )
+            .unwrap();
+            serial.write(text.as_bytes()).unwrap();
+            return Ok(());
+        }
+        _ => {}
+    }
+    let rfid = rfid.as_mut().map_err(|e| *e)?;
+
+    // WUPA also wakes a card we halted after the last command
+    let atqa = rfid.wupa().map_err(|_| "No card in the field")?;
+    let uid = rfid.select(&atqa).map_err(|_| "Select failed")?;
+    let card = card::identify(&uid, pcd)
+        .map_err(|_| "Card lost during activation")?
+        .ok_or("Unsupported card type")?;
+
+    let result = match card {
+        Card::Classic(card) => run_classic(command, session, &uid, card, pcd, serial),
+        Card::Ultralight(tag) => run_ultralight(command, &uid, tag, pcd, serial),
+    };
+
+    let _ = pcd.stop_crypto1();
+    let _ = rfid.hlta();
+    result
+}
+
+/// Reports the chip version, the self-test result and the receiver gain.
+fn run_diag<E, SPI: SpiDevice<Error = E>, B: UsbBus>(
+    init_error: Option<&'static str>,
+    pcd: &mut Pcd<SPI>,
+    serial: &mut SerialPort<B>,
+) -> Result<(), &'static str> {
+    let mut text: String<64> = String::new();
+    if let Some(e) = init_error {
+        write!(text, "Driver: {}\r\n", e).unwrap();
+        serial.write(text.as_bytes()).unwrap();
+        text.clear();
+    }
+
+    let version = diag::version(pcd).map_err(|_| "SPI error talking to the reader")?;
+    write!(text, "Chip: 0x{:02X} {}\r\n", version.0, version.name()).unwrap();
+    serial.write(text.as_bytes()).unwrap();
+    if !version.is_present() {
+        return Err("Nothing answers on SPI, check the wiring and power");
+    }
+
+    text.clear();
+    match diag::self_test(pcd).map_err(|_| "Reader stopped answering during the self-test")? {
+        SelfTest::Failed { offset } => write!(text, "Self-test: FAILED at byte {}\r\n", offset),
+        result => write!(text, "Self-test: {}\r\n", result.as_str()),
+    }
+    .unwrap();
+    serial.write(text.as_bytes()).unwrap();
+
+    text.clear();
+    let gain = diag::gain(pcd).map_err(|_| "Reader not responding")?;
+    write!(text, "Gain: {} dB\r\n", gain.db()).unwrap();
+    serial.write(text.as_bytes()).unwrap();
+    Ok(())
+}
+
+fn run_classic<E, SPI: SpiDevice<Error = E>, B: UsbBus>(
+    command: Command,
+    session: &Session,
+    uid: &mfrc522::Uid,
+    card: CardType,
+    pcd: &mut Pcd<SPI>,
+    serial: &mut SerialPort<B>,
+) -> Result<(), &'static str> {
+    match command {
+        Command::Uid => print_uid(uid, card.name(), serial),
+        Command::Dump => {
+            // The session key first, then the well-known ones
+            let mut keys = [session.key; 1 + keys::DEFAULT_KEYS.len()];
+            keys[1..].copy_from_slice(&keys::DEFAULT_KEYS);
+
+            for sector in 0..card.sector_count() {
+                let first = card.first_block(sector).ok_or("Sector out of range")?;
+                let blocks = card.blocks_in_sector(sector).ok_or("Sector out of range")?;
+                let mut buff: String<64> = String::new();
+                write!(buff, "-----------SECTOR {}-----------\r\n", sector).unwrap();
+                serial.write(buff.as_bytes()).unwrap();
+
+                let found = keys::find_key(uid, first, &keys, pcd)
+                    .map_err(|_| "Card lost during authentication")?;
+                if found.is_none() {
+                    serial.write(b"No known key opens this sector\r\n").unwrap();
+                    continue;
+                }
+                for rel_block in 0..blocks {
+                    let data =
+                        classic::read_block(first + rel_block, pcd).map_err(|_| "Read failed")?;
+                    print_block(first + rel_block, &data, serial);
+                }
+            }
+        }
+        Command::Read { block } => {
+            open_block(uid, card, block, session, pcd)?;
+            let data = classic::read_block(block, pcd).map_err(|_| "Read failed")?;
+            print_block(block, &data, serial);
+        }
+        Command::Write { block, data, len } => {
+            if len != classic::BLOCK_SIZE {
+                return Err("Classic blocks take 16 bytes");
+            }
+            if block == 0 {
+                return Err("Refusing to write the manufacturer block");
+            }
+            let sector = card.sector_of(block).ok_or("Block out of range")?;
+            let first = card.first_block(sector).ok_or("Block out of range")?;
+            if geometry::is_trailer(sector, block - first) {
+                return Err("Refusing to write a sector trailer, use change-key");
+            }
+
+            open_block(uid, card, block, session, pcd)?;
+            classic::write_block(block, &data, pcd).map_err(|_| "Write failed")?;
+            serial.write(b"OK\r\n").unwrap();
+        }
+        Command::Auth {
+            sector,
+            key_type,
+            key,
+        } => {
+            let first = card.first_block(sector).ok_or("Sector out of range")?;
+            pcd.activate(uid)
+                .map_err(|_| "Card lost during activation")?;
+            pcd.authenticate(uid, first, key_type, &key)
+                .map_err(|_| "Authentication failed")?;
+            serial.write(b"Authentication OK\r\n").unwrap();
+        }
+        Command::Help
+        | Command::SetKey { .. }
+        | Command::Diag
+        | Command::Gain { .. }
+        | Command::Stats { .. } => {}
+    }
+    Ok(())
+}
+
+/// Authenticates the sector of `block` with the session key.
+fn open_block<E, SPI: SpiDevice<Error = E>>(
+    uid: &mfrc522::Uid,
+    card: CardType,
+    block: u8,
+    session: &Session,
+    pcd: &mut Pcd<SPI>,
+) -> Result<(), &'static str> {
+    card.sector_of(block).ok_or("Block out of range")?;
+    pcd.activate(uid)
+        .map_err(|_| "Card lost during activation")?;
+    pcd.authenticate(uid, block, session.key_type, &session.key)
+        .map_err(|_| "Auth failed")
+}
+
+fn run_ultralight<E, SPI: SpiDevice<Error = E>, B: UsbBus>(
+    command: Command,
+    uid: &mfrc522::Uid,
+    tag: TagType,
+    pcd: &mut Pcd<SPI>,
+    serial: &mut SerialPort<B>,
+) -> Result<(), &'static str> {
+    match command {
+        Command::Uid => print_uid(uid, tag.name(), serial),
+        Command::Dump => {
+            for page in (0..tag.page_count()).step_by(4) {
+                let data = ultralight::read_pages(page, pcd).map_err(|_| "Read failed")?;
+                print_pages(tag, page, &data, serial);
+            }
+        }
+        Command::Read { block: page } => {
+            if page >= tag.page_count() {
+                return Err("Page out of range");
+            }
+            let data = ultralight::read_pages(page, pcd).map_err(|_| "Read failed")?;
+            print_pages(tag, page, &data, serial);
+        }
+        Command::Write {
+            block: page,
+            data,
+            len,
+        } => {
+            if len != ultralight::PAGE_SIZE {
+                return Err("Pages take 4 bytes");
+            }
+            // The lock bytes and the OTP CC can't be undone
+            if !tag.user_pages().contains(&page) {
+                return Err("Refusing to write outside user memory");
+            }
+            let data = data[..ultralight::PAGE_SIZE].try_into().unwrap();
+            ultralight::write_page(page, &data, pcd).map_err(|_| "Write failed")?;
+            serial.write(b"OK\r\n").unwrap();
+        }
+        Command::Auth { .. } => return Err("Ultralight tags have no keys"),
+        Command::Help
+        | Command::SetKey { .. }
+        | Command::Diag
+        | Command::Gain { .. }
+        | Command::Stats { .. } => {}
+    }
+    Ok(())
+}
+
+fn print_uid<B: UsbBus>(uid: &mfrc522::Uid, name: &str, serial: &mut SerialPort<B>) {
+    print_hex_to_serial(uid.as_bytes(), serial);
+    serial.write(b"(").unwrap();
+    serial.write(name.as_bytes()).unwrap();
+    serial.write(b")\r\n").unwrap();
+}
+
+fn print_block<B: UsbBus>(block: u8, data: &[u8], serial: &mut SerialPort<B>) {
+    let mut buff: String<64> = String::new();
+    write!(buff, "BLOCK {:>3} | ", block).unwrap();
+    serial.write(buff.as_bytes()).unwrap();
+    print_hex_to_serial(data, serial);
+    serial.write(b"\r\n").unwrap();
+}
+
+/// Prints the four pages a READ returned, leaving out the ones that wrapped
+/// around past the end of the tag.
+fn print_pages<B: UsbBus>(tag: TagType, first: u8, data: &[u8], serial: &mut SerialPort<B>) {
+    let mut buff: String<64> = String::new();
+    for (i, page_data) in data.chunks(ultralight::PAGE_SIZE).enumerate() {
+        let page = first + i as u8;
+        if page >= tag.page_count() {
+            break;
+        }
+        write!(buff, "PAGE {:>3} | ", page).unwrap();
+        serial.write(buff.as_bytes()).unwrap();
+        buff.clear();
+        print_hex_to_serial(page_data, serial);
+        write!(buff, "| {}\r\n", tag.page_kind(page)).unwrap();
+        serial.write(buff.as_bytes()).unwrap();
+        buff.clear();
+    }
+}
+
+fn print_hex_to_serial<B: UsbBus>(data: &[u8], serial: &mut SerialPort<B>) {
+    let mut buff: String<64> = String::new();
+    for &d in data.iter() {
+        write!(buff, "{:02x} ", d).unwrap();
+    }
+    serial.write(buff.as_bytes()).unwrap();
+}
+
+#[link_section = ".bi_entries"]
+#[used]
+pub static PICOTOOL_ENTRIES: [hal::binary_info::EntryAddr; 5] = [
+    hal::binary_info::rp_cargo_bin_name!(),
+    hal::binary_info::rp_cargo_version!(),
+    hal::binary_info::rp_program_description!(c"RFID Shell"),
+    hal::binary_info::rp_cargo_homepage_url!(),
+    hal::binary_info::rp_program_build_attribute!(),
+];
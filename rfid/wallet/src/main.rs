@@ -0,0 +1,190 @@
+#![no_std]
+#![no_main]
+
+use hal::block::ImageDef;
+use heapless::String;
+use panic_halt as _;
+use rp235x_hal::{self as hal, Clock};
+
+use usb_device::{class_prelude::*, prelude::*};
+use usbd_serial::SerialPort;
+
+use core::cell::RefCell;
+use core::fmt::Write;
+
+use embedded_hal::spi::SpiDevice;
+use embedded_hal_bus::spi::ExclusiveDevice;
+
+use mfrc522::{comm::blocking::spi::SpiInterface, Mfrc522};
+
+use mifare::access::KeyType;
+use mifare::card::{self, Card};
+use mifare::diag;
+use mifare::error::RfidError;
+use mifare::keys::{self, FoundKey};
+use mifare::pcd::{Pcd, SharedSpi};
+use mifare::serial::{halt, report};
+use mifare::wallet::{Wallet, WalletError};
+
+use hal::fugit::RateExtU32;
+
+#[link_section = ".start_block"]
+#[used]
+pub static IMAGE_DEF: ImageDef = hal::block::ImageDef::secure_exe();
+
+const XTAL_FREQ_HZ: u32 = 12_000_000u32;
+
+/// Sector holding the balance (block 0) and its backup (block 1).
+const WALLET_SECTOR: u8 = 1;
+/// Credit put on a card the first time it is seen.
+const INITIAL_CREDIT: i32 = 1000;
+/// What one meal costs.
+const PRICE: u32 = 250;
+
+#[hal::entry]
+fn main() -> ! {
+    // Boiler Plate
+    let mut pac = hal::pac::Peripherals::take().unwrap();
+    let mut watchdog = hal::Watchdog::new(pac.WATCHDOG);
+    let clocks = hal::clocks::init_clocks_and_plls(
+        XTAL_FREQ_HZ,
+        pac.XOSC,
+        pac.CLOCKS,
+        pac.PLL_SYS,
+        pac.PLL_USB,
+        &mut pac.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+    let sio = hal::Sio::new(pac.SIO);
+    let pins = hal::gpio::Pins::new(
+        pac.IO_BANK0,
+        pac.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut pac.RESETS,
+    );
+    let timer = hal::Timer::new_timer0(pac.TIMER0, &mut pac.RESETS, &clocks);
+
+    // For USB Serial
+    let usb_bus = UsbBusAllocator::new(hal::usb::UsbBus::new(
+        pac.USB,
+        pac.USB_DPRAM,
+        clocks.usb_clock,
+        true,
+        &mut pac.RESETS,
+    ));
+
+    // Set up the USB Communications Class Device driver
+    let mut serial = SerialPort::new(&usb_bus);
+
+    // Create a USB device with a fake VID and PID
+    let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x16c0, 0x27dd))
+        .strings(&[StringDescriptors::default()
+            .manufacturer("Fake company")
+            .product("Serial port")
+            .serial_number("TEST")])
+        .unwrap()
+        .device_class(2) // from: https://www.usb.org/defined-class-codes
+        .build();
+
+    // RFID Setup
+    let spi_mosi = pins.gpio7.into_function::<hal::gpio::FunctionSpi>();
+    let spi_miso = pins.gpio4.into_function::<hal::gpio::FunctionSpi>();
+    let spi_sclk = pins.gpio6.into_function::<hal::gpio::FunctionSpi>();
+    let spi_bus = hal::spi::Spi::<_, _, _, 8>::new(pac.SPI0, (spi_mosi, spi_miso, spi_sclk));
+
+    let spi_cs = pins.gpio5.into_push_pull_output();
+    let spi = spi_bus.init(
+        &mut pac.RESETS,
+        clocks.peripheral_clock.freq(),
+        1_000.kHz(),
+        embedded_hal::spi::MODE_0,
+    );
+
+    let spi = ExclusiveDevice::new(spi, spi_cs, timer).unwrap();
+    // The driver polls for cards; Pcd runs the value block commands
+    let spi = RefCell::new(spi);
+    let mut pcd = Pcd::new(SharedSpi::new(&spi));
+    let mut rfid = diag::probe(
+        &mut pcd,
+        Mfrc522::new(SpiInterface::new(SharedSpi::new(&spi))),
+    )
+    .unwrap_or_else(|message| halt(&mut usb_dev, &mut serial, message));
+
+    loop {
+        let _ = usb_dev.poll(&mut [&mut serial]);
+
+        if let Ok(atqa) = rfid.reqa() {
+            if let Ok(uid) = rfid.select(&atqa) {
+                serial.write("\r\nUID: ".as_bytes()).unwrap();
+                print_hex_to_serial(uid.as_bytes(), &mut serial);
+                serial.write("\r\n".as_bytes()).unwrap();
+
+                if let Err(e) = pay(&uid, &mut pcd, &mut serial) {
+                    report(&e, &mut serial);
+                }
+                rfid.hlta().unwrap();
+                rfid.stop_crypto1().unwrap();
+            }
+        }
+    }
+}
+
+/// Debits one meal, setting up the wallet with the initial credit first if
+/// the card doesn't have one yet.
+fn pay<E, SPI: SpiDevice<Error = E>, B: UsbBus>(
+    uid: &mfrc522::Uid,
+    pcd: &mut Pcd<SPI>,
+    serial: &mut SerialPort<B>,
+) -> Result<(), WalletError<E>> {
+    let card = match card::identify(uid, pcd).map_err(RfidError::Activation)? {
+        Some(Card::Classic(card)) => card,
+        _ => return Err(RfidError::UnsupportedCard.into()),
+    };
+    // A fresh card opens with the transport key, which may do everything
+    let key = FoundKey {
+        key_type: KeyType::A,
+        key: keys::TRANSPORT_KEY,
+    };
+    let wallet = Wallet::new(uid, card, WALLET_SECTOR, key)?;
+
+    let balance = match wallet.balance(pcd) {
+        Ok(balance) => balance,
+        Err(WalletError::NotFormatted) => {
+            serial.write("New wallet\r\n".as_bytes()).unwrap();
+            wallet.format(INITIAL_CREDIT, pcd)?;
+            INITIAL_CREDIT
+        }
+        Err(e) => return Err(e),
+    };
+    print_balance("Balance: ", balance, serial);
+
+    let balance = wallet.debit(PRICE, pcd)?;
+    print_balance("Paid, left: ", balance, serial);
+    Ok(())
+}
+
+fn print_balance<B: UsbBus>(label: &str, balance: i32, serial: &mut SerialPort<B>) {
+    let mut buff: String<64> = String::new();
+    write!(buff, "{}{}\r\n", label, balance).unwrap();
+    serial.write(buff.as_bytes()).unwrap();
+}
+
+fn print_hex_to_serial<B: UsbBus>(data: &[u8], serial: &mut SerialPort<B>) {
+    let mut buff: String<64> = String::new();
+    for &d in data.iter() {
+        write!(buff, "{:02x} ", d).unwrap();
+    }
+    serial.write(buff.as_bytes()).unwrap();
+}
+
+#[link_section = ".bi_entries"]
+#[used]
+pub static PICOTOOL_ENTRIES: [hal::binary_info::EntryAddr; 5] = [
+    hal::binary_info::rp_cargo_bin_name!(),
+    hal::binary_info::rp_cargo_version!(),
+    hal::binary_info::rp_program_description!(c"RFID Wallet"),
+    hal::binary_info::rp_cargo_homepage_url!(),
+    hal::binary_info::rp_program_build_attribute!(),
+];
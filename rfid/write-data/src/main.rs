@@ -9,12 +9,23 @@ use rp235x_hal::{self as hal, Clock};
 use usb_device::{class_prelude::*, prelude::*};
 use usbd_serial::SerialPort;
 
+use core::cell::RefCell;
 use core::fmt::Write;
 
+use embedded_hal::spi::SpiDevice;
 use embedded_hal_bus::spi::ExclusiveDevice;
 
 use mfrc522::{comm::blocking::spi::SpiInterface, Mfrc522};
 
+use mifare::access::{AccessConditions, KeyType};
+use mifare::card::{self, Card};
+use mifare::diag;
+use mifare::error::RfidError;
+use mifare::geometry::{self, CardType};
+use mifare::pcd::{Pcd, SharedSpi};
+use mifare::serial::{halt, report};
+use mifare::ultralight::{self, TagType};
+
 use hal::fugit::RateExtU32;
 
 #[link_section = ".start_block"]
@@ -85,11 +96,19 @@ fn main() -> ! {
     );
 
     let spi = ExclusiveDevice::new(spi, spi_cs, timer).unwrap();
-    let itf = SpiInterface::new(spi);
-    let mut rfid = Mfrc522::new(itf).init().unwrap();
+    // Pcd shares the SPI device with the driver to read the card's SAK
+    let spi = RefCell::new(spi);
+    let mut pcd = Pcd::new(SharedSpi::new(&spi));
+    let mut rfid = diag::probe(
+        &mut pcd,
+        Mfrc522::new(SpiInterface::new(SharedSpi::new(&spi))),
+    )
+    .unwrap_or_else(|message| halt(&mut usb_dev, &mut serial, message));
 
     let target_sector = 4;
     let rel_block = 2;
+    // Ultralight and NTAG tags have no sectors; the data goes to a user page
+    let target_page = 8;
     const DATA: [u8; 16] = [
         b'i', b'm', b'p', b'l', b'R', b'u', b's', b't', // "implRust"
         0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // Remaining bytes as 0x00
@@ -100,22 +119,59 @@ fn main() -> ! {
 
         if let Ok(atqa) = rfid.reqa() {
             if let Ok(uid) = rfid.select(&atqa) {
-                serial
-                    .write("\r\n----Before Write----\r\n".as_bytes())
-                    .unwrap();
-                if let Err(e) = read_sector(&uid, target_sector, &mut rfid, &mut serial) {
-                    serial.write(e.as_bytes()).unwrap();
-                }
+                let card = match card::detect(&uid, &mut pcd) {
+                    Ok(card) => card,
+                    Err(e) => {
+                        report(&e, &mut serial);
+                        rfid.hlta().unwrap();
+                        continue;
+                    }
+                };
+                match card {
+                    Card::Classic(card) => {
+                        serial
+                            .write("\r\n----Before Write----\r\n".as_bytes())
+                            .unwrap();
+                        if let Err(e) =
+                            read_sector(&uid, card, target_sector, &mut rfid, &mut serial)
+                        {
+                            report(&e, &mut serial);
+                        }
 
-                if let Err(e) = write_block(&uid, target_sector, rel_block, DATA, &mut rfid) {
-                    serial.write(e.as_bytes()).unwrap();
-                }
+                        if let Err(e) =
+                            write_block(&uid, card, target_sector, rel_block, DATA, &mut rfid)
+                        {
+                            report(&e, &mut serial);
+                        }
+
+                        serial
+                            .write("\r\n----After Write----\r\n".as_bytes())
+                            .unwrap();
+                        if let Err(e) =
+                            read_sector(&uid, card, target_sector, &mut rfid, &mut serial)
+                        {
+                            report(&e, &mut serial);
+                        }
+                    }
+                    Card::Ultralight(tag) => {
+                        serial
+                            .write("\r\n----Before Write----\r\n".as_bytes())
+                            .unwrap();
+                        if let Err(e) = read_pages(target_page, &mut pcd, &mut serial) {
+                            report(&e, &mut serial);
+                        }
+
+                        if let Err(e) = write_pages(tag, target_page, &DATA, &mut pcd) {
+                            report(&e, &mut serial);
+                        }
 
-                serial
-                    .write("\r\n----After Write----\r\n".as_bytes())
-                    .unwrap();
-                if let Err(e) = read_sector(&uid, target_sector, &mut rfid, &mut serial) {
-                    serial.write(e.as_bytes()).unwrap();
+                        serial
+                            .write("\r\n----After Write----\r\n".as_bytes())
+                            .unwrap();
+                        if let Err(e) = read_pages(target_page, &mut pcd, &mut serial) {
+                            report(&e, &mut serial);
+                        }
+                    }
                 }
                 rfid.hlta().unwrap();
                 rfid.stop_crypto1().unwrap();
@@ -126,42 +182,148 @@ fn main() -> ! {
 
 fn write_block<E, COMM: mfrc522::comm::Interface<Error = E>>(
     uid: &mfrc522::Uid,
+    card: CardType,
     sector: u8,
     rel_block: u8,
     data: [u8; 16],
     rfid: &mut Mfrc522<COMM, mfrc522::Initialized>,
-) -> Result<(), &'static str> {
+) -> Result<(), RfidError<E>> {
     const AUTH_KEY: [u8; 6] = [0xFF; 6];
 
-    let block_offset = sector * 4;
-    let abs_block = block_offset + rel_block;
+    let block_offset = card.first_block(sector).ok_or(RfidError::OutOfRange {
+        sector,
+        block: None,
+    })?;
+    let abs_block = card
+        .abs_block(sector, rel_block)
+        .ok_or(RfidError::OutOfRange {
+            sector,
+            block: Some(rel_block),
+        })?;
+    let trailer_block = card.trailer_block(sector).ok_or(RfidError::OutOfRange {
+        sector,
+        block: None,
+    })?;
+
+    if abs_block == trailer_block {
+        return Err(RfidError::Refused {
+            block: abs_block,
+            reason: "refusing to write the sector trailer",
+        });
+    }
 
     rfid.mf_authenticate(uid, block_offset, &AUTH_KEY)
-        .map_err(|_| "Auth failed")?;
+        .map_err(|cause| RfidError::Auth {
+            sector,
+            key_type: KeyType::A,
+            cause,
+        })?;
 
-    rfid.mf_write(abs_block, data).map_err(|_| "Write failed")?;
+    // Check the trailer first, so we report why a write would be rejected
+    let trailer = rfid
+        .mf_read(trailer_block)
+        .map_err(|cause| RfidError::Read {
+            sector,
+            block: trailer_block,
+            cause,
+        })?;
+    let access = AccessConditions::from_trailer(&trailer)
+        .map_err(|error| RfidError::InvalidTrailer { sector, error })?;
+    let group = geometry::access_group(sector, rel_block);
+    if !access.data_permissions(group).write.allows(KeyType::A) {
+        return Err(RfidError::AccessDenied {
+            sector,
+            block: abs_block,
+        });
+    }
+
+    rfid.mf_write(abs_block, data)
+        .map_err(|cause| RfidError::Write {
+            sector,
+            block: abs_block,
+            cause,
+        })?;
 
     Ok(())
 }
 
 fn read_sector<E, COMM: mfrc522::comm::Interface<Error = E>, B: UsbBus>(
     uid: &mfrc522::Uid,
+    card: CardType,
     sector: u8,
     rfid: &mut Mfrc522<COMM, mfrc522::Initialized>,
     serial: &mut SerialPort<B>,
-) -> Result<(), &'static str> {
+) -> Result<(), RfidError<E>> {
     const AUTH_KEY: [u8; 6] = [0xFF; 6];
 
-    let block_offset = sector * 4;
+    let out_of_range = || RfidError::OutOfRange {
+        sector,
+        block: None,
+    };
+    let block_offset = card.first_block(sector).ok_or_else(out_of_range)?;
+    let blocks = card.blocks_in_sector(sector).ok_or_else(out_of_range)?;
     rfid.mf_authenticate(uid, block_offset, &AUTH_KEY)
-        .map_err(|_| "Auth failed")?;
+        .map_err(|cause| RfidError::Auth {
+            sector,
+            key_type: KeyType::A,
+            cause,
+        })?;
 
-    for abs_block in block_offset..block_offset + 4 {
-        let data = rfid.mf_read(abs_block).map_err(|_| "Read failed")?;
+    for rel_block in 0..blocks {
+        let block = block_offset + rel_block;
+        let data = rfid.mf_read(block).map_err(|cause| RfidError::Read {
+            sector,
+            block,
+            cause,
+        })?;
         print_hex_to_serial(&data, serial);
-        serial
-            .write("\r\n".as_bytes())
-            .map_err(|_| "Write failed")?;
+        serial.write("\r\n".as_bytes()).unwrap();
+    }
+    Ok(())
+}
+
+/// Writes `data` to consecutive pages of a page based tag, starting at `page`.
+fn write_pages<E, SPI: SpiDevice<Error = E>>(
+    tag: TagType,
+    page: u8,
+    data: &[u8],
+    pcd: &mut Pcd<SPI>,
+) -> Result<(), RfidError<E>> {
+    let pages = data.len().div_ceil(ultralight::PAGE_SIZE) as u8;
+    let user = tag.user_pages();
+    if page < user.start || page + pages > user.end {
+        return Err(RfidError::Refused {
+            block: page,
+            reason: "pages outside user memory",
+        });
+    }
+
+    // The static lock bits are in page 2; a locked page NAKs the write
+    let header =
+        ultralight::read_pages(0, pcd).map_err(|cause| RfidError::ReadPage { page: 0, cause })?;
+    let lock = [header[10], header[11]];
+    if let Some(locked) = (page..page + pages).find(|&p| ultralight::is_statically_locked(&lock, p))
+    {
+        return Err(RfidError::Refused {
+            block: locked,
+            reason: "page is locked",
+        });
+    }
+
+    ultralight::write_pages(page, data, pcd).map_err(|cause| RfidError::WritePage { page, cause })
+}
+
+/// Prints the four pages starting at `page`.
+fn read_pages<E, SPI: SpiDevice<Error = E>, B: UsbBus>(
+    page: u8,
+    pcd: &mut Pcd<SPI>,
+    serial: &mut SerialPort<B>,
+) -> Result<(), RfidError<E>> {
+    let data =
+        ultralight::read_pages(page, pcd).map_err(|cause| RfidError::ReadPage { page, cause })?;
+    for page_data in data.chunks(ultralight::PAGE_SIZE) {
+        print_hex_to_serial(page_data, serial);
+        serial.write("\r\n".as_bytes()).unwrap();
     }
     Ok(())
 }
@@ -0,0 +1,239 @@
+#![no_std]
+#![no_main]
+
+use hal::block::ImageDef;
+use heapless::String;
+use panic_halt as _;
+use rp235x_hal::{self as hal, Clock};
+
+use usb_device::{class_prelude::*, prelude::*};
+use usbd_serial::SerialPort;
+
+use core::cell::RefCell;
+use core::fmt::Write;
+
+use embedded_hal::spi::SpiDevice;
+use embedded_hal_bus::spi::ExclusiveDevice;
+
+use mfrc522::{comm::blocking::spi::SpiInterface, Mfrc522};
+
+use mifare::access::KeyType;
+use mifare::card::{self, Card};
+use mifare::diag;
+use mifare::keys::{self, FoundKey};
+use mifare::ndef::{self, MessageWriter, NdefError, TagError};
+use mifare::pcd::{Pcd, SharedSpi};
+use mifare::serial::halt;
+use mifare::{classic, ultralight};
+
+use hal::fugit::RateExtU32;
+
+#[link_section = ".start_block"]
+#[used]
+pub static IMAGE_DEF: ImageDef = hal::block::ImageDef::secure_exe();
+
+const XTAL_FREQ_HZ: u32 = 12_000_000u32;
+
+#[hal::entry]
+fn main() -> ! {
+    // Boiler Plate
+    let mut pac = hal::pac::Peripherals::take().unwrap();
+    let mut watchdog = hal::Watchdog::new(pac.WATCHDOG);
+    let clocks = hal::clocks::init_clocks_and_plls(
+        XTAL_FREQ_HZ,
+        pac.XOSC,
+        pac.CLOCKS,
+        pac.PLL_SYS,
+        pac.PLL_USB,
+        &mut pac.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+    let sio = hal::Sio::new(pac.SIO);
+    let pins = hal::gpio::Pins::new(
+        pac.IO_BANK0,
+        pac.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut pac.RESETS,
+    );
+    let timer = hal::Timer::new_timer0(pac.TIMER0, &mut pac.RESETS, &clocks);
+
+    // For USB Serial
+    let usb_bus = UsbBusAllocator::new(hal::usb::UsbBus::new(
+        pac.USB,
+        pac.USB_DPRAM,
+        clocks.usb_clock,
+        true,
+        &mut pac.RESETS,
+    ));
+
+    // Set up the USB Communications Class Device driver
+    let mut serial = SerialPort::new(&usb_bus);
+
+    // Create a USB device with a fake VID and PID
+    let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x16c0, 0x27dd))
+        .strings(&[StringDescriptors::default()
+            .manufacturer("Fake company")
+            .product("Serial port")
+            .serial_number("TEST")])
+        .unwrap()
+        .device_class(2) // from: https://www.usb.org/defined-class-codes
+        .build();
+
+    // RFID Setup
+    let spi_mosi = pins.gpio7.into_function::<hal::gpio::FunctionSpi>();
+    let spi_miso = pins.gpio4.into_function::<hal::gpio::FunctionSpi>();
+    let spi_sclk = pins.gpio6.into_function::<hal::gpio::FunctionSpi>();
+    let spi_bus = hal::spi::Spi::<_, _, _, 8>::new(pac.SPI0, (spi_mosi, spi_miso, spi_sclk));
+
+    let spi_cs = pins.gpio5.into_push_pull_output();
+    let spi = spi_bus.init(
+        &mut pac.RESETS,
+        clocks.peripheral_clock.freq(),
+        1_000.kHz(),
+        embedded_hal::spi::MODE_0,
+    );
+
+    let spi = ExclusiveDevice::new(spi, spi_cs, timer).unwrap();
+    // The driver polls for cards; Pcd reads and writes the NDEF data
+    let spi = RefCell::new(spi);
+    let mut pcd = Pcd::new(SharedSpi::new(&spi));
+    let mut rfid = diag::probe(
+        &mut pcd,
+        Mfrc522::new(SpiInterface::new(SharedSpi::new(&spi))),
+    )
+    .unwrap_or_else(|message| halt(&mut usb_dev, &mut serial, message));
+
+    // What a phone shows when it reads the tag
+    let mut message_buf = [0u8; 128];
+    let mut writer = MessageWriter::new(&mut message_buf);
+    writer.text("en", "Hello from Pico").unwrap();
+    writer
+        .uri("https://github.com/ImplFerris/pico-pico")
+        .unwrap();
+    let message = writer.finish().unwrap();
+
+    loop {
+        let _ = usb_dev.poll(&mut [&mut serial]);
+
+        if let Ok(atqa) = rfid.reqa() {
+            if let Ok(uid) = rfid.select(&atqa) {
+                if let Err(e) = write_ndef(&uid, message, &mut pcd, &mut serial) {
+                    serial.write(e.as_bytes()).unwrap();
+                }
+                rfid.hlta().unwrap();
+                rfid.stop_crypto1().unwrap();
+            }
+        }
+    }
+}
+
+fn write_ndef<E, SPI: SpiDevice<Error = E>, B: UsbBus>(
+    uid: &mfrc522::Uid,
+    message: &[u8],
+    pcd: &mut Pcd<SPI>,
+    serial: &mut SerialPort<B>,
+) -> Result<(), &'static str> {
+    let card = card::identify(uid, pcd)
+        .map_err(|_| "Card lost during activation")?
+        .ok_or("Unsupported card type")?;
+    serial.write("\r\n".as_bytes()).unwrap();
+    serial.write(card.name().as_bytes()).unwrap();
+    serial.write("\r\n".as_bytes()).unwrap();
+
+    let mut buf = [0u8; 256];
+    serial
+        .write("\r\n----Before Write----\r\n".as_bytes())
+        .unwrap();
+    match read_ndef(uid, card, pcd, &mut buf) {
+        Ok(old) => print_records(old, serial),
+        // A blank card gets formatted first
+        Err(TagError::Ndef(NdefError::NotFormatted)) => {
+            serial.write("Formatting for NDEF\r\n".as_bytes()).unwrap();
+            format_ndef(uid, card, pcd).map_err(|e| e.as_str())?;
+        }
+        Err(e) => {
+            serial.write(e.as_str().as_bytes()).unwrap();
+        }
+    }
+
+    let result = match card {
+        Card::Classic(card) => classic::write_ndef(uid, card, message, pcd),
+        Card::Ultralight(tag) => ultralight::write_ndef(tag, message, pcd),
+    };
+    result.map_err(|e| e.as_str())?;
+
+    serial
+        .write("\r\n----After Write----\r\n".as_bytes())
+        .unwrap();
+    let written = read_ndef(uid, card, pcd, &mut buf).map_err(|e| e.as_str())?;
+    print_records(written, serial);
+    Ok(())
+}
+
+fn read_ndef<'b, E, SPI: SpiDevice<Error = E>>(
+    uid: &mfrc522::Uid,
+    card: Card,
+    pcd: &mut Pcd<SPI>,
+    buf: &'b mut [u8],
+) -> Result<&'b [u8], TagError<E>> {
+    match card {
+        Card::Classic(card) => classic::read_ndef(uid, card, pcd, buf),
+        Card::Ultralight(tag) => ultralight::read_ndef(tag, pcd, buf),
+    }
+}
+
+fn format_ndef<E, SPI: SpiDevice<Error = E>>(
+    uid: &mfrc522::Uid,
+    card: Card,
+    pcd: &mut Pcd<SPI>,
+) -> Result<(), TagError<E>> {
+    match card {
+        // A fresh Classic card opens with the transport key
+        Card::Classic(card) => {
+            let auth = FoundKey {
+                key_type: KeyType::A,
+                key: keys::TRANSPORT_KEY,
+            };
+            classic::format_ndef(uid, card, &auth, pcd)
+        }
+        Card::Ultralight(tag) => ultralight::format_ndef(tag, pcd),
+    }
+}
+
+fn print_records<B: UsbBus>(message: &[u8], serial: &mut SerialPort<B>) {
+    let mut buff: String<64> = String::new();
+    for record in ndef::records(message) {
+        match record {
+            Ok(record) => {
+                // Long records don't fit the buffer; show what does
+                let _ = ndef::describe(&record, &mut buff);
+                serial.write(buff.as_bytes()).unwrap();
+                buff.clear();
+            }
+            Err(e) => {
+                serial.write(e.as_str().as_bytes()).unwrap();
+            }
+        }
+        serial.write("\r\n".as_bytes()).unwrap();
+    }
+}
+
+fn print_hex_to_serial<B: UsbBus>(data: &[u8], serial: &mut SerialPort<B>) {
+    let mut buff: String<64> = String::new();
+    for &d in data.iter() {
+        write!(buff, "{:02x} ", d).unwrap();
+    }
+    serial.write(buff.as_bytes()).unwrap();
+}
+
+#[link_section = ".bi_entries"]
+#[used]
+pub static PICOTOOL_ENTRIES: [hal::binary_info::EntryAddr; 5] = [
+    hal::binary_info::rp_cargo_bin_name!(),
+    hal::binary_info::rp_cargo_version!(),
+    hal::binary_info::rp_program_description!(c"RFID Write NDEF"),
+    hal::binary_info::rp_cargo_homepage_url!(),
+    hal::binary_info::rp_program_build_attribute!(),
+];
@@ -0,0 +1,92 @@
+//! A clock in the RP2350's always-on (AON) timer.
+//!
+//! The AON timer lives in POWMAN and counts milliseconds in 64 bits. It keeps
+//! running through resets of the rest of the chip, but not through a power
+//! cycle, so after power up the time has to be set again, from a DS3231 or
+//! over serial. The clock keeps milliseconds since 1970 in the counter and
+//! only starts it once it's been set, so a running timer means a valid time.
+
+use rp235x_hal::pac::POWMAN;
+
+use crate::date::DateTime;
+
+/// POWMAN ignores writes without this in the top half.
+const PASSWORD: u32 = 0x5AFE << 16;
+
+const TIMER_RUN: u32 = 1 << 1;
+const TIMER_USE_XOSC: u32 = 1 << 9;
+const TIMER_USING_XOSC: u32 = 1 << 16;
+
+pub struct AonClock {
+    powman: POWMAN,
+    xosc_khz: u32,
+}
+
+impl AonClock {
+    /// Takes over the AON timer. `xosc_hz` is the crystal frequency, which
+    /// the timer's millisecond tick is divided down from; the low-power
+    /// oscillator it uses otherwise is only accurate to a few percent.
+    pub fn new(powman: POWMAN, xosc_hz: u32) -> Self {
+        Self {
+            powman,
+            xosc_khz: xosc_hz / 1000,
+        }
+    }
+
+    /// Whether the clock has been set, possibly before the last reset.
+    pub fn is_set(&self) -> bool {
+        self.timer() & TIMER_RUN != 0
+    }
+
+    /// The current time, or `None` until the clock is set.
+    pub fn now(&self) -> Option<DateTime> {
+        self.is_set()
+            .then(|| DateTime::from_unix(self.millis() / 1000))
+    }
+
+    pub fn set(&self, time: &DateTime) {
+        let ms = time.to_unix() * 1000;
+
+        // The counter can only be loaded while it's stopped
+        self.write_timer(self.timer() & !TIMER_RUN);
+        let p = &self.powman;
+        let part = |shift: u32| PASSWORD | ((ms >> shift) as u32 & 0xFFFF);
+        p.set_time_63to48().write(|w| unsafe { w.bits(part(48)) });
+        p.set_time_47to32().write(|w| unsafe { w.bits(part(32)) });
+        p.set_time_31to16().write(|w| unsafe { w.bits(part(16)) });
+        p.set_time_15to0().write(|w| unsafe { w.bits(part(0)) });
+
+        p.xosc_freq_khz_int()
+            .write(|w| unsafe { w.bits(PASSWORD | self.xosc_khz) });
+        p.xosc_freq_khz_frac()
+            .write(|w| unsafe { w.bits(PASSWORD) });
+        self.write_timer(self.timer() | TIMER_USE_XOSC);
+        self.write_timer(self.timer() | TIMER_RUN);
+        while self.timer() & TIMER_USING_XOSC == 0 {}
+    }
+
+    /// Milliseconds since 1970.
+    fn millis(&self) -> u64 {
+        // The two halves are read separately, so read again if the upper one
+        // ticked over in between
+        let p = &self.powman;
+        loop {
+            let upper = p.read_time_upper().read().bits();
+            let lower = p.read_time_lower().read().bits();
+            if p.read_time_upper().read().bits() == upper {
+                return (u64::from(upper) << 32) | u64::from(lower);
+            }
+        }
+    }
+
+    fn timer(&self) -> u32 {
+        self.powman.timer().read().bits()
+    }
+
+    fn write_timer(&self, bits: u32) {
+        // Only the lower half holds settings, the rest is status
+        self.powman
+            .timer()
+            .write(|w| unsafe { w.bits(PASSWORD | (bits & 0xFFFF)) });
+    }
+}
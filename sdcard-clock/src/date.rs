@@ -0,0 +1,363 @@
+//! Calendar dates and times, as seconds since 1970-01-01 00:00:00 and as the
+//! packed date and time words FAT stores in directory entries.
+//!
+//! There's no time zone handling; the clock is set to local time, which is
+//! what FAT expects.
+
+use core::fmt;
+
+pub const SECONDS_PER_DAY: u64 = 86_400;
+
+pub const fn is_leap_year(year: u16) -> bool {
+    (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400)
+}
+
+pub const fn days_in_month(year: u16, month: u8) -> u8 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days from 1970-01-01 to a date from 1970 on, with `month` and `day`
+/// counted from 1.
+pub const fn days_since_epoch(year: u16, month: u8, day: u8) -> u64 {
+    // Count years from March so the leap day comes last
+    let y = (if month <= 2 { year - 1 } else { year }) as u64;
+    let m = (if month > 2 { month - 3 } else { month + 9 }) as u64;
+    let era = y / 400;
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m + 2) / 5 + day as u64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// A date and time of day, with `month` and `day` counted from 1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+/// A timestamp as FAT stores it: years from 1980 in the top seven bits of
+/// `date`, then month and day; hours, minutes and seconds halved in `time`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FatTimestamp {
+    pub date: u16,
+    pub time: u16,
+}
+
+impl DateTime {
+    /// The earliest time FAT can store, which files get until the clock is
+    /// set.
+    pub const FAT_MIN: DateTime = DateTime::midnight(1980, 1, 1);
+    /// The latest time FAT can store.
+    pub const FAT_MAX: DateTime = DateTime {
+        year: 2107,
+        month: 12,
+        day: 31,
+        hour: 23,
+        minute: 59,
+        second: 59,
+    };
+
+    pub const fn midnight(year: u16, month: u8, day: u8) -> DateTime {
+        DateTime {
+            year,
+            month,
+            day,
+            hour: 0,
+            minute: 0,
+            second: 0,
+        }
+    }
+
+    /// The date and time `seconds` after 1970-01-01 00:00:00. Years past
+    /// 65535 wrap.
+    pub const fn from_unix(seconds: u64) -> DateTime {
+        let days = seconds / SECONDS_PER_DAY;
+        let time = seconds % SECONDS_PER_DAY;
+
+        // The inverse of days_since_epoch, again with years starting in March
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let day_of_era = z - era * 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let m = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * m + 2) / 5 + 1;
+        let month = if m < 10 { m + 3 } else { m - 9 };
+        let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time / 60 % 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+
+    /// Seconds since 1970-01-01 00:00:00. The date must be valid and from
+    /// 1970 on.
+    pub const fn to_unix(&self) -> u64 {
+        days_since_epoch(self.year, self.month, self.day) * SECONDS_PER_DAY
+            + self.hour as u64 * 3600
+            + self.minute as u64 * 60
+            + self.second as u64
+    }
+
+    pub const fn is_valid(&self) -> bool {
+        self.month >= 1
+            && self.month <= 12
+            && self.day >= 1
+            && self.day <= days_in_month(self.year, self.month)
+            && self.hour < 24
+            && self.minute < 60
+            && self.second < 60
+    }
+
+    /// Days of the week counted from Monday as 1, as the DS3231 keeps them.
+    /// From 1970 on.
+    pub const fn weekday(&self) -> u8 {
+        // 1970-01-01 was a Thursday
+        ((days_since_epoch(self.year, self.month, self.day) + 3) % 7 + 1) as u8
+    }
+
+    /// This time moved into the range FAT can store.
+    pub fn fat_clamped(self) -> DateTime {
+        self.clamp(DateTime::FAT_MIN, DateTime::FAT_MAX)
+    }
+
+    /// Packs the time for a directory entry, clamped to the range FAT can
+    /// store. FAT counts seconds in twos, so odd seconds round down.
+    pub fn to_fat(&self) -> FatTimestamp {
+        let t = self.fat_clamped();
+        FatTimestamp {
+            date: ((t.year - 1980) << 9) | (u16::from(t.month) << 5) | u16::from(t.day),
+            time: (u16::from(t.hour) << 11) | (u16::from(t.minute) << 5) | u16::from(t.second / 2),
+        }
+    }
+
+    /// Unpacks a directory entry's date and time. `None` for fields out of
+    /// range, like the zero date of an entry that never had one.
+    pub fn from_fat(fat: FatTimestamp) -> Option<DateTime> {
+        let t = DateTime {
+            year: 1980 + (fat.date >> 9),
+            month: ((fat.date >> 5) & 0xF) as u8,
+            day: (fat.date & 0x1F) as u8,
+            hour: (fat.time >> 11) as u8,
+            minute: ((fat.time >> 5) & 0x3F) as u8,
+            second: (fat.time & 0x1F) as u8 * 2,
+        };
+        t.is_valid().then_some(t)
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// Parses `YYYY-MM-DD HH:MM` or `YYYY-MM-DD HH:MM:SS`, with a space or a
+/// `T` in between. Returns `None` for anything malformed or before 1970.
+pub fn parse(s: &str) -> Option<DateTime> {
+    let (date, time) = s.split_once(['T', ' '])?;
+    let time = time.trim_start();
+
+    let mut parts = date.split('-');
+    let year = number(parts.next()?, 4)?;
+    let month = number(parts.next()?, 2)?;
+    let day = number(parts.next()?, 2)?;
+    if parts.next().is_some() {
+        return None;
+    }
+
+    let mut parts = time.split(':');
+    let hour = number(parts.next()?, 2)?;
+    let minute = number(parts.next()?, 2)?;
+    let second = match parts.next() {
+        Some(second) => number(second, 2)?,
+        None => 0,
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+
+    let parsed = DateTime {
+        year,
+        month: month as u8,
+        day: day as u8,
+        hour: hour as u8,
+        minute: minute as u8,
+        second: second as u8,
+    };
+    (year >= 1970 && parsed.is_valid()).then_some(parsed)
+}
+
+/// A decimal number of exactly `digits` digits.
+fn number(s: &str, digits: usize) -> Option<u16> {
+    if s.len() != digits || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTime {
+        DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        }
+    }
+
+    #[test]
+    fn seconds_since_1970() {
+        // From `date -u -d @<seconds>`
+        let cases = [
+            (0, at(1970, 1, 1, 0, 0, 0)),
+            (951_782_400, at(2000, 2, 29, 0, 0, 0)),
+            (1_792_247_408, at(2026, 10, 17, 14, 30, 8)),
+            (4_107_542_399, at(2100, 2, 28, 23, 59, 59)),
+            (4_354_819_199, at(2107, 12, 31, 23, 59, 59)),
+        ];
+        for (seconds, time) in cases {
+            assert_eq!(DateTime::from_unix(seconds), time, "from {}", seconds);
+            assert_eq!(time.to_unix(), seconds, "to {}", time);
+        }
+    }
+
+    #[test]
+    fn every_day_to_2200() {
+        let mut days = 0;
+        for year in 1970..2200 {
+            for month in 1..=12 {
+                for day in 1..=days_in_month(year, month) {
+                    let time = at(year, month, day, 23, 59, 59);
+                    let seconds = days * SECONDS_PER_DAY + 86_399;
+                    assert_eq!(time.to_unix(), seconds);
+                    assert_eq!(DateTime::from_unix(seconds), time);
+                    days += 1;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fat_timestamps() {
+        let time = at(2026, 10, 17, 14, 30, 9);
+        // (46 << 9) | (10 << 5) | 17 and (14 << 11) | (30 << 5) | 4
+        let packed = FatTimestamp {
+            date: 0x5D51,
+            time: 0x73C4,
+        };
+        assert_eq!(time.to_fat(), packed);
+        // Odd seconds are lost
+        assert_eq!(
+            DateTime::from_fat(packed),
+            Some(at(2026, 10, 17, 14, 30, 8))
+        );
+
+        assert_eq!(
+            DateTime::FAT_MIN.to_fat(),
+            FatTimestamp {
+                date: 0x21,
+                time: 0
+            }
+        );
+        assert_eq!(
+            DateTime::FAT_MAX.to_fat(),
+            FatTimestamp {
+                date: 0xFF9F,
+                time: 0xBF7D
+            }
+        );
+        // Clamped to the range FAT can store
+        assert_eq!(DateTime::from_unix(0).to_fat(), DateTime::FAT_MIN.to_fat());
+        assert_eq!(at(2150, 6, 1, 0, 0, 0).to_fat(), DateTime::FAT_MAX.to_fat());
+        assert_eq!(DateTime::from_fat(FatTimestamp { date: 0, time: 0 }), None);
+        assert_eq!(
+            DateTime::from_fat(FatTimestamp {
+                date: 0x21,
+                time: 30
+            }),
+            None
+        );
+
+        for seconds in (315_532_800..4_354_819_199).step_by(7_777_777) {
+            let time = DateTime::from_unix(seconds);
+            let even = DateTime {
+                second: time.second & !1,
+                ..time
+            };
+            assert_eq!(DateTime::from_fat(time.to_fat()), Some(even), "{}", time);
+        }
+    }
+
+    #[test]
+    fn parsing() {
+        let good = [
+            ("2026-10-17 14:30", at(2026, 10, 17, 14, 30, 0)),
+            ("2026-10-17T14:30:08", at(2026, 10, 17, 14, 30, 8)),
+            ("2024-02-29  00:00:59", at(2024, 2, 29, 0, 0, 59)),
+            ("1970-01-01 00:00", at(1970, 1, 1, 0, 0, 0)),
+        ];
+        for (s, expected) in good {
+            assert_eq!(parse(s), Some(expected), "{}", s);
+        }
+        for s in [
+            "",
+            "2026-10-17",
+            "2026-10-17 14",
+            "2026-10-17 14:30:08:00",
+            "2026-10-17 24:00",
+            "2026-10-17 14:60",
+            "2026-10-17 14:30:60",
+            "2025-02-29 12:00",
+            "2026-13-01 12:00",
+            "2026-00-01 12:00",
+            "1969-12-31 23:59",
+            "26-10-17 14:30",
+            "2026-1-17 14:30",
+            "2026-10-17 4:30",
+            "2026-10-17 +4:30",
+        ] {
+            assert_eq!(parse(s), None, "{}", s);
+        }
+    }
+
+    #[test]
+    fn weekdays() {
+        let cases = [
+            (at(1970, 1, 1, 0, 0, 0), 4),
+            (at(2000, 1, 1, 0, 0, 0), 6),
+            (at(2024, 2, 29, 0, 0, 0), 4),
+            (at(2026, 10, 17, 0, 0, 0), 6),
+            (at(2026, 10, 18, 0, 0, 0), 7),
+            (at(2026, 10, 19, 0, 0, 0), 1),
+        ];
+        for (time, weekday) in cases {
+            assert_eq!(time.weekday(), weekday, "{}", time);
+        }
+    }
+}
@@ -0,0 +1,281 @@
+//! Driver for the DS3231, a battery-backed real-time clock on I²C.
+//!
+//! The DS3231 keeps counting through power cycles, so it gives the time
+//! straight after boot. It holds the year as two digits and a century bit,
+//! which covers 2000 to 2199.
+
+use embedded_hal::i2c::I2c;
+
+use crate::date::DateTime;
+
+/// The DS3231's fixed I²C address.
+pub const ADDRESS: u8 = 0x68;
+
+const REG_SECONDS: u8 = 0x00;
+const REG_STATUS: u8 = 0x0F;
+
+/// Hours register: 12 hour mode instead of 24.
+const HOURS_12H: u8 = 1 << 6;
+const HOURS_PM: u8 = 1 << 5;
+/// Month register: years from 2100.
+const MONTH_CENTURY: u8 = 1 << 7;
+/// Status register: the oscillator stopped, so the time is lost. Set at first
+/// power up and when the battery runs out.
+const STATUS_OSF: u8 = 1 << 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<E> {
+    I2c(E),
+    /// The registers don't hold a valid time.
+    BadTime,
+    /// The DS3231 can only store 2000 to 2199.
+    OutOfRange,
+}
+
+impl<E> Error<E> {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Error::I2c(_) => "RTC not responding",
+            Error::BadTime => "RTC holds an invalid time",
+            Error::OutOfRange => "The RTC only stores years 2000 to 2199",
+        }
+    }
+}
+
+pub struct Ds3231<I2C> {
+    i2c: I2C,
+}
+
+impl<I2C: I2c> Ds3231<I2C> {
+    pub fn new(i2c: I2C) -> Self {
+        Self { i2c }
+    }
+
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    /// The current time, or `None` if the clock stopped since it was last
+    /// set and has to be set again.
+    pub fn now(&mut self) -> Result<Option<DateTime>, Error<I2C::Error>> {
+        let mut status = [0u8];
+        self.i2c
+            .write_read(ADDRESS, &[REG_STATUS], &mut status)
+            .map_err(Error::I2c)?;
+        if status[0] & STATUS_OSF != 0 {
+            return Ok(None);
+        }
+
+        let mut regs = [0u8; 7];
+        self.i2c
+            .write_read(ADDRESS, &[REG_SECONDS], &mut regs)
+            .map_err(Error::I2c)?;
+        decode(&regs).map(Some).ok_or(Error::BadTime)
+    }
+
+    /// Sets the clock and clears the stopped flag.
+    pub fn set(&mut self, time: &DateTime) -> Result<(), Error<I2C::Error>> {
+        let regs = encode(time).ok_or(Error::OutOfRange)?;
+        let mut write = [0u8; 8];
+        write[0] = REG_SECONDS;
+        write[1..].copy_from_slice(&regs);
+        self.i2c.write(ADDRESS, &write).map_err(Error::I2c)?;
+
+        let mut status = [0u8];
+        self.i2c
+            .write_read(ADDRESS, &[REG_STATUS], &mut status)
+            .map_err(Error::I2c)?;
+        self.i2c
+            .write(ADDRESS, &[REG_STATUS, status[0] & !STATUS_OSF])
+            .map_err(Error::I2c)
+    }
+}
+
+/// Registers 0 to 6, seconds to year, from a time.
+pub fn encode(time: &DateTime) -> Option<[u8; 7]> {
+    if !time.is_valid() || !(2000..=2199).contains(&time.year) {
+        return None;
+    }
+    let century = if time.year >= 2100 { MONTH_CENTURY } else { 0 };
+    Some([
+        bcd(time.second),
+        bcd(time.minute),
+        bcd(time.hour),
+        time.weekday(),
+        bcd(time.day),
+        bcd(time.month) | century,
+        bcd((time.year % 100) as u8),
+    ])
+}
+
+/// The time in registers 0 to 6. Reads 12 hour mode too, in case something
+/// else set the clock up that way.
+pub fn decode(regs: &[u8; 7]) -> Option<DateTime> {
+    let hour = if regs[2] & HOURS_12H != 0 {
+        let hour = from_bcd(regs[2] & 0x1F)?;
+        if !(1..=12).contains(&hour) {
+            return None;
+        }
+        hour % 12 + if regs[2] & HOURS_PM != 0 { 12 } else { 0 }
+    } else {
+        from_bcd(regs[2] & 0x3F)?
+    };
+    let century = if regs[5] & MONTH_CENTURY != 0 { 100 } else { 0 };
+    let time = DateTime {
+        year: 2000 + century + u16::from(from_bcd(regs[6])?),
+        month: from_bcd(regs[5] & 0x1F)?,
+        day: from_bcd(regs[4] & 0x3F)?,
+        hour,
+        minute: from_bcd(regs[1] & 0x7F)?,
+        second: from_bcd(regs[0] & 0x7F)?,
+    };
+    time.is_valid().then_some(time)
+}
+
+const fn bcd(n: u8) -> u8 {
+    ((n / 10) << 4) | (n % 10)
+}
+
+fn from_bcd(b: u8) -> Option<u8> {
+    let (tens, ones) = (b >> 4, b & 0xF);
+    (tens < 10 && ones < 10).then_some(tens * 10 + ones)
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_hal::i2c::{ErrorKind, ErrorType, NoAcknowledgeSource, Operation};
+
+    use super::*;
+
+    fn at(year: u16, month: u8, day: u8, hour: u8, minute: u8, second: u8) -> DateTime {
+        DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        }
+    }
+
+    #[test]
+    fn registers() {
+        let time = at(2026, 10, 17, 14, 30, 8);
+        let regs = [0x08, 0x30, 0x14, 6, 0x17, 0x10, 0x26];
+        assert_eq!(encode(&time), Some(regs));
+        assert_eq!(decode(&regs), Some(time));
+
+        let late = at(2199, 12, 31, 23, 59, 59);
+        let regs = encode(&late).unwrap();
+        assert_eq!(regs[5], 0x92, "century bit");
+        assert_eq!(decode(&regs), Some(late));
+        assert_eq!(encode(&at(1999, 12, 31, 0, 0, 0)), None);
+        assert_eq!(encode(&at(2200, 1, 1, 0, 0, 0)), None);
+    }
+
+    #[test]
+    fn twelve_hour_mode() {
+        // 12 AM is midnight, 12 PM is noon
+        let twelve_hour = [(0x52, 0), (0x41, 1), (0x72, 12), (0x71, 23)];
+        for (reg, hour) in twelve_hour {
+            let regs = [0, 0, reg, 1, 0x01, 0x01, 0x26];
+            assert_eq!(decode(&regs).map(|t| t.hour), Some(hour), "{:#04x}", reg);
+        }
+    }
+
+    #[test]
+    fn bad_registers() {
+        let bad = [
+            [0x60, 0, 0, 1, 1, 1, 0],
+            [0, 0x0A, 0, 1, 1, 1, 0],
+            [0, 0, 0x24, 1, 1, 1, 0],
+            [0, 0, 0x40, 1, 1, 1, 0],
+            [0, 0, 0, 1, 0x30, 0x02, 0x26],
+            [0, 0, 0, 1, 0, 1, 0],
+            [0, 0, 0, 1, 1, 0x13, 0],
+        ];
+        for regs in bad {
+            assert_eq!(decode(&regs), None, "{:02x?}", regs);
+        }
+    }
+
+    /// The DS3231's registers behind a register pointer that a write sets and
+    /// reads and writes advance, as on the chip.
+    struct FakeDs3231 {
+        regs: [u8; 0x13],
+        pointer: usize,
+        present: bool,
+    }
+
+    impl FakeDs3231 {
+        fn new() -> Self {
+            let mut regs = [0; 0x13];
+            // Oscillator stopped, as at first power up
+            regs[0x0F] = 0x88;
+            Self {
+                regs,
+                pointer: 0,
+                present: true,
+            }
+        }
+    }
+
+    impl ErrorType for FakeDs3231 {
+        type Error = ErrorKind;
+    }
+
+    impl I2c for FakeDs3231 {
+        fn transaction(
+            &mut self,
+            address: u8,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            if address != ADDRESS || !self.present {
+                return Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address));
+            }
+            for operation in operations {
+                match operation {
+                    Operation::Write(bytes) => {
+                        if let Some((&pointer, data)) = bytes.split_first() {
+                            self.pointer = usize::from(pointer);
+                            for &b in data {
+                                self.regs[self.pointer % self.regs.len()] = b;
+                                self.pointer += 1;
+                            }
+                        }
+                    }
+                    Operation::Read(buf) => {
+                        for b in buf.iter_mut() {
+                            *b = self.regs[self.pointer % self.regs.len()];
+                            self.pointer += 1;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn driver() {
+        let mut rtc = Ds3231::new(FakeDs3231::new());
+        assert_eq!(rtc.now(), Ok(None), "before setting");
+
+        let time = at(2026, 10, 17, 14, 30, 8);
+        assert_eq!(rtc.set(&time), Ok(()));
+        assert_eq!(rtc.now(), Ok(Some(time)));
+        let mut chip = rtc.release();
+        // Only the stopped flag is cleared
+        assert_eq!(chip.regs[0x0F], 0x08);
+
+        chip.regs[0] = 0x7A;
+        let mut rtc = Ds3231::new(chip);
+        assert_eq!(rtc.now(), Err(Error::BadTime));
+        assert_eq!(rtc.set(&at(2200, 1, 1, 0, 0, 0)), Err(Error::OutOfRange));
+
+        let mut chip = rtc.release();
+        chip.present = false;
+        let mut rtc = Ds3231::new(chip);
+        assert!(matches!(rtc.now(), Err(Error::I2c(_))));
+    }
+}
@@ -0,0 +1,13 @@
+//! Wall clock time for FAT timestamps.
+//!
+//! [`date`] converts between calendar dates, seconds since 1970 and the
+//! packed FAT format, and [`ds3231`] reads and sets an external DS3231 over
+//! I²C. Both are plain `no_std` code that can be checked on the host. With
+//! the `rp235x` feature, [`aon`] keeps the time in the RP2350's always-on
+//! timer.
+#![no_std]
+
+#[cfg(feature = "rp235x")]
+pub mod aon;
+pub mod date;
+pub mod ds3231;
@@ -0,0 +1,59 @@
+//! What a read and write throughput test needs: data that shows if it comes
+//! back wrong, and rates worked out and printed without floats.
+
+use core::fmt;
+
+/// Fills `buf` with the test data from `offset` into the file. Each 4 byte
+/// word holds its own index, so data read back from the wrong place, or
+/// left over from before, doesn't pass for the right data.
+pub fn fill(offset: u32, buf: &mut [u8]) {
+    for (i, byte) in buf.iter_mut().enumerate() {
+        *byte = expected(offset + i as u32);
+    }
+}
+
+/// Where `buf`, read from `offset` into the file, first differs from the
+/// test data.
+pub fn check(offset: u32, buf: &[u8]) -> Option<u32> {
+    buf.iter()
+        .enumerate()
+        .find(|&(i, &byte)| byte != expected(offset + i as u32))
+        .map(|(i, _)| offset + i as u32)
+}
+
+fn expected(pos: u32) -> u8 {
+    ((pos / 4) >> (8 * (pos % 4))) as u8
+}
+
+/// `bytes` moved in `micros` microseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate {
+    pub bytes: u64,
+    pub micros: u64,
+}
+
+impl Rate {
+    /// Tenths of a KiB a second.
+    pub fn kib_tenths(&self) -> u64 {
+        (self.bytes * 10_000_000)
+            .checked_div(self.micros * 1024)
+            .unwrap_or(0)
+    }
+}
+
+/// Like `262144 bytes in 1.024 s, 250.0 KiB/s`.
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = self.micros / 1000;
+        let rate = self.kib_tenths();
+        write!(
+            f,
+            "{} bytes in {}.{:03} s, {}.{} KiB/s",
+            self.bytes,
+            ms / 1000,
+            ms % 1000,
+            rate / 10,
+            rate % 10
+        )
+    }
+}
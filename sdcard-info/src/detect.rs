@@ -0,0 +1,267 @@
+//! Noticing when the card comes and goes.
+//!
+//! If the socket's card detect switch is wired up, it says whether a card is
+//! in; without it, a card that's been found is probed now and then, and an
+//! empty slot is tried now and then. Either way [`Monitor`] initializes a
+//! card that turns up, retries a few times with growing gaps when that
+//! fails, and reports each change as an [`Event`] for the serial port.
+
+use core::fmt;
+
+/// The card, as the monitor drives it.
+pub trait Slot {
+    /// Initializes the card from scratch.
+    fn init(&mut self) -> Result<(), &'static str>;
+
+    /// Whether the card that was initialized still answers.
+    fn probe(&mut self) -> bool;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DetectConfig {
+    /// How long the card detect pin has to stay put to count.
+    pub debounce_ms: u32,
+    /// Time between probes without a card detect pin.
+    pub probe_ms: u32,
+    /// Attempts at initializing a card before giving up.
+    pub retries: u32,
+    /// Wait after the first failed attempt, doubled after each one.
+    pub retry_ms: u32,
+    /// Without a card detect pin, how long to wait after giving up before
+    /// looking for a card again.
+    pub cooldown_ms: u32,
+}
+
+impl DetectConfig {
+    /// Probes every second, and five attempts over about 1.5 seconds.
+    pub const DEFAULT: DetectConfig = DetectConfig {
+        debounce_ms: 50,
+        probe_ms: 1000,
+        retries: 5,
+        retry_ms: 100,
+        cooldown_ms: 30_000,
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    Inserted,
+    Removed,
+    Ready,
+    /// An attempt at initializing failed, and there'll be another.
+    Failed {
+        attempt: u32,
+        retries: u32,
+        error: &'static str,
+    },
+    /// The last attempt failed too. Without a card detect pin the monitor
+    /// looks again after `retry_ms`; with one, once the card is reinserted.
+    GaveUp {
+        error: &'static str,
+        retry_ms: Option<u32>,
+    },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::Inserted => f.write_str("Card inserted"),
+            Event::Removed => f.write_str("Card removed"),
+            Event::Ready => f.write_str("Card ready"),
+            Event::Failed {
+                attempt,
+                retries,
+                error,
+            } => write!(
+                f,
+                "Card init failed ({} of {}): {}, retrying",
+                attempt, retries, error
+            ),
+            Event::GaveUp {
+                error,
+                retry_ms: Some(ms),
+            } => write!(
+                f,
+                "Card init failed: {}, giving up for {} s",
+                error,
+                ms / 1000
+            ),
+            Event::GaveUp {
+                error,
+                retry_ms: None,
+            } => write!(
+                f,
+                "Card init failed: {}, giving up until it's reinserted",
+                error
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// No card, or none found the last time.
+    Absent {
+        next_ms: u64,
+    },
+    /// Attempts at initializing, the next one due at `next_ms`.
+    Starting {
+        attempt: u32,
+        next_ms: u64,
+    },
+    Ready {
+        next_probe_ms: u64,
+    },
+    GaveUp {
+        until_ms: u64,
+    },
+}
+
+pub struct Monitor {
+    config: DetectConfig,
+    state: State,
+    /// The card detect pin as last read, and since when.
+    level: bool,
+    since_ms: u64,
+    /// The pin's level once it's settled.
+    inserted: bool,
+}
+
+impl Monitor {
+    pub fn new(config: DetectConfig) -> Self {
+        Self {
+            config,
+            state: State::Absent { next_ms: 0 },
+            level: false,
+            since_ms: 0,
+            inserted: false,
+        }
+    }
+
+    /// Call this often. `inserted` is the card detect pin, if there is
+    /// one. Returns what changed, if anything.
+    pub fn poll(
+        &mut self,
+        slot: &mut impl Slot,
+        now_ms: u64,
+        inserted: Option<bool>,
+    ) -> Option<Event> {
+        if let Some(level) = inserted {
+            if level != self.level {
+                self.level = level;
+                self.since_ms = now_ms;
+            }
+            let settled = now_ms - self.since_ms >= u64::from(self.config.debounce_ms);
+            if settled && level != self.inserted {
+                self.inserted = level;
+                return Some(if level {
+                    self.state = State::Starting {
+                        attempt: 0,
+                        next_ms: now_ms,
+                    };
+                    Event::Inserted
+                } else {
+                    self.state = State::Absent { next_ms: 0 };
+                    Event::Removed
+                });
+            }
+            if !self.inserted {
+                return None;
+            }
+        }
+
+        let probe_ms = u64::from(self.config.probe_ms);
+        match self.state {
+            // Only without a pin: an init that works is how a card is found
+            State::Absent { next_ms } if now_ms >= next_ms => {
+                self.state = State::Absent {
+                    next_ms: now_ms + probe_ms,
+                };
+                if slot.init().is_ok() {
+                    self.state = State::Ready {
+                        next_probe_ms: now_ms + probe_ms,
+                    };
+                    return Some(Event::Ready);
+                }
+            }
+            State::Starting { attempt, next_ms } if now_ms >= next_ms => {
+                let attempt = attempt + 1;
+                match slot.init() {
+                    Ok(()) => {
+                        self.state = State::Ready {
+                            next_probe_ms: now_ms + probe_ms,
+                        };
+                        return Some(Event::Ready);
+                    }
+                    Err(error) if attempt >= self.config.retries => {
+                        let retry_ms = inserted.is_none().then_some(self.config.cooldown_ms);
+                        self.state = State::GaveUp {
+                            until_ms: retry_ms.map_or(u64::MAX, |ms| now_ms + u64::from(ms)),
+                        };
+                        return Some(Event::GaveUp { error, retry_ms });
+                    }
+                    Err(error) => {
+                        let wait = u64::from(self.config.retry_ms) << (attempt - 1).min(16);
+                        self.state = State::Starting {
+                            attempt,
+                            next_ms: now_ms + wait,
+                        };
+                        return Some(Event::Failed {
+                            attempt,
+                            retries: self.config.retries,
+                            error,
+                        });
+                    }
+                }
+            }
+            // The pin says when the card goes; without one, ask the card
+            State::Ready { next_probe_ms } if inserted.is_none() && now_ms >= next_probe_ms => {
+                if slot.probe() {
+                    self.state = State::Ready {
+                        next_probe_ms: now_ms + probe_ms,
+                    };
+                } else {
+                    self.state = State::Absent { next_ms: now_ms };
+                    return Some(Event::Removed);
+                }
+            }
+            State::GaveUp { until_ms } if now_ms >= until_ms => {
+                self.state = State::Absent { next_ms: now_ms };
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// A read or write on the card failed. It's initialized again, with
+    /// the usual retries, from the next poll.
+    pub fn card_failed(&mut self, now_ms: u64) {
+        if let State::Ready { .. } = self.state {
+            self.state = State::Starting {
+                attempt: 0,
+                next_ms: now_ms,
+            };
+        }
+    }
+
+    /// The card was initialized some other way, such as on request.
+    pub fn card_found(&mut self, now_ms: u64) {
+        self.state = State::Ready {
+            next_probe_ms: now_ms + u64::from(self.config.probe_ms),
+        };
+    }
+
+    pub fn is_ready(&self) -> bool {
+        matches!(self.state, State::Ready { .. })
+    }
+
+    /// Why the card can't be used, for commands that need it.
+    pub fn status(&self) -> &'static str {
+        match self.state {
+            State::Absent { .. } => "No card",
+            State::Starting { .. } => "Card not ready yet",
+            State::Ready { .. } => "Card ready",
+            State::GaveUp { .. } => "Card failed to initialize",
+        }
+    }
+}
@@ -0,0 +1,13 @@
+//! Identifying an SD card and getting the most out of its SPI link.
+//!
+//! [`register`] decodes the card's CID and CSD registers, [`spi`] reads them
+//! over any [`SpiDevice`](embedded_hal::spi::SpiDevice) and raises the clock
+//! as far as the card and the wiring allow, [`bench`] has what a throughput
+//! test needs, and [`detect`] notices cards coming and going. Plain `no_std` code, so it can be checked on the
+//! host against a card made up in software.
+#![no_std]
+
+pub mod bench;
+pub mod detect;
+pub mod register;
+pub mod spi;
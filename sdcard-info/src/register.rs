@@ -0,0 +1,254 @@
+//! The CID and CSD registers, 16 bytes each, most significant byte first.
+//!
+//! The CID says who made the card and when, the CSD how big and how fast it
+//! is. Both end in a CRC7 of the other 15 bytes.
+
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterError {
+    /// The register's own CRC7 doesn't match.
+    BadCrc,
+    /// A CSD layout other than version 1.0 or 2.0, such as an SDUC card's.
+    UnknownCsd,
+}
+
+impl RegisterError {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            RegisterError::BadCrc => "Register CRC doesn't match",
+            RegisterError::UnknownCsd => "Unknown CSD version",
+        }
+    }
+}
+
+/// Card identification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cid {
+    pub manufacturer: u8,
+    /// OEM or application ID, two ASCII characters.
+    pub oem: [u8; 2],
+    /// Product name, five ASCII characters.
+    pub product: [u8; 5],
+    /// Product revision, major and minor.
+    pub revision: (u8, u8),
+    pub serial: u32,
+    pub year: u16,
+    pub month: u8,
+}
+
+impl Cid {
+    pub fn parse(raw: &[u8; 16]) -> Result<Cid, RegisterError> {
+        check_crc(raw)?;
+        Ok(Cid {
+            manufacturer: raw[0],
+            oem: [raw[1], raw[2]],
+            product: [raw[3], raw[4], raw[5], raw[6], raw[7]],
+            revision: (raw[8] >> 4, raw[8] & 0x0F),
+            serial: u32::from_be_bytes([raw[9], raw[10], raw[11], raw[12]]),
+            year: 2000 + u16::from(((raw[13] & 0x0F) << 4) | (raw[14] >> 4)),
+            month: raw[14] & 0x0F,
+        })
+    }
+
+    /// The manufacturer's name, for the IDs that are well known. The list
+    /// is kept by the SD Association and isn't public.
+    pub fn manufacturer_name(&self) -> Option<&'static str> {
+        Some(match self.manufacturer {
+            0x01 => "Panasonic",
+            0x02 => "Toshiba",
+            0x03 => "SanDisk",
+            0x1B => "Samsung",
+            0x1D => "ADATA",
+            0x27 => "Phison",
+            0x28 => "Lexar",
+            0x31 => "Silicon Power",
+            0x41 => "Kingston",
+            0x74 => "Transcend",
+            0x76 => "Patriot",
+            0x82 => "Sony",
+            _ => return None,
+        })
+    }
+
+    pub fn oem_str(&self) -> &str {
+        ascii(&self.oem)
+    }
+
+    pub fn product_str(&self) -> &str {
+        ascii(&self.product)
+    }
+}
+
+/// Standard capacity cards address bytes, the others 512 byte blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CardKind {
+    /// Up to 2 GB, CSD version 1.0.
+    Sdsc,
+    /// Up to 32 GB.
+    Sdhc,
+    /// Up to 2 TB.
+    Sdxc,
+}
+
+impl CardKind {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            CardKind::Sdsc => "SDSC",
+            CardKind::Sdhc => "SDHC",
+            CardKind::Sdxc => "SDXC",
+        }
+    }
+}
+
+/// Card specific data, the parts of it worth knowing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Csd {
+    /// 1 or 2, for CSD version 1.0 or 2.0.
+    pub version: u8,
+    pub capacity: u64,
+    /// Fastest clock the card takes, from TRAN_SPEED.
+    pub max_speed_hz: u32,
+}
+
+impl Csd {
+    pub fn parse(raw: &[u8; 16]) -> Result<Csd, RegisterError> {
+        check_crc(raw)?;
+        let capacity = match raw[0] >> 6 {
+            0 => {
+                let block_len = u32::from(raw[5] & 0x0F);
+                let size = (u64::from(raw[6] & 0x03) << 10)
+                    | (u64::from(raw[7]) << 2)
+                    | (u64::from(raw[8]) >> 6);
+                let mult = (u32::from(raw[9] & 0x03) << 1) | (u32::from(raw[10]) >> 7);
+                (size + 1) << (mult + 2 + block_len)
+            }
+            1 => {
+                let size =
+                    (u64::from(raw[7] & 0x3F) << 16) | (u64::from(raw[8]) << 8) | u64::from(raw[9]);
+                (size + 1) * 512 * 1024
+            }
+            _ => return Err(RegisterError::UnknownCsd),
+        };
+        Ok(Csd {
+            version: (raw[0] >> 6) + 1,
+            capacity,
+            max_speed_hz: tran_speed(raw[3]),
+        })
+    }
+
+    pub fn kind(&self) -> CardKind {
+        if self.version == 1 {
+            CardKind::Sdsc
+        } else if self.capacity <= 32 << 30 {
+            CardKind::Sdhc
+        } else {
+            CardKind::Sdxc
+        }
+    }
+}
+
+/// What an SD card says about itself, and the clock it ended up at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CardInfo {
+    pub cid: Cid,
+    pub csd: Csd,
+    pub spi_hz: u32,
+}
+
+/// A few lines for the serial port, each ending in CRLF.
+impl fmt::Display for CardInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (cid, csd) = (&self.cid, &self.csd);
+        f.write_str("Manufacturer: ")?;
+        if let Some(name) = cid.manufacturer_name() {
+            write!(f, "{} ", name)?;
+        }
+        write!(f, "(0x{:02X}), OEM {}\r\n", cid.manufacturer, cid.oem_str())?;
+        write!(
+            f,
+            "Product: {} rev {}.{}, serial 0x{:08X}, made {}-{:02}\r\n",
+            cid.product_str(),
+            cid.revision.0,
+            cid.revision.1,
+            cid.serial,
+            cid.year,
+            cid.month
+        )?;
+        let tenths = csd.capacity * 10 / (1 << 30);
+        write!(
+            f,
+            "Capacity: {} bytes ({}.{} GiB), {}\r\n",
+            csd.capacity,
+            tenths / 10,
+            tenths % 10,
+            csd.kind().as_str()
+        )?;
+        write!(
+            f,
+            "SPI clock: {} kHz, card allows {} kHz\r\n",
+            self.spi_hz / 1000,
+            csd.max_speed_hz / 1000
+        )
+    }
+}
+
+/// CRC7 with polynomial x^7 + x^3 + 1, as commands and registers use.
+pub fn crc7(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        for i in (0..8).rev() {
+            let feedback = ((byte >> i) ^ (crc >> 6)) & 1;
+            crc = (crc << 1) & 0x7F;
+            if feedback == 1 {
+                crc ^= 0x09;
+            }
+        }
+    }
+    crc
+}
+
+/// CRC16-CCITT, as data blocks use.
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+fn check_crc(raw: &[u8; 16]) -> Result<(), RegisterError> {
+    if raw[15] >> 1 == crc7(&raw[..15]) {
+        Ok(())
+    } else {
+        Err(RegisterError::BadCrc)
+    }
+}
+
+/// TRAN_SPEED: a rate unit in the low three bits and a multiplier above.
+fn tran_speed(byte: u8) -> u32 {
+    // Tenths
+    const VALUES: [u32; 16] = [
+        0, 10, 12, 13, 15, 20, 25, 30, 35, 40, 45, 50, 55, 60, 70, 80,
+    ];
+    const UNITS: [u32; 4] = [10_000, 100_000, 1_000_000, 10_000_000];
+    match UNITS.get(usize::from(byte & 0x07)) {
+        Some(unit) => VALUES[usize::from((byte >> 3) & 0x0F)] * unit,
+        None => 0,
+    }
+}
+
+/// Text from a register, or nothing if it isn't printable.
+fn ascii(bytes: &[u8]) -> &str {
+    match core::str::from_utf8(bytes) {
+        Ok(s) if s.bytes().all(|b| b.is_ascii_graphic() || b == b' ') => s,
+        _ => "",
+    }
+}
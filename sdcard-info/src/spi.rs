@@ -0,0 +1,143 @@
+//! Reading the registers over SPI, and finding the fastest clock that still
+//! reads them back intact.
+//!
+//! The card has to be initialized already; this only sends CMD9 and CMD10.
+//! Each command is a single SPI transaction, so the chip select stays low
+//! from the command to the end of the data.
+
+use embedded_hal::spi::{Operation, SpiDevice};
+
+use crate::register::{crc16, crc7, CardInfo, Cid, Csd, RegisterError};
+
+/// The clock cards are initialized at.
+pub const INIT_HZ: u32 = 400_000;
+
+const SEND_CSD: u8 = 9;
+const SEND_CID: u8 = 10;
+
+/// Bytes clocked for each command: a byte of 0xFF, the command, and time
+/// for the response, the data token, the register and its CRC16.
+const TRANSFER: usize = 48;
+
+/// Bytes the card may take to answer a command.
+const MAX_NCR: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CardError<E> {
+    Spi(E),
+    /// No response to the command.
+    NoResponse,
+    /// The card answered with these R1 error bits.
+    Rejected(u8),
+    /// The card answered with a data error token.
+    DataError(u8),
+    /// No data came after the response.
+    NoData,
+    /// The data's CRC16 doesn't match.
+    BadCrc,
+    Register(RegisterError),
+}
+
+impl<E> CardError<E> {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            CardError::Spi(_) => "SPI error",
+            CardError::NoResponse => "Card not responding",
+            CardError::Rejected(_) => "Card rejected the command",
+            CardError::DataError(_) => "Card couldn't send the register",
+            CardError::NoData => "Card sent no data",
+            CardError::BadCrc => "Data CRC doesn't match",
+            CardError::Register(e) => e.as_str(),
+        }
+    }
+}
+
+impl<E> From<RegisterError> for CardError<E> {
+    fn from(e: RegisterError) -> Self {
+        CardError::Register(e)
+    }
+}
+
+pub fn read_cid<S: SpiDevice>(spi: &mut S) -> Result<Cid, CardError<S::Error>> {
+    Ok(Cid::parse(&read_register(spi, SEND_CID)?)?)
+}
+
+pub fn read_csd<S: SpiDevice>(spi: &mut S) -> Result<Csd, CardError<S::Error>> {
+    Ok(Csd::parse(&read_register(spi, SEND_CSD)?)?)
+}
+
+/// Reads the registers at the clock the card was initialized at, then
+/// raises it to the card's maximum or `max_hz`, whichever is lower.
+/// `set_rate` sets the SPI clock and returns the rate it got.
+///
+/// Long wires and breadboards don't always manage what the card does, so
+/// the CSD is read again at each rate, and the clock halved until it comes
+/// back the same. Fails only if the card can't be read at [`INIT_HZ`].
+pub fn identify<S: SpiDevice>(
+    spi: &mut S,
+    max_hz: u32,
+    mut set_rate: impl FnMut(&mut S, u32) -> u32,
+) -> Result<CardInfo, CardError<S::Error>> {
+    set_rate(spi, INIT_HZ);
+    let cid = read_cid(spi)?;
+    let csd = read_csd(spi)?;
+
+    let mut target = csd.max_speed_hz.min(max_hz).max(INIT_HZ);
+    loop {
+        let spi_hz = set_rate(spi, target);
+        // Bits lost on the way usually fail the CRCs, but check it all
+        let again = read_csd(spi).and_then(|again| {
+            if again == csd {
+                Ok(())
+            } else {
+                Err(CardError::BadCrc)
+            }
+        });
+        match again {
+            Ok(()) => return Ok(CardInfo { cid, csd, spi_hz }),
+            Err(e) if target == INIT_HZ => return Err(e),
+            Err(_) => target = (target / 2).max(INIT_HZ),
+        }
+    }
+}
+
+/// Sends CMD9 or CMD10 and picks the register out of what comes back.
+fn read_register<S: SpiDevice>(spi: &mut S, command: u8) -> Result<[u8; 16], CardError<S::Error>> {
+    let mut buf = [0xFF; TRANSFER];
+    // No argument, so bytes 2 to 5 stay zero
+    buf[1] = 0x40 | command;
+    buf[2..6].fill(0);
+    buf[6] = (crc7(&buf[1..6]) << 1) | 1;
+    spi.transaction(&mut [Operation::TransferInPlace(&mut buf)])
+        .map_err(CardError::Spi)?;
+
+    // R1 has its top bit clear
+    let reply = &buf[7..];
+    let at = reply
+        .iter()
+        .take(MAX_NCR + 1)
+        .position(|&b| b & 0x80 == 0)
+        .ok_or(CardError::NoResponse)?;
+    if reply[at] != 0 {
+        return Err(CardError::Rejected(reply[at]));
+    }
+
+    // Then 0xFF until the start token, or an error token
+    let data = &reply[at + 1..];
+    let start = data
+        .iter()
+        .position(|&b| b != 0xFF)
+        .ok_or(CardError::NoData)?;
+    match data[start] {
+        0xFE => {}
+        token if token & 0xE0 == 0 => return Err(CardError::DataError(token)),
+        _ => return Err(CardError::NoData),
+    }
+    let data = data.get(start + 1..start + 19).ok_or(CardError::NoData)?;
+    let mut register = [0; 16];
+    register.copy_from_slice(&data[..16]);
+    if crc16(&register) != u16::from_be_bytes([data[16], data[17]]) {
+        return Err(CardError::BadCrc);
+    }
+    Ok(register)
+}
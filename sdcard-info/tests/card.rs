@@ -0,0 +1,576 @@
+//! Runs the register decoding and the SPI register reads on the host,
+//! against a card made up in software.
+
+use std::convert::Infallible;
+
+use embedded_hal::spi::{ErrorType, Operation, SpiDevice};
+use sdcard_info::bench::{check, fill, Rate};
+use sdcard_info::detect::{DetectConfig, Event, Monitor, Slot};
+use sdcard_info::register::{crc16, crc7, CardKind, Cid, Csd, RegisterError};
+use sdcard_info::spi::{identify, read_cid, read_csd, CardError, INIT_HZ};
+
+/// A register from its first 15 bytes, with the CRC7 filled in.
+fn register(bytes: [u8; 15]) -> [u8; 16] {
+    let mut raw = [0; 16];
+    raw[..15].copy_from_slice(&bytes);
+    raw[15] = (crc7(&bytes) << 1) | 1;
+    raw
+}
+
+/// A 16 GB SanDisk card.
+const CID: [u8; 15] = [
+    0x03, b'S', b'D', b'S', b'C', b'1', b'6', b'G', 0x80, 0x12, 0x34, 0x56, 0x78, 0x01, 0x46,
+];
+const CSD_V2: [u8; 15] = [
+    0x40, 0x0E, 0x00, 0x32, 0x5B, 0x59, 0x00, 0x00, 0x76, 0xB2, 0x7F, 0x80, 0x0A, 0x40, 0x40,
+];
+/// A 2 GB card with 1024 byte blocks.
+const CSD_V1: [u8; 15] = [
+    0x00, 0x26, 0x00, 0x32, 0x5F, 0x5A, 0x83, 0xAE, 0xFE, 0xFB, 0xCF, 0xFF, 0x92, 0x80, 0x40,
+];
+
+#[test]
+fn crcs() {
+    // The CRCs every SPI mode driver sends for CMD0 and CMD8
+    assert_eq!(crc7(&[0x40, 0, 0, 0, 0]), 0x4A, "CMD0");
+    assert_eq!(crc7(&[0x48, 0, 0, 0x01, 0xAA]), 0x43, "CMD8");
+    assert_eq!(crc16(&[0xFF; 512]), 0x7FA1, "block of 0xFF");
+    assert_eq!(crc16(&[]), 0, "empty");
+}
+
+#[test]
+fn cid() {
+    let cid = Cid::parse(&register(CID)).unwrap();
+    assert_eq!(cid.manufacturer_name(), Some("SanDisk"), "manufacturer");
+    assert_eq!(cid.oem_str(), "SD", "oem");
+    assert_eq!(cid.product_str(), "SC16G", "product");
+    assert_eq!(cid.revision, (8, 0), "revision");
+    assert_eq!(cid.serial, 0x1234_5678, "serial");
+    assert_eq!((cid.year, cid.month), (2020, 6), "date");
+
+    let mut bad = register(CID);
+    bad[4] ^= 0x01;
+    assert_eq!(Cid::parse(&bad), Err(RegisterError::BadCrc), "bad crc");
+
+    let mut odd = CID;
+    odd[0] = 0xEE;
+    odd[3] = 0;
+    let odd = Cid::parse(&register(odd)).unwrap();
+    assert_eq!(odd.manufacturer_name(), None, "unknown maker");
+    assert_eq!(odd.product_str(), "", "unprintable name");
+}
+
+#[test]
+fn csd_v1() {
+    let csd = Csd::parse(&register(CSD_V1)).unwrap();
+    assert_eq!(csd.version, 1, "version");
+    // (3771 + 1) * 2^(7 + 2) * 1024
+    assert_eq!(csd.capacity, 1_977_614_336, "capacity");
+    assert_eq!(csd.max_speed_hz, 25_000_000, "speed");
+    assert_eq!(csd.kind(), CardKind::Sdsc, "kind");
+}
+
+#[test]
+fn csd_v2() {
+    let csd = Csd::parse(&register(CSD_V2)).unwrap();
+    assert_eq!(csd.version, 2, "version");
+    // (0x76B2 + 1) * 512 KiB
+    assert_eq!(csd.capacity, 15_931_539_456, "capacity");
+    assert_eq!(csd.kind(), CardKind::Sdhc, "kind");
+
+    // 50 MHz high speed, and a 64 GB card
+    let mut fast = CSD_V2;
+    fast[3] = 0x5A;
+    fast[7] = 0x01;
+    fast[8] = 0xDC;
+    fast[9] = 0xFF;
+    let csd = Csd::parse(&register(fast)).unwrap();
+    assert_eq!(csd.max_speed_hz, 50_000_000, "high speed");
+    assert_eq!(csd.capacity, 0x1DD00 * 512 * 1024, "capacity");
+    assert_eq!(csd.kind(), CardKind::Sdxc, "sdxc");
+
+    let mut sduc = CSD_V2;
+    sduc[0] = 0x80;
+    assert_eq!(
+        Csd::parse(&register(sduc)),
+        Err(RegisterError::UnknownCsd),
+        "version 3"
+    );
+}
+
+/// A card on the other end of the SPI bus, answering CMD9 and CMD10.
+struct FakeCard {
+    cid: [u8; 16],
+    csd: [u8; 16],
+    /// 0xFF bytes before the R1 response and before the data.
+    ncr: usize,
+    nac: usize,
+    r1: u8,
+    /// Sent instead of the start token.
+    token: u8,
+    spi_hz: u32,
+    /// Above this, the last data byte comes out wrong.
+    max_hz: u32,
+    commands: Vec<u8>,
+}
+
+impl FakeCard {
+    fn new() -> FakeCard {
+        FakeCard {
+            cid: register(CID),
+            csd: register(CSD_V2),
+            ncr: 1,
+            nac: 2,
+            r1: 0,
+            token: 0xFE,
+            spi_hz: INIT_HZ,
+            max_hz: u32::MAX,
+            commands: Vec::new(),
+        }
+    }
+
+    fn answer(&mut self, buf: &mut [u8]) {
+        let Some(at) = buf.iter().position(|&b| b & 0xC0 == 0x40) else {
+            return;
+        };
+        let command = buf[at] & 0x3F;
+        self.commands.push(command);
+        if buf[at + 5] != (crc7(&buf[at..at + 5]) << 1) | 1 {
+            // Illegal command and CRC error bits
+            buf[at + 6 + self.ncr] = 0x0C;
+            return;
+        }
+        let register = match command {
+            9 => self.csd,
+            10 => self.cid,
+            _ => return,
+        };
+        let mut reply = vec![0xFF; self.ncr];
+        reply.push(self.r1);
+        if self.r1 == 0 {
+            reply.extend(std::iter::repeat_n(0xFF, self.nac));
+            reply.push(self.token);
+            reply.extend_from_slice(&register);
+            reply.extend_from_slice(&crc16(&register).to_be_bytes());
+            if self.spi_hz > self.max_hz {
+                let last = reply.len() - 3;
+                reply[last] ^= 0x10;
+            }
+        }
+        for (slot, byte) in buf[at + 6..].iter_mut().zip(reply) {
+            *slot = byte;
+        }
+    }
+}
+
+impl ErrorType for FakeCard {
+    type Error = Infallible;
+}
+
+impl SpiDevice for FakeCard {
+    fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Infallible> {
+        for op in operations {
+            if let Operation::TransferInPlace(buf) = op {
+                self.answer(buf);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn register_reads() {
+    let mut card = FakeCard::new();
+    let cid = read_cid(&mut card).unwrap();
+    assert_eq!(cid.serial, 0x1234_5678, "cid");
+    let csd = read_csd(&mut card).unwrap();
+    assert_eq!(csd.capacity, 15_931_539_456, "csd");
+    assert_eq!(card.commands, vec![10, 9], "commands");
+
+    // Slow cards, as far as the transfer allows
+    for (ncr, nac) in [(0, 0), (8, 0), (1, 12)] {
+        let mut card = FakeCard::new();
+        card.ncr = ncr;
+        card.nac = nac;
+        assert_eq!(
+            read_csd(&mut card).map(|c| c.version),
+            Ok(2),
+            "ncr {} nac {}",
+            ncr,
+            nac
+        );
+    }
+}
+
+#[test]
+fn read_errors() {
+    let mut card = FakeCard::new();
+    card.ncr = 9;
+    assert_eq!(read_csd(&mut card), Err(CardError::NoResponse), "late");
+
+    let mut card = FakeCard::new();
+    card.r1 = 0x04;
+    assert_eq!(
+        read_csd(&mut card),
+        Err(CardError::Rejected(0x04)),
+        "rejected"
+    );
+
+    let mut card = FakeCard::new();
+    card.token = 0x08;
+    assert_eq!(
+        read_csd(&mut card),
+        Err(CardError::DataError(0x08)),
+        "error token"
+    );
+
+    let mut card = FakeCard::new();
+    card.nac = 30;
+    assert_eq!(read_csd(&mut card), Err(CardError::NoData), "no data");
+
+    let mut card = FakeCard::new();
+    card.csd[15] ^= 0x02;
+    assert_eq!(
+        read_csd(&mut card),
+        Err(CardError::Register(RegisterError::BadCrc)),
+        "register crc"
+    );
+
+    let mut card = FakeCard::new();
+    card.spi_hz = 1_000_000;
+    card.max_hz = 0;
+    assert_eq!(read_csd(&mut card), Err(CardError::BadCrc), "data crc");
+}
+
+#[test]
+fn clock() {
+    let set = |card: &mut FakeCard, hz: u32| {
+        // Like a divider: never above what's asked
+        card.spi_hz = hz / 1000 * 1000;
+        card.spi_hz
+    };
+
+    let mut card = FakeCard::new();
+    let info = identify(&mut card, 75_000_000, set).unwrap();
+    assert_eq!(info.spi_hz, 25_000_000, "card's maximum");
+
+    let mut card = FakeCard::new();
+    let info = identify(&mut card, 12_000_000, set).unwrap();
+    assert_eq!(info.spi_hz, 12_000_000, "bus maximum");
+
+    // Wiring that loses bits above 7 MHz
+    let mut card = FakeCard::new();
+    card.max_hz = 7_000_000;
+    let info = identify(&mut card, 75_000_000, set).unwrap();
+    assert_eq!(info.spi_hz, 6_250_000, "halved");
+    assert_eq!(card.spi_hz, 6_250_000, "left at");
+
+    let mut card = FakeCard::new();
+    card.max_hz = 100_000;
+    card.spi_hz = 0;
+    assert_eq!(
+        identify(&mut card, 75_000_000, set).map(|i| i.spi_hz),
+        Err(CardError::BadCrc),
+        "not even slowly"
+    );
+}
+
+#[test]
+fn report() {
+    let mut card = FakeCard::new();
+    let info = identify(&mut card, 75_000_000, |card, hz| {
+        card.spi_hz = hz;
+        hz
+    })
+    .unwrap();
+    assert_eq!(
+        info.to_string(),
+        "Manufacturer: SanDisk (0x03), OEM SD\r\n\
+         Product: SC16G rev 8.0, serial 0x12345678, made 2020-06\r\n\
+         Capacity: 15931539456 bytes (14.8 GiB), SDHC\r\n\
+         SPI clock: 25000 kHz, card allows 25000 kHz\r\n"
+            .to_string(),
+        "report"
+    );
+}
+
+#[test]
+fn bench() {
+    let mut data = vec![0; 4096];
+    fill(0, &mut data);
+    assert_eq!(&data[..8], &[0, 0, 0, 0, 1, 0, 0, 0][..], "first words");
+    assert_eq!(check(0, &data), None, "intact");
+
+    // Filling in pieces gives the same as all at once
+    let mut pieces = vec![0; 4096];
+    for (i, chunk) in pieces.chunks_mut(100).enumerate() {
+        fill(i as u32 * 100, chunk);
+    }
+    assert!(pieces == data, "pieces");
+
+    // A block from the wrong place. Word 0 and word 256 share a low byte.
+    let mut wrong = data.clone();
+    wrong.copy_within(0..512, 1024);
+    assert_eq!(check(0, &wrong), Some(1025), "misplaced");
+    assert_eq!(check(512, &data[512..1024]), None, "offset");
+
+    let rate = Rate {
+        bytes: 262_144,
+        micros: 1_024_000,
+    };
+    assert_eq!(
+        rate.to_string(),
+        "262144 bytes in 1.024 s, 250.0 KiB/s".to_string(),
+        "rate"
+    );
+    assert_eq!(
+        Rate {
+            bytes: 1,
+            micros: 0,
+        }
+        .kib_tenths(),
+        0,
+        "no time"
+    );
+}
+
+/// A slot whose card comes and goes as the check says.
+struct FakeSlot {
+    present: bool,
+    /// Attempts at initializing that fail even with a card in.
+    failures: u32,
+    inits: u32,
+    probes: u32,
+}
+
+impl FakeSlot {
+    fn new(present: bool) -> FakeSlot {
+        FakeSlot {
+            present,
+            failures: 0,
+            inits: 0,
+            probes: 0,
+        }
+    }
+}
+
+impl Slot for FakeSlot {
+    fn init(&mut self) -> Result<(), &'static str> {
+        self.inits += 1;
+        if !self.present {
+            Err("No card")
+        } else if self.failures > 0 {
+            self.failures -= 1;
+            Err("Card not responding")
+        } else {
+            Ok(())
+        }
+    }
+
+    fn probe(&mut self) -> bool {
+        self.probes += 1;
+        self.present
+    }
+}
+
+#[test]
+fn detect_pin() {
+    let mut monitor = Monitor::new(DetectConfig::DEFAULT);
+    let mut slot = FakeSlot::new(false);
+    assert_eq!(monitor.poll(&mut slot, 0, Some(false)), None, "empty");
+    assert_eq!(slot.inits, 0, "no tries");
+    assert_eq!(monitor.status(), "No card", "status");
+
+    // A bounce shorter than the debounce time is ignored
+    slot.present = true;
+    assert_eq!(monitor.poll(&mut slot, 10, Some(true)), None, "bounce");
+    assert_eq!(
+        monitor.poll(&mut slot, 30, Some(false)),
+        None,
+        "bounce over"
+    );
+    assert_eq!(monitor.poll(&mut slot, 100, Some(true)), None, "settling");
+    assert_eq!(
+        monitor.poll(&mut slot, 150, Some(true)),
+        Some(Event::Inserted),
+        "inserted"
+    );
+    assert_eq!(
+        monitor.poll(&mut slot, 151, Some(true)),
+        Some(Event::Ready),
+        "ready"
+    );
+    assert!(monitor.is_ready(), "is ready");
+
+    // The pin is trusted, so no probing
+    assert_eq!(monitor.poll(&mut slot, 10_000, Some(true)), None, "quiet");
+    assert_eq!(slot.probes, 0, "no probes");
+
+    slot.present = false;
+    assert_eq!(monitor.poll(&mut slot, 10_100, Some(false)), None, "pulled");
+    assert_eq!(
+        monitor.poll(&mut slot, 10_150, Some(false)),
+        Some(Event::Removed),
+        "removed"
+    );
+    assert!(!monitor.is_ready(), "not ready");
+    assert_eq!(slot.inits, 1, "inits");
+}
+
+#[test]
+fn probing() {
+    let mut monitor = Monitor::new(DetectConfig::DEFAULT);
+    let mut slot = FakeSlot::new(false);
+    assert_eq!(monitor.poll(&mut slot, 0, None), None, "empty");
+    assert_eq!(slot.inits, 1, "tried");
+    assert_eq!(monitor.poll(&mut slot, 500, None), None, "too soon");
+    assert_eq!(slot.inits, 1, "not again yet");
+
+    slot.present = true;
+    assert_eq!(
+        monitor.poll(&mut slot, 1000, None),
+        Some(Event::Ready),
+        "found"
+    );
+    assert_eq!(monitor.poll(&mut slot, 1500, None), None, "between probes");
+    assert_eq!(monitor.poll(&mut slot, 2000, None), None, "still there");
+    assert_eq!(slot.probes, 1, "probed");
+
+    slot.present = false;
+    assert_eq!(
+        monitor.poll(&mut slot, 3000, None),
+        Some(Event::Removed),
+        "gone"
+    );
+    assert_eq!(monitor.status(), "No card", "status");
+    slot.present = true;
+    assert_eq!(
+        monitor.poll(&mut slot, 3001, None),
+        Some(Event::Ready),
+        "back"
+    );
+}
+
+#[test]
+fn retries() {
+    let config = DetectConfig::DEFAULT;
+    let mut monitor = Monitor::new(config);
+    let mut slot = FakeSlot::new(true);
+    slot.failures = 100;
+    monitor.poll(&mut slot, 0, Some(true));
+    assert_eq!(
+        monitor.poll(&mut slot, 50, Some(true)),
+        Some(Event::Inserted),
+        "inserted"
+    );
+
+    // 100, 200, 400 and 800 ms between attempts
+    let mut now = 50;
+    for (attempt, wait) in [(1, 100), (2, 200), (3, 400), (4, 800)] {
+        assert_eq!(
+            monitor.poll(&mut slot, now, Some(true)),
+            Some(Event::Failed {
+                attempt,
+                retries: config.retries,
+                error: "Card not responding",
+            }),
+            "attempt {}",
+            attempt
+        );
+        assert_eq!(
+            monitor.poll(&mut slot, now + wait - 1, Some(true)),
+            None,
+            "waits"
+        );
+        now += wait;
+    }
+    let gave_up = monitor.poll(&mut slot, now, Some(true));
+    assert_eq!(
+        gave_up,
+        Some(Event::GaveUp {
+            error: "Card not responding",
+            retry_ms: None,
+        }),
+        "gave up"
+    );
+    assert_eq!(
+        gave_up.map(|e| e.to_string()),
+        Some("Card init failed: Card not responding, giving up until it's reinserted".to_string()),
+        "message"
+    );
+    assert_eq!(slot.inits, config.retries, "attempts");
+    assert_eq!(
+        monitor.poll(&mut slot, now + 60_000, Some(true)),
+        None,
+        "stays put"
+    );
+    assert_eq!(slot.inits, config.retries, "no more");
+    assert_eq!(monitor.status(), "Card failed to initialize", "status");
+
+    // Taking the card out and back in starts over
+    slot.failures = 1;
+    monitor.poll(&mut slot, now + 61_000, Some(false));
+    assert_eq!(
+        monitor.poll(&mut slot, now + 61_050, Some(false)),
+        Some(Event::Removed),
+        "out"
+    );
+    monitor.poll(&mut slot, now + 62_000, Some(true));
+    assert_eq!(
+        monitor.poll(&mut slot, now + 62_050, Some(true)),
+        Some(Event::Inserted),
+        "in"
+    );
+    assert_eq!(
+        monitor
+            .poll(&mut slot, now + 62_050, Some(true))
+            .map(|e| e.to_string()),
+        Some("Card init failed (1 of 5): Card not responding, retrying".to_string()),
+        "first fails"
+    );
+    assert_eq!(
+        monitor.poll(&mut slot, now + 62_150, Some(true)),
+        Some(Event::Ready),
+        "second works"
+    );
+}
+
+#[test]
+fn failed_card() {
+    let config = DetectConfig::DEFAULT;
+    let mut monitor = Monitor::new(config);
+    let mut slot = FakeSlot::new(true);
+    assert_eq!(
+        monitor.poll(&mut slot, 0, None),
+        Some(Event::Ready),
+        "found"
+    );
+
+    // A write failed, and the card doesn't come back
+    slot.failures = 100;
+    monitor.card_failed(10);
+    assert_eq!(monitor.status(), "Card not ready yet", "status");
+    let mut last = None;
+    for now in (10..2000).step_by(10) {
+        last = monitor.poll(&mut slot, now, None).or(last);
+    }
+    assert_eq!(
+        last.map(|e| e.to_string()),
+        Some("Card init failed: Card not responding, giving up for 30 s".to_string()),
+        "gave up"
+    );
+    assert_eq!(slot.inits, 1 + config.retries, "attempts");
+    assert_eq!(monitor.poll(&mut slot, 31_000, None), None, "cooling down");
+    assert_eq!(slot.inits, 1 + config.retries, "no more");
+
+    // Then it's looked for again, like an empty slot
+    slot.failures = 0;
+    monitor.poll(&mut slot, 32_000, None);
+    assert_eq!(
+        monitor.poll(&mut slot, 32_001, None),
+        Some(Event::Ready),
+        "found again"
+    );
+    monitor.card_found(40_000);
+    assert!(monitor.is_ready(), "ready");
+}
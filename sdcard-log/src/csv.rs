@@ -0,0 +1,143 @@
+//! CSV rows as RFC 4180 has them: fields separated by commas, rows ending in
+//! CRLF, and fields with commas, quotes, line breaks or spaces at either end
+//! in double quotes, with quotes inside doubled.
+
+use core::fmt::{self, Write};
+
+/// Longest row, line ending included.
+pub const MAX_ROW: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowError {
+    /// The row doesn't fit in [`MAX_ROW`] bytes.
+    TooLong,
+}
+
+impl RowError {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            RowError::TooLong => "Row too long",
+        }
+    }
+}
+
+/// One row being put together a field at a time.
+#[derive(Clone)]
+pub struct Row {
+    buf: [u8; MAX_ROW],
+    len: usize,
+    fields: usize,
+    too_long: bool,
+}
+
+impl Default for Row {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Row {
+    pub fn new() -> Self {
+        Self {
+            buf: [0; MAX_ROW],
+            len: 0,
+            fields: 0,
+            too_long: false,
+        }
+    }
+
+    /// A row of column names.
+    pub fn header(columns: &[&str]) -> Self {
+        let mut row = Row::new();
+        for column in columns {
+            row.field(column);
+        }
+        row
+    }
+
+    /// Empties the row to start the next one.
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.fields = 0;
+        self.too_long = false;
+    }
+
+    /// Adds a field, formatted with `Display`. Numbers come out as they are;
+    /// text is quoted where needed.
+    pub fn field(&mut self, value: impl fmt::Display) -> &mut Self {
+        let mut text = Cursor {
+            buf: [0; MAX_ROW],
+            len: 0,
+        };
+        if write!(text, "{}", value).is_err() {
+            self.too_long = true;
+        }
+        let text = &text.buf[..text.len];
+
+        if self.fields > 0 {
+            self.push(b',');
+        }
+        self.fields += 1;
+        let quoted = text.iter().any(|b| b",\"\r\n".contains(b))
+            || text.first() == Some(&b' ')
+            || text.last() == Some(&b' ');
+        if quoted {
+            self.push(b'"');
+        }
+        for &b in text {
+            if b == b'"' {
+                self.push(b'"');
+            }
+            self.push(b);
+        }
+        if quoted {
+            self.push(b'"');
+        }
+        self
+    }
+
+    /// Adds an empty field, for a value that's missing.
+    pub fn empty(&mut self) -> &mut Self {
+        self.field("")
+    }
+
+    pub fn fields(&self) -> usize {
+        self.fields
+    }
+
+    /// The finished row, with its line ending.
+    pub fn as_bytes(&mut self) -> Result<&[u8], RowError> {
+        // Leave room for the CRLF as fields are added, then fill it in here
+        if self.too_long || self.len + 2 > MAX_ROW {
+            return Err(RowError::TooLong);
+        }
+        self.buf[self.len..self.len + 2].copy_from_slice(b"\r\n");
+        Ok(&self.buf[..self.len + 2])
+    }
+
+    fn push(&mut self, b: u8) {
+        match self.buf.get_mut(self.len) {
+            Some(slot) => {
+                *slot = b;
+                self.len += 1;
+            }
+            None => self.too_long = true,
+        }
+    }
+}
+
+/// Formats into a fixed buffer.
+struct Cursor {
+    buf: [u8; MAX_ROW],
+    len: usize,
+}
+
+impl Write for Cursor {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        let slot = self.buf.get_mut(self.len..end).ok_or(fmt::Error)?;
+        slot.copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+//! Counting the free clusters on a FAT16 or FAT32 volume.
+//!
+//! The file system code doesn't say how much room is left, so this works it
+//! out from the blocks themselves: the card's first block gives where the
+//! volume starts, the volume's boot sector gives where its FAT is, and each
+//! block of the FAT fed to [`FreeClusters`] adds up the entries that are
+//! zero. Reading the blocks is left to the caller.
+
+/// Size of a block on the card.
+pub const BLOCK: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatError {
+    /// Neither a boot sector nor a partition table with a FAT partition.
+    NoVolume,
+    /// The boot sector doesn't describe a FAT volume this can read.
+    BadBootSector,
+    /// FAT12, only used on floppy sized volumes.
+    Fat12,
+}
+
+impl FatError {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            FatError::NoVolume => "No FAT volume",
+            FatError::BadBootSector => "Bad boot sector",
+            FatError::Fat12 => "FAT12 not supported",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FatType {
+    Fat16,
+    Fat32,
+}
+
+/// Where a volume's FAT is and how big its clusters are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FatLayout {
+    pub fat_type: FatType,
+    /// Block of the first FAT, counted from the start of the card.
+    pub fat_start: u32,
+    /// Data clusters on the volume.
+    pub clusters: u32,
+    pub cluster_bytes: u32,
+}
+
+impl FatLayout {
+    /// Finds the first volume from the card's first block: a partition
+    /// table, or the boot sector itself on cards formatted without one.
+    /// Returns the block the volume starts at.
+    pub fn volume_start(block0: &[u8; BLOCK]) -> Result<u32, FatError> {
+        if block0[510..] != [0x55, 0xAA] {
+            return Err(FatError::NoVolume);
+        }
+        if is_boot_sector(block0) {
+            return Ok(0);
+        }
+        // The first of the four partition entries
+        let entry = &block0[446..462];
+        match entry[4] {
+            // FAT16 and FAT32, with and without LBA
+            0x04 | 0x06 | 0x0B | 0x0C | 0x0E => Ok(le32(entry, 8)),
+            _ => Err(FatError::NoVolume),
+        }
+    }
+
+    /// Reads the boot sector of a volume that starts at block `start`.
+    pub fn parse(start: u32, boot: &[u8; BLOCK]) -> Result<FatLayout, FatError> {
+        if !is_boot_sector(boot) || boot[510..] != [0x55, 0xAA] {
+            return Err(FatError::BadBootSector);
+        }
+        let per_cluster = u32::from(boot[13]);
+        let reserved = u32::from(le16(boot, 14));
+        let fats = u32::from(boot[16]);
+        let root_entries = u32::from(le16(boot, 17));
+        let total = match le16(boot, 19) {
+            0 => le32(boot, 32),
+            n => u32::from(n),
+        };
+        let fat_size = match le16(boot, 22) {
+            0 => le32(boot, 36),
+            n => u32::from(n),
+        };
+        if !per_cluster.is_power_of_two() || reserved == 0 || fats == 0 || fat_size == 0 {
+            return Err(FatError::BadBootSector);
+        }
+
+        let root_blocks = (root_entries * 32).div_ceil(BLOCK as u32);
+        let meta = reserved + fats * fat_size + root_blocks;
+        let clusters = total.checked_sub(meta).ok_or(FatError::BadBootSector)? / per_cluster;
+        // The cluster count alone decides the type
+        let fat_type = match clusters {
+            0..4085 => return Err(FatError::Fat12),
+            4085..65525 => FatType::Fat16,
+            _ => FatType::Fat32,
+        };
+        let layout = FatLayout {
+            fat_type,
+            fat_start: start + reserved,
+            clusters,
+            cluster_bytes: per_cluster * BLOCK as u32,
+        };
+        if layout.fat_blocks() > fat_size {
+            return Err(FatError::BadBootSector);
+        }
+        Ok(layout)
+    }
+
+    /// Blocks of the FAT that hold entries for the volume's clusters.
+    pub fn fat_blocks(&self) -> u32 {
+        // Entries 0 and 1 are reserved, the data clusters start at 2
+        (self.clusters + 2).div_ceil(self.entries_per_block())
+    }
+
+    pub fn entries_per_block(&self) -> u32 {
+        match self.fat_type {
+            FatType::Fat16 => BLOCK as u32 / 2,
+            FatType::Fat32 => BLOCK as u32 / 4,
+        }
+    }
+
+    /// Space taken on the card by a file of `len` bytes.
+    pub fn size_on_disk(&self, len: u64) -> u64 {
+        len.div_ceil(u64::from(self.cluster_bytes)) * u64::from(self.cluster_bytes)
+    }
+}
+
+/// Adds up free clusters a FAT block at a time.
+#[derive(Debug, Clone)]
+pub struct FreeClusters {
+    layout: FatLayout,
+    /// Entries looked at so far, reserved ones included.
+    entries: u32,
+    free: u32,
+}
+
+impl FreeClusters {
+    pub fn new(layout: FatLayout) -> Self {
+        Self {
+            layout,
+            entries: 0,
+            free: 0,
+        }
+    }
+
+    /// The block to read next, counted from the start of the card. `None`
+    /// once the whole FAT has been fed in.
+    pub fn next_block(&self) -> Option<u32> {
+        let done = self.entries / self.layout.entries_per_block();
+        (self.entries < self.layout.clusters + 2).then(|| self.layout.fat_start + done)
+    }
+
+    /// Counts the entries in the block [`next_block`](Self::next_block)
+    /// asked for.
+    pub fn feed(&mut self, block: &[u8; BLOCK]) {
+        let end = self.layout.clusters + 2;
+        for i in 0..self.layout.entries_per_block() {
+            let entry = self.entries;
+            if entry >= end {
+                break;
+            }
+            self.entries += 1;
+            if entry < 2 {
+                continue;
+            }
+            let at = i as usize;
+            let value = match self.layout.fat_type {
+                FatType::Fat16 => u32::from(le16(block, at * 2)),
+                // The top four bits are reserved
+                FatType::Fat32 => le32(block, at * 4) & 0x0FFF_FFFF,
+            };
+            if value == 0 {
+                self.free += 1;
+            }
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.next_block().is_none()
+    }
+
+    pub fn free_clusters(&self) -> u32 {
+        self.free
+    }
+
+    pub fn free_bytes(&self) -> u64 {
+        u64::from(self.free) * u64::from(self.layout.cluster_bytes)
+    }
+}
+
+/// A jump instruction and 512 byte sectors, as a FAT boot sector starts.
+fn is_boot_sector(block: &[u8; BLOCK]) -> bool {
+    matches!(block[0], 0xEB | 0xE9) && le16(block, 11) == BLOCK as u16
+}
+
+fn le16(block: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([block[at], block[at + 1]])
+}
+
+fn le32(block: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([block[at], block[at + 1], block[at + 2], block[at + 3]])
+}
@@ -0,0 +1,14 @@
+//! CSV data logging to a file on the SD card.
+//!
+//! [`csv`] formats rows and [`logger`] collects them into 512 byte sectors
+//! and decides when to flush them to the card. [`rotate`] spreads the log
+//! over numbered files and picks old ones to delete, and [`fat`] works out
+//! the card's free space. The file itself is behind the
+//! [`Sink`](logger::Sink) trait, so this is plain `no_std` code that can be
+//! checked on the host.
+#![no_std]
+
+pub mod csv;
+pub mod fat;
+pub mod logger;
+pub mod rotate;
@@ -0,0 +1,224 @@
+//! Appending rows to a log file a sector at a time.
+//!
+//! Rows collect in a 512 byte buffer that's written out when it reaches the
+//! end of a sector of the file, so the card mostly sees whole, aligned
+//! sectors. What's written isn't safe from a power loss until the file is
+//! synced, which also updates its size in the directory; the
+//! [`FlushPolicy`] decides how often that happens, trading card wear for how
+//! much data a power cut can take.
+
+use crate::csv::{Row, RowError};
+
+/// SD cards read and write in sectors of this size.
+pub const SECTOR: usize = 512;
+
+/// Where the log goes, normally a file opened for appending.
+pub trait Sink {
+    type Error;
+
+    /// Writes to the end of the file.
+    fn append(&mut self, data: &[u8]) -> Result<(), Self::Error>;
+
+    /// Makes everything appended so far survive a power loss.
+    fn sync(&mut self) -> Result<(), Self::Error>;
+}
+
+/// When buffered rows are flushed to the card and synced. Whichever limit
+/// is reached first triggers it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlushPolicy {
+    /// Rows kept in memory at most.
+    pub max_rows: u32,
+    /// How long a row stays in memory at most, in milliseconds.
+    pub max_age_ms: u32,
+}
+
+impl FlushPolicy {
+    /// A flush every 10 seconds, or every 50 rows when logging faster.
+    pub const DEFAULT: FlushPolicy = FlushPolicy {
+        max_rows: 50,
+        max_age_ms: 10_000,
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogError<E> {
+    Sink(E),
+    Row(RowError),
+    /// The row has a different number of fields than the header.
+    Columns {
+        expected: usize,
+        got: usize,
+    },
+}
+
+impl<E> From<RowError> for LogError<E> {
+    fn from(e: RowError) -> Self {
+        LogError::Row(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LogStats {
+    pub rows: u32,
+    /// Writes to the card, whole sectors or not.
+    pub writes: u32,
+    pub syncs: u32,
+}
+
+pub struct Logger<S> {
+    sink: S,
+    buf: [u8; SECTOR],
+    len: usize,
+    /// Bytes in the file, not counting the buffer.
+    written: u32,
+    columns: usize,
+    policy: FlushPolicy,
+    /// Rows in the buffer or written but not synced yet.
+    pending: u32,
+    /// When the oldest of those was logged.
+    oldest_ms: u64,
+    stats: LogStats,
+}
+
+impl<S: Sink> Logger<S> {
+    /// Starts logging to `sink`, a file already `file_len` bytes long. A new,
+    /// empty file gets `header` as its first row, synced straight away; an
+    /// existing one is assumed to have it.
+    pub fn open(
+        sink: S,
+        file_len: u32,
+        header: &[&str],
+        policy: FlushPolicy,
+    ) -> Result<Self, LogError<S::Error>> {
+        let mut logger = Logger {
+            sink,
+            buf: [0; SECTOR],
+            len: 0,
+            written: file_len,
+            columns: header.len(),
+            policy,
+            pending: 0,
+            oldest_ms: 0,
+            stats: LogStats::default(),
+        };
+        if file_len == 0 {
+            logger.buffer(Row::header(header).as_bytes()?)?;
+            logger.flush()?;
+        }
+        Ok(logger)
+    }
+
+    /// Adds a row, flushing if that reaches the row limit or the oldest row
+    /// is due. A row that fails is not logged, and the rows before it stay
+    /// buffered for the next try.
+    pub fn log(&mut self, row: &mut Row, now_ms: u64) -> Result<(), LogError<S::Error>> {
+        if row.fields() != self.columns {
+            return Err(LogError::Columns {
+                expected: self.columns,
+                got: row.fields(),
+            });
+        }
+        self.buffer(row.as_bytes()?)?;
+        if self.pending == 0 {
+            self.oldest_ms = now_ms;
+        }
+        self.pending += 1;
+        self.stats.rows += 1;
+
+        if self.pending >= self.policy.max_rows {
+            self.flush()?;
+        } else {
+            self.poll(now_ms)?;
+        }
+        Ok(())
+    }
+
+    /// Flushes if the oldest row has waited long enough. Call this now and
+    /// then when rows come in slowly. Returns whether it flushed.
+    pub fn poll(&mut self, now_ms: u64) -> Result<bool, LogError<S::Error>> {
+        let due = self.pending > 0
+            && now_ms.saturating_sub(self.oldest_ms) >= u64::from(self.policy.max_age_ms);
+        if due {
+            self.flush()?;
+        }
+        Ok(due)
+    }
+
+    /// Writes out the buffer and syncs the file.
+    pub fn flush(&mut self) -> Result<(), LogError<S::Error>> {
+        if self.len > 0 {
+            self.write_buffer()?;
+        }
+        self.sink.sync().map_err(LogError::Sink)?;
+        self.stats.syncs += 1;
+        self.pending = 0;
+        Ok(())
+    }
+
+    /// The file's length with the buffer written out.
+    pub fn file_len(&self) -> u32 {
+        self.written + self.len as u32
+    }
+
+    /// Rows that a power loss now would take.
+    pub fn pending(&self) -> u32 {
+        self.pending
+    }
+
+    pub fn stats(&self) -> LogStats {
+        self.stats
+    }
+
+    pub fn sink(&self) -> &S {
+        &self.sink
+    }
+
+    pub fn sink_mut(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    /// Flushes and gives back the sink.
+    pub fn close(mut self) -> Result<S, LogError<S::Error>> {
+        self.flush()?;
+        Ok(self.sink)
+    }
+
+    /// Room left before the buffer reaches the end of the file's current
+    /// sector.
+    fn room(&self) -> usize {
+        SECTOR - (self.written as usize + self.len) % SECTOR
+    }
+
+    /// Copies `data` into the buffer, writing the buffer out each time it
+    /// reaches a sector boundary. On failure nothing of `data` stays buffered.
+    fn buffer(&mut self, mut data: &[u8]) -> Result<(), LogError<S::Error>> {
+        let mut copied = 0;
+        while !data.is_empty() {
+            let n = self.room().min(data.len());
+            self.buf[self.len..self.len + n].copy_from_slice(&data[..n]);
+            self.len += n;
+            copied += n;
+            data = &data[n..];
+            if self.room() == SECTOR {
+                if let Err(e) = self.write_buffer() {
+                    // Nothing was written, so this is still all in the buffer
+                    self.len -= copied;
+                    return Err(e);
+                }
+                copied = 0;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_buffer(&mut self) -> Result<(), LogError<S::Error>> {
+        self.sink
+            .append(&self.buf[..self.len])
+            .map_err(LogError::Sink)?;
+        self.written += self.len as u32;
+        self.len = 0;
+        self.stats.writes += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,226 @@
+//! Spreading a log over numbered files, `LOG0001.CSV` to `LOG9999.CSV`.
+//!
+//! A new file is started when the current one reaches a size limit or a new
+//! day begins, and the oldest files are deleted to keep the logs under a
+//! total size and leave free space on the card. After `LOG9999.CSV` the
+//! numbers start again from `LOG0001.CSV`, which is long deleted by then.
+
+use core::fmt;
+
+/// Highest file number.
+pub const MAX_NUMBER: u16 = 9999;
+
+/// Number of bytes in the bitmap of existing files.
+const BITMAP: usize = (MAX_NUMBER as usize).div_ceil(8);
+
+/// The name of a log file, `LOG0001.CSV` to `LOG9999.CSV`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct LogName {
+    number: u16,
+    text: [u8; 11],
+}
+
+impl LogName {
+    /// `number` from 1 to [`MAX_NUMBER`].
+    pub fn new(number: u16) -> Option<LogName> {
+        if !(1..=MAX_NUMBER).contains(&number) {
+            return None;
+        }
+        let mut text = *b"LOG0000.CSV";
+        let mut n = number;
+        for slot in text[3..7].iter_mut().rev() {
+            *slot = b'0' + (n % 10) as u8;
+            n /= 10;
+        }
+        Some(LogName { number, text })
+    }
+
+    /// Recognises a log file's name, as it's stored: upper case 8.3.
+    pub fn parse(name: &str) -> Option<LogName> {
+        let digits = name.strip_prefix("LOG")?.strip_suffix(".CSV")?;
+        if digits.len() != 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        LogName::new(digits.parse().ok()?)
+    }
+
+    pub fn number(&self) -> u16 {
+        self.number
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only ASCII gets in
+        core::str::from_utf8(&self.text).unwrap_or("")
+    }
+
+    /// The next name, going back to `LOG0001.CSV` after `LOG9999.CSV`.
+    pub fn next(&self) -> LogName {
+        // In range by construction
+        LogName::new(after(self.number)).unwrap_or(*self)
+    }
+}
+
+impl fmt::Display for LogName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for LogName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+/// When to start a new file and how much space the logs may take.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RotatePolicy {
+    /// Largest a file gets. Rows aren't split, so a file stops short of this.
+    pub max_file_bytes: u32,
+    /// Start a new file when the date changes, if the clock is set.
+    pub daily: bool,
+    /// The most all log files together may take.
+    pub max_total_bytes: u64,
+    /// Free space to leave on the card, for other files and the file system.
+    pub min_free_bytes: u64,
+}
+
+impl RotatePolicy {
+    /// Daily files of up to 1 MiB, 64 MiB of logs in all and 1 MiB left free.
+    pub const DEFAULT: RotatePolicy = RotatePolicy {
+        max_file_bytes: 1 << 20,
+        daily: true,
+        max_total_bytes: 64 << 20,
+        min_free_bytes: 1 << 20,
+    };
+
+    /// Whether a row of `row_len` bytes should go to a new file rather than
+    /// the current one, which is `file_len` bytes long and was started at
+    /// `opened_day`. Days are counted from any fixed date, and are `None`
+    /// while the clock isn't set.
+    pub fn rotation_due(
+        &self,
+        file_len: u32,
+        row_len: usize,
+        opened_day: Option<u32>,
+        today: Option<u32>,
+    ) -> bool {
+        let too_big = u64::from(file_len) + row_len as u64 > u64::from(self.max_file_bytes);
+        let new_day = match (opened_day, today) {
+            (Some(opened), Some(today)) => self.daily && opened != today,
+            _ => false,
+        };
+        too_big || new_day
+    }
+}
+
+/// What a scan of the directory found of the log files.
+#[derive(Clone)]
+pub struct LogFiles {
+    present: [u8; BITMAP],
+    count: u16,
+    total_bytes: u64,
+}
+
+impl Default for LogFiles {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogFiles {
+    pub fn new() -> Self {
+        Self {
+            present: [0; BITMAP],
+            count: 0,
+            total_bytes: 0,
+        }
+    }
+
+    /// Adds a directory entry. Anything that isn't a log file is skipped.
+    pub fn add(&mut self, name: &str, size: u32) {
+        let Some(name) = LogName::parse(name) else {
+            return;
+        };
+        let (byte, bit) = Self::slot(name.number);
+        if self.present[byte] & bit == 0 {
+            self.present[byte] |= bit;
+            self.count += 1;
+            self.total_bytes += u64::from(size);
+        }
+    }
+
+    pub fn count(&self) -> u16 {
+        self.count
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.total_bytes
+    }
+
+    pub fn contains(&self, number: u16) -> bool {
+        let (byte, bit) = Self::slot(number);
+        (1..=MAX_NUMBER).contains(&number) && self.present[byte] & bit != 0
+    }
+
+    /// The oldest and newest files. Numbers wrap around, so the files are
+    /// taken as a run that starts after the widest gap in the numbers.
+    pub fn range(&self) -> Option<(LogName, LogName)> {
+        let first = (1..=MAX_NUMBER).find(|&n| self.contains(n))?;
+
+        // Walk once round from the first file, back to it. With no gap at
+        // all, every number is taken and the run starts at 1.
+        let (mut oldest, mut newest) = (first, MAX_NUMBER);
+        let (mut gap, mut widest, mut last) = (0, 0, first);
+        let mut n = first;
+        for _ in 0..MAX_NUMBER {
+            n = after(n);
+            if self.contains(n) {
+                if gap > widest {
+                    widest = gap;
+                    oldest = n;
+                    newest = last;
+                }
+                gap = 0;
+                last = n;
+            } else {
+                gap += 1;
+            }
+        }
+        Some((LogName::new(oldest)?, LogName::new(newest)?))
+    }
+
+    /// The name for a new file: after the newest, or `LOG0001.CSV` if there
+    /// are none. `None` if every number is taken.
+    pub fn next_name(&self) -> Option<LogName> {
+        match self.range() {
+            None => LogName::new(1),
+            Some((_, newest)) => Some(newest.next()).filter(|n| !self.contains(n.number)),
+        }
+    }
+
+    /// The file to delete to get within the policy's limits, given the
+    /// card's free space. The newest file, the one being written, is never
+    /// picked.
+    pub fn to_delete(&self, policy: &RotatePolicy, free_bytes: u64) -> Option<LogName> {
+        let over = self.total_bytes > policy.max_total_bytes || free_bytes < policy.min_free_bytes;
+        if !over || self.count < 2 {
+            return None;
+        }
+        self.range().map(|(oldest, _)| oldest)
+    }
+
+    fn slot(number: u16) -> (usize, u8) {
+        let i = usize::from(number.saturating_sub(1)) % (BITMAP * 8);
+        (i / 8, 1 << (i % 8))
+    }
+}
+
+/// The number after `n`, wrapping round.
+fn after(n: u16) -> u16 {
+    if n >= MAX_NUMBER {
+        1
+    } else {
+        n + 1
+    }
+}
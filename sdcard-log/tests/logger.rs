@@ -0,0 +1,559 @@
+//! Runs the CSV formatting and the logger on the host, against a file in
+//! memory that keeps track of what a power cut would leave.
+
+use sdcard_log::csv::{Row, RowError, MAX_ROW};
+use sdcard_log::fat::{FatError, FatLayout, FatType, FreeClusters, BLOCK};
+use sdcard_log::logger::{FlushPolicy, LogError, LogStats, Logger, Sink, SECTOR};
+use sdcard_log::rotate::{LogFiles, LogName, RotatePolicy, MAX_NUMBER};
+
+const HEADER: [&str; 3] = ["time", "distance_cm", "note"];
+
+fn row_text(row: &mut Row) -> Result<String, RowError> {
+    row.as_bytes()
+        .map(|bytes| String::from_utf8(bytes.to_vec()).unwrap())
+}
+
+#[test]
+fn fields() {
+    let mut row = Row::new();
+    row.field(12)
+        .field(-3.25f32)
+        .field("ok")
+        .empty()
+        .field(true);
+    assert_eq!(row.fields(), 5, "fields");
+    assert_eq!(
+        row_text(&mut row),
+        Ok("12,-3.25,ok,,true\r\n".into()),
+        "row"
+    );
+
+    row.clear();
+    assert_eq!(row_text(&mut row), Ok("\r\n".into()), "cleared");
+    row.field(format_args!("{:.1}", 17.04f32));
+    assert_eq!(row_text(&mut row), Ok("17.0\r\n".into()), "format_args");
+
+    let mut header = Row::header(&HEADER);
+    assert_eq!(
+        row_text(&mut header),
+        Ok("time,distance_cm,note\r\n".into()),
+        "header"
+    );
+}
+
+#[test]
+fn quoting() {
+    let cases = [
+        ("plain", "plain"),
+        ("a,b", "\"a,b\""),
+        ("say \"hi\"", "\"say \"\"hi\"\"\""),
+        ("two\nlines", "\"two\nlines\""),
+        ("cr\r", "\"cr\r\""),
+        (" padded", "\" padded\""),
+        ("padded ", "\"padded \""),
+        ("in side", "in side"),
+        ("\"", "\"\"\"\""),
+    ];
+    for (value, expected) in cases {
+        let mut row = Row::new();
+        row.field(value);
+        assert_eq!(row_text(&mut row), Ok(format!("{}\r\n", expected)), "{}", value);
+    }
+}
+
+#[test]
+fn long_rows() {
+    // Exactly fits with the CRLF
+    let mut row = Row::new();
+    row.field("x".repeat(MAX_ROW - 2));
+    assert_eq!(row_text(&mut row).map(|r| r.len()), Ok(MAX_ROW), "fits");
+
+    let mut row = Row::new();
+    row.field("x".repeat(MAX_ROW - 1));
+    assert_eq!(
+        row_text(&mut row),
+        Err(RowError::TooLong),
+        "no room for CRLF"
+    );
+
+    // Quotes make it longer than the value
+    let mut row = Row::new();
+    row.field("\"".repeat(MAX_ROW / 2));
+    assert_eq!(row_text(&mut row), Err(RowError::TooLong), "doubled quotes");
+
+    let mut row = Row::new();
+    row.field("x".repeat(MAX_ROW * 2));
+    assert_eq!(
+        row_text(&mut row),
+        Err(RowError::TooLong),
+        "longer than a row"
+    );
+
+    let mut row = Row::new();
+    for _ in 0..MAX_ROW {
+        row.field(1);
+    }
+    assert_eq!(
+        row_text(&mut row),
+        Err(RowError::TooLong),
+        "too many fields"
+    );
+    row.clear();
+    row.field(1);
+    assert_eq!(row_text(&mut row), Ok("1\r\n".into()), "usable after clear");
+}
+
+/// A file in memory. `synced` is how much of it a power cut would leave.
+#[derive(Default)]
+struct MemFile {
+    data: Vec<u8>,
+    synced: usize,
+    /// Where each append started and how long it was.
+    appends: Vec<(usize, usize)>,
+    syncs: u32,
+    fail: bool,
+}
+
+impl Sink for MemFile {
+    type Error = &'static str;
+
+    fn append(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        if self.fail {
+            return Err("card gone");
+        }
+        self.appends.push((self.data.len(), data.len()));
+        self.data.extend_from_slice(data);
+        Ok(())
+    }
+
+    fn sync(&mut self) -> Result<(), Self::Error> {
+        if self.fail {
+            return Err("card gone");
+        }
+        self.synced = self.data.len();
+        self.syncs += 1;
+        Ok(())
+    }
+}
+
+impl MemFile {
+    fn with(content: &str) -> MemFile {
+        MemFile {
+            data: content.as_bytes().to_vec(),
+            synced: content.len(),
+            ..MemFile::default()
+        }
+    }
+
+    fn text(&self) -> &str {
+        std::str::from_utf8(&self.data).unwrap()
+    }
+
+    fn after_power_cut(&self) -> &str {
+        std::str::from_utf8(&self.data[..self.synced]).unwrap()
+    }
+}
+
+type LogResult = Result<(), LogError<&'static str>>;
+
+fn open(file: MemFile, policy: FlushPolicy) -> Logger<MemFile> {
+    let len = file.data.len() as u32;
+    Logger::open(file, len, &HEADER, policy).unwrap()
+}
+
+fn log(logger: &mut Logger<MemFile>, i: u32, now_ms: u64) -> LogResult {
+    let mut row = Row::new();
+    row.field(now_ms).field(i).field("ok");
+    logger.log(&mut row, now_ms)
+}
+
+const NEVER: FlushPolicy = FlushPolicy {
+    max_rows: u32::MAX,
+    max_age_ms: u32::MAX,
+};
+
+#[test]
+fn header() {
+    let logger = open(MemFile::default(), NEVER);
+    let file = logger.sink();
+    assert_eq!(file.text(), "time,distance_cm,note\r\n", "new file");
+    assert_eq!(file.after_power_cut(), file.text(), "synced");
+
+    let existing = "time,distance_cm,note\r\n1,2,ok\r\n";
+    let mut logger = open(MemFile::with(existing), NEVER);
+    assert_eq!(logger.sink().text(), existing, "existing file");
+    assert_eq!(logger.file_len(), existing.len() as u32, "file_len");
+
+    let mut row = Row::new();
+    row.field(1).field(2);
+    assert_eq!(
+        logger.log(&mut row, 0),
+        Err(LogError::Columns {
+            expected: 3,
+            got: 2,
+        }),
+        "wrong columns"
+    );
+}
+
+#[test]
+fn sectors() {
+    // Start in the middle of a sector, like a file appended to before
+    let existing = format!("{}\r\n", "x".repeat(698));
+    let mut logger = open(MemFile::with(&existing), NEVER);
+    let mut expected = existing.clone();
+    for i in 0..200 {
+        log(&mut logger, i, u64::from(i)).unwrap();
+        expected.push_str(&format!("{},{},ok\r\n", i, i));
+    }
+    let buffered = logger.file_len() as usize - logger.sink().data.len();
+    assert_eq!(logger.file_len() as usize, expected.len(), "file_len");
+
+    let file = logger.sink();
+    let appends = &file.appends;
+    // The first write fills up the sector the file ended in
+    assert_eq!(appends[0], (700, 1024 - 700), "first write");
+    for &(start, len) in &appends[1..] {
+        if start % SECTOR != 0 || len != SECTOR {
+            panic!("write of {} at {} isn't a whole sector", len, start);
+        }
+    }
+    assert_eq!(file.syncs, 0, "nothing synced");
+    if buffered >= SECTOR {
+        panic!("{} bytes buffered", buffered);
+    }
+
+    let writes = appends.len() as u32;
+    let stats = logger.stats();
+    let file = logger.close().unwrap();
+    assert_eq!(file.text(), expected.as_str(), "content");
+    assert_eq!(file.after_power_cut(), file.text(), "synced");
+    assert_eq!(
+        stats,
+        LogStats {
+            rows: 200,
+            writes,
+            syncs: 0,
+        },
+        "stats"
+    );
+}
+
+#[test]
+fn flushing() {
+    let policy = FlushPolicy {
+        max_rows: 5,
+        max_age_ms: 1000,
+    };
+    let mut logger = open(MemFile::default(), policy);
+    let syncs = |logger: &Logger<MemFile>| logger.sink().syncs;
+    assert_eq!(syncs(&logger), 1, "header sync");
+
+    // Every fifth row
+    for i in 0..12 {
+        log(&mut logger, i, 0).unwrap();
+    }
+    assert_eq!(syncs(&logger), 3, "row limit");
+    assert_eq!(logger.pending(), 2, "pending");
+
+    // The two left get flushed once the first of them is a second old
+    assert_eq!(logger.poll(999), Ok(false), "too soon");
+    assert_eq!(logger.poll(1000), Ok(true), "due");
+    assert_eq!(syncs(&logger), 4, "age limit");
+    assert_eq!(logger.poll(5000), Ok(false), "nothing left");
+
+    // Logging slowly, the age is checked on every row too
+    log(&mut logger, 0, 10_000).unwrap();
+    log(&mut logger, 1, 10_500).unwrap();
+    assert_eq!(syncs(&logger), 4, "within a second");
+    log(&mut logger, 2, 11_000).unwrap();
+    assert_eq!(syncs(&logger), 5, "a second old");
+    assert_eq!(logger.pending(), 0, "flushed");
+}
+
+#[test]
+fn power_loss() {
+    let policy = FlushPolicy {
+        max_rows: 7,
+        max_age_ms: u32::MAX,
+    };
+    let mut logger = open(MemFile::default(), policy);
+    for i in 0..60 {
+        log(&mut logger, i, 0).unwrap();
+
+        // What's left after a cut is whole rows, and at most the unsynced ones
+        // are missing
+        let file = logger.sink();
+        let left = file.after_power_cut();
+        if !left.ends_with("\r\n") {
+            panic!("partial row after row {}", i);
+        }
+        let rows = left.lines().count() as u32 - 1;
+        assert_eq!(i + 1 - rows, logger.pending(), "rows after row {}", i);
+        if logger.pending() >= policy.max_rows {
+            panic!("{} rows pending", logger.pending());
+        }
+    }
+}
+
+#[test]
+fn write_errors() {
+    let mut logger = open(MemFile::default(), NEVER);
+    let mut i = 0;
+    // Fill up to just before the end of the first sector
+    while logger.file_len() < 500 {
+        log(&mut logger, i, 0).unwrap();
+        i += 1;
+    }
+    let before = logger.file_len();
+
+    // This row crosses into the next sector, so it needs a write
+    logger.sink_mut().fail = true;
+    assert_eq!(
+        log(&mut logger, i, 0),
+        Err(LogError::Sink("card gone")),
+        "write fails"
+    );
+    assert_eq!(logger.file_len(), before, "row dropped");
+    assert_eq!(
+        logger.flush(),
+        Err(LogError::Sink("card gone")),
+        "flush fails"
+    );
+
+    // Once the card is back, the rows buffered before are still there
+    logger.sink_mut().fail = false;
+    log(&mut logger, i, 0).unwrap();
+    let file = logger.close().unwrap();
+    let rows: Vec<&str> = file.text().lines().skip(1).collect();
+    assert_eq!(rows.len() as u32, i + 1, "rows");
+    assert_eq!(
+        rows.last().copied(),
+        Some(format!("0,{},ok", i).as_str()),
+        "last row"
+    );
+    let numbers: Vec<u32> = rows
+        .iter()
+        .map(|r| r.split(',').nth(1).unwrap().parse().unwrap())
+        .collect();
+    assert_eq!(numbers, (0..=i).collect::<Vec<_>>(), "in order");
+}
+
+#[test]
+fn file_names() {
+    let name = |n| LogName::new(n).map(|name| name.to_string());
+    assert_eq!(name(1), Some("LOG0001.CSV".into()), "first");
+    assert_eq!(name(MAX_NUMBER), Some("LOG9999.CSV".into()), "last");
+    assert_eq!(name(0), None, "zero");
+    assert_eq!(name(MAX_NUMBER + 1), None, "too big");
+    assert_eq!(
+        LogName::new(MAX_NUMBER).map(|n| n.next().number()),
+        Some(1),
+        "wraps"
+    );
+
+    let parse = |s| LogName::parse(s).map(|n| n.number());
+    assert_eq!(parse("LOG0042.CSV"), Some(42), "parse");
+    for other in [
+        "LOG0000.CSV",
+        "LOG042.CSV",
+        "LOG00042.CSV",
+        "LOG+042.CSV",
+        "LOG0042.TXT",
+        "DATA.CSV",
+        "log0042.csv",
+    ] {
+        assert_eq!(parse(other), None, "{}", other);
+    }
+}
+
+#[test]
+fn rotation() {
+    let policy = RotatePolicy {
+        max_file_bytes: 1000,
+        daily: true,
+        ..RotatePolicy::DEFAULT
+    };
+    assert!(!policy.rotation_due(990, 10, None, None), "fits");
+    assert!(policy.rotation_due(991, 10, None, None), "too big");
+    assert!(!policy.rotation_due(0, 10, Some(7), Some(7)), "same day");
+    assert!(policy.rotation_due(0, 10, Some(7), Some(8)), "new day");
+    assert!(!policy.rotation_due(0, 10, None, Some(8)), "clock not set");
+    let sized = RotatePolicy {
+        daily: false,
+        ..policy
+    };
+    assert!(!sized.rotation_due(0, 10, Some(7), Some(8)), "by size only");
+
+    let mut files = LogFiles::new();
+    assert_eq!(files.next_name(), LogName::new(1), "none yet");
+    files.add("DATA.CSV", 500);
+    files.add("LOG0003.CSV", 100);
+    files.add("LOG0004.CSV", 200);
+    files.add("LOG0004.CSV", 200);
+    assert_eq!(files.count(), 2, "count");
+    assert_eq!(files.total_bytes(), 300, "total");
+    assert_eq!(files.range(), LogName::new(3).zip(LogName::new(4)), "range");
+    assert_eq!(files.next_name(), LogName::new(5), "next");
+
+    // Past the last number, the run wraps round
+    let mut files = LogFiles::new();
+    for n in [MAX_NUMBER - 1, MAX_NUMBER, 1, 2] {
+        files.add(LogName::new(n).unwrap().as_str(), 1);
+    }
+    assert_eq!(
+        files.range(),
+        LogName::new(MAX_NUMBER - 1).zip(LogName::new(2)),
+        "wrapped range"
+    );
+    assert_eq!(files.next_name(), LogName::new(3), "wrapped next");
+
+    let mut files = LogFiles::new();
+    for n in 1..=MAX_NUMBER {
+        files.add(LogName::new(n).unwrap().as_str(), 1);
+    }
+    assert_eq!(
+        files.range(),
+        LogName::new(1).zip(LogName::new(MAX_NUMBER)),
+        "all taken"
+    );
+    assert_eq!(files.next_name(), None, "no next");
+}
+
+#[test]
+fn oldest_first() {
+    let policy = RotatePolicy {
+        max_total_bytes: 1000,
+        min_free_bytes: 100,
+        ..RotatePolicy::DEFAULT
+    };
+    // Delete as the firmware does, one at a time from a fresh scan
+    let mut sizes: Vec<(u16, u32)> = (10..20).map(|n| (n, 150)).collect();
+    let mut free = 1000;
+    let scan = |sizes: &[(u16, u32)]| {
+        let mut files = LogFiles::new();
+        for &(n, size) in sizes {
+            files.add(LogName::new(n).unwrap().as_str(), size);
+        }
+        files
+    };
+    let mut deleted = Vec::new();
+    while let Some(oldest) = scan(&sizes).to_delete(&policy, free) {
+        let at = sizes
+            .iter()
+            .position(|&(n, _)| n == oldest.number())
+            .unwrap();
+        free += u64::from(sizes.remove(at).1);
+        deleted.push(oldest.number());
+    }
+    assert_eq!(deleted, vec![10, 11, 12, 13], "over total");
+
+    // A nearly full card, with the logs well under their limit
+    let mut deleted = Vec::new();
+    free = 0;
+    while let Some(oldest) = scan(&sizes).to_delete(&policy, free) {
+        let at = sizes
+            .iter()
+            .position(|&(n, _)| n == oldest.number())
+            .unwrap();
+        free += u64::from(sizes.remove(at).1);
+        deleted.push(oldest.number());
+    }
+    assert_eq!(deleted, vec![14], "card full");
+
+    // The file being written is kept whatever happens
+    let files = scan(&[(19, 5000)]);
+    assert_eq!(files.to_delete(&policy, 0), None, "only file");
+}
+
+/// A FAT16 volume of `clusters` clusters of `per_cluster` blocks, behind a
+/// partition table. `used` clusters are taken from cluster 2 on.
+fn fat16_card(clusters: u32, per_cluster: u8, used: u32) -> Vec<[u8; BLOCK]> {
+    let start = 8u32;
+    let reserved = 4u16;
+    let fat_size = (clusters + 2).div_ceil(256) as u16;
+    let root_blocks = 32;
+    let total = u32::from(reserved)
+        + 2 * u32::from(fat_size)
+        + root_blocks
+        + clusters * u32::from(per_cluster);
+
+    let mut card = vec![[0u8; BLOCK]; (start + u32::from(reserved) + u32::from(fat_size)) as usize];
+    let mbr = &mut card[0];
+    mbr[446 + 4] = 0x06;
+    mbr[446 + 8..446 + 12].copy_from_slice(&start.to_le_bytes());
+    mbr[446 + 12..446 + 16].copy_from_slice(&total.to_le_bytes());
+    mbr[510..].copy_from_slice(&[0x55, 0xAA]);
+
+    let boot = &mut card[start as usize];
+    boot[..3].copy_from_slice(&[0xEB, 0x3C, 0x90]);
+    boot[11..13].copy_from_slice(&(BLOCK as u16).to_le_bytes());
+    boot[13] = per_cluster;
+    boot[14..16].copy_from_slice(&reserved.to_le_bytes());
+    boot[16] = 2;
+    boot[17..19].copy_from_slice(&512u16.to_le_bytes());
+    boot[22..24].copy_from_slice(&fat_size.to_le_bytes());
+    boot[32..36].copy_from_slice(&total.to_le_bytes());
+    boot[510..].copy_from_slice(&[0x55, 0xAA]);
+
+    // Reserved entries, then a chain through the used clusters
+    let fat = (start + u32::from(reserved)) as usize;
+    let mut set = |entry: u32, value: u16| {
+        let block = &mut card[fat + entry as usize / 256];
+        let at = (entry as usize % 256) * 2;
+        block[at..at + 2].copy_from_slice(&value.to_le_bytes());
+    };
+    set(0, 0xFFF8);
+    set(1, 0xFFFF);
+    for c in 2..used + 2 {
+        set(c, if c == used + 1 { 0xFFFF } else { c as u16 + 1 });
+    }
+    // The rest of the last FAT block is zero too, but isn't clusters
+    card
+}
+
+#[test]
+fn free_clusters() {
+    let card = fat16_card(5000, 4, 1234);
+    let start = FatLayout::volume_start(&card[0]).unwrap();
+    assert_eq!(start, 8, "volume start");
+    let layout = FatLayout::parse(start, &card[start as usize]).unwrap();
+    assert_eq!(
+        layout,
+        FatLayout {
+            fat_type: FatType::Fat16,
+            fat_start: 12,
+            clusters: 5000,
+            cluster_bytes: 2048,
+        },
+        "layout"
+    );
+    assert_eq!(layout.fat_blocks(), 20, "fat blocks");
+    assert_eq!(layout.size_on_disk(2049), 4096, "on disk");
+    assert_eq!(layout.size_on_disk(0), 0, "empty file");
+
+    let mut count = FreeClusters::new(layout);
+    let mut reads = 0;
+    while let Some(block) = count.next_block() {
+        count.feed(&card[block as usize]);
+        reads += 1;
+    }
+    assert_eq!(reads, 20, "reads");
+    assert_eq!(count.free_clusters(), 5000 - 1234, "free");
+    assert_eq!(count.free_bytes(), (5000 - 1234) * 2048, "free bytes");
+
+    // A card formatted without a partition table
+    let bare = &card[8];
+    assert_eq!(FatLayout::volume_start(bare), Ok(0), "no partitions");
+    assert_eq!(
+        FatLayout::volume_start(&[0; BLOCK]),
+        Err(FatError::NoVolume),
+        "blank card"
+    );
+    let small = fat16_card(4000, 1, 0);
+    assert_eq!(
+        FatLayout::parse(8, &small[8]),
+        Err(FatError::Fat12),
+        "FAT12"
+    );
+}
@@ -0,0 +1,643 @@
+//! Logs an HC-SR04 ultrasonic sensor and a joystick to the SD card once a
+//! second, and echoes each row over USB serial.
+//!
+//! The log goes to `LOG0001.CSV`, `LOG0002.CSV` and so on, a new file each
+//! day or when one reaches 1 MiB, and the oldest files are deleted to keep
+//! the logs to 64 MiB and leave 1 MiB of the card free, so a unit left in
+//! the field keeps logging when the card fills up.
+//!
+//! Wiring: SD card on SPI0 (GPIO1 CS, GPIO2 SCK, GPIO3 MOSI, GPIO4 MISO),
+//! HC-SR04 echo on GPIO16 and trigger on GPIO17, joystick VRx on GPIO27,
+//! VRy on GPIO26 and its button on GPIO15, and an optional DS3231 on I2C1
+//! (GPIO18 SDA, GPIO19 SCL). Send `date YYYY-MM-DD HH:MM[:SS]` to set the
+//! clock.
+#![no_std]
+#![no_main]
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal_0_2::adc::OneShot;
+use hal::block::ImageDef;
+use panic_halt as _;
+use rp235x_hal::{self as hal, Clock as _};
+
+use usb_device::{class_prelude::*, prelude::*};
+use usbd_serial::SerialPort;
+
+use hal::fugit::RateExtU32;
+use heapless::String;
+
+use core::fmt::Write;
+
+use embedded_hal_bus::spi::ExclusiveDevice;
+use embedded_sdmmc::{
+    Block, BlockDevice, BlockIdx, DirEntry, Directory, File, Mode, SdCard, TimeSource, Timestamp,
+    VolumeIdx, VolumeManager,
+};
+use hal::gpio::{FunctionI2C, Pin};
+
+use sdcard_clock::aon::AonClock;
+use sdcard_clock::date::{self, DateTime};
+use sdcard_clock::ds3231::Ds3231;
+use sdcard_info::spi;
+use sdcard_log::csv::Row;
+use sdcard_log::fat::{FatLayout, FreeClusters};
+use sdcard_log::logger::{FlushPolicy, LogError, Logger, Sink};
+use sdcard_log::rotate::{LogFiles, LogName, RotatePolicy};
+
+#[link_section = ".start_block"]
+#[used]
+pub static IMAGE_DEF: ImageDef = hal::block::ImageDef::secure_exe();
+
+const XTAL_FREQ_HZ: u32 = 12_000_000u32;
+
+const HEADER: [&str; 6] = [
+    "time",
+    "uptime_ms",
+    "distance_cm",
+    "joy_x",
+    "joy_y",
+    "button",
+];
+
+/// When to start a new file and how much of the card the logs may take.
+const ROTATE: RotatePolicy = RotatePolicy::DEFAULT;
+
+/// Time between samples.
+const SAMPLE_MS: u64 = 1000;
+
+/// How long to wait for an echo, a bit over the HC-SR04's 4 m range and back.
+const ECHO_TIMEOUT_US: u64 = 30_000;
+
+/// File dates from the AON timer. Until the clock is set files get
+/// 1980-01-01, the earliest date FAT can store.
+#[derive(Clone, Copy)]
+struct Clock<'a>(&'a AonClock);
+
+impl TimeSource for Clock<'_> {
+    fn get_timestamp(&self) -> Timestamp {
+        let now = self.0.now().unwrap_or(DateTime::FAT_MIN).fat_clamped();
+        Timestamp {
+            year_since_1970: (now.year - 1970) as u8,
+            zero_indexed_month: now.month - 1,
+            zero_indexed_day: now.day - 1,
+            hours: now.hour,
+            minutes: now.minute,
+            seconds: now.second,
+        }
+    }
+}
+
+/// The log file, opened for appending.
+struct LogFile<'a, D: BlockDevice, T: TimeSource>(File<'a, D, T, 4, 4, 1>);
+
+impl<D: BlockDevice, T: TimeSource> Sink for LogFile<'_, D, T> {
+    type Error = embedded_sdmmc::Error<D::Error>;
+
+    fn append(&mut self, data: &[u8]) -> Result<(), Self::Error> {
+        self.0.write(data)
+    }
+
+    /// Writes out the cached blocks and the file's new size.
+    fn sync(&mut self) -> Result<(), Self::Error> {
+        self.0.flush()
+    }
+}
+
+/// The file being logged to.
+struct Current<'a, D: BlockDevice, T: TimeSource> {
+    logger: Logger<LogFile<'a, D, T>>,
+    name: LogName,
+    /// The day the file was started, if the clock was set.
+    day: Option<u32>,
+    /// How long the file was when [`Space`] last took it into account.
+    counted: u32,
+}
+
+/// Free space on the card. Counting it means reading the whole FAT, so it's
+/// done once at boot and then kept up to date from what's written and
+/// deleted. If the FAT can't be read, only the cap on the logs' total size
+/// applies.
+struct Space {
+    layout: Option<FatLayout>,
+    free: u64,
+}
+
+impl Space {
+    const UNKNOWN: Space = Space {
+        layout: None,
+        free: u64::MAX,
+    };
+
+    /// Counts the free clusters. Call it with no files open, so that the
+    /// FAT on the card is up to date.
+    fn count<D: BlockDevice, T: TimeSource>(
+        volume_mgr: &mut VolumeManager<D, T>,
+    ) -> Result<Space, &'static str> {
+        const NO_CARD: &str = "SD card not responding";
+        let device = volume_mgr.device();
+        let mut block = [Block::new()];
+        device.read(&mut block, BlockIdx(0)).map_err(|_| NO_CARD)?;
+        let start = FatLayout::volume_start(&block[0].contents).map_err(|e| e.as_str())?;
+        device
+            .read(&mut block, BlockIdx(start))
+            .map_err(|_| NO_CARD)?;
+        let layout = FatLayout::parse(start, &block[0].contents).map_err(|e| e.as_str())?;
+
+        let mut free = FreeClusters::new(layout);
+        while let Some(i) = free.next_block() {
+            device.read(&mut block, BlockIdx(i)).map_err(|_| NO_CARD)?;
+            free.feed(&block[0].contents);
+        }
+        Ok(Space {
+            layout: Some(layout),
+            free: free.free_bytes(),
+        })
+    }
+
+    fn on_disk(&self, len: u32) -> u64 {
+        self.layout
+            .map_or(u64::from(len), |l| l.size_on_disk(u64::from(len)))
+    }
+
+    /// A file grew from `from` to `to` bytes.
+    fn written(&mut self, from: u32, to: u32) {
+        let used = self.on_disk(to).saturating_sub(self.on_disk(from));
+        self.free = self.free.saturating_sub(used);
+    }
+
+    /// A file of `len` bytes was deleted.
+    fn freed(&mut self, len: u32) {
+        self.free = self.free.saturating_add(self.on_disk(len));
+    }
+}
+
+#[hal::entry]
+fn main() -> ! {
+    let mut pac = hal::pac::Peripherals::take().unwrap();
+    let mut watchdog = hal::Watchdog::new(pac.WATCHDOG);
+
+    let clocks = hal::clocks::init_clocks_and_plls(
+        XTAL_FREQ_HZ,
+        pac.XOSC,
+        pac.CLOCKS,
+        pac.PLL_SYS,
+        pac.PLL_USB,
+        &mut pac.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+    let mut timer = hal::Timer::new_timer0(pac.TIMER0, &mut pac.RESETS, &clocks);
+
+    let sio = hal::Sio::new(pac.SIO);
+    let pins = hal::gpio::Pins::new(
+        pac.IO_BANK0,
+        pac.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut pac.RESETS,
+    );
+
+    let usb_bus = UsbBusAllocator::new(hal::usb::UsbBus::new(
+        pac.USB,
+        pac.USB_DPRAM,
+        clocks.usb_clock,
+        true,
+        &mut pac.RESETS,
+    ));
+
+    let mut serial = SerialPort::new(&usb_bus);
+
+    let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x16c0, 0x27dd))
+        .strings(&[StringDescriptors::default()
+            .manufacturer("implRust")
+            .product("Ferris")
+            .serial_number("TEST")])
+        .unwrap()
+        .device_class(2) // 2 for the CDC, from: https://www.usb.org/defined-class-codes
+        .build();
+
+    // An optional DS3231 RTC module on I2C1
+    let sda_pin: Pin<_, FunctionI2C, _> = pins.gpio18.reconfigure();
+    let scl_pin: Pin<_, FunctionI2C, _> = pins.gpio19.reconfigure();
+    let i2c = hal::I2C::i2c1(
+        pac.I2C1,
+        sda_pin,
+        scl_pin,
+        400.kHz(),
+        &mut pac.RESETS,
+        &clocks.system_clock,
+    );
+    let mut ds3231 = Ds3231::new(i2c);
+
+    // The AON timer keeps the time across resets. After a power cycle, take
+    // it from the DS3231 if there is one and it's been set.
+    let aon = AonClock::new(pac.POWMAN, XTAL_FREQ_HZ);
+    if !aon.is_set() {
+        if let Ok(Some(now)) = ds3231.now() {
+            aon.set(&now);
+        }
+    }
+
+    let mut echo = pins.gpio16.into_pull_down_input();
+    let mut trigger = pins.gpio17.into_push_pull_output();
+
+    let mut btn = pins.gpio15.into_pull_up_input();
+    let mut adc = hal::Adc::new(pac.ADC, &mut pac.RESETS);
+    let mut vrx_pin = hal::adc::AdcPin::new(pins.gpio27).unwrap();
+    let mut vry_pin = hal::adc::AdcPin::new(pins.gpio26).unwrap();
+
+    let spi_cs = pins.gpio1.into_push_pull_output();
+    let spi_sck = pins.gpio2.into_function::<hal::gpio::FunctionSpi>();
+    let spi_mosi = pins.gpio3.into_function::<hal::gpio::FunctionSpi>();
+    let spi_miso = pins.gpio4.into_function::<hal::gpio::FunctionSpi>();
+    let spi_bus = hal::spi::Spi::<_, _, _, 8>::new(pac.SPI0, (spi_mosi, spi_miso, spi_sck));
+
+    let spi = spi_bus.init(
+        &mut pac.RESETS,
+        clocks.peripheral_clock.freq(),
+        400.kHz(), // card initialization happens at low baud rate
+        embedded_hal::spi::MODE_0,
+    );
+
+    let spi = ExclusiveDevice::new(spi, spi_cs, timer).unwrap();
+    let sdcard = SdCard::new(spi, timer);
+    let mut volume_mgr = VolumeManager::new(sdcard, Clock(&aon));
+
+    // Give the host a moment to open the port, so errors can be seen
+    let start = timer.get_counter().ticks();
+    while timer.get_counter().ticks() - start < 2_000_000 {
+        let _ = usb_dev.poll(&mut [&mut serial]);
+    }
+
+    // The card starts at 400 kHz; once it's up, run it as fast as it goes
+    let peri_hz = clocks.peripheral_clock.freq();
+    let identified = volume_mgr
+        .device()
+        .num_bytes()
+        .map_err(|_| "SD card not responding")
+        .and_then(|_| {
+            volume_mgr
+                .device()
+                .spi(|dev| {
+                    // SPI runs at up to half the peripheral clock
+                    spi::identify(dev, peri_hz.to_Hz() / 2, |dev, hz| {
+                        dev.bus_mut().set_baudrate(peri_hz, hz.Hz()).to_Hz()
+                    })
+                })
+                .map_err(|e| e.as_str())
+        });
+    match identified {
+        Ok(info) => {
+            let mut report: String<256> = String::new();
+            write!(report, "{}", info).unwrap();
+            for part in report.split_inclusive("\r\n") {
+                let _ = serial.write(part.as_bytes());
+            }
+        }
+        Err(e) => say(&mut serial, e),
+    }
+
+    let mut buff: String<96> = String::new();
+    say(&mut serial, "Counting free space on the card");
+    let mut space = match Space::count(&mut volume_mgr) {
+        Ok(space) => {
+            write!(buff, "{} KiB free", space.free / 1024).unwrap();
+            say(&mut serial, &buff);
+            buff.clear();
+            space
+        }
+        Err(e) => {
+            say(&mut serial, e);
+            Space::UNKNOWN
+        }
+    };
+
+    let mut volume = match volume_mgr.open_volume(VolumeIdx(0)) {
+        Ok(volume) => volume,
+        Err(_) => halt(&mut usb_dev, &mut serial, "No FAT volume on the card"),
+    };
+    let mut root_dir = match volume.open_root_dir() {
+        Ok(dir) => dir,
+        Err(_) => halt(&mut usb_dev, &mut serial, "Can't open the root directory"),
+    };
+
+    // Carry on with the newest file, or start the first one
+    let newest = scan(&mut root_dir)
+        .ok()
+        .and_then(|files| files.range())
+        .map(|(_, newest)| newest);
+    let opened = match newest {
+        Some(name) => {
+            let day = root_dir
+                .find_directory_entry(name.as_str())
+                .ok()
+                .and_then(|entry| created_day(&entry));
+            open(&mut root_dir, name, day)
+        }
+        None => open_next(&mut root_dir, &mut space, today(&aon)),
+    };
+    let mut current = match opened {
+        Ok(current) => Some(current),
+        Err(e) => {
+            say(&mut serial, e);
+            None
+        }
+    };
+
+    let mut line: String<32> = String::new();
+    let mut row = Row::new();
+    let mut next_sample = 0u64;
+
+    loop {
+        if usb_dev.poll(&mut [&mut serial]) {
+            let mut buf = [0u8; 64];
+            let count = serial.read(&mut buf).unwrap_or(0);
+            for &byte in &buf[..count] {
+                if byte != b'\r' && byte != b'\n' {
+                    let _ = line.push(byte as char);
+                    continue;
+                }
+                if line.is_empty() {
+                    continue;
+                }
+                // date YYYY-MM-DD HH:MM[:SS] sets the clock
+                match line
+                    .strip_prefix("date ")
+                    .and_then(|t| date::parse(t.trim()))
+                {
+                    Some(time) => {
+                        aon.set(&time);
+                        write!(buff, "Clock set to {}\r\n", time).unwrap();
+                        if let Err(e) = ds3231.set(&time) {
+                            write!(buff, "DS3231: {}\r\n", e.as_str()).unwrap();
+                        }
+                    }
+                    None => buff
+                        .push_str("Send date YYYY-MM-DD HH:MM[:SS]\r\n")
+                        .unwrap(),
+                }
+                let _ = serial.write(buff.as_bytes());
+                buff.clear();
+                line.clear();
+            }
+        }
+
+        let now_ms = timer.get_counter().ticks() / 1000;
+        if now_ms < next_sample {
+            if let Some(Err(e)) = current.as_mut().map(|c| c.logger.poll(now_ms)) {
+                say(&mut serial, log_error(&e));
+            }
+            continue;
+        }
+        next_sample = now_ms + SAMPLE_MS;
+
+        // A 10 µs pulse on the trigger starts a measurement, and the echo
+        // pin stays high for as long as the sound took to come back
+        trigger.set_high().unwrap();
+        timer.delay_us(10);
+        trigger.set_low().unwrap();
+        let sent = timer.get_counter().ticks();
+        let timed_out = || timer.get_counter().ticks() - sent >= ECHO_TIMEOUT_US;
+        let mut distance = None;
+        while echo.is_low().unwrap() && !timed_out() {}
+        if echo.is_high().unwrap() {
+            let rise = timer.get_counter().ticks();
+            while echo.is_high().unwrap() && !timed_out() {}
+            if echo.is_low().unwrap() {
+                // Sound travels 0.0343 cm/µs, there and back
+                let fall = timer.get_counter().ticks();
+                distance = Some((fall - rise) as f32 * 0.0343 / 2.0);
+            }
+        }
+
+        let vrx: Option<u16> = adc.read(&mut vrx_pin).ok();
+        let vry: Option<u16> = adc.read(&mut vry_pin).ok();
+        let pressed = btn.is_low().unwrap();
+
+        row.clear();
+        match aon.now() {
+            Some(time) => row.field(time),
+            None => row.empty(),
+        };
+        row.field(now_ms);
+        match distance {
+            Some(cm) => row.field(format_args!("{:.1}", cm)),
+            None => row.empty(),
+        };
+        match vrx {
+            Some(x) => row.field(x),
+            None => row.empty(),
+        };
+        match vry {
+            Some(y) => row.field(y),
+            None => row.empty(),
+        };
+        row.field(u8::from(pressed));
+
+        // A new file when this one is full or the date has changed
+        let day = today(&aon);
+        let row_len = row.as_bytes().map_or(0, |bytes| bytes.len());
+        if let Some(full) =
+            current.take_if(|c| ROTATE.rotation_due(c.logger.file_len(), row_len, c.day, day))
+        {
+            close(full, &mut space);
+        }
+
+        // Twice at most: if the card is full, old files are deleted to make
+        // room and the row goes to a new file
+        for _ in 0..2 {
+            if current.is_none() {
+                match open_next(&mut root_dir, &mut space, day) {
+                    Ok(next) => {
+                        write!(buff, "Logging to {}", next.name).unwrap();
+                        say(&mut serial, &buff);
+                        buff.clear();
+                        current = Some(next);
+                    }
+                    Err(e) => {
+                        say(&mut serial, e);
+                        break;
+                    }
+                }
+            }
+            let Some(log) = current.as_mut() else {
+                break;
+            };
+            match log.logger.log(&mut row, now_ms) {
+                Ok(()) => {
+                    if let Ok(bytes) = row.as_bytes() {
+                        let _ = serial.write(bytes);
+                    }
+                    break;
+                }
+                Err(LogError::Sink(embedded_sdmmc::Error::DiskFull)) => {
+                    // The count was off, so make room whatever it says
+                    say(&mut serial, "Card full, deleting old logs");
+                    space.free = 0;
+                    if let Some(full) = current.take() {
+                        close(full, &mut space);
+                    }
+                }
+                Err(e) => {
+                    say(&mut serial, log_error(&e));
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Finds the log files in `dir`.
+fn scan<D: BlockDevice, T: TimeSource>(
+    dir: &mut Directory<'_, D, T, 4, 4, 1>,
+) -> Result<LogFiles, &'static str> {
+    let mut files = LogFiles::new();
+    dir.iterate_dir(|entry| {
+        if entry.attributes.is_directory() || entry.attributes.is_volume() {
+            return;
+        }
+        let mut name: String<12> = String::new();
+        if write!(name, "{}", entry.name).is_ok() {
+            files.add(&name, entry.size);
+        }
+    })
+    .map_err(sd_error)?;
+    Ok(files)
+}
+
+/// Deletes the oldest log files until the logs are within [`ROTATE`]'s
+/// limits and there's a file number free, and returns what's left.
+fn make_room<D: BlockDevice, T: TimeSource>(
+    dir: &mut Directory<'_, D, T, 4, 4, 1>,
+    space: &mut Space,
+) -> Result<LogFiles, &'static str> {
+    loop {
+        let files = scan(dir)?;
+        let oldest = match files.to_delete(&ROTATE, space.free) {
+            Some(oldest) => oldest,
+            None if files.next_name().is_none() => match files.range() {
+                Some((oldest, _)) => oldest,
+                None => return Ok(files),
+            },
+            None => return Ok(files),
+        };
+        let entry = dir
+            .find_directory_entry(oldest.as_str())
+            .map_err(sd_error)?;
+        dir.delete_file_in_dir(oldest.as_str()).map_err(sd_error)?;
+        space.freed(entry.size);
+    }
+}
+
+/// Makes room and starts the next log file.
+fn open_next<'a, D: BlockDevice, T: TimeSource>(
+    dir: &mut Directory<'a, D, T, 4, 4, 1>,
+    space: &mut Space,
+    day: Option<u32>,
+) -> Result<Current<'a, D, T>, &'static str> {
+    let files = make_room(dir, space)?;
+    let name = files.next_name().ok_or("No log file numbers left")?;
+    open(dir, name, day)
+}
+
+/// Opens a log file for appending, writing the header if it's new.
+fn open<'a, D: BlockDevice, T: TimeSource>(
+    dir: &mut Directory<'a, D, T, 4, 4, 1>,
+    name: LogName,
+    day: Option<u32>,
+) -> Result<Current<'a, D, T>, &'static str> {
+    let file = dir
+        .open_file_in_dir(name.as_str(), Mode::ReadWriteCreateOrAppend)
+        .map_err(sd_error)?;
+    let len = file.length();
+    let logger = Logger::open(LogFile(file), len, &HEADER, FlushPolicy::DEFAULT)
+        .map_err(|e| log_error(&e))?;
+    Ok(Current {
+        logger,
+        name,
+        day,
+        counted: len,
+    })
+}
+
+/// Flushes and closes a log file. Whatever goes wrong, the file is done
+/// with: the next row goes to a new one.
+fn close<D: BlockDevice, T: TimeSource>(current: Current<'_, D, T>, space: &mut Space) {
+    space.written(current.counted, current.logger.file_len());
+    if let Ok(LogFile(file)) = current.logger.close() {
+        let _ = file.close();
+    }
+}
+
+/// Days since 1970, if the clock is set.
+fn today(aon: &AonClock) -> Option<u32> {
+    aon.now().map(|now| day_number(&now))
+}
+
+fn day_number(time: &DateTime) -> u32 {
+    (time.to_unix() / date::SECONDS_PER_DAY) as u32
+}
+
+/// The day a file was created. Files made before the clock was set are
+/// dated 1980-01-01, and count as undated.
+fn created_day(entry: &DirEntry) -> Option<u32> {
+    let t = &entry.ctime;
+    let created = DateTime::midnight(
+        1970 + u16::from(t.year_since_1970),
+        t.zero_indexed_month + 1,
+        t.zero_indexed_day + 1,
+    );
+    (created.is_valid() && created != DateTime::FAT_MIN).then(|| day_number(&created))
+}
+
+fn say<B: UsbBus>(serial: &mut SerialPort<B>, message: &str) {
+    let _ = serial.write(message.as_bytes());
+    let _ = serial.write(b"\r\n");
+}
+
+fn sd_error<E>(e: embedded_sdmmc::Error<E>) -> &'static str {
+    use embedded_sdmmc::Error;
+    match e {
+        Error::DiskFull => "Card full",
+        Error::NotFound => "No such file",
+        Error::FormatError(_) | Error::NoSuchVolume => "No FAT volume on the card",
+        Error::DeviceError(_) => "SD card not responding",
+        _ => "SD card error",
+    }
+}
+
+fn log_error<E>(e: &LogError<embedded_sdmmc::Error<E>>) -> &'static str {
+    match e {
+        LogError::Sink(embedded_sdmmc::Error::DiskFull) => "Card full, row not logged",
+        LogError::Sink(_) => "Can't write to the card, row not logged",
+        LogError::Row(e) => e.as_str(),
+        LogError::Columns { .. } => "Row doesn't match the header",
+    }
+}
+
+/// Repeats `message` over serial every few seconds and stops there.
+fn halt<B: UsbBus>(usb_dev: &mut UsbDevice<B>, serial: &mut SerialPort<B>, message: &str) -> ! {
+    let mut polls = 0u32;
+    loop {
+        if usb_dev.poll(&mut [&mut *serial]) {
+            let mut buf = [0u8; 64];
+            let _ = serial.read(&mut buf);
+        }
+        polls = polls.wrapping_add(1);
+        if polls.is_multiple_of(2_000_000) {
+            let _ = serial.write(message.as_bytes());
+            let _ = serial.write(b"\r\n");
+        }
+    }
+}
+
+#[link_section = ".bi_entries"]
+#[used]
+pub static PICOTOOL_ENTRIES: [hal::binary_info::EntryAddr; 5] = [
+    hal::binary_info::rp_cargo_bin_name!(),
+    hal::binary_info::rp_cargo_version!(),
+    hal::binary_info::rp_program_description!(c"SD Card Data Logger"),
+    hal::binary_info::rp_cargo_homepage_url!(),
+    hal::binary_info::rp_program_build_attribute!(),
+];
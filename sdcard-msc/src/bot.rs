@@ -0,0 +1,326 @@
+//! Bulk-Only Transport: every command is a command block wrapper (CBW)
+//! from the PC, data one way or the other, and a command status wrapper
+//! (CSW) back.
+//!
+//! [`Bot`] only sees packets, so the USB side stays in
+//! [`class`](crate::class). When the PC expects more data than a command
+//! has, in either direction, the rest is padded with zeros or taken and
+//! dropped, and the CSW's residue says how much of it was real. That keeps
+//! both ends in step without stalling endpoints, which is only done for a
+//! CBW that makes no sense.
+
+use crate::scsi::{Reply, Scsi, Sense, Storage, BLOCK};
+
+/// Largest packet on a full speed bulk endpoint.
+pub const PACKET: usize = 64;
+
+const CBW_SIGNATURE: u32 = 0x4342_5355;
+const CSW_SIGNATURE: u32 = 0x5342_5355;
+const CBW_LEN: usize = 31;
+const CSW_LEN: usize = 13;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Passed = 0,
+    Failed = 1,
+    /// The PC and the command disagree about the data.
+    PhaseError = 2,
+}
+
+/// A command block wrapper.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cbw {
+    pub tag: u32,
+    /// Bytes the PC expects to move.
+    pub data_len: u32,
+    /// Whether they go to the PC.
+    pub data_in: bool,
+    pub lun: u8,
+    pub cdb: [u8; 16],
+    pub cdb_len: usize,
+}
+
+impl Cbw {
+    pub fn parse(packet: &[u8]) -> Option<Cbw> {
+        if packet.len() != CBW_LEN || u32_at(packet, 0) != CBW_SIGNATURE {
+            return None;
+        }
+        let cdb_len = usize::from(packet[14] & 0x1F);
+        if !(1..=16).contains(&cdb_len) {
+            return None;
+        }
+        let mut cdb = [0; 16];
+        cdb.copy_from_slice(&packet[15..31]);
+        Some(Cbw {
+            tag: u32_at(packet, 4),
+            data_len: u32_at(packet, 8),
+            data_in: packet[12] & 0x80 != 0,
+            lun: packet[13] & 0x0F,
+            cdb,
+            cdb_len,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Waiting for a CBW.
+    Idle,
+    /// Sending the buffer up to `len`.
+    Send { at: usize, len: usize },
+    /// Sending blocks. The buffer holds the one at `lba`, unless `at` is
+    /// at its end.
+    Read { lba: u32, left: u32, at: usize },
+    /// Taking blocks, the one for `lba` filling the buffer up to `at`.
+    Write { lba: u32, left: u32, at: usize },
+    /// Zeros to the PC, or data from it dropped, for the rest of what it
+    /// expects.
+    Pad,
+    /// The CSW is next.
+    Status,
+    /// After a bad CBW, until the PC resets the transport.
+    Stalled,
+}
+
+pub struct Bot {
+    scsi: Scsi,
+    buf: [u8; BLOCK],
+    state: State,
+    tag: u32,
+    data_in: bool,
+    /// Bytes the PC expects, how many it's had including padding, and how
+    /// many were real data.
+    expected: u32,
+    moved: u32,
+    data: u32,
+    status: Status,
+}
+
+impl Default for Bot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bot {
+    pub const fn new() -> Self {
+        Self {
+            scsi: Scsi::new(),
+            buf: [0; BLOCK],
+            state: State::Idle,
+            tag: 0,
+            data_in: false,
+            expected: 0,
+            moved: 0,
+            data: 0,
+            status: Status::Passed,
+        }
+    }
+
+    pub fn scsi_mut(&mut self) -> &mut Scsi {
+        &mut self.scsi
+    }
+
+    /// Whether both endpoints should stall until [`reset`](Self::reset).
+    pub fn is_stalled(&self) -> bool {
+        self.state == State::Stalled
+    }
+
+    /// Bulk-Only Mass Storage Reset, or a USB reset: the next packet from
+    /// the PC is a CBW.
+    pub fn reset(&mut self) {
+        self.state = State::Idle;
+    }
+
+    /// Takes a packet the PC sent.
+    pub fn out_packet(&mut self, storage: &mut impl Storage, packet: &[u8]) {
+        match self.state {
+            State::Idle => self.command(storage, packet),
+            State::Write { .. } | State::Pad if !self.data_in => {
+                let room = (self.expected - self.moved) as usize;
+                let packet = &packet[..packet.len().min(room)];
+                for &byte in packet {
+                    if let State::Write { lba, left, at } = self.state {
+                        self.buf[at] = byte;
+                        self.state = if at + 1 < BLOCK {
+                            State::Write {
+                                lba,
+                                left,
+                                at: at + 1,
+                            }
+                        } else if self.scsi.write(storage, lba, &self.buf).is_err() {
+                            self.status = Status::Failed;
+                            State::Pad
+                        } else {
+                            // Only blocks that made it to the card count
+                            self.data += BLOCK as u32;
+                            if left > 1 {
+                                State::Write {
+                                    lba: lba + 1,
+                                    left: left - 1,
+                                    at: 0,
+                                }
+                            } else {
+                                State::Pad
+                            }
+                        };
+                    }
+                }
+                self.moved += packet.len() as u32;
+                self.data_done();
+            }
+            // Not what the PC should be sending now
+            _ => {}
+        }
+    }
+
+    /// Fills `packet` with what goes to the PC next, if anything, and
+    /// returns its length.
+    pub fn in_packet(&mut self, storage: &mut impl Storage, packet: &mut [u8]) -> Option<usize> {
+        if self.state == State::Status {
+            let residue = self.expected - self.data;
+            packet[..4].copy_from_slice(&CSW_SIGNATURE.to_le_bytes());
+            packet[4..8].copy_from_slice(&self.tag.to_le_bytes());
+            packet[8..12].copy_from_slice(&residue.to_le_bytes());
+            packet[12] = self.status as u8;
+            self.state = State::Idle;
+            return Some(CSW_LEN);
+        }
+        if !self.data_in
+            || !matches!(
+                self.state,
+                State::Send { .. } | State::Read { .. } | State::Pad
+            )
+        {
+            return None;
+        }
+
+        let want = packet.len().min((self.expected - self.moved) as usize);
+        let mut n = 0;
+        while n < want {
+            match self.state {
+                State::Send { at, len } => {
+                    let take = (want - n).min(len - at);
+                    packet[n..n + take].copy_from_slice(&self.buf[at..at + take]);
+                    n += take;
+                    self.data += take as u32;
+                    self.state = if at + take < len {
+                        State::Send { at: at + take, len }
+                    } else {
+                        State::Pad
+                    };
+                }
+                State::Read { lba, left, at } if at == BLOCK => {
+                    self.state = match self.scsi.read(storage, lba, &mut self.buf) {
+                        Ok(()) => State::Read { lba, left, at: 0 },
+                        Err(_) => {
+                            self.status = Status::Failed;
+                            State::Pad
+                        }
+                    };
+                }
+                State::Read { lba, left, at } => {
+                    let take = (want - n).min(BLOCK - at);
+                    packet[n..n + take].copy_from_slice(&self.buf[at..at + take]);
+                    n += take;
+                    self.data += take as u32;
+                    self.state = if at + take < BLOCK {
+                        State::Read {
+                            lba,
+                            left,
+                            at: at + take,
+                        }
+                    } else if left > 1 {
+                        State::Read {
+                            lba: lba + 1,
+                            left: left - 1,
+                            at: BLOCK,
+                        }
+                    } else {
+                        State::Pad
+                    };
+                }
+                _ => {
+                    packet[n..want].fill(0);
+                    n = want;
+                }
+            }
+        }
+        self.moved += n as u32;
+        self.data_done();
+        Some(n)
+    }
+
+    /// Starts the command in a CBW.
+    fn command(&mut self, storage: &mut impl Storage, packet: &[u8]) {
+        let Some(cbw) = Cbw::parse(packet) else {
+            self.state = State::Stalled;
+            return;
+        };
+        self.tag = cbw.tag;
+        self.data_in = cbw.data_in;
+        self.expected = cbw.data_len;
+        self.moved = 0;
+        self.data = 0;
+        self.status = Status::Passed;
+
+        // There's only LUN 0
+        let reply = match cbw.lun {
+            0 => self
+                .scsi
+                .start(storage, &cbw.cdb[..cbw.cdb_len], &mut self.buf),
+            _ => Err(Sense::INVALID_FIELD),
+        };
+        let (data_in, state) = match reply {
+            Ok(Reply::Done) => (self.data_in, State::Pad),
+            Ok(Reply::Send(len)) => (true, State::Send { at: 0, len }),
+            Ok(Reply::Read { lba, count }) => (
+                true,
+                State::Read {
+                    lba,
+                    left: count,
+                    at: BLOCK,
+                },
+            ),
+            Ok(Reply::Write { lba, count }) => (
+                false,
+                State::Write {
+                    lba,
+                    left: count,
+                    at: 0,
+                },
+            ),
+            Err(_) => {
+                self.status = Status::Failed;
+                (self.data_in, State::Pad)
+            }
+        };
+        self.state = if state == State::Pad {
+            state
+        } else if self.expected == 0 || data_in != self.data_in {
+            // Data the PC isn't expecting, or the wrong way
+            self.status = Status::PhaseError;
+            State::Pad
+        } else {
+            state
+        };
+        self.data_done();
+    }
+
+    /// Moves on to the CSW once the PC has had all it expects. A block
+    /// command cut short by that is a phase error; a reply that's been
+    /// cut is just what the PC asked for.
+    fn data_done(&mut self) {
+        if self.moved < self.expected {
+            return;
+        }
+        if matches!(self.state, State::Read { .. } | State::Write { .. }) {
+            self.status = Status::PhaseError;
+        }
+        self.state = State::Status;
+    }
+}
+
+fn u32_at(bytes: &[u8], i: usize) -> u32 {
+    u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]])
+}
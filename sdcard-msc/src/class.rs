@@ -0,0 +1,108 @@
+//! The mass storage interface on the USB device.
+//!
+//! [`MscClass`] only handles the descriptors and the two class requests
+//! inside `UsbDevice::poll`. The commands themselves run in
+//! [`process`](MscClass::process), called after each poll with the card,
+//! so the card doesn't have to belong to the class and can still be used
+//! in between.
+
+use usb_device::class_prelude::*;
+use usb_device::Result;
+
+use crate::bot::{Bot, PACKET};
+use crate::scsi::{Scsi, Storage};
+
+const CLASS_MSC: u8 = 0x08;
+const SUBCLASS_SCSI: u8 = 0x06;
+const PROTOCOL_BOT: u8 = 0x50;
+
+const GET_MAX_LUN: u8 = 0xFE;
+const BULK_ONLY_RESET: u8 = 0xFF;
+
+pub struct MscClass<'a, B: UsbBus> {
+    interface: InterfaceNumber,
+    read_ep: EndpointOut<'a, B>,
+    write_ep: EndpointIn<'a, B>,
+    bot: Bot,
+    /// A packet the IN endpoint wasn't free for yet.
+    pending: Option<([u8; PACKET], usize)>,
+}
+
+impl<'a, B: UsbBus> MscClass<'a, B> {
+    pub fn new(alloc: &'a UsbBusAllocator<B>) -> Self {
+        Self {
+            interface: alloc.interface(),
+            read_ep: alloc.bulk(PACKET as u16),
+            write_ep: alloc.bulk(PACKET as u16),
+            bot: Bot::new(),
+            pending: None,
+        }
+    }
+
+    pub fn scsi_mut(&mut self) -> &mut Scsi {
+        self.bot.scsi_mut()
+    }
+
+    /// Moves the current command along: takes a packet from the PC if
+    /// there is one, and sends one if the endpoint is free.
+    pub fn process(&mut self, storage: &mut impl Storage) {
+        if self.bot.is_stalled() {
+            // The PC clearing the halt doesn't end it, only a reset does
+            self.read_ep.stall();
+            self.write_ep.stall();
+            return;
+        }
+        let mut packet = [0; PACKET];
+        if let Ok(n) = self.read_ep.read(&mut packet) {
+            self.bot.out_packet(storage, &packet[..n]);
+        }
+        if self.pending.is_none() {
+            self.pending = self
+                .bot
+                .in_packet(storage, &mut packet)
+                .map(|n| (packet, n));
+        }
+        if let Some((packet, n)) = self.pending {
+            if self.write_ep.write(&packet[..n]).is_ok() {
+                self.pending = None;
+            }
+        }
+    }
+
+    fn is_ours(&self, req: &control::Request) -> bool {
+        req.request_type == control::RequestType::Class
+            && req.recipient == control::Recipient::Interface
+            && req.index == u16::from(u8::from(self.interface))
+    }
+}
+
+impl<B: UsbBus> UsbClass<B> for MscClass<'_, B> {
+    fn get_configuration_descriptors(&self, writer: &mut DescriptorWriter) -> Result<()> {
+        writer.interface(self.interface, CLASS_MSC, SUBCLASS_SCSI, PROTOCOL_BOT)?;
+        writer.endpoint(&self.write_ep)?;
+        writer.endpoint(&self.read_ep)
+    }
+
+    fn reset(&mut self) {
+        self.bot.reset();
+        self.pending = None;
+    }
+
+    fn control_in(&mut self, xfer: ControlIn<B>) {
+        let req = *xfer.request();
+        if self.is_ours(&req) && req.request == GET_MAX_LUN {
+            // A single LUN, number 0
+            let _ = xfer.accept_with(&[0]);
+        }
+    }
+
+    fn control_out(&mut self, xfer: ControlOut<B>) {
+        let req = *xfer.request();
+        if self.is_ours(&req) && req.request == BULK_ONLY_RESET {
+            self.reset();
+            self.read_ep.unstall();
+            self.write_ep.unstall();
+            let _ = xfer.accept();
+        }
+    }
+}
@@ -0,0 +1,12 @@
+//! A USB mass storage class, so a PC sees the SD card as a removable disk.
+//!
+//! [`scsi`] answers the SCSI commands, [`bot`] wraps them in the Bulk-Only
+//! Transport, and [`class`] puts that on a `usb-device` bus next to any
+//! other class, such as a CDC serial port. The disk is behind the
+//! [`Storage`](scsi::Storage) trait, so the commands are plain `no_std`
+//! code that can be checked on the host against a disk in memory.
+#![no_std]
+
+pub mod bot;
+pub mod class;
+pub mod scsi;
@@ -0,0 +1,292 @@
+//! The SCSI commands a PC sends a USB stick, the transparent command set.
+//!
+//! Only what Linux, macOS and Windows use on a removable disk: inquiry,
+//! capacity, sense, mode sense and 10 byte reads and writes. Blocks are
+//! always 512 bytes, and an SD card has fewer than 2^32 of them, so there's
+//! no need for the 16 byte commands.
+
+/// Bytes in a block.
+pub const BLOCK: usize = 512;
+
+const TEST_UNIT_READY: u8 = 0x00;
+const REQUEST_SENSE: u8 = 0x03;
+const INQUIRY: u8 = 0x12;
+const MODE_SENSE_6: u8 = 0x1A;
+const START_STOP_UNIT: u8 = 0x1B;
+const PREVENT_ALLOW_MEDIUM_REMOVAL: u8 = 0x1E;
+const READ_FORMAT_CAPACITIES: u8 = 0x23;
+const READ_CAPACITY_10: u8 = 0x25;
+const READ_10: u8 = 0x28;
+const WRITE_10: u8 = 0x2A;
+const VERIFY_10: u8 = 0x2F;
+const SYNCHRONIZE_CACHE_10: u8 = 0x35;
+const MODE_SENSE_10: u8 = 0x5A;
+
+/// What the disk is, as far as the PC can tell.
+const VENDOR: &[u8; 8] = b"implRust";
+const PRODUCT: &[u8; 16] = b"SD Card Reader  ";
+const REVISION: &[u8; 4] = b"0.1 ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageError {
+    /// No card, or it isn't ready.
+    NoMedium,
+    /// The card didn't do what it was asked.
+    Failed,
+}
+
+impl StorageError {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            StorageError::NoMedium => "No card",
+            StorageError::Failed => "Card error",
+        }
+    }
+}
+
+/// The disk behind the commands.
+pub trait Storage {
+    fn block_count(&mut self) -> Result<u32, StorageError>;
+
+    fn read_block(&mut self, lba: u32, block: &mut [u8; BLOCK]) -> Result<(), StorageError>;
+
+    fn write_block(&mut self, lba: u32, block: &[u8; BLOCK]) -> Result<(), StorageError>;
+}
+
+/// Why the last command failed: a sense key, and an additional sense code
+/// and qualifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sense {
+    pub key: u8,
+    pub asc: u8,
+    pub ascq: u8,
+}
+
+impl Sense {
+    pub const NONE: Sense = Sense::new(0x00, 0x00, 0x00);
+    pub const NO_MEDIUM: Sense = Sense::new(0x02, 0x3A, 0x00);
+    pub const READ_ERROR: Sense = Sense::new(0x03, 0x11, 0x00);
+    pub const WRITE_ERROR: Sense = Sense::new(0x03, 0x0C, 0x00);
+    pub const INVALID_COMMAND: Sense = Sense::new(0x05, 0x20, 0x00);
+    pub const OUT_OF_RANGE: Sense = Sense::new(0x05, 0x21, 0x00);
+    pub const INVALID_FIELD: Sense = Sense::new(0x05, 0x24, 0x00);
+    /// Unit attention: the card may have been changed.
+    pub const MEDIUM_CHANGED: Sense = Sense::new(0x06, 0x28, 0x00);
+
+    pub const fn new(key: u8, asc: u8, ascq: u8) -> Sense {
+        Sense { key, asc, ascq }
+    }
+}
+
+/// What a command does once it's been started.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reply {
+    /// Nothing more, there's no data.
+    Done,
+    /// Send the first `n` bytes of the buffer to the PC.
+    Send(usize),
+    /// Send `count` blocks from `lba` on, each read with [`Scsi::read`].
+    Read { lba: u32, count: u32 },
+    /// Take `count` blocks for `lba` on, each written with [`Scsi::write`].
+    Write { lba: u32, count: u32 },
+}
+
+/// The command set's state: the sense data for REQUEST SENSE.
+pub struct Scsi {
+    sense: Sense,
+    /// Reported once, by the next command that needs the card.
+    medium_changed: bool,
+}
+
+impl Default for Scsi {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scsi {
+    pub const fn new() -> Self {
+        Self {
+            sense: Sense::NONE,
+            medium_changed: false,
+        }
+    }
+
+    /// The last command's sense data.
+    pub fn sense(&self) -> Sense {
+        self.sense
+    }
+
+    /// A card was inserted or swapped. The PC is told before it reads
+    /// anything, so it doesn't trust what it cached about the last one.
+    pub fn medium_changed(&mut self) {
+        self.medium_changed = true;
+    }
+
+    /// Starts the command in `cdb`. Data for [`Reply::Send`] is put in
+    /// `buf`, already cut to the length the PC asked for.
+    pub fn start(
+        &mut self,
+        storage: &mut impl Storage,
+        cdb: &[u8],
+        buf: &mut [u8; BLOCK],
+    ) -> Result<Reply, Sense> {
+        let reply = self.command(storage, cdb, buf);
+        self.sense = match reply {
+            Ok(_) => Sense::NONE,
+            Err(sense) => sense,
+        };
+        reply
+    }
+
+    /// Reads one block of a [`Reply::Read`].
+    pub fn read(
+        &mut self,
+        storage: &mut impl Storage,
+        lba: u32,
+        buf: &mut [u8; BLOCK],
+    ) -> Result<(), Sense> {
+        storage
+            .read_block(lba, buf)
+            .map_err(|e| self.fail(e, Sense::READ_ERROR))
+    }
+
+    /// Writes one block of a [`Reply::Write`].
+    pub fn write(
+        &mut self,
+        storage: &mut impl Storage,
+        lba: u32,
+        buf: &[u8; BLOCK],
+    ) -> Result<(), Sense> {
+        storage
+            .write_block(lba, buf)
+            .map_err(|e| self.fail(e, Sense::WRITE_ERROR))
+    }
+
+    fn fail(&mut self, e: StorageError, failed: Sense) -> Sense {
+        self.sense = match e {
+            StorageError::NoMedium => Sense::NO_MEDIUM,
+            StorageError::Failed => failed,
+        };
+        self.sense
+    }
+
+    fn command(
+        &mut self,
+        storage: &mut impl Storage,
+        cdb: &[u8],
+        buf: &mut [u8; BLOCK],
+    ) -> Result<Reply, Sense> {
+        let &opcode = cdb.first().ok_or(Sense::INVALID_COMMAND)?;
+        // Enough zeros after a short CDB that reading its fields can't fail
+        let mut padded = [0u8; 16];
+        let len = cdb.len().min(padded.len());
+        padded[..len].copy_from_slice(&cdb[..len]);
+        let cdb = &padded;
+        let u16_at = |i: usize| u16::from_be_bytes([cdb[i], cdb[i + 1]]);
+        let u32_at = |i: usize| u32::from_be_bytes([cdb[i], cdb[i + 1], cdb[i + 2], cdb[i + 3]]);
+
+        // These work without a card, and don't clear a unit attention
+        match opcode {
+            INQUIRY => {
+                // Only the standard data, no vital product data pages
+                if cdb[1] & 0x01 != 0 {
+                    return Err(Sense::INVALID_FIELD);
+                }
+                buf[..36].fill(0);
+                buf[1] = 0x80; // Removable
+                buf[2] = 0x04; // SPC-2
+                buf[3] = 0x02;
+                buf[4] = 36 - 5;
+                buf[8..16].copy_from_slice(VENDOR);
+                buf[16..32].copy_from_slice(PRODUCT);
+                buf[32..36].copy_from_slice(REVISION);
+                return Ok(send(36, usize::from(u16_at(3))));
+            }
+            REQUEST_SENSE => {
+                let sense = if self.medium_changed {
+                    self.medium_changed = false;
+                    Sense::MEDIUM_CHANGED
+                } else {
+                    self.sense
+                };
+                // Fixed format, current errors
+                buf[..18].fill(0);
+                buf[0] = 0x70;
+                buf[2] = sense.key;
+                buf[7] = 18 - 8;
+                buf[12] = sense.asc;
+                buf[13] = sense.ascq;
+                return Ok(send(18, usize::from(cdb[4])));
+            }
+            _ => {}
+        }
+
+        if self.medium_changed {
+            self.medium_changed = false;
+            return Err(Sense::MEDIUM_CHANGED);
+        }
+        let blocks = storage
+            .block_count()
+            .map_err(|e| self.fail(e, Sense::READ_ERROR));
+
+        match opcode {
+            TEST_UNIT_READY => blocks.map(|_| Reply::Done),
+            // The card can be pulled whatever the PC asks for
+            START_STOP_UNIT | PREVENT_ALLOW_MEDIUM_REMOVAL => Ok(Reply::Done),
+            // Writes go straight to the card
+            SYNCHRONIZE_CACHE_10 => blocks.map(|_| Reply::Done),
+            READ_CAPACITY_10 => {
+                let blocks = blocks?;
+                buf[..4].copy_from_slice(&(blocks.max(1) - 1).to_be_bytes());
+                buf[4..8].copy_from_slice(&(BLOCK as u32).to_be_bytes());
+                Ok(Reply::Send(8))
+            }
+            READ_FORMAT_CAPACITIES => {
+                let blocks = blocks?;
+                buf[..12].fill(0);
+                buf[3] = 8;
+                buf[4..8].copy_from_slice(&blocks.to_be_bytes());
+                // Formatted media, then the block length in three bytes
+                buf[8] = 0x02;
+                buf[9..12].copy_from_slice(&(BLOCK as u32).to_be_bytes()[1..]);
+                Ok(send(12, usize::from(u16_at(7))))
+            }
+            // Just the header: no block descriptors, no pages, not write
+            // protected
+            MODE_SENSE_6 => {
+                blocks?;
+                buf[..4].copy_from_slice(&[3, 0, 0, 0]);
+                Ok(send(4, usize::from(cdb[4])))
+            }
+            MODE_SENSE_10 => {
+                blocks?;
+                buf[..8].copy_from_slice(&[0, 6, 0, 0, 0, 0, 0, 0]);
+                Ok(send(8, usize::from(u16_at(7))))
+            }
+            READ_10 | WRITE_10 | VERIFY_10 => {
+                let blocks = blocks?;
+                let (lba, count) = (u32_at(2), u32::from(u16_at(7)));
+                if lba.checked_add(count).is_none_or(|end| end > blocks) {
+                    return Err(Sense::OUT_OF_RANGE);
+                }
+                Ok(match opcode {
+                    _ if count == 0 => Reply::Done,
+                    READ_10 => Reply::Read { lba, count },
+                    WRITE_10 => Reply::Write { lba, count },
+                    // Nothing to compare against, the card checks its own
+                    _ => Reply::Done,
+                })
+            }
+            _ => Err(Sense::INVALID_COMMAND),
+        }
+    }
+}
+
+/// Sends `len` bytes, or fewer if the PC only has room for `allocated`.
+fn send(len: usize, allocated: usize) -> Reply {
+    match len.min(allocated) {
+        0 => Reply::Done,
+        n => Reply::Send(n),
+    }
+}
@@ -0,0 +1,536 @@
+//! Runs the SCSI commands and the Bulk-Only Transport on the host, against
+//! a disk in memory.
+
+use sdcard_msc::bot::{Bot, Status, PACKET};
+use sdcard_msc::scsi::{Sense, Storage, StorageError, BLOCK};
+
+/// A disk in memory, with a card that can be pulled and blocks that fail.
+struct RamDisk {
+    blocks: Vec<[u8; BLOCK]>,
+    present: bool,
+    bad_block: Option<u32>,
+}
+
+impl RamDisk {
+    /// Each block filled with its own number and the byte's offset.
+    fn new(count: u32) -> RamDisk {
+        let blocks = (0..count)
+            .map(|lba| {
+                let mut block = [0; BLOCK];
+                for (i, byte) in block.iter_mut().enumerate() {
+                    *byte = (lba as usize * 7 + i) as u8;
+                }
+                block
+            })
+            .collect();
+        RamDisk {
+            blocks,
+            present: true,
+            bad_block: None,
+        }
+    }
+}
+
+impl Storage for RamDisk {
+    fn block_count(&mut self) -> Result<u32, StorageError> {
+        if self.present {
+            Ok(self.blocks.len() as u32)
+        } else {
+            Err(StorageError::NoMedium)
+        }
+    }
+
+    fn read_block(&mut self, lba: u32, block: &mut [u8; BLOCK]) -> Result<(), StorageError> {
+        self.block_count()?;
+        if self.bad_block == Some(lba) {
+            return Err(StorageError::Failed);
+        }
+        block.copy_from_slice(&self.blocks[lba as usize]);
+        Ok(())
+    }
+
+    fn write_block(&mut self, lba: u32, block: &[u8; BLOCK]) -> Result<(), StorageError> {
+        self.block_count()?;
+        if self.bad_block == Some(lba) {
+            return Err(StorageError::Failed);
+        }
+        self.blocks[lba as usize] = *block;
+        Ok(())
+    }
+}
+
+/// A command status wrapper, taken apart.
+#[derive(Debug, PartialEq)]
+struct Csw {
+    tag: u32,
+    residue: u32,
+    status: u8,
+}
+
+/// What came back for a command.
+struct Done {
+    data: Vec<u8>,
+    /// Length of each data packet.
+    packets: Vec<usize>,
+    csw: Csw,
+}
+
+const TAG: u32 = 0x1234_5678;
+
+fn cbw(cdb: &[u8], data_len: u32, data_in: bool) -> Vec<u8> {
+    let mut packet = vec![0; 31];
+    packet[..4].copy_from_slice(b"USBC");
+    packet[4..8].copy_from_slice(&TAG.to_le_bytes());
+    packet[8..12].copy_from_slice(&data_len.to_le_bytes());
+    packet[12] = if data_in { 0x80 } else { 0 };
+    packet[14] = cdb.len() as u8;
+    packet[15..15 + cdb.len()].copy_from_slice(cdb);
+    packet
+}
+
+/// Runs a command the way a PC would: the CBW, `out` in packets if the
+/// data goes to the device, then IN packets until the CSW.
+fn run(
+    bot: &mut Bot,
+    disk: &mut RamDisk,
+    cdb: &[u8],
+    data_len: u32,
+    data_in: bool,
+    out: &[u8],
+) -> Done {
+    bot.out_packet(disk, &cbw(cdb, data_len, data_in));
+    if !data_in {
+        for chunk in out.chunks(PACKET) {
+            bot.out_packet(disk, chunk);
+        }
+    }
+    let mut data = Vec::new();
+    let mut packets = Vec::new();
+    let mut packet = [0; PACKET];
+    while data_in && data.len() < data_len as usize {
+        let n = bot
+            .in_packet(disk, &mut packet)
+            .unwrap_or_else(|| panic!("no data after {} bytes", data.len()));
+        data.extend_from_slice(&packet[..n]);
+        packets.push(n);
+    }
+    let n = bot.in_packet(disk, &mut packet).expect("no CSW");
+    assert!(
+        n == 13 && &packet[..4] == b"USBS",
+        "bad CSW {:02X?}",
+        &packet[..n]
+    );
+    let word =
+        |i: usize| u32::from_le_bytes([packet[i], packet[i + 1], packet[i + 2], packet[i + 3]]);
+    let csw = Csw {
+        tag: word(4),
+        residue: word(8),
+        status: packet[12],
+    };
+    assert!(
+        bot.in_packet(disk, &mut packet).is_none(),
+        "more after the CSW"
+    );
+    Done { data, packets, csw }
+}
+
+fn passed(residue: u32) -> Csw {
+    Csw {
+        tag: TAG,
+        residue,
+        status: Status::Passed as u8,
+    }
+}
+
+fn failed(residue: u32) -> Csw {
+    Csw {
+        tag: TAG,
+        residue,
+        status: Status::Failed as u8,
+    }
+}
+
+fn request_sense(bot: &mut Bot, disk: &mut RamDisk) -> Sense {
+    let done = run(bot, disk, &[0x03, 0, 0, 0, 18, 0], 18, true, &[]);
+    assert_eq!(done.csw, passed(0), "sense status");
+    Sense::new(done.data[2], done.data[12], done.data[13])
+}
+
+fn read10(lba: u32, count: u16) -> Vec<u8> {
+    let mut cdb = vec![0x28, 0];
+    cdb.extend_from_slice(&lba.to_be_bytes());
+    cdb.push(0);
+    cdb.extend_from_slice(&count.to_be_bytes());
+    cdb.push(0);
+    cdb
+}
+
+fn write10(lba: u32, count: u16) -> Vec<u8> {
+    let mut cdb = read10(lba, count);
+    cdb[0] = 0x2A;
+    cdb
+}
+
+#[test]
+fn inquiry() {
+    let (mut bot, mut disk) = (Bot::new(), RamDisk::new(16));
+    let done = run(&mut bot, &mut disk, &[0x12, 0, 0, 0, 36, 0], 36, true, &[]);
+    assert_eq!(done.csw, passed(0), "status");
+    assert_eq!(done.data[1], 0x80, "removable");
+    assert_eq!(&done.data[8..16], b"implRust", "vendor");
+    assert_eq!(&done.data[16..32], b"SD Card Reader  ", "product");
+
+    // Windows asks for more than there is: the rest is padding
+    let done = run(
+        &mut bot,
+        &mut disk,
+        &[0x12, 0, 0, 0, 0xFF, 0],
+        255,
+        true,
+        &[],
+    );
+    assert_eq!(done.data.len(), 255, "padded");
+    assert!(done.data[36..].iter().all(|&b| b == 0), "zeros");
+    assert_eq!(done.csw, passed(255 - 36), "residue");
+    assert_eq!(done.packets, vec![64, 64, 64, 63], "packets");
+
+    // Less than the standard data
+    let done = run(&mut bot, &mut disk, &[0x12, 0, 0, 0, 8, 0], 8, true, &[]);
+    assert_eq!((done.data.len(), done.csw), (8, passed(0)), "cut short");
+
+    let done = run(
+        &mut bot,
+        &mut disk,
+        &[0x12, 1, 0x80, 0, 36, 0],
+        36,
+        true,
+        &[],
+    );
+    assert_eq!(done.csw, failed(36), "vital product data");
+    assert_eq!(
+        request_sense(&mut bot, &mut disk),
+        Sense::INVALID_FIELD,
+        "sense"
+    );
+}
+
+#[test]
+fn capacity() {
+    let (mut bot, mut disk) = (Bot::new(), RamDisk::new(100));
+    let done = run(
+        &mut bot,
+        &mut disk,
+        &[0x25, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        8,
+        true,
+        &[],
+    );
+    assert_eq!(done.csw, passed(0), "status");
+    assert_eq!(&done.data[..4], &[0, 0, 0, 99][..], "last block");
+    assert_eq!(&done.data[4..], &[0, 0, 2, 0][..], "block size");
+
+    let done = run(
+        &mut bot,
+        &mut disk,
+        &[0x23, 0, 0, 0, 0, 0, 0, 0, 252, 0],
+        252,
+        true,
+        &[],
+    );
+    assert_eq!(
+        &done.data[..12],
+        &[0, 0, 0, 8, 0, 0, 0, 100, 2, 0, 2, 0][..],
+        "format capacities"
+    );
+    assert_eq!(done.csw, passed(240), "format residue");
+
+    let done = run(
+        &mut bot,
+        &mut disk,
+        &[0x1A, 0, 0x3F, 0, 192, 0],
+        192,
+        true,
+        &[],
+    );
+    assert_eq!(&done.data[..4], &[3, 0, 0, 0][..], "mode sense 6");
+    let done = run(
+        &mut bot,
+        &mut disk,
+        &[0x5A, 0, 0x3F, 0, 0, 0, 0, 0, 8, 0],
+        8,
+        true,
+        &[],
+    );
+    assert_eq!((done.data[1], done.csw), (6, passed(0)), "mode sense 10");
+
+    for cdb in [
+        [0x00, 0, 0, 0, 0, 0],
+        [0x1E, 0, 0, 0, 1, 0],
+        [0x1B, 0, 0, 0, 2, 0],
+    ] {
+        let done = run(&mut bot, &mut disk, &cdb, 0, false, &[]);
+        assert_eq!(done.csw, passed(0), "command {:02X}", cdb[0]);
+    }
+}
+
+#[test]
+fn read() {
+    let (mut bot, mut disk) = (Bot::new(), RamDisk::new(16));
+    let done = run(&mut bot, &mut disk, &read10(5, 3), 3 * 512, true, &[]);
+    assert_eq!(done.csw, passed(0), "status");
+    let expected: Vec<u8> = disk.blocks[5..8].iter().flatten().copied().collect();
+    assert!(done.data == expected, "data");
+    assert!(done.packets.iter().all(|&n| n == PACKET), "full packets");
+
+    let done = run(&mut bot, &mut disk, &read10(15, 1), 512, true, &[]);
+    assert!(done.data == disk.blocks[15], "last block");
+
+    let done = run(&mut bot, &mut disk, &read10(3, 0), 0, true, &[]);
+    assert_eq!(done.csw, passed(0), "nothing");
+}
+
+#[test]
+fn write() {
+    let (mut bot, mut disk) = (Bot::new(), RamDisk::new(16));
+    let data: Vec<u8> = (0..1024).map(|i| (i * 3 + 1) as u8).collect();
+    let done = run(&mut bot, &mut disk, &write10(9, 2), 1024, false, &data);
+    assert_eq!(done.csw, passed(0), "status");
+    assert!(disk.blocks[9][..] == data[..512], "first block");
+    assert!(disk.blocks[10][..] == data[512..], "second block");
+    assert!(
+        disk.blocks[11] == RamDisk::new(16).blocks[11],
+        "next untouched"
+    );
+
+    // Read back what was written
+    let done = run(&mut bot, &mut disk, &read10(9, 2), 1024, true, &[]);
+    assert!(done.data == data, "read back");
+
+    let done = run(
+        &mut bot,
+        &mut disk,
+        &[0x35, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        0,
+        false,
+        &[],
+    );
+    assert_eq!(done.csw, passed(0), "synchronize cache");
+}
+
+#[test]
+fn out_of_range() {
+    let (mut bot, mut disk) = (Bot::new(), RamDisk::new(16));
+    let done = run(&mut bot, &mut disk, &read10(15, 2), 1024, true, &[]);
+    assert_eq!(done.csw, failed(1024), "read past end");
+    assert_eq!(done.data, vec![0; 1024], "padding");
+    assert_eq!(
+        request_sense(&mut bot, &mut disk),
+        Sense::OUT_OF_RANGE,
+        "sense"
+    );
+
+    // The data the PC sends anyway is taken and dropped
+    let done = run(
+        &mut bot,
+        &mut disk,
+        &write10(u32::MAX, 1),
+        512,
+        false,
+        &[0xAA; 512],
+    );
+    assert_eq!(done.csw, failed(512), "write past end");
+    assert!(disk.blocks == RamDisk::new(16).blocks, "untouched");
+
+    // Sense is only for the last command
+    run(&mut bot, &mut disk, &[0, 0, 0, 0, 0, 0], 0, false, &[]);
+    assert_eq!(request_sense(&mut bot, &mut disk), Sense::NONE, "cleared");
+}
+
+#[test]
+fn no_card() {
+    let (mut bot, mut disk) = (Bot::new(), RamDisk::new(16));
+    disk.present = false;
+    let done = run(&mut bot, &mut disk, &[0, 0, 0, 0, 0, 0], 0, false, &[]);
+    assert_eq!(done.csw, failed(0), "not ready");
+    assert_eq!(
+        request_sense(&mut bot, &mut disk),
+        Sense::NO_MEDIUM,
+        "sense"
+    );
+    let done = run(&mut bot, &mut disk, &[0x12, 0, 0, 0, 36, 0], 36, true, &[]);
+    assert_eq!(done.csw, passed(0), "inquiry still works");
+    let done = run(&mut bot, &mut disk, &read10(0, 1), 512, true, &[]);
+    assert_eq!(done.csw, failed(512), "read");
+
+    // A card goes in: the next command says so, once
+    disk.present = true;
+    bot.scsi_mut().medium_changed();
+    let done = run(&mut bot, &mut disk, &[0, 0, 0, 0, 0, 0], 0, false, &[]);
+    assert_eq!(done.csw, failed(0), "unit attention");
+    assert_eq!(
+        request_sense(&mut bot, &mut disk),
+        Sense::MEDIUM_CHANGED,
+        "medium changed"
+    );
+    let done = run(&mut bot, &mut disk, &[0, 0, 0, 0, 0, 0], 0, false, &[]);
+    assert_eq!(done.csw, passed(0), "ready");
+
+    // Asking for the sense straight away clears it too
+    bot.scsi_mut().medium_changed();
+    assert_eq!(
+        request_sense(&mut bot, &mut disk),
+        Sense::MEDIUM_CHANGED,
+        "sense first"
+    );
+    let done = run(&mut bot, &mut disk, &[0, 0, 0, 0, 0, 0], 0, false, &[]);
+    assert_eq!(done.csw, passed(0), "then ready");
+}
+
+#[test]
+fn card_errors() {
+    let (mut bot, mut disk) = (Bot::new(), RamDisk::new(16));
+    disk.bad_block = Some(6);
+    let done = run(&mut bot, &mut disk, &read10(4, 4), 2048, true, &[]);
+    // Two good blocks, then padding
+    assert_eq!(done.csw, failed(2048 - 1024), "read status");
+    assert!(done.data[..512] == disk.blocks[4], "good part");
+    assert!(done.data[1024..].iter().all(|&b| b == 0), "padding");
+    assert_eq!(
+        request_sense(&mut bot, &mut disk),
+        Sense::READ_ERROR,
+        "read sense"
+    );
+
+    let done = run(
+        &mut bot,
+        &mut disk,
+        &write10(5, 3),
+        1536,
+        false,
+        &[0x55; 1536],
+    );
+    assert_eq!(done.csw, failed(1536 - 512), "write status");
+    assert_eq!(disk.blocks[5], [0x55; 512], "written before");
+    assert!(disk.blocks[7] == RamDisk::new(16).blocks[7], "not after");
+    assert_eq!(
+        request_sense(&mut bot, &mut disk),
+        Sense::WRITE_ERROR,
+        "write sense"
+    );
+
+    // Pulled halfway through
+    disk.bad_block = None;
+    bot.out_packet(&mut disk, &cbw(&read10(0, 2), 1024, true));
+    let mut packet = [0; PACKET];
+    for _ in 0..8 {
+        bot.in_packet(&mut disk, &mut packet);
+    }
+    disk.present = false;
+    for _ in 0..8 {
+        bot.in_packet(&mut disk, &mut packet);
+    }
+    let n = bot.in_packet(&mut disk, &mut packet);
+    assert_eq!((n, packet[12]), (Some(13), Status::Failed as u8), "csw");
+    assert_eq!(
+        request_sense(&mut bot, &mut disk),
+        Sense::NO_MEDIUM,
+        "no card"
+    );
+}
+
+#[test]
+fn phase_errors() {
+    let (mut bot, mut disk) = (Bot::new(), RamDisk::new(16));
+    let phase = |residue| Csw {
+        tag: TAG,
+        residue,
+        status: Status::PhaseError as u8,
+    };
+
+    // The PC expects less than the blocks
+    let done = run(&mut bot, &mut disk, &read10(0, 2), 512, true, &[]);
+    assert_eq!(done.csw, phase(0), "short read");
+    assert!(done.data == disk.blocks[0], "data");
+
+    // Or data the other way
+    let done = run(&mut bot, &mut disk, &read10(0, 1), 512, false, &[0; 512]);
+    assert_eq!(done.csw, phase(512), "read as out");
+    let done = run(&mut bot, &mut disk, &write10(0, 1), 512, true, &[]);
+    assert_eq!(done.csw, phase(512), "write as in");
+    assert!(disk.blocks == RamDisk::new(16).blocks, "untouched");
+
+    // Or none at all
+    let done = run(&mut bot, &mut disk, &read10(0, 1), 0, true, &[]);
+    assert_eq!(done.csw, phase(0), "no data");
+
+    // Data for a command that has none is padded
+    let done = run(&mut bot, &mut disk, &[0, 0, 0, 0, 0, 0], 64, true, &[]);
+    assert_eq!(done.csw, passed(64), "unit ready with data");
+
+    // And the transport carries on
+    let done = run(&mut bot, &mut disk, &read10(1, 1), 512, true, &[]);
+    assert_eq!(
+        (done.csw, done.data == disk.blocks[1]),
+        (passed(0), true),
+        "after"
+    );
+}
+
+#[test]
+fn bad_cbw() {
+    let (mut bot, mut disk) = (Bot::new(), RamDisk::new(16));
+    let mut bad = cbw(&[0, 0, 0, 0, 0, 0], 0, false);
+    bad[0] = b'X';
+    bot.out_packet(&mut disk, &bad);
+    assert!(bot.is_stalled(), "stalled");
+    let mut packet = [0; PACKET];
+    assert_eq!(
+        bot.in_packet(&mut disk, &mut packet),
+        None,
+        "nothing to send"
+    );
+
+    // Still stalled, even for a good one
+    bot.out_packet(&mut disk, &cbw(&[0, 0, 0, 0, 0, 0], 0, false));
+    assert!(bot.is_stalled(), "stays stalled");
+
+    bot.reset();
+    let done = run(&mut bot, &mut disk, &[0, 0, 0, 0, 0, 0], 0, false, &[]);
+    assert_eq!(done.csw, passed(0), "after reset");
+
+    for (what, bad) in [
+        ("short", cbw(&[0], 0, false)[..30].to_vec()),
+        ("no cdb", cbw(&[], 0, false)),
+    ] {
+        let mut bot = Bot::new();
+        bot.out_packet(&mut disk, &bad);
+        assert!(bot.is_stalled(), "{}", what);
+    }
+}
+
+#[test]
+fn unknown_command() {
+    let (mut bot, mut disk) = (Bot::new(), RamDisk::new(16));
+    // READ CAPACITY (16) isn't needed below 2 TiB
+    let mut cdb = [0; 16];
+    cdb[0] = 0x9E;
+    cdb[1] = 0x10;
+    let done = run(&mut bot, &mut disk, &cdb, 32, true, &[]);
+    assert_eq!(done.csw, failed(32), "status");
+    assert_eq!(
+        request_sense(&mut bot, &mut disk),
+        Sense::INVALID_COMMAND,
+        "sense"
+    );
+
+    // Only LUN 0
+    let mut packet = cbw(&[0, 0, 0, 0, 0, 0], 0, false);
+    packet[13] = 1;
+    bot.out_packet(&mut disk, &packet);
+    let mut reply = [0; PACKET];
+    assert_eq!(
+        bot.in_packet(&mut disk, &mut reply).map(|_| reply[12]),
+        Some(1),
+        "lun 1"
+    );
+}
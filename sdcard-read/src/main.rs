@@ -1,21 +1,37 @@
 #![no_std]
 #![no_main]
 
-use embedded_hal::delay::DelayNs;
 use hal::block::ImageDef;
 use panic_halt as _;
-use rp235x_hal::{self as hal, Clock};
+use rp235x_hal::{self as hal, Clock as _};
 
 use usb_device::{class_prelude::*, prelude::*};
 use usbd_serial::SerialPort;
 
-use hal::fugit::RateExtU32;
+use hal::fugit::{HertzU32, RateExtU32};
 use heapless::String;
 
-use core::fmt::Write;
+use core::fmt::{self, Write};
 
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal::i2c::I2c;
 use embedded_hal_bus::spi::ExclusiveDevice;
-use embedded_sdmmc::{SdCard, TimeSource, Timestamp, VolumeIdx, VolumeManager};
+use embedded_sdmmc::{
+    BlockDevice, DirEntry, Directory, Mode, SdCard, TimeSource, Timestamp, VolumeIdx, VolumeManager,
+};
+use hal::gpio::{FunctionI2C, Pin};
+
+use sdcard_clock::aon::AonClock;
+use sdcard_clock::date::{self, DateTime};
+use sdcard_clock::ds3231::Ds3231;
+use sdcard_info::bench::{self, Rate};
+use sdcard_info::detect::{self, DetectConfig, Event, Monitor};
+use sdcard_info::register::CardInfo;
+use sdcard_info::spi;
+
+use sdcard_shell::command::{self, Command, ParseError};
+use sdcard_shell::path::Path;
+use sdcard_shell::stream::{tail_start, Head, HexDump, Text};
 
 #[link_section = ".start_block"]
 #[used]
@@ -23,22 +39,161 @@ pub static IMAGE_DEF: ImageDef = hal::block::ImageDef::secure_exe();
 
 const XTAL_FREQ_HZ: u32 = 12_000_000u32;
 
-/// A dummy timesource, which is mostly important for creating files.
-#[derive(Default)]
-pub struct DummyTimesource();
+/// Longest command line we accept.
+const LINE_LEN: usize = 96;
+
+/// What a command fails with when the host stops taking its output.
+const HOST_GONE: &str = "Output stopped, host not reading";
+
+/// Bytes read from a file at a time, one SD block.
+const CHUNK: usize = 512;
+
+/// USB polls without the host taking any data before output is abandoned.
+const MAX_STALLS: u32 = 100_000;
+
+/// What a command fails with when the card stops answering. The card is
+/// then initialized again.
+const CARD_GONE: &str = "SD card not responding";
+
+/// GPIO0 is wired to the socket's card detect switch, which connects it to
+/// ground while a card is in. Without one the card is probed instead.
+const CARD_DETECT: bool = false;
+
+type Timer = hal::Timer<hal::timer::CopyableTimer0>;
+
+/// The SD card's end of SPI0.
+type CardSpi<P, CS> =
+    ExclusiveDevice<hal::spi::Spi<hal::spi::Enabled, hal::pac::SPI0, P, 8>, CS, Timer>;
+
+/// An SD card whose SPI clock can be raised once it's initialized.
+trait SpiCard: BlockDevice {
+    /// Initializes the card afresh at 400 kHz, reads its CID and CSD, and
+    /// raises the SPI clock as far as they and the wiring allow.
+    fn identify(&self, peri_hz: HertzU32) -> Result<CardInfo, &'static str>;
+
+    /// Whether the card that was identified as `known` still answers, and
+    /// is the same card.
+    fn is_still(&self, known: &CardInfo) -> bool;
+}
+
+impl<P, CS> SpiCard for SdCard<CardSpi<P, CS>, Timer>
+where
+    P: hal::spi::ValidSpiPinout<hal::pac::SPI0>,
+    CS: OutputPin,
+{
+    fn identify(&self, peri_hz: HertzU32) -> Result<CardInfo, &'static str> {
+        let set_rate = |dev: &mut CardSpi<P, CS>, hz: u32| {
+            dev.bus_mut().set_baudrate(peri_hz, hz.Hz()).to_Hz()
+        };
+        self.spi(|dev| set_rate(dev, spi::INIT_HZ));
+        self.mark_card_uninit();
+        self.num_bytes().map_err(|_| CARD_GONE)?;
+        // SPI runs at up to half the peripheral clock
+        self.spi(|dev| spi::identify(dev, peri_hz.to_Hz() / 2, set_rate))
+            .map_err(|e| e.as_str())
+    }
+
+    fn is_still(&self, known: &CardInfo) -> bool {
+        self.spi(|dev| spi::read_cid(dev))
+            .is_ok_and(|cid| cid == known.cid)
+    }
+}
+
+/// What the card commands need besides the card.
+struct Board {
+    peri_hz: HertzU32,
+    timer: Timer,
+}
+
+impl Board {
+    fn now_ms(&self) -> u64 {
+        self.timer.get_counter().ticks() / 1000
+    }
+}
+
+/// Whether there's a card to use, and what it is.
+struct CardSlot {
+    monitor: Monitor,
+    info: Option<CardInfo>,
+}
+
+/// The card as the monitor drives it.
+struct Socket<'a, D: BlockDevice, T: TimeSource> {
+    volume_mgr: &'a mut VolumeManager<D, T>,
+    peri_hz: HertzU32,
+    info: &'a mut Option<CardInfo>,
+}
+
+impl<D: SpiCard, T: TimeSource> detect::Slot for Socket<'_, D, T> {
+    fn init(&mut self) -> Result<(), &'static str> {
+        *self.info = Some(self.volume_mgr.device().identify(self.peri_hz)?);
+        Ok(())
+    }
+
+    fn probe(&mut self) -> bool {
+        // Another card in its place counts as this one gone
+        self.info
+            .is_some_and(|info| self.volume_mgr.device().is_still(&info))
+    }
+}
+
+/// File dates from the AON timer. Until the clock is set files get
+/// 1980-01-01, the earliest date FAT can store.
+#[derive(Clone, Copy)]
+struct Clock<'a>(&'a AonClock);
 
-impl TimeSource for DummyTimesource {
-    // In theory you could use the RTC of the rp2040 here, if you had
-    // any external time synchronizing device.
+impl TimeSource for Clock<'_> {
     fn get_timestamp(&self) -> Timestamp {
+        let now = self.0.now().unwrap_or(DateTime::FAT_MIN).fat_clamped();
         Timestamp {
-            year_since_1970: 0,
-            zero_indexed_month: 0,
-            zero_indexed_day: 0,
-            hours: 0,
-            minutes: 0,
-            seconds: 0,
+            year_since_1970: (now.year - 1970) as u8,
+            zero_indexed_month: now.month - 1,
+            zero_indexed_day: now.day - 1,
+            hours: now.hour,
+            minutes: now.minute,
+            seconds: now.second,
+        }
+    }
+}
+
+/// Where the time is kept: the AON timer, which survives resets, and an
+/// optional DS3231, which survives power cycles too.
+struct Rtc<'a, I2C> {
+    aon: &'a AonClock,
+    ds3231: Ds3231<I2C>,
+}
+
+/// The serial port with backpressure: while the USB buffer is full the
+/// device is polled until the host takes some, so long output isn't cut
+/// short. Gives up if the host stops reading.
+struct Console<'a, 'b, B: UsbBus> {
+    serial: &'a mut SerialPort<'b, B>,
+    usb_dev: &'a mut UsbDevice<'b, B>,
+}
+
+impl<B: UsbBus> Console<'_, '_, B> {
+    fn write_bytes(&mut self, mut data: &[u8]) -> fmt::Result {
+        let mut stalls = 0;
+        while !data.is_empty() {
+            match self.serial.write(data) {
+                Ok(n) => {
+                    data = &data[n..];
+                    stalls = 0;
+                }
+                Err(UsbError::WouldBlock) if stalls < MAX_STALLS => {
+                    stalls += 1;
+                    self.usb_dev.poll(&mut [&mut *self.serial]);
+                }
+                Err(_) => return Err(fmt::Error),
+            }
         }
+        Ok(())
+    }
+}
+
+impl<B: UsbBus> Write for Console<'_, '_, B> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_bytes(s.as_bytes())
     }
 }
 
@@ -58,7 +213,7 @@ fn main() -> ! {
     )
     .ok()
     .unwrap();
-    let mut timer = hal::Timer::new_timer0(pac.TIMER0, &mut pac.RESETS, &clocks);
+    let timer = hal::Timer::new_timer0(pac.TIMER0, &mut pac.RESETS, &clocks);
 
     let sio = hal::Sio::new(pac.SIO);
     let pins = hal::gpio::Pins::new(
@@ -87,6 +242,31 @@ fn main() -> ! {
         .device_class(2) // 2 for the CDC, from: https://www.usb.org/defined-class-codes
         .build();
 
+    // An optional DS3231 RTC module on I2C1
+    let sda_pin: Pin<_, FunctionI2C, _> = pins.gpio18.reconfigure();
+    let scl_pin: Pin<_, FunctionI2C, _> = pins.gpio19.reconfigure();
+    let i2c = hal::I2C::i2c1(
+        pac.I2C1,
+        sda_pin,
+        scl_pin,
+        400.kHz(),
+        &mut pac.RESETS,
+        &clocks.system_clock,
+    );
+
+    // The AON timer keeps the time across resets. After a power cycle, take
+    // it from the DS3231 if there is one and it's been set.
+    let aon = AonClock::new(pac.POWMAN, XTAL_FREQ_HZ);
+    let mut rtc = Rtc {
+        aon: &aon,
+        ds3231: Ds3231::new(i2c),
+    };
+    if !aon.is_set() {
+        if let Ok(Some(now)) = rtc.ds3231.now() {
+            aon.set(&now);
+        }
+    }
+
     let spi_cs = pins.gpio1.into_push_pull_output();
     let spi_sck = pins.gpio2.into_function::<hal::gpio::FunctionSpi>();
     let spi_mosi = pins.gpio3.into_function::<hal::gpio::FunctionSpi>();
@@ -102,59 +282,446 @@ fn main() -> ! {
 
     let spi = ExclusiveDevice::new(spi, spi_cs, timer).unwrap();
     let sdcard = SdCard::new(spi, timer);
-    let mut buff: String<64> = String::new();
+    let mut volume_mgr = VolumeManager::new(sdcard, Clock(&aon));
+    let board = Board {
+        peri_hz: clocks.peripheral_clock.freq(),
+        timer,
+    };
+    let mut card_detect = CARD_DETECT.then(|| pins.gpio0.into_pull_up_input());
+    let mut slot = CardSlot {
+        monitor: Monitor::new(DetectConfig::DEFAULT),
+        info: None,
+    };
 
-    let mut volume_mgr = VolumeManager::new(sdcard, DummyTimesource::default());
+    let mut cwd = Path::root();
+    let mut line: String<LINE_LEN> = String::new();
+    let mut last_byte = 0u8;
 
-    let mut is_read = false;
     loop {
-        let _ = usb_dev.poll(&mut [&mut serial]);
-        if !is_read && timer.get_counter().ticks() >= 2_000_000 {
-            is_read = true;
-            serial
-                .write("Init SD card controller and retrieve card size...".as_bytes())
-                .unwrap();
-            match volume_mgr.device().num_bytes() {
-                Ok(size) => {
-                    write!(buff, "card size is {} bytes\r\n", size).unwrap();
-                    serial.write(buff.as_bytes()).unwrap();
+        // Cards are looked for, and set up at full speed, between commands
+        let inserted = card_detect
+            .as_mut()
+            .map(|pin| pin.is_low().unwrap_or(false));
+        let mut socket = Socket {
+            volume_mgr: &mut volume_mgr,
+            peri_hz: board.peri_hz,
+            info: &mut slot.info,
+        };
+        if let Some(event) = slot.monitor.poll(&mut socket, board.now_ms(), inserted) {
+            if event == Event::Removed {
+                slot.info = None;
+                volume_mgr = reopen(volume_mgr);
+            }
+            if usb_dev.state() == UsbDeviceState::Configured {
+                let mut console = Console {
+                    serial: &mut serial,
+                    usb_dev: &mut usb_dev,
+                };
+                // Between prompts, so the prompt and what's typed come back
+                let _ = write!(console, "\r\n{}\r\n{}> {}", event, cwd, line);
+            }
+        }
+
+        if !usb_dev.poll(&mut [&mut serial]) {
+            continue;
+        }
+        let mut buf = [0u8; 64];
+        let Ok(count) = serial.read(&mut buf) else {
+            continue;
+        };
+
+        let mut console = Console {
+            serial: &mut serial,
+            usb_dev: &mut usb_dev,
+        };
+        for &byte in &buf[..count] {
+            match byte {
+                // Terminals send \r, \n or both
+                b'\n' if last_byte == b'\r' => {}
+                b'\r' | b'\n' => {
+                    let _ = console.write_str("\r\n");
+                    let result = match command::parse(&line) {
+                        Ok(command) => run(
+                            command,
+                            &mut cwd,
+                            &mut rtc,
+                            &board,
+                            &mut slot,
+                            &mut volume_mgr,
+                            &mut console,
+                        ),
+                        Err(ParseError::Empty) => Ok(()),
+                        Err(e) => Err(e.as_str()),
+                    };
+                    if let Err(e) = result {
+                        let _ = write!(console, "{}\r\n", e);
+                    }
+                    if result == Err(CARD_GONE) {
+                        slot.monitor.card_failed(board.now_ms());
+                        slot.info = None;
+                        volume_mgr = reopen(volume_mgr);
+                    }
+                    line.clear();
+                    let _ = write!(console, "{}> ", cwd);
                 }
-                Err(e) => {
-                    write!(buff, "Error: {:?}", e).unwrap();
-                    serial.write(buff.as_bytes()).unwrap();
+                // Backspace and DEL
+                0x08 | 0x7F => {
+                    if line.pop().is_some() {
+                        let _ = console.write_bytes(b"\x08 \x08");
+                    }
                 }
+                b' '..=b'~' => {
+                    if line.push(byte as char).is_ok() {
+                        let _ = console.write_bytes(&[byte]);
+                    }
+                }
+                _ => {}
             }
-            buff.clear();
+            last_byte = byte;
+        }
+    }
+}
 
-            let Ok(mut volume0) = volume_mgr.open_volume(VolumeIdx(0)) else {
-                let _ = serial.write("err in open_volume".as_bytes());
-                continue;
-            };
+/// Runs one command. The volume is opened afresh every time, so a card
+/// swapped between commands is picked up.
+fn run<D: SpiCard, T: TimeSource, I2C: I2c, B: UsbBus>(
+    command: Command,
+    cwd: &mut Path,
+    rtc: &mut Rtc<I2C>,
+    board: &Board,
+    slot: &mut CardSlot,
+    volume_mgr: &mut VolumeManager<D, T>,
+    console: &mut Console<B>,
+) -> Result<(), &'static str> {
+    const PAST_END: &str = "Start is past the end of the file";
 
-            let Ok(mut root_dir) = volume0.open_root_dir() else {
-                serial.write("err in open_root_dir".as_bytes()).unwrap();
-                continue;
-            };
+    // These don't need the card
+    match command {
+        Command::Help => return console.write_str(command::HELP).map_err(|_| HOST_GONE),
+        Command::Pwd => return write!(console, "{}\r\n", cwd).map_err(|_| HOST_GONE),
+        Command::Date { time } => return date(time, rtc, console),
+        // Also how to try again once the monitor has given up
+        Command::Card => {
+            let info = volume_mgr.device().identify(board.peri_hz)?;
+            slot.info = Some(info);
+            slot.monitor.card_found(board.now_ms());
+            return write!(console, "{}", info).map_err(|_| HOST_GONE);
+        }
+        _ if !slot.monitor.is_ready() => return Err(slot.monitor.status()),
+        // The root has no entry of its own
+        Command::Stat { path } if cwd.join(path).is_ok_and(|p| p.is_root()) => {
+            let size = volume_mgr.device().num_bytes().map_err(|_| CARD_GONE)?;
+            return write!(console, "/: root directory, card is {} bytes\r\n", size)
+                .map_err(|_| HOST_GONE);
+        }
+        _ => {}
+    }
+
+    let mut volume = volume_mgr.open_volume(VolumeIdx(0)).map_err(sd_error)?;
+    let mut dir = volume.open_root_dir().map_err(sd_error)?;
+
+    match command {
+        Command::Cd { path } => {
+            let target = cwd.join(path.unwrap_or("/")).map_err(|e| e.as_str())?;
+            for name in target.names() {
+                dir.change_dir(name.as_str()).map_err(sd_error)?;
+            }
+            *cwd = target;
+        }
+        Command::Ls { path } => {
+            let target = cwd.join(path.unwrap_or(".")).map_err(|e| e.as_str())?;
+            for name in target.names() {
+                dir.change_dir(name.as_str()).map_err(sd_error)?;
+            }
+            // The listing is written an entry at a time, straight to the port
+            let mut written = Ok(());
+            let (mut files, mut dirs, mut bytes) = (0u32, 0u32, 0u64);
+            dir.iterate_dir(|entry| {
+                if entry.attributes.is_volume() || written.is_err() {
+                    return;
+                }
+                if entry.attributes.is_directory() {
+                    dirs += 1;
+                } else {
+                    files += 1;
+                    bytes += u64::from(entry.size);
+                }
+                written = list_entry(entry, console);
+            })
+            .map_err(sd_error)?;
+            written.map_err(|_| HOST_GONE)?;
+            write!(
+                console,
+                "{} files, {} directories, {} bytes\r\n",
+                files, dirs, bytes
+            )
+            .map_err(|_| HOST_GONE)?;
+        }
+        Command::Cat { path, .. }
+        | Command::Hexdump { path, .. }
+        | Command::Head { path, .. }
+        | Command::Tail { path, .. } => {
+            let target = cwd.join(path).map_err(|e| e.as_str())?;
+            let (dirs, name) = target.split_last().ok_or("Is a directory")?;
+            for dir_name in dirs {
+                dir.change_dir(dir_name.as_str()).map_err(sd_error)?;
+            }
+            let mut file = dir
+                .open_file_in_dir(name.as_str(), Mode::ReadOnly)
+                .map_err(sd_error)?;
+            let size = file.length();
 
-            let Ok(mut my_file) =
-                root_dir.open_file_in_dir("RUST.TXT", embedded_sdmmc::Mode::ReadOnly)
-            else {
-                serial.write("err in open_file_in_dir".as_bytes()).unwrap();
-                continue;
+            // Remembers the last byte, to end the output on a fresh line
+            let mut last = b'\n';
+            let mut out = |bytes: &[u8]| {
+                if let Some(&b) = bytes.last() {
+                    last = b;
+                }
+                console.write_bytes(bytes).map_err(|_| HOST_GONE)
             };
 
-            while !my_file.is_eof() {
-                let mut buffer = [0u8; 32];
-                let num_read = my_file.read(&mut buffer).unwrap();
-                for b in &buffer[0..num_read] {
-                    write!(buff, "{}", *b as char).unwrap();
+            match command {
+                Command::Cat { range, .. } => {
+                    if range.start > size {
+                        return Err(PAST_END);
+                    }
+                    file.seek_from_start(range.start).map_err(sd_error)?;
+                    let mut text = Text::new();
+                    stream(
+                        range.end(size) - range.start,
+                        |buf| file.read(buf).map_err(sd_error),
+                        |chunk| text.feed(chunk, &mut out).map(|_| true),
+                    )?;
+                    text.finish(&mut out)?;
                 }
+                Command::Hexdump { range, .. } => {
+                    if range.start > size {
+                        return Err(PAST_END);
+                    }
+                    file.seek_from_start(range.start).map_err(sd_error)?;
+                    let mut dump = HexDump::new(u64::from(range.start));
+                    stream(
+                        range.end(size) - range.start,
+                        |buf| file.read(buf).map_err(sd_error),
+                        |chunk| dump.feed(chunk, &mut out).map(|_| true),
+                    )?;
+                    dump.finish(&mut out)?;
+                }
+                Command::Head { lines, .. } => {
+                    let mut head = Head::new(lines);
+                    let mut text = Text::new();
+                    stream(
+                        size,
+                        |buf| file.read(buf).map_err(sd_error),
+                        |chunk| {
+                            text.feed(head.take(chunk), &mut out)?;
+                            Ok(!head.is_done())
+                        },
+                    )?;
+                    text.finish(&mut out)?;
+                }
+                Command::Tail { lines, .. } => {
+                    let start = tail_start(size, lines, |at, buf| {
+                        file.seek_from_start(at).map_err(sd_error)?;
+                        file.read(buf).map_err(sd_error)
+                    })?;
+                    file.seek_from_start(start).map_err(sd_error)?;
+                    let mut text = Text::new();
+                    stream(
+                        size - start,
+                        |buf| file.read(buf).map_err(sd_error),
+                        |chunk| text.feed(chunk, &mut out).map(|_| true),
+                    )?;
+                    text.finish(&mut out)?;
+                }
+                _ => {}
+            }
+            if last != b'\n' {
+                console.write_str("\r\n").map_err(|_| HOST_GONE)?;
             }
-            serial.write(buff.as_bytes()).unwrap();
         }
-        buff.clear();
+        Command::Stat { path } => {
+            let target = cwd.join(path).map_err(|e| e.as_str())?;
+            let (dirs, name) = target.split_last().ok_or("Is the root directory")?;
+            for dir_name in dirs {
+                dir.change_dir(dir_name.as_str()).map_err(sd_error)?;
+            }
+            let entry = dir.find_directory_entry(name.as_str()).map_err(sd_error)?;
+            stat_entry(&target, &entry, console).map_err(|_| HOST_GONE)?;
+        }
+        Command::Bench { kib } => bench(kib, &mut dir, &board.timer, console)?,
+        Command::Help | Command::Pwd | Command::Date { .. } | Command::Card => {}
+    }
+    Ok(())
+}
+
+/// Writes `kib` KiB to a file in the root directory, reads it back and
+/// checks it, deletes it, and shows how fast each way went.
+fn bench<D: BlockDevice, T: TimeSource, B: UsbBus>(
+    kib: u32,
+    dir: &mut Directory<'_, D, T, 4, 4, 1>,
+    timer: &Timer,
+    console: &mut Console<B>,
+) -> Result<(), &'static str> {
+    const NAME: &str = "BENCH.TMP";
+    let size = kib
+        .checked_mul(1024)
+        .filter(|&size| size > 0)
+        .ok_or("Size is 1 to 4194303 KiB")?;
+    let mut buf = [0u8; CHUNK];
+    let micros = || timer.get_counter().ticks();
+
+    let mut file = dir
+        .open_file_in_dir(NAME, Mode::ReadWriteCreateOrTruncate)
+        .map_err(sd_error)?;
+    let start = micros();
+    let mut pos = 0;
+    while pos < size {
+        let n = CHUNK.min((size - pos) as usize);
+        bench::fill(pos, &mut buf[..n]);
+        file.write(&buf[..n]).map_err(sd_error)?;
+        pos += n as u32;
+    }
+    file.flush().map_err(sd_error)?;
+    let write = Rate {
+        bytes: u64::from(size),
+        micros: micros() - start,
+    };
+    file.close().map_err(sd_error)?;
+
+    let mut file = dir
+        .open_file_in_dir(NAME, Mode::ReadOnly)
+        .map_err(sd_error)?;
+    let start = micros();
+    let mut pos = 0;
+    let mut wrong = None;
+    while !file.is_eof() {
+        let n = file.read(&mut buf).map_err(sd_error)?;
+        wrong = wrong.or(bench::check(pos, &buf[..n]));
+        pos += n as u32;
+    }
+    let read = Rate {
+        bytes: u64::from(pos),
+        micros: micros() - start,
+    };
+    file.close().map_err(sd_error)?;
+    dir.delete_file_in_dir(NAME).map_err(sd_error)?;
+
+    write!(console, "Write: {}\r\nRead:  {}\r\n", write, read).map_err(|_| HOST_GONE)?;
+    match wrong {
+        Some(at) => write!(console, "Read back wrong data at byte {}\r\n", at),
+        None if pos != size => write!(console, "Read back {} of {} bytes\r\n", pos, size),
+        None => Ok(()),
+    }
+    .map_err(|_| HOST_GONE)
+}
+
+/// Shows the time, or sets both clocks to `time`.
+fn date<I2C: I2c, B: UsbBus>(
+    time: Option<&str>,
+    rtc: &mut Rtc<I2C>,
+    console: &mut Console<B>,
+) -> Result<(), &'static str> {
+    let Some(time) = time else {
+        return match rtc.aon.now() {
+            Some(now) => write!(console, "{}\r\n", now).map_err(|_| HOST_GONE),
+            None => Err("Clock not set, use date YYYY-MM-DD HH:MM[:SS]"),
+        };
+    };
+    let time = date::parse(time).ok_or("Dates are YYYY-MM-DD HH:MM[:SS]")?;
+    rtc.aon.set(&time);
+    // Not having a DS3231 is fine, the AON timer still has the time
+    match rtc.ds3231.set(&time) {
+        Ok(()) => write!(console, "Clock set to {}\r\n", time),
+        Err(e) => write!(console, "Clock set to {}, DS3231: {}\r\n", time, e.as_str()),
+    }
+    .map_err(|_| HOST_GONE)
+}
+
+/// Reads up to `len` bytes a chunk at a time with `read` and hands each chunk
+/// to `each`, which returns whether it wants more.
+fn stream(
+    mut len: u32,
+    mut read: impl FnMut(&mut [u8]) -> Result<usize, &'static str>,
+    mut each: impl FnMut(&[u8]) -> Result<bool, &'static str>,
+) -> Result<(), &'static str> {
+    let mut buffer = [0u8; CHUNK];
+    while len > 0 {
+        let want = buffer.len().min(len as usize);
+        let num_read = read(&mut buffer[..want])?;
+        if num_read == 0 || !each(&buffer[..num_read])? {
+            break;
+        }
+        len -= num_read as u32;
+    }
+    Ok(())
+}
+
+/// One line of `ls`: modification time, size or `<DIR>`, and name.
+fn list_entry<B: UsbBus>(entry: &DirEntry, console: &mut Console<B>) -> fmt::Result {
+    write_time(&entry.mtime, console)?;
+    if entry.attributes.is_directory() {
+        write!(console, "  {:>10}  {}\r\n", "<DIR>", entry.name)
+    } else {
+        write!(console, "  {:>10}  {}\r\n", entry.size, entry.name)
+    }
+}
+
+fn stat_entry<B: UsbBus>(path: &Path, entry: &DirEntry, console: &mut Console<B>) -> fmt::Result {
+    let attrs = &entry.attributes;
+    let kind = if attrs.is_directory() {
+        "directory"
+    } else {
+        "file"
+    };
+    write!(console, "  Path: {}\r\n  Type: {}\r\n", path, kind)?;
+    write!(console, "  Size: {} bytes\r\n", entry.size)?;
+    console.write_str("  Modified: ")?;
+    write_time(&entry.mtime, console)?;
+    console.write_str("\r\n  Created: ")?;
+    write_time(&entry.ctime, console)?;
+    write!(
+        console,
+        "\r\n  Attributes: {}{}{}{}\r\n  First cluster: {}\r\n",
+        if attrs.is_read_only() { 'R' } else { '-' },
+        if attrs.is_hidden() { 'H' } else { '-' },
+        if attrs.is_system() { 'S' } else { '-' },
+        if attrs.is_archive() { 'A' } else { '-' },
+        entry.cluster.0
+    )
+}
+
+fn write_time<B: UsbBus>(time: &Timestamp, console: &mut Console<B>) -> fmt::Result {
+    write!(
+        console,
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        1970 + u16::from(time.year_since_1970),
+        time.zero_indexed_month + 1,
+        time.zero_indexed_day + 1,
+        time.hours,
+        time.minutes,
+        time.seconds
+    )
+}
+
+/// Starts the volume manager over, closing everything that was open. A
+/// handle dropped while the card was gone can't be closed properly and
+/// would otherwise stay open for good.
+fn reopen<D: BlockDevice, T: TimeSource>(volume_mgr: VolumeManager<D, T>) -> VolumeManager<D, T> {
+    let (device, time_source) = volume_mgr.free();
+    VolumeManager::new(device, time_source)
+}
 
-        timer.delay_ms(50);
+fn sd_error<E>(e: embedded_sdmmc::Error<E>) -> &'static str {
+    use embedded_sdmmc::Error;
+    match e {
+        Error::NotFound => "No such file or directory",
+        Error::OpenedDirAsFile => "Is a directory",
+        Error::OpenedFileAsDir => "Not a directory",
+        Error::FilenameError(_) => "Bad file name",
+        Error::FormatError(_) | Error::NoSuchVolume => "No FAT volume on the card",
+        Error::DeviceError(_) => CARD_GONE,
+        _ => "SD card error",
     }
 }
 
@@ -163,7 +730,7 @@ fn main() -> ! {
 pub static PICOTOOL_ENTRIES: [hal::binary_info::EntryAddr; 5] = [
     hal::binary_info::rp_cargo_bin_name!(),
     hal::binary_info::rp_cargo_version!(),
-    hal::binary_info::rp_program_description!(c"USB Fun"),
+    hal::binary_info::rp_program_description!(c"SD Card Shell"),
     hal::binary_info::rp_cargo_homepage_url!(),
     hal::binary_info::rp_program_build_attribute!(),
 ];
@@ -0,0 +1,161 @@
+//! Parser for the shell's command lines.
+
+/// Lines `head` and `tail` show when not told.
+pub const DEFAULT_LINES: u32 = 10;
+
+/// Size of the file `bench` writes and reads back when not told, in KiB.
+pub const DEFAULT_BENCH_KIB: u32 = 256;
+
+pub const HELP: &str = "\
+ls [dir]                      list a directory\r\n\
+cd [dir]                      change directory, to the root without an argument\r\n\
+pwd                           print the current directory\r\n\
+cat <file> [start [len]]      print a file, or len bytes from start\r\n\
+hexdump <file> [start [len]]  the same in hex, also hd\r\n\
+head <file> [lines]           the first lines of a file, 10 by default\r\n\
+tail <file> [lines]           the last lines of a file, 10 by default\r\n\
+stat <path>                   size, dates and attributes of a file or directory\r\n\
+date [YYYY-MM-DD HH:MM[:SS]]  show or set the clock used for file dates\r\n\
+card                          the card's maker, size and SPI clock\r\n\
+bench [KiB]                   time writing and reading a file, 256 KiB by default\r\n\
+help                          this text\r\n";
+
+/// A parsed command. Paths are as typed; resolve them against the working
+/// directory with [`Path::join`](crate::path::Path::join). `Date` has the
+/// rest of the line, if any, to set the clock to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command<'a> {
+    Help,
+    Ls { path: Option<&'a str> },
+    Cd { path: Option<&'a str> },
+    Pwd,
+    Cat { path: &'a str, range: ByteRange },
+    Hexdump { path: &'a str, range: ByteRange },
+    Head { path: &'a str, lines: u32 },
+    Tail { path: &'a str, lines: u32 },
+    Stat { path: &'a str },
+    Date { time: Option<&'a str> },
+    Card,
+    Bench { kib: u32 },
+}
+
+/// Part of a file: `len` bytes from `start`, or to the end without a `len`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ByteRange {
+    pub start: u32,
+    pub len: Option<u32>,
+}
+
+impl ByteRange {
+    /// Where the range ends in a file of `size` bytes.
+    pub fn end(&self, size: u32) -> u32 {
+        match self.len {
+            Some(len) => self.start.saturating_add(len).min(size),
+            None => size,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    Empty,
+    UnknownCommand,
+    MissingArgument,
+    TooManyArguments,
+    /// Not a number, or too big for 32 bits.
+    BadNumber,
+}
+
+impl ParseError {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            ParseError::Empty => "",
+            ParseError::UnknownCommand => "Unknown command, try help",
+            ParseError::MissingArgument => "Missing argument",
+            ParseError::TooManyArguments => "Too many arguments",
+            ParseError::BadNumber => "Numbers are decimal or 0x hex",
+        }
+    }
+}
+
+/// Parses one line. Words are separated by spaces; commands are case
+/// insensitive. Numbers can be decimal or hex with `0x`.
+pub fn parse(line: &str) -> Result<Command<'_>, ParseError> {
+    let mut args = line.split_whitespace();
+    let name = args.next().ok_or(ParseError::Empty)?;
+
+    let command = if name.eq_ignore_ascii_case("help") || name == "?" {
+        Command::Help
+    } else if name.eq_ignore_ascii_case("ls") || name.eq_ignore_ascii_case("dir") {
+        Command::Ls { path: args.next() }
+    } else if name.eq_ignore_ascii_case("cd") {
+        Command::Cd { path: args.next() }
+    } else if name.eq_ignore_ascii_case("pwd") {
+        Command::Pwd
+    } else if name.eq_ignore_ascii_case("cat") {
+        Command::Cat {
+            path: next(&mut args)?,
+            range: range(&mut args)?,
+        }
+    } else if name.eq_ignore_ascii_case("hexdump") || name.eq_ignore_ascii_case("hd") {
+        Command::Hexdump {
+            path: next(&mut args)?,
+            range: range(&mut args)?,
+        }
+    } else if name.eq_ignore_ascii_case("head") {
+        Command::Head {
+            path: next(&mut args)?,
+            lines: number(args.next())?.unwrap_or(DEFAULT_LINES),
+        }
+    } else if name.eq_ignore_ascii_case("tail") {
+        Command::Tail {
+            path: next(&mut args)?,
+            lines: number(args.next())?.unwrap_or(DEFAULT_LINES),
+        }
+    } else if name.eq_ignore_ascii_case("stat") {
+        Command::Stat {
+            path: next(&mut args)?,
+        }
+    } else if name.eq_ignore_ascii_case("date") {
+        // The date and time have a space in between, so take them whole
+        let rest = line.trim_start()[name.len()..].trim();
+        return Ok(Command::Date {
+            time: (!rest.is_empty()).then_some(rest),
+        });
+    } else if name.eq_ignore_ascii_case("card") {
+        Command::Card
+    } else if name.eq_ignore_ascii_case("bench") {
+        Command::Bench {
+            kib: number(args.next())?.unwrap_or(DEFAULT_BENCH_KIB),
+        }
+    } else {
+        return Err(ParseError::UnknownCommand);
+    };
+
+    if args.next().is_some() {
+        return Err(ParseError::TooManyArguments);
+    }
+    Ok(command)
+}
+
+fn next<'a>(args: &mut impl Iterator<Item = &'a str>) -> Result<&'a str, ParseError> {
+    args.next().ok_or(ParseError::MissingArgument)
+}
+
+fn range<'a>(args: &mut impl Iterator<Item = &'a str>) -> Result<ByteRange, ParseError> {
+    Ok(ByteRange {
+        start: number(args.next())?.unwrap_or(0),
+        len: number(args.next())?,
+    })
+}
+
+fn number(arg: Option<&str>) -> Result<Option<u32>, ParseError> {
+    let Some(arg) = arg else {
+        return Ok(None);
+    };
+    let parsed = match arg.strip_prefix("0x").or_else(|| arg.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => arg.parse(),
+    };
+    parsed.map(Some).map_err(|_| ParseError::BadNumber)
+}
@@ -0,0 +1,9 @@
+//! The SD card shell's command parser, path handling and output streaming.
+//!
+//! Plain `no_std` code with no dependency on the HAL or the SD card driver,
+//! so it can be built and checked on the host.
+#![no_std]
+
+pub mod command;
+pub mod path;
+pub mod stream;
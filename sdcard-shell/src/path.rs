@@ -0,0 +1,140 @@
+//! Paths made of FAT short (8.3) names.
+//!
+//! The shell keeps the working directory as a [`Path`] and resolves what the
+//! user types against it. `/` starts from the root, `.` and empty parts are
+//! skipped, and `..` goes up a level, stopping at the root. Names are
+//! upper-cased, since that's how they are stored.
+
+use core::fmt;
+
+/// Deepest directory a path can reach.
+pub const MAX_DEPTH: usize = 8;
+
+/// Characters FAT allows in a short name besides letters and digits.
+const SPECIAL: &[u8] = b"!#$%&'()-@^_`{}~";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathError {
+    /// Not a valid 8.3 name.
+    BadName,
+    TooDeep,
+}
+
+impl PathError {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            PathError::BadName => "Names must be 8.3, like NOTES.TXT",
+            PathError::TooDeep => "Path too deep",
+        }
+    }
+}
+
+/// One 8.3 name, upper case, as `BASE` or `BASE.EXT`.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct Name {
+    bytes: [u8; 12],
+    len: u8,
+}
+
+impl Name {
+    pub fn new(name: &str) -> Result<Name, PathError> {
+        let (base, ext) = match name.split_once('.') {
+            Some((base, ext)) => (base, Some(ext)),
+            None => (name, None),
+        };
+        let valid = |part: &str, max: usize| {
+            (1..=max).contains(&part.len())
+                && part
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || SPECIAL.contains(&b))
+        };
+        if !valid(base, 8) || !ext.is_none_or(|ext| valid(ext, 3)) {
+            return Err(PathError::BadName);
+        }
+
+        let mut out = Name::default();
+        for b in name.bytes() {
+            out.bytes[out.len as usize] = b.to_ascii_uppercase();
+            out.len += 1;
+        }
+        Ok(out)
+    }
+
+    pub fn as_str(&self) -> &str {
+        // Only ASCII gets in
+        core::str::from_utf8(&self.bytes[..self.len as usize]).unwrap_or("")
+    }
+}
+
+impl fmt::Display for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Name {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+/// An absolute path, as the names leading down from the root.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Path {
+    names: [Name; MAX_DEPTH],
+    depth: usize,
+}
+
+impl Path {
+    pub fn root() -> Path {
+        Path::default()
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.depth == 0
+    }
+
+    pub fn names(&self) -> &[Name] {
+        &self.names[..self.depth]
+    }
+
+    /// The directory the last name is in, and the last name. `None` for the
+    /// root, which has no entry of its own.
+    pub fn split_last(&self) -> Option<(&[Name], &Name)> {
+        self.names().split_last().map(|(last, dirs)| (dirs, last))
+    }
+
+    /// Resolves `input` against this path.
+    pub fn join(&self, input: &str) -> Result<Path, PathError> {
+        let mut path = if input.starts_with('/') {
+            Path::root()
+        } else {
+            *self
+        };
+        for part in input.split('/') {
+            match part {
+                "" | "." => {}
+                ".." => path.depth = path.depth.saturating_sub(1),
+                name => {
+                    let name = Name::new(name)?;
+                    let slot = path.names.get_mut(path.depth).ok_or(PathError::TooDeep)?;
+                    *slot = name;
+                    path.depth += 1;
+                }
+            }
+        }
+        Ok(path)
+    }
+}
+
+impl fmt::Display for Path {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_root() {
+            return f.write_str("/");
+        }
+        for name in self.names() {
+            write!(f, "/{}", name)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,258 @@
+//! Turning file contents into terminal output a chunk at a time, so files of
+//! any size go out through a small buffer.
+//!
+//! Output goes to a callback that takes bytes and may fail; the firmware's
+//! callback blocks until USB has room and fails if the host stops reading.
+
+/// Shown in place of bytes that aren't UTF-8.
+const REPLACEMENT: &[u8] = "\u{FFFD}".as_bytes();
+
+/// Bytes per hexdump line.
+const HEX_LINE: usize = 16;
+/// Where the ASCII column starts: `00000000  `, 16 × `xx `, the gap after
+/// the eighth byte and one more space.
+const HEX_ASCII: usize = 10 + HEX_LINE * 3 + 2;
+/// The ASCII column is `|` + 16 + `|`, then CRLF.
+const HEX_LINE_LEN: usize = HEX_ASCII + HEX_LINE + 4;
+
+/// Passes UTF-8 text through with newlines as CRLF. A character split
+/// between two chunks is held back until the rest of it arrives, and bytes
+/// that can't be decoded come out as U+FFFD.
+#[derive(Debug, Clone, Default)]
+pub struct Text {
+    /// The start of a character cut off at the end of the last chunk.
+    pending: [u8; 4],
+    pending_len: usize,
+    last_cr: bool,
+}
+
+impl Text {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn feed<E>(
+        &mut self,
+        mut chunk: &[u8],
+        out: &mut impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        // Finish the character left over from the last chunk first
+        while self.pending_len > 0 {
+            let Some((&b, rest)) = chunk.split_first() else {
+                return Ok(());
+            };
+            self.pending[self.pending_len] = b;
+            match core::str::from_utf8(&self.pending[..=self.pending_len]) {
+                Ok(_) => {
+                    chunk = rest;
+                    out(&self.pending[..=self.pending_len])?;
+                    self.pending_len = 0;
+                    self.last_cr = false;
+                }
+                Err(e) if e.error_len().is_none() => {
+                    chunk = rest;
+                    self.pending_len += 1;
+                }
+                // Leave the byte that broke it for the main loop
+                Err(_) => {
+                    out(REPLACEMENT)?;
+                    self.pending_len = 0;
+                    self.last_cr = false;
+                }
+            }
+        }
+
+        loop {
+            match core::str::from_utf8(chunk) {
+                Ok(text) => return self.text(text.as_bytes(), out),
+                Err(e) => {
+                    let (valid, rest) = chunk.split_at(e.valid_up_to());
+                    self.text(valid, out)?;
+                    match e.error_len() {
+                        Some(len) => {
+                            out(REPLACEMENT)?;
+                            self.last_cr = false;
+                            chunk = &rest[len..];
+                        }
+                        // Cut off by the end of the chunk
+                        None => {
+                            self.pending[..rest.len()].copy_from_slice(rest);
+                            self.pending_len = rest.len();
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Ends the stream. A character cut short by the end of the file comes
+    /// out as U+FFFD.
+    pub fn finish<E>(&mut self, out: &mut impl FnMut(&[u8]) -> Result<(), E>) -> Result<(), E> {
+        if self.pending_len > 0 {
+            self.pending_len = 0;
+            out(REPLACEMENT)?;
+        }
+        Ok(())
+    }
+
+    /// Writes valid text, turning a `\n` not already after a `\r` into CRLF.
+    fn text<E>(
+        &mut self,
+        text: &[u8],
+        out: &mut impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let mut start = 0;
+        for (i, &b) in text.iter().enumerate() {
+            if b == b'\n' && !self.last_cr {
+                out(&text[start..i])?;
+                out(b"\r")?;
+                start = i;
+            }
+            self.last_cr = b == b'\r';
+        }
+        out(&text[start..])
+    }
+}
+
+/// Formats bytes like `hexdump -C`: offset, sixteen bytes in hex, and the
+/// printable ones as ASCII.
+#[derive(Debug, Clone)]
+pub struct HexDump {
+    offset: u64,
+    line: [u8; HEX_LINE],
+    len: usize,
+}
+
+impl HexDump {
+    /// `offset` is where in the file the first byte fed comes from.
+    pub fn new(offset: u64) -> Self {
+        Self {
+            offset,
+            line: [0; HEX_LINE],
+            len: 0,
+        }
+    }
+
+    pub fn feed<E>(
+        &mut self,
+        chunk: &[u8],
+        out: &mut impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), E> {
+        for &b in chunk {
+            self.line[self.len] = b;
+            self.len += 1;
+            if self.len == HEX_LINE {
+                self.flush(out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes out the last, partial line.
+    pub fn finish<E>(&mut self, out: &mut impl FnMut(&[u8]) -> Result<(), E>) -> Result<(), E> {
+        if self.len > 0 {
+            self.flush(out)?;
+        }
+        Ok(())
+    }
+
+    fn flush<E>(&mut self, out: &mut impl FnMut(&[u8]) -> Result<(), E>) -> Result<(), E> {
+        const HEX: &[u8; 16] = b"0123456789abcdef";
+        let mut text = [b' '; HEX_LINE_LEN];
+        for (i, slot) in text[..8].iter_mut().enumerate() {
+            *slot = HEX[((self.offset >> (28 - 4 * i)) & 0xF) as usize];
+        }
+        let ascii = HEX_ASCII;
+        text[ascii] = b'|';
+        for (i, &b) in self.line[..self.len].iter().enumerate() {
+            // An extra space after the eighth byte
+            let at = 10 + i * 3 + i / 8;
+            text[at] = HEX[usize::from(b >> 4)];
+            text[at + 1] = HEX[usize::from(b & 0xF)];
+            text[ascii + 1 + i] = if b.is_ascii_graphic() || b == b' ' {
+                b
+            } else {
+                b'.'
+            };
+        }
+        let end = ascii + 1 + self.len;
+        text[end..end + 3].copy_from_slice(b"|\r\n");
+
+        self.offset += self.len as u64;
+        self.len = 0;
+        out(&text[..end + 3])
+    }
+}
+
+/// Lets the first `lines` lines through.
+#[derive(Debug, Clone)]
+pub struct Head {
+    left: u32,
+}
+
+impl Head {
+    pub fn new(lines: u32) -> Self {
+        Self { left: lines }
+    }
+
+    /// The part of `chunk` within the limit.
+    pub fn take<'a>(&mut self, chunk: &'a [u8]) -> &'a [u8] {
+        if self.left == 0 {
+            return &[];
+        }
+        for (i, &b) in chunk.iter().enumerate() {
+            if b == b'\n' {
+                self.left -= 1;
+                if self.left == 0 {
+                    return &chunk[..=i];
+                }
+            }
+        }
+        chunk
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.left == 0
+    }
+}
+
+/// Finds where the last `lines` lines of a file of `len` bytes start,
+/// reading backwards from the end with `read_at(offset, buf)` so only the
+/// tail of the file is read. A newline at the very end doesn't start another
+/// line.
+pub fn tail_start<E>(
+    len: u32,
+    lines: u32,
+    mut read_at: impl FnMut(u32, &mut [u8]) -> Result<usize, E>,
+) -> Result<u32, E> {
+    if lines == 0 {
+        return Ok(len);
+    }
+    let mut buf = [0u8; 64];
+    let mut end = len;
+    let mut seen = 0;
+    let mut last_byte = true;
+    while end > 0 {
+        let start = end.saturating_sub(buf.len() as u32);
+        let want = (end - start) as usize;
+        let mut got = 0;
+        while got < want {
+            match read_at(start + got as u32, &mut buf[got..want])? {
+                0 => break,
+                n => got += n,
+            }
+        }
+        for i in (0..got).rev() {
+            if buf[i] == b'\n' && !last_byte {
+                seen += 1;
+                if seen == lines {
+                    return Ok(start + i as u32 + 1);
+                }
+            }
+            last_byte = false;
+        }
+        end = start;
+    }
+    Ok(0)
+}
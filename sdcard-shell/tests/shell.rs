@@ -0,0 +1,372 @@
+//! Runs the shell's parser, path handling and output streaming on the host.
+
+use sdcard_shell::command::{self, ByteRange, Command, ParseError, DEFAULT_LINES};
+use sdcard_shell::path::{Name, Path, PathError, MAX_DEPTH};
+use sdcard_shell::stream::{tail_start, Head, HexDump, Text};
+
+fn path(s: &str) -> Path {
+    Path::root().join(s).unwrap()
+}
+
+#[test]
+fn commands() {
+    let cases = [
+        ("help", Command::Help),
+        ("?", Command::Help),
+        ("ls", Command::Ls { path: None }),
+        ("  LS   logs ", Command::Ls { path: Some("logs") }),
+        ("dir /", Command::Ls { path: Some("/") }),
+        ("cd", Command::Cd { path: None }),
+        ("cd ..", Command::Cd { path: Some("..") }),
+        ("pwd", Command::Pwd),
+        (
+            "cat rust.txt",
+            Command::Cat {
+                path: "rust.txt",
+                range: ByteRange::default(),
+            },
+        ),
+        (
+            "cat a.bin 100 0x20",
+            Command::Cat {
+                path: "a.bin",
+                range: ByteRange {
+                    start: 100,
+                    len: Some(32),
+                },
+            },
+        ),
+        (
+            "hd a.bin 0X1F0",
+            Command::Hexdump {
+                path: "a.bin",
+                range: ByteRange {
+                    start: 0x1f0,
+                    len: None,
+                },
+            },
+        ),
+        (
+            "head log.csv",
+            Command::Head {
+                path: "log.csv",
+                lines: DEFAULT_LINES,
+            },
+        ),
+        (
+            "tail log.csv 3",
+            Command::Tail {
+                path: "log.csv",
+                lines: 3,
+            },
+        ),
+        ("date", Command::Date { time: None }),
+        (
+            " DATE  2026-10-17 14:30 ",
+            Command::Date {
+                time: Some("2026-10-17 14:30"),
+            },
+        ),
+        ("card", Command::Card),
+        ("bench", Command::Bench { kib: 256 }),
+        ("Bench 0x400", Command::Bench { kib: 1024 }),
+        (
+            "Stat /LOGS/A.CSV",
+            Command::Stat {
+                path: "/LOGS/A.CSV",
+            },
+        ),
+    ];
+    for (line, expected) in cases {
+        assert_eq!(command::parse(line), Ok(expected), "{}", line);
+    }
+}
+
+#[test]
+fn bad_commands() {
+    let cases = [
+        ("", ParseError::Empty),
+        ("   ", ParseError::Empty),
+        ("rm x", ParseError::UnknownCommand),
+        ("cat", ParseError::MissingArgument),
+        ("stat", ParseError::MissingArgument),
+        ("ls a b", ParseError::TooManyArguments),
+        ("pwd x", ParseError::TooManyArguments),
+        ("cat a 1 2 3", ParseError::TooManyArguments),
+        ("tail a 1 2", ParseError::TooManyArguments),
+        ("head", ParseError::MissingArgument),
+        ("head a -1", ParseError::BadNumber),
+        ("cat a 4294967296", ParseError::BadNumber),
+        ("hd a 0x", ParseError::BadNumber),
+        ("hd a 12k", ParseError::BadNumber),
+        ("card x", ParseError::TooManyArguments),
+        ("bench 1M", ParseError::BadNumber),
+    ];
+    for (line, expected) in cases {
+        assert_eq!(command::parse(line), Err(expected), "{}", line);
+    }
+}
+
+#[test]
+fn names() {
+    for (name, expected) in [
+        ("rust.txt", "RUST.TXT"),
+        ("LOGS", "LOGS"),
+        ("a", "A"),
+        ("12345678.abc", "12345678.ABC"),
+        ("~$x_1.{}", "~$X_1.{}"),
+    ] {
+        let parsed = Name::new(name).unwrap_or_else(|e| panic!("{}: {:?}", name, e));
+        assert_eq!(parsed.as_str(), expected, "{}", name);
+    }
+    for name in [
+        "",
+        ".txt",
+        "name.",
+        "toolongname",
+        "a.text",
+        "a.b.c",
+        "sp ace",
+        "star*",
+        "ü.txt",
+    ] {
+        assert_eq!(Name::new(name).err(), Some(PathError::BadName), "{}", name);
+    }
+}
+
+#[test]
+fn paths() {
+    let cwd = path("/logs/2026");
+    let cases = [
+        ("", "/LOGS/2026"),
+        (".", "/LOGS/2026"),
+        ("jan.csv", "/LOGS/2026/JAN.CSV"),
+        ("..", "/LOGS"),
+        ("../..", "/"),
+        ("../../../..", "/"),
+        ("/", "/"),
+        ("/data//x/./y.bin", "/DATA/X/Y.BIN"),
+        ("../2025/", "/LOGS/2025"),
+    ];
+    for (input, expected) in cases {
+        let joined = cwd
+            .join(input)
+            .unwrap_or_else(|e| panic!("{}: {:?}", input, e));
+        assert_eq!(joined.to_string().as_str(), expected, "{}", input);
+    }
+
+    let file = cwd.join("jan.csv").unwrap();
+    let (dirs, last) = file.split_last().unwrap();
+    assert_eq!(
+        (dirs.len(), dirs[1].as_str(), last.as_str()),
+        (2, "2026", "JAN.CSV"),
+        "split_last"
+    );
+    assert_eq!(Path::root().split_last(), None, "split_last of root");
+    assert!(cwd.join("/..").unwrap().is_root(), "is_root");
+}
+
+#[test]
+fn limits() {
+    let deep = "/a".repeat(MAX_DEPTH);
+    let cwd = path(&deep);
+    assert_eq!(cwd.names().len(), MAX_DEPTH, "depth");
+    assert_eq!(cwd.join("b").err(), Some(PathError::TooDeep), "one more");
+    // Going up and down again within the limit is fine
+    assert_eq!(
+        cwd.join("../b").map(|p| p.names().len()),
+        Ok(MAX_DEPTH),
+        "up and down"
+    );
+    assert_eq!(
+        cwd.join("../bad name").err(),
+        Some(PathError::BadName),
+        "bad part"
+    );
+}
+
+/// Runs `feed` over `input` cut into `chunk` byte pieces and collects what
+/// comes out.
+fn stream(input: &[u8], chunk: usize, mut feed: impl FnMut(&[u8], &mut Vec<u8>)) -> Vec<u8> {
+    let mut out = Vec::new();
+    for piece in input.chunks(chunk.max(1)) {
+        feed(piece, &mut out);
+    }
+    out
+}
+
+fn text(input: &[u8], chunk: usize) -> Vec<u8> {
+    let mut text = Text::new();
+    let mut out = stream(input, chunk, |piece, out| {
+        text.feed::<()>(piece, &mut |b| {
+            out.extend_from_slice(b);
+            Ok(())
+        })
+        .unwrap()
+    });
+    text.finish::<()>(&mut |b| {
+        out.extend_from_slice(b);
+        Ok(())
+    })
+    .unwrap();
+    out
+}
+
+#[test]
+fn utf8_chunks() {
+    let input = "a€b 𝄞 ü".as_bytes();
+    // Every way of cutting it gives the same text back
+    for chunk in 1..=input.len() {
+        assert_eq!(text(input, chunk), input.to_vec(), "{} byte chunks", chunk);
+    }
+
+    let bad: [(&[u8], &str); 5] = [
+        (b"a\xffb", "a\u{FFFD}b"),
+        // Two continuation bytes on their own
+        (b"\x80\x80", "\u{FFFD}\u{FFFD}"),
+        // A lead byte followed by ASCII keeps the ASCII
+        (b"\xe2x", "\u{FFFD}x"),
+        // Cut short by the end of the file
+        (b"ok\xe2\x82", "ok\u{FFFD}"),
+        (b"\xf0\x9d\x84", "\u{FFFD}"),
+    ];
+    for (input, expected) in bad {
+        for chunk in 1..=input.len() {
+            let got = text(input, chunk);
+            assert_eq!(
+                String::from_utf8_lossy(&got).as_ref(),
+                expected,
+                "{:?} in {} byte chunks",
+                input,
+                chunk
+            );
+        }
+    }
+}
+
+#[test]
+fn line_endings() {
+    let cases: [(&[u8], &[u8]); 4] = [
+        (b"a\nb\n", b"a\r\nb\r\n"),
+        (b"a\r\nb", b"a\r\nb"),
+        (b"\n\n", b"\r\n\r\n"),
+        (b"\r\r\n", b"\r\r\n"),
+    ];
+    for (input, expected) in cases {
+        for chunk in 1..=input.len() {
+            assert_eq!(
+                text(input, chunk),
+                expected.to_vec(),
+                "{:?} in {} byte chunks",
+                input,
+                chunk
+            );
+        }
+    }
+}
+
+fn dump(input: &[u8], offset: u64, chunk: usize) -> String {
+    let mut dump = HexDump::new(offset);
+    let mut out = stream(input, chunk, |piece, out| {
+        dump.feed::<()>(piece, &mut |b| {
+            out.extend_from_slice(b);
+            Ok(())
+        })
+        .unwrap()
+    });
+    dump.finish::<()>(&mut |b| {
+        out.extend_from_slice(b);
+        Ok(())
+    })
+    .unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn hexdump() {
+    // As `hexdump -C` prints them
+    let expected = "\
+00000000  48 65 6c 6c 6f 2c 20 53  44 20 63 61 72 64 21 0a  |Hello, SD card!.|\r\n\
+00000010  00 ff 7f 41                                       |...A|\r\n";
+    let input = b"Hello, SD card!\n\x00\xff\x7fA";
+    for chunk in [1, 5, 16, 64] {
+        assert_eq!(
+            dump(input, 0, chunk).as_str(),
+            expected,
+            "{} byte chunks",
+            chunk
+        );
+    }
+
+    assert_eq!(
+        dump(b"12345678", 0x1234_5670, 3).as_str(),
+        "12345670  31 32 33 34 35 36 37 38                           |12345678|\r\n",
+        "offset"
+    );
+    assert_eq!(dump(b"", 0, 1).as_str(), "", "nothing");
+}
+
+#[test]
+fn head() {
+    let input = b"one\ntwo\nthree\nfour\n";
+    let cases: [(u32, &[u8]); 4] = [
+        (0, b""),
+        (1, b"one\n"),
+        (3, b"one\ntwo\nthree\n"),
+        (10, input),
+    ];
+    for (lines, expected) in cases {
+        for chunk in 1..=input.len() {
+            let mut head = Head::new(lines);
+            let got = stream(input, chunk, |piece, out| {
+                out.extend_from_slice(head.take(piece))
+            });
+            assert_eq!(
+                got.as_slice(),
+                expected,
+                "{} lines in {} byte chunks",
+                lines,
+                chunk
+            );
+            assert_eq!(head.is_done(), lines <= 4, "is_done");
+        }
+    }
+}
+
+#[test]
+fn tail() {
+    // Longer than the 64 byte blocks it reads backwards in
+    let long: String = (0..40).map(|i| format!("line {}\n", i)).collect();
+    let cases: [(&str, u32, &str); 8] = [
+        ("a\nb\nc\n", 1, "c\n"),
+        ("a\nb\nc\n", 2, "b\nc\n"),
+        ("a\nb\nc", 1, "c"),
+        ("a\nb\nc", 2, "b\nc"),
+        ("a\nb\n", 5, "a\nb\n"),
+        ("a\nb\n", 0, ""),
+        ("", 3, ""),
+        (&long, 2, "line 38\nline 39\n"),
+    ];
+    for (input, lines, expected) in cases {
+        let bytes = input.as_bytes();
+        let mut reads = 0;
+        let start = tail_start::<()>(bytes.len() as u32, lines, |at, buf| {
+            reads += 1;
+            let at = at as usize;
+            let n = buf.len().min(bytes.len() - at);
+            buf[..n].copy_from_slice(&bytes[at..at + n]);
+            Ok(n)
+        })
+        .unwrap();
+        assert_eq!(
+            &input[start as usize..],
+            expected,
+            "{:?}, {} lines",
+            input,
+            lines
+        );
+        if input.len() > 64 {
+            assert_eq!(reads, 1, "blocks read");
+        }
+    }
+}
@@ -0,0 +1,294 @@
+//! A USB card reader: the SD card shows up on the PC as a removable disk,
+//! and a serial port next to it says when a card comes and goes.
+//!
+//! The PC gets the raw blocks, so it mounts, reads, writes and formats the
+//! card itself. Press Enter on the serial port to see what the card is.
+//!
+//! Wiring: SD card on SPI0 (GPIO1 CS, GPIO2 SCK, GPIO3 MOSI, GPIO4 MISO),
+//! and optionally the socket's card detect switch on GPIO0.
+#![no_std]
+#![no_main]
+
+use hal::block::ImageDef;
+use panic_halt as _;
+use rp235x_hal::{self as hal, Clock as _};
+
+use usb_device::{class_prelude::*, prelude::*};
+use usbd_serial::SerialPort;
+
+use hal::fugit::{HertzU32, RateExtU32};
+use heapless::String;
+
+use core::fmt::Write;
+
+use embedded_hal::digital::{InputPin, OutputPin};
+use embedded_hal_bus::spi::ExclusiveDevice;
+use embedded_sdmmc::{Block, BlockDevice, BlockIdx, SdCard};
+
+use sdcard_info::detect::{self, DetectConfig, Event, Monitor};
+use sdcard_info::register::CardInfo;
+use sdcard_info::spi;
+use sdcard_msc::class::MscClass;
+use sdcard_msc::scsi::{Storage, StorageError, BLOCK};
+
+#[link_section = ".start_block"]
+#[used]
+pub static IMAGE_DEF: ImageDef = hal::block::ImageDef::secure_exe();
+
+const XTAL_FREQ_HZ: u32 = 12_000_000u32;
+
+/// USB polls without the host taking any data before output is abandoned.
+const MAX_STALLS: u32 = 100_000;
+
+/// GPIO0 is wired to the socket's card detect switch, which connects it to
+/// ground while a card is in. Without one the card is probed instead.
+const CARD_DETECT: bool = false;
+
+type Timer = hal::Timer<hal::timer::CopyableTimer0>;
+
+/// The SD card's end of SPI0.
+type CardSpi<P, CS> =
+    ExclusiveDevice<hal::spi::Spi<hal::spi::Enabled, hal::pac::SPI0, P, 8>, CS, Timer>;
+
+/// The card as the monitor drives it.
+struct Socket<'a, D> {
+    sdcard: &'a D,
+    peri_hz: HertzU32,
+    info: &'a mut Option<CardInfo>,
+}
+
+impl<P, CS> detect::Slot for Socket<'_, SdCard<CardSpi<P, CS>, Timer>>
+where
+    P: hal::spi::ValidSpiPinout<hal::pac::SPI0>,
+    CS: OutputPin,
+{
+    /// Starts the card at 400 kHz, then runs it as fast as it goes.
+    fn init(&mut self) -> Result<(), &'static str> {
+        let peri_hz = self.peri_hz;
+        let set_rate = |dev: &mut CardSpi<P, CS>, hz: u32| {
+            dev.bus_mut().set_baudrate(peri_hz, hz.Hz()).to_Hz()
+        };
+        self.sdcard.spi(|dev| set_rate(dev, spi::INIT_HZ));
+        self.sdcard.mark_card_uninit();
+        self.sdcard
+            .num_bytes()
+            .map_err(|_| "SD card not responding")?;
+        // SPI runs at up to half the peripheral clock
+        let info = self
+            .sdcard
+            .spi(|dev| spi::identify(dev, peri_hz.to_Hz() / 2, set_rate))
+            .map_err(|e| e.as_str())?;
+        *self.info = Some(info);
+        Ok(())
+    }
+
+    fn probe(&mut self) -> bool {
+        // Another card in its place counts as this one gone
+        self.info.is_some_and(|info| {
+            self.sdcard
+                .spi(|dev| spi::read_cid(dev))
+                .is_ok_and(|cid| cid == info.cid)
+        })
+    }
+}
+
+/// The card as the PC sees it. There's only a disk while the monitor says
+/// the card is ready.
+struct Disk<'a, D> {
+    sdcard: &'a D,
+    blocks: Option<u32>,
+    /// Whether a read or write failed, so the card wants setting up again.
+    failed: bool,
+}
+
+impl<D: BlockDevice> Storage for Disk<'_, D> {
+    fn block_count(&mut self) -> Result<u32, StorageError> {
+        self.blocks.ok_or(StorageError::NoMedium)
+    }
+
+    fn read_block(&mut self, lba: u32, block: &mut [u8; BLOCK]) -> Result<(), StorageError> {
+        self.block_count()?;
+        let mut blocks = [Block::new()];
+        if self.sdcard.read(&mut blocks, BlockIdx(lba)).is_err() {
+            self.failed = true;
+            return Err(StorageError::Failed);
+        }
+        block.copy_from_slice(&blocks[0].contents);
+        Ok(())
+    }
+
+    fn write_block(&mut self, lba: u32, block: &[u8; BLOCK]) -> Result<(), StorageError> {
+        self.block_count()?;
+        let mut blocks = [Block::new()];
+        blocks[0].contents.copy_from_slice(block);
+        if self.sdcard.write(&blocks, BlockIdx(lba)).is_err() {
+            self.failed = true;
+            return Err(StorageError::Failed);
+        }
+        Ok(())
+    }
+}
+
+#[hal::entry]
+fn main() -> ! {
+    let mut pac = hal::pac::Peripherals::take().unwrap();
+    let mut watchdog = hal::Watchdog::new(pac.WATCHDOG);
+
+    let clocks = hal::clocks::init_clocks_and_plls(
+        XTAL_FREQ_HZ,
+        pac.XOSC,
+        pac.CLOCKS,
+        pac.PLL_SYS,
+        pac.PLL_USB,
+        &mut pac.RESETS,
+        &mut watchdog,
+    )
+    .ok()
+    .unwrap();
+    let timer: Timer = hal::Timer::new_timer0(pac.TIMER0, &mut pac.RESETS, &clocks);
+
+    let sio = hal::Sio::new(pac.SIO);
+    let pins = hal::gpio::Pins::new(
+        pac.IO_BANK0,
+        pac.PADS_BANK0,
+        sio.gpio_bank0,
+        &mut pac.RESETS,
+    );
+
+    let usb_bus = UsbBusAllocator::new(hal::usb::UsbBus::new(
+        pac.USB,
+        pac.USB_DPRAM,
+        clocks.usb_clock,
+        true,
+        &mut pac.RESETS,
+    ));
+
+    let mut serial = SerialPort::new(&usb_bus);
+    let mut msc = MscClass::new(&usb_bus);
+
+    // Two functions on one device: the serial port's two interfaces come
+    // with an association descriptor, the disk is one interface of its own
+    let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x16c0, 0x27dd))
+        .strings(&[StringDescriptors::default()
+            .manufacturer("implRust")
+            .product("Ferris Card Reader")
+            .serial_number("TEST")])
+        .unwrap()
+        .composite_with_iads()
+        .build();
+
+    let spi_cs = pins.gpio1.into_push_pull_output();
+    let spi_sck = pins.gpio2.into_function::<hal::gpio::FunctionSpi>();
+    let spi_mosi = pins.gpio3.into_function::<hal::gpio::FunctionSpi>();
+    let spi_miso = pins.gpio4.into_function::<hal::gpio::FunctionSpi>();
+    let spi_bus = hal::spi::Spi::<_, _, _, 8>::new(pac.SPI0, (spi_mosi, spi_miso, spi_sck));
+
+    let spi = spi_bus.init(
+        &mut pac.RESETS,
+        clocks.peripheral_clock.freq(),
+        400.kHz(), // card initialization happens at low baud rate
+        embedded_hal::spi::MODE_0,
+    );
+
+    let spi = ExclusiveDevice::new(spi, spi_cs, timer).unwrap();
+    let sdcard = SdCard::new(spi, timer);
+    let peri_hz = clocks.peripheral_clock.freq();
+
+    let mut card_detect = CARD_DETECT.then(|| pins.gpio0.into_pull_up_input());
+    let mut monitor = Monitor::new(DetectConfig::DEFAULT);
+    let mut card_info = None;
+    let mut text: String<256> = String::new();
+
+    loop {
+        usb_dev.poll(&mut [&mut serial, &mut msc]);
+
+        // Enter shows the card
+        let mut buf = [0u8; 64];
+        let count = serial.read(&mut buf).unwrap_or(0);
+        if buf[..count].iter().any(|&b| b == b'\r' || b == b'\n') {
+            match card_info.filter(|_| monitor.is_ready()) {
+                Some(info) => {
+                    let _ = write!(text, "{}", info);
+                }
+                None => {
+                    let _ = write!(text, "{}\r\n", monitor.status());
+                }
+            }
+            say(&mut usb_dev, &mut serial, &mut msc, &text);
+            text.clear();
+        }
+
+        let now_ms = timer.get_counter().ticks() / 1000;
+        let inserted = card_detect
+            .as_mut()
+            .map(|pin| pin.is_low().unwrap_or(false));
+        let mut socket = Socket {
+            sdcard: &sdcard,
+            peri_hz,
+            info: &mut card_info,
+        };
+        if let Some(event) = monitor.poll(&mut socket, now_ms, inserted) {
+            let _ = write!(text, "{}\r\n", event);
+            match (event, card_info) {
+                // The PC forgets what it knew about the last card
+                (Event::Ready, Some(info)) => {
+                    msc.scsi_mut().medium_changed();
+                    let _ = write!(text, "{}", info);
+                }
+                (Event::Removed, _) => card_info = None,
+                _ => {}
+            }
+            say(&mut usb_dev, &mut serial, &mut msc, &text);
+            text.clear();
+        }
+
+        let mut disk = Disk {
+            sdcard: &sdcard,
+            blocks: card_info
+                .filter(|_| monitor.is_ready())
+                .map(|info| (info.csd.capacity / BLOCK as u64) as u32),
+            failed: false,
+        };
+        msc.process(&mut disk);
+        if disk.failed {
+            // The PC sees no disk until the card's been set up again
+            monitor.card_failed(now_ms);
+        }
+    }
+}
+
+/// Writes all of `text`, polling the device while the port's buffer is full.
+/// Gives up if the PC stops taking data, so a closed terminal can't hold up
+/// the disk.
+fn say<B: UsbBus>(
+    usb_dev: &mut UsbDevice<B>,
+    serial: &mut SerialPort<B>,
+    msc: &mut MscClass<B>,
+    text: &str,
+) {
+    let mut data = text.as_bytes();
+    let mut stalls = 0;
+    while !data.is_empty() {
+        match serial.write(data) {
+            Ok(n) => {
+                data = &data[n..];
+                stalls = 0;
+            }
+            Err(UsbError::WouldBlock) if stalls < MAX_STALLS => {
+                stalls += 1;
+                usb_dev.poll(&mut [&mut *serial, &mut *msc]);
+            }
+            Err(_) => return,
+        }
+    }
+}
+
+#[link_section = ".bi_entries"]
+#[used]
+pub static PICOTOOL_ENTRIES: [hal::binary_info::EntryAddr; 5] = [
+    hal::binary_info::rp_cargo_bin_name!(),
+    hal::binary_info::rp_cargo_version!(),
+    hal::binary_info::rp_program_description!(c"SD Card Reader"),
+    hal::binary_info::rp_cargo_homepage_url!(),
+    hal::binary_info::rp_program_build_attribute!(),
+];
@@ -4,18 +4,27 @@
 use embedded_hal::delay::DelayNs;
 use hal::block::ImageDef;
 use panic_halt as _;
-use rp235x_hal::{self as hal, Clock};
+use rp235x_hal::{self as hal, Clock as _};
 
 use usb_device::{class_prelude::*, prelude::*};
 use usbd_serial::SerialPort;
 
-use hal::fugit::RateExtU32;
+use hal::fugit::{HertzU32, RateExtU32};
 use heapless::String;
 
 use core::fmt::Write;
 
+use embedded_hal::digital::{InputPin, OutputPin};
 use embedded_hal_bus::spi::ExclusiveDevice;
-use embedded_sdmmc::{SdCard, TimeSource, Timestamp, VolumeIdx, VolumeManager};
+use embedded_sdmmc::{BlockDevice, Mode, SdCard, TimeSource, Timestamp, VolumeIdx, VolumeManager};
+use hal::gpio::{FunctionI2C, Pin};
+
+use sdcard_clock::aon::AonClock;
+use sdcard_clock::date::{self, DateTime};
+use sdcard_clock::ds3231::Ds3231;
+use sdcard_info::detect::{self, DetectConfig, Event, Monitor};
+use sdcard_info::register::CardInfo;
+use sdcard_info::spi;
 
 #[link_section = ".start_block"]
 #[used]
@@ -23,21 +32,78 @@ pub static IMAGE_DEF: ImageDef = hal::block::ImageDef::secure_exe();
 
 const XTAL_FREQ_HZ: u32 = 12_000_000u32;
 
-/// A dummy timesource, which is mostly important for creating files.
-#[derive(Default)]
-pub struct DummyTimesource();
+/// What writing fails with when the card stops answering. The card is then
+/// initialized again and the write retried.
+const CARD_GONE: &str = "SD card not responding";
+
+/// GPIO0 is wired to the socket's card detect switch, which connects it to
+/// ground while a card is in. Without one the card is probed instead.
+const CARD_DETECT: bool = false;
+
+type Timer = hal::Timer<hal::timer::CopyableTimer0>;
+
+/// The SD card's end of SPI0.
+type CardSpi<P, CS> =
+    ExclusiveDevice<hal::spi::Spi<hal::spi::Enabled, hal::pac::SPI0, P, 8>, CS, Timer>;
+
+/// The card as the monitor drives it.
+struct Socket<'a, D: BlockDevice, T: TimeSource> {
+    volume_mgr: &'a mut VolumeManager<D, T>,
+    peri_hz: HertzU32,
+    info: &'a mut Option<CardInfo>,
+}
+
+impl<P, CS, T> detect::Slot for Socket<'_, SdCard<CardSpi<P, CS>, Timer>, T>
+where
+    P: hal::spi::ValidSpiPinout<hal::pac::SPI0>,
+    CS: OutputPin,
+    T: TimeSource,
+{
+    /// Starts the card at 400 kHz, then runs it as fast as it goes.
+    fn init(&mut self) -> Result<(), &'static str> {
+        let peri_hz = self.peri_hz;
+        let set_rate = |dev: &mut CardSpi<P, CS>, hz: u32| {
+            dev.bus_mut().set_baudrate(peri_hz, hz.Hz()).to_Hz()
+        };
+        let sdcard = self.volume_mgr.device();
+        sdcard.spi(|dev| set_rate(dev, spi::INIT_HZ));
+        sdcard.mark_card_uninit();
+        sdcard.num_bytes().map_err(|_| CARD_GONE)?;
+        // SPI runs at up to half the peripheral clock
+        let info = sdcard
+            .spi(|dev| spi::identify(dev, peri_hz.to_Hz() / 2, set_rate))
+            .map_err(|e| e.as_str())?;
+        *self.info = Some(info);
+        Ok(())
+    }
+
+    fn probe(&mut self) -> bool {
+        // Another card in its place counts as this one gone
+        let sdcard = self.volume_mgr.device();
+        self.info.is_some_and(|info| {
+            sdcard
+                .spi(|dev| spi::read_cid(dev))
+                .is_ok_and(|cid| cid == info.cid)
+        })
+    }
+}
+
+/// File dates from the AON timer. The file is only written once the clock
+/// is set, but until then this gives 1980-01-01, the earliest date FAT can
+/// store.
+#[derive(Clone, Copy)]
+struct Clock<'a>(&'a AonClock);
 
-impl TimeSource for DummyTimesource {
-    // In theory you could use the RTC of the rp2040 here, if you had
-    // any external time synchronizing device.
+impl TimeSource for Clock<'_> {
     fn get_timestamp(&self) -> Timestamp {
+        let now = self.0.now().unwrap_or(DateTime::FAT_MIN).fat_clamped();
         Timestamp {
-            year_since_1970: 0,
-            zero_indexed_month: 0,
-            zero_indexed_day: 0,
-            hours: 0,
-            minutes: 0,
-            seconds: 0,
+            year_since_1970: (now.year - 1970) as u8,
+            zero_indexed_month: now.month - 1,
+            zero_indexed_day: now.day - 1,
+            hours: now.hour,
+            minutes: now.minute,
+            seconds: now.second,
         }
     }
 }
@@ -58,7 +124,7 @@ fn main() -> ! {
     )
     .ok()
     .unwrap();
-    let mut timer = hal::Timer::new_timer0(pac.TIMER0, &mut pac.RESETS, &clocks);
+    let mut timer: Timer = hal::Timer::new_timer0(pac.TIMER0, &mut pac.RESETS, &clocks);
 
     let sio = hal::Sio::new(pac.SIO);
     let pins = hal::gpio::Pins::new(
@@ -87,6 +153,28 @@ fn main() -> ! {
         .device_class(2) // 2 for the CDC, from: https://www.usb.org/defined-class-codes
         .build();
 
+    // An optional DS3231 RTC module on I2C1
+    let sda_pin: Pin<_, FunctionI2C, _> = pins.gpio18.reconfigure();
+    let scl_pin: Pin<_, FunctionI2C, _> = pins.gpio19.reconfigure();
+    let i2c = hal::I2C::i2c1(
+        pac.I2C1,
+        sda_pin,
+        scl_pin,
+        400.kHz(),
+        &mut pac.RESETS,
+        &clocks.system_clock,
+    );
+    let mut ds3231 = Ds3231::new(i2c);
+
+    // The AON timer keeps the time across resets. After a power cycle, take
+    // it from the DS3231 if there is one and it's been set.
+    let aon = AonClock::new(pac.POWMAN, XTAL_FREQ_HZ);
+    if !aon.is_set() {
+        if let Ok(Some(now)) = ds3231.now() {
+            aon.set(&now);
+        }
+    }
+
     let spi_cs = pins.gpio1.into_push_pull_output();
     let spi_sck = pins.gpio2.into_function::<hal::gpio::FunctionSpi>();
     let spi_mosi = pins.gpio3.into_function::<hal::gpio::FunctionSpi>();
@@ -102,49 +190,121 @@ fn main() -> ! {
 
     let spi = ExclusiveDevice::new(spi, spi_cs, timer).unwrap();
     let sdcard = SdCard::new(spi, timer);
-    let mut buff: String<64> = String::new();
+    let mut buff: String<96> = String::new();
+    let mut line: String<32> = String::new();
 
-    let mut volume_mgr = VolumeManager::new(sdcard, DummyTimesource::default());
+    let mut volume_mgr = VolumeManager::new(sdcard, Clock(&aon));
+    let peri_hz = clocks.peripheral_clock.freq();
+    let mut card_detect = CARD_DETECT.then(|| pins.gpio0.into_pull_up_input());
+    let mut monitor = Monitor::new(DetectConfig::DEFAULT);
+    let mut card_info = None;
 
+    // Each card that turns up gets the file once, or an error saying why not
     let mut is_written = false;
+    let mut asked = false;
     loop {
-        let _ = usb_dev.poll(&mut [&mut serial]);
-        if !is_written && timer.get_counter().ticks() >= 2_000_000 {
-            is_written = true;
-            serial
-                .write("Init SD card controller and retrieve card size...".as_bytes())
-                .unwrap();
-            match volume_mgr.device().num_bytes() {
-                Ok(size) => {
-                    write!(buff, "card size is {} bytes\r\n", size).unwrap();
-                    serial.write(buff.as_bytes()).unwrap();
+        if usb_dev.poll(&mut [&mut serial]) {
+            let mut buf = [0u8; 64];
+            let count = serial.read(&mut buf).unwrap_or(0);
+            for &byte in &buf[..count] {
+                if byte != b'\r' && byte != b'\n' {
+                    let _ = line.push(byte as char);
+                    continue;
                 }
-                Err(e) => {
-                    write!(buff, "Error: {:?}", e).unwrap();
-                    serial.write(buff.as_bytes()).unwrap();
+                if line.is_empty() {
+                    continue;
+                }
+                // date YYYY-MM-DD HH:MM[:SS] sets the clock
+                match line
+                    .strip_prefix("date ")
+                    .and_then(|t| date::parse(t.trim()))
+                {
+                    Some(time) => {
+                        aon.set(&time);
+                        write!(buff, "Clock set to {}\r\n", time).unwrap();
+                        if let Err(e) = ds3231.set(&time) {
+                            write!(buff, "DS3231: {}\r\n", e.as_str()).unwrap();
+                        }
+                    }
+                    None => buff
+                        .push_str("Send date YYYY-MM-DD HH:MM[:SS]\r\n")
+                        .unwrap(),
                 }
+                let _ = serial.write(buff.as_bytes());
+                buff.clear();
+                line.clear();
             }
-            buff.clear();
-
-            let Ok(mut volume0) = volume_mgr.open_volume(VolumeIdx(0)) else {
-                let _ = serial.write("err in open_volume".as_bytes());
-                continue;
-            };
+        }
+        // Give the host time to open the port and see what happens
+        let now_ms = timer.get_counter().ticks() / 1000;
+        if now_ms < 2000 {
+            timer.delay_ms(50);
+            continue;
+        }
 
-            let Ok(mut root_dir) = volume0.open_root_dir() else {
-                serial.write("err in open_root_dir".as_bytes()).unwrap();
-                continue;
-            };
+        let inserted = card_detect
+            .as_mut()
+            .map(|pin| pin.is_low().unwrap_or(false));
+        let mut socket = Socket {
+            volume_mgr: &mut volume_mgr,
+            peri_hz,
+            info: &mut card_info,
+        };
+        if let Some(event) = monitor.poll(&mut socket, now_ms, inserted) {
+            let _ = write!(buff, "{}\r\n", event);
+            let _ = serial.write(buff.as_bytes());
+            buff.clear();
+            match event {
+                Event::Ready => {
+                    is_written = false;
+                    if let Some(info) = card_info {
+                        let mut report: String<256> = String::new();
+                        write!(report, "{}", info).unwrap();
+                        for part in report.split_inclusive("\r\n") {
+                            let _ = serial.write(part.as_bytes());
+                        }
+                    }
+                }
+                Event::Removed => {
+                    card_info = None;
+                    volume_mgr = reopen(volume_mgr);
+                }
+                _ => {}
+            }
+        }
 
-            let Ok(mut my_file) = root_dir.open_file_in_dir(
-                "FERRIS.TXT",
-                embedded_sdmmc::Mode::ReadWriteCreateOrTruncate,
-            ) else {
-                serial.write("err in open_file_in_dir".as_bytes()).unwrap();
+        if monitor.is_ready() && !is_written {
+            let Some(now) = aon.now() else {
+                if !asked {
+                    asked = true;
+                    let _ = serial.write(
+                        "Set the clock for the file's date: date YYYY-MM-DD HH:MM[:SS]\r\n"
+                            .as_bytes(),
+                    );
+                }
+                timer.delay_ms(50);
                 continue;
             };
-            my_file.write("🦀".as_bytes()).unwrap();
-            serial.write("Written".as_bytes()).unwrap();
+            write!(buff, "Time is {}\r\n", now).unwrap();
+            match write_ferris(&mut volume_mgr) {
+                Ok(()) => {
+                    is_written = true;
+                    buff.push_str("Written FERRIS.TXT\r\n").unwrap();
+                }
+                Err(CARD_GONE) => {
+                    // Once the card is set up again, the write is tried again
+                    write!(buff, "Writing FERRIS.TXT: {}\r\n", CARD_GONE).unwrap();
+                    monitor.card_failed(now_ms);
+                    card_info = None;
+                    volume_mgr = reopen(volume_mgr);
+                }
+                Err(e) => {
+                    // Retrying won't put a FAT volume on the card
+                    is_written = true;
+                    write!(buff, "Writing FERRIS.TXT: {}\r\n", e).unwrap();
+                }
+            }
+            let _ = serial.write(buff.as_bytes());
         }
         buff.clear();
 
@@ -152,6 +312,38 @@ fn main() -> ! {
     }
 }
 
+/// Writes a crab to FERRIS.TXT in the root directory.
+fn write_ferris<D: BlockDevice, T: TimeSource>(
+    volume_mgr: &mut VolumeManager<D, T>,
+) -> Result<(), &'static str> {
+    let mut volume = volume_mgr.open_volume(VolumeIdx(0)).map_err(sd_error)?;
+    let mut root_dir = volume.open_root_dir().map_err(sd_error)?;
+    let mut file = root_dir
+        .open_file_in_dir("FERRIS.TXT", Mode::ReadWriteCreateOrTruncate)
+        .map_err(sd_error)?;
+    file.write("🦀".as_bytes()).map_err(sd_error)?;
+    // Closing writes the directory entry, so it can fail too
+    file.close().map_err(sd_error)
+}
+
+/// Starts the volume manager over, closing everything that was open. A
+/// handle dropped while the card was gone can't be closed properly and
+/// would otherwise stay open for good.
+fn reopen<D: BlockDevice, T: TimeSource>(volume_mgr: VolumeManager<D, T>) -> VolumeManager<D, T> {
+    let (device, time_source) = volume_mgr.free();
+    VolumeManager::new(device, time_source)
+}
+
+fn sd_error<E>(e: embedded_sdmmc::Error<E>) -> &'static str {
+    use embedded_sdmmc::Error;
+    match e {
+        Error::FormatError(_) | Error::NoSuchVolume => "No FAT volume on the card",
+        Error::DeviceError(_) => CARD_GONE,
+        Error::DiskFull => "Card is full",
+        _ => "SD card error",
+    }
+}
+
 #[link_section = ".bi_entries"]
 #[used]
 pub static PICOTOOL_ENTRIES: [hal::binary_info::EntryAddr; 5] = [